@@ -11,7 +11,7 @@ fn bench(c: &mut Criterion) {
 
     c.bench_function("FirstSecond", |b| {
         b.iter_batched(
-            || NDS::load_rom(&bios7_path, &bios9_path, &firmware_path, rom_path),
+            || NDS::load_rom(&bios7_path, &bios9_path, &firmware_path, rom_path).unwrap(),
             |mut nds| {
                 for _ in 0..60 {
                     nds.emulate_frame();
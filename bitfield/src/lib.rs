@@ -8,6 +8,9 @@ use syn::{
 };
 
 const MAX_BITS: usize = 64;
+const INT_TYPES: [&str; 5] = ["u8", "u16", "u32", "u64", "usize"];
+// Enum fields are converted to and from u8
+const MAX_ENUM_BITS: u8 = 8;
 
 fn parse_tokens(input: proc_macro::TokenStream) -> Result<TokenStream> {
     let input_copy: TokenStream = input.clone().into();
@@ -24,6 +27,12 @@ fn parse_tokens(input: proc_macro::TokenStream) -> Result<TokenStream> {
     };
 
     let mut full_mask = ((1u64 << (base_type_size - 1)) << 1).wrapping_sub(1);
+    let mut debug_fields = Vec::new();
+    let mut field_infos = Vec::new();
+    let mut ro_mask = 0u64;
+    let mut wo_mask = 0u64;
+    let mut w1c_mask = 0u64;
+    let mut enum_fields = Vec::new();
     let fns = bitfield_struct
         .fields
         .named
@@ -31,6 +40,7 @@ fn parse_tokens(input: proc_macro::TokenStream) -> Result<TokenStream> {
         .map(|f| {
             let skip_getter = f.skip_getter;
             let skip_setter = f.skip_setter;
+            let access = f.access;
             let vis = &f.vis;
             let name = f.ident.as_ref();
             let field_type = &f.used_type;
@@ -42,7 +52,16 @@ fn parse_tokens(input: proc_macro::TokenStream) -> Result<TokenStream> {
                 return Err(Error::new_spanned(range.to_token_stream(), message));
             };
 
-            if field_type.to_token_stream().to_string() == "bool" && lo != hi {
+            let type_str = field_type.to_token_stream().to_string();
+            let kind = if type_str == "bool" {
+                FieldKind::Bool
+            } else if type_str == "_" || INT_TYPES.contains(&type_str.as_str()) {
+                FieldKind::Int
+            } else {
+                FieldKind::Enum
+            };
+
+            if kind == FieldKind::Bool && lo != hi {
                 return make_range_error("Bitfield range is too large for a bool");
             }
             if range.range_limit.is_some() && lo == hi {
@@ -57,19 +76,51 @@ fn parse_tokens(input: proc_macro::TokenStream) -> Result<TokenStream> {
             if lo >= base_type_size || hi >= base_type_size {
                 return make_range_error("Bitfield range exceeds base type size");
             }
+            if kind == FieldKind::Enum && range_diff >= MAX_ENUM_BITS {
+                return make_range_error("Bitfield range is too large for an enum");
+            }
 
-            let getter_ret = if range_diff == 0 {
-                quote! { value != 0 }
-            } else {
-                quote! { value as #field_type }
+            let getter_ret = match kind {
+                FieldKind::Bool => quote! { value != 0 },
+                FieldKind::Int => quote! { value as #field_type },
+                FieldKind::Enum => quote! {
+                    match <#field_type as ::core::convert::TryFrom<u8>>::try_from(value as u8) {
+                        Ok(value) => value,
+                        Err(_) => panic!("Invalid value {} for bitfield type {}", value, #type_str),
+                    }
+                },
+            };
+            let set_value = match kind {
+                FieldKind::Enum => {
+                    quote! { <#field_type as ::core::convert::Into<u8>>::into(value) }
+                }
+                _ => quote! { value },
             };
             let mask = ((1u64 << range_diff) << 1).wrapping_sub(1);
+            let raw_value = quote! {
+                (self.0 >> #lo) & (#mask as #base_type)
+            };
+            let get_value = quote! {
+                {
+                    let value = #raw_value;
+                    #getter_ret
+                }
+            };
 
             let update_full_mask = mask << lo;
             if full_mask & update_full_mask != update_full_mask {
                 return make_range_error("Bitfield range overlaps with another bitfield range");
             }
             full_mask &= !(update_full_mask);
+            match access {
+                Access::ReadWrite => (),
+                Access::ReadOnly => ro_mask |= update_full_mask,
+                Access::WriteOnly => wo_mask |= update_full_mask,
+                Access::WriteOneToClear => w1c_mask |= update_full_mask,
+            }
+            if kind == FieldKind::Enum {
+                enum_fields.push((lo, mask, field_type.clone()));
+            }
 
             let set_name = if let Some(name) = name {
                 format_ident!("set_{}", name)
@@ -77,12 +128,25 @@ fn parse_tokens(input: proc_macro::TokenStream) -> Result<TokenStream> {
                 return Ok(TokenStream::new());
             };
 
+            // Debug output and the field table include fields without getters, and invalid enum
+            // values are shown as numbers
+            let name_str = name.unwrap().to_string();
+            debug_fields.push(match kind {
+                FieldKind::Enum => quote! {
+                    let value = #raw_value;
+                    match <#field_type as ::core::convert::TryFrom<u8>>::try_from(value as u8) {
+                        Ok(value) => debug_struct.field(#name_str, &value),
+                        Err(_) => debug_struct.field(#name_str, &value),
+                    };
+                },
+                _ => quote! { debug_struct.field(#name_str, &#get_value); },
+            });
+            field_infos.push(quote! { (#name_str, #lo..=#hi, #type_str) });
+
             let getter = if !skip_getter {
                 quote! {
                     #vis fn #name(&self) -> #field_type {
-                        let mask = #mask as #base_type;
-                        let value = (self.0 >> #lo) & mask;
-                        #getter_ret
+                        #get_value
                     }
                 }
             } else {
@@ -91,7 +155,7 @@ fn parse_tokens(input: proc_macro::TokenStream) -> Result<TokenStream> {
             let setter = if !skip_setter {
                 quote! {
                     #vis fn #set_name(&mut self, value: #field_type) {
-                        let value = value as #base_type;
+                        let value = #set_value as #base_type;
                         let mask = #mask as #base_type;
                         self.0 = (self.0 & !(mask << #lo)) | ((value & mask) << #lo);
                     }
@@ -106,26 +170,82 @@ fn parse_tokens(input: proc_macro::TokenStream) -> Result<TokenStream> {
             })
         })
         .collect::<Result<Vec<_>>>()?;
+    // Byte accesses are how the CPU sees the register, so they follow the fields' access rules
+    // Enum fields keep their previous value when the written one doesn't convert, so their
+    // getters can't fail after a byte write
     let byte_fns = (0..(base_type_size / 8)).map(|i| {
         let get_name = format_ident!("byte{}", i);
         let set_name = format_ident!("set_byte{}", i);
+        let readable_mask = !wo_mask;
+        let writable_mask = !(ro_mask | w1c_mask);
+        let enum_checks = enum_fields
+            .iter()
+            .filter(|(lo, mask, _)| (mask << lo) >> (8 * i) & 0xFF != 0)
+            .map(|(lo, mask, field_type)| {
+                quote! {
+                    {
+                        let field_mask = (#mask as #base_type) << #lo;
+                        let value = ((self.0 & field_mask) >> #lo) as u8;
+                        if <#field_type as ::core::convert::TryFrom<u8>>::try_from(value).is_err() {
+                            self.0 = self.0 & !field_mask | prev & field_mask;
+                        }
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        let save_prev = if enum_checks.is_empty() {
+            TokenStream::new()
+        } else {
+            quote! { let prev = self.0; }
+        };
         quote! {
             #struct_vis fn #get_name(&self) -> u8 {
-                (self.0 >> (8 * #i)) as u8
+                ((self.0 & #readable_mask as #base_type) >> (8 * #i)) as u8
             }
 
             #struct_vis fn #set_name(&mut self, value: u8) {
+                #save_prev
                 let shift = 8 * #i;
-                let cleared = self.0 & !(0xFF << shift);
+                let writable = (0xFF << shift) & #writable_mask as #base_type;
                 let val_shifted = (value as #base_type) << (8 * #i);
-                self.0 = cleared | val_shifted;
+                let cleared = val_shifted & #w1c_mask as #base_type;
+                self.0 = (self.0 & !writable | val_shifted & writable) & !cleared;
+                #(#enum_checks)*
             }
         }
     });
 
+    let name_str = name.to_string();
+    let serde_impls = if cfg!(feature = "serde") {
+        quote! {
+            impl ::serde::Serialize for #name {
+                fn serialize<S: ::serde::Serializer>(
+                    &self,
+                    serializer: S,
+                ) -> ::core::result::Result<S::Ok, S::Error> {
+                    ::serde::Serialize::serialize(&self.0, serializer)
+                }
+            }
+
+            impl<'de> ::serde::Deserialize<'de> for #name {
+                fn deserialize<D: ::serde::Deserializer<'de>>(
+                    deserializer: D,
+                ) -> ::core::result::Result<Self, D::Error> {
+                    <#base_type as ::serde::Deserialize>::deserialize(deserializer).map(Self)
+                }
+            }
+        }
+    } else {
+        TokenStream::new()
+    };
+
     let expanded = quote! {
         #expanded_struct
         impl #name {
+            // The name, bit range and type of each named field, in declaration order
+            pub const FIELDS: &'static [(&'static str, ::core::ops::RangeInclusive<u8>, &'static str)] =
+                &[#(#field_infos),*];
+
             pub fn new() -> Self {
                 Self(0)
             }
@@ -133,6 +253,28 @@ fn parse_tokens(input: proc_macro::TokenStream) -> Result<TokenStream> {
             #(#fns)*
             #(#byte_fns)*
         }
+
+        impl ::core::fmt::Debug for #name {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                let mut debug_struct = f.debug_struct(#name_str);
+                #(#debug_fields)*
+                debug_struct.finish()
+            }
+        }
+
+        impl ::core::convert::From<#base_type> for #name {
+            fn from(value: #base_type) -> Self {
+                Self(value)
+            }
+        }
+
+        impl ::core::convert::From<#name> for #base_type {
+            fn from(value: #name) -> Self {
+                value.0
+            }
+        }
+
+        #serde_impls
     };
 
     if full_mask != 0 {
@@ -160,9 +302,25 @@ struct BitfieldRange {
     pub hi_token: Option<LitInt>,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum FieldKind {
+    Bool,
+    Int,
+    Enum,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    ReadWrite,
+    ReadOnly,
+    WriteOnly,
+    WriteOneToClear,
+}
+
 struct BitfieldField {
     pub skip_getter: bool,
     pub skip_setter: bool,
+    pub access: Access,
     pub vis: Visibility,
     pub ident: Option<Ident>,
     pub _colon_token: Token![:],
@@ -228,14 +386,8 @@ impl Parse for BitfieldField {
 
         let mut skip_getter = false;
         let mut skip_setter = false;
-        if attrs.len() > 1 {
-            return Err(Error::new_spanned(
-                &attrs[1],
-                "Only up to one attribute is allowed",
-            ));
-        }
-        if attrs.len() == 1 {
-            let attr = attrs.first().unwrap();
+        let mut access = None;
+        for attr in attrs.iter() {
             match attr.style {
                 AttrStyle::Outer => (),
                 _ => {
@@ -259,12 +411,6 @@ impl Parse for BitfieldField {
             }
 
             let segment = attr.path.segments.first().unwrap();
-            if segment.ident.to_string() != "skip" {
-                return Err(Error::new_spanned(
-                    attr,
-                    "Bitfield field attribute segment must be `skip`",
-                ));
-            }
             match &segment.arguments {
                 PathArguments::None => (),
                 _ => {
@@ -274,6 +420,36 @@ impl Parse for BitfieldField {
                     ));
                 }
             };
+            let segment_str = segment.ident.to_string();
+            let attr_access = match segment_str.as_str() {
+                "skip" => None,
+                "ro" => Some(Access::ReadOnly),
+                "wo" => Some(Access::WriteOnly),
+                "w1c" => Some(Access::WriteOneToClear),
+                _ => {
+                    return Err(Error::new_spanned(
+                        attr,
+                        "Bitfield field attribute segment must be `skip`, `ro`, `wo` or `w1c`",
+                    ));
+                }
+            };
+            if let Some(attr_access) = attr_access {
+                if !attr.tokens.is_empty() {
+                    return Err(Error::new_spanned(
+                        attr,
+                        "Bitfield field access attribute must have no arguments",
+                    ));
+                }
+                if access.is_some() {
+                    return Err(Error::new_spanned(
+                        attr,
+                        "Only one access attribute is allowed",
+                    ));
+                }
+                access = Some(attr_access);
+                continue;
+            }
+
             let skipped_list: Punctuated<Ident, Token![,]> =
                 attr.parse_args_with(Punctuated::parse_terminated)?;
             for item in skipped_list {
@@ -305,6 +481,7 @@ impl Parse for BitfieldField {
         Ok(BitfieldField {
             skip_getter,
             skip_setter,
+            access: access.unwrap_or(Access::ReadWrite),
             vis,
             ident,
             _colon_token,
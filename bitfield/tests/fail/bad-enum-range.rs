@@ -0,0 +1,17 @@
+use bitfield::bitfield;
+
+#[derive(Clone, Copy, Debug)]
+enum Mode {
+    A = 0,
+}
+
+bitfield! {
+    struct Bitfield: u16 {
+        a: Mode @ 0..=8,
+        b: u8 @ 9..=15,
+    }
+}
+
+fn main() {
+    
+}
@@ -0,0 +1,14 @@
+use bitfield::bitfield;
+
+bitfield! {
+    struct Bitfield: u8 {
+        #[ro]
+        #[w1c]
+        a: bool @ 0,
+        b: u8 @ 1..=7,
+    }
+}
+
+fn main() {
+    
+}
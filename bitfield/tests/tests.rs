@@ -66,6 +66,127 @@ bitfield! {
     }
 }
 
+#[test]
+fn introspection() {
+    let mut skipped_bitfield = SkippedBitfield::from(0xFF);
+    skipped_bitfield.set_b(0b01);
+    assert_eq!(
+        format!("{:?}", skipped_bitfield),
+        "SkippedBitfield { a: true, b: 1 }"
+    );
+    assert_eq!(u8::from(skipped_bitfield), 0x7F);
+    assert_eq!(
+        SkippedBitfield::FIELDS,
+        &[("a", 1..=1, "bool"), ("b", 6..=7, "u8")]
+    );
+    assert_eq!(BasicBitfield::FIELDS.len(), 7);
+    assert_eq!(BasicBitfield::FIELDS[5], ("f", 8..=12, "u8"));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Off = 0,
+    Slow = 1,
+    Fast = 2,
+}
+
+impl TryFrom<u8> for Mode {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Mode::Off),
+            1 => Ok(Mode::Slow),
+            2 => Ok(Mode::Fast),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<Mode> for u8 {
+    fn from(mode: Mode) -> Self {
+        mode as u8
+    }
+}
+
+bitfield! {
+    struct AccessBitfield: u16 {
+        mode: Mode @ 0..=1,
+        #[ro]
+        busy: bool @ 2,
+        #[wo]
+        start: bool @ 3,
+        #[w1c]
+        irq: bool @ 4,
+        #[ro]
+        _: u8 @ 5..=7,
+        #[skip(setter)]
+        #[ro]
+        version: u8 @ 8..=15,
+    }
+}
+
+#[test]
+fn enum_fields() {
+    let mut access_bitfield = AccessBitfield::new();
+    assert_eq!(access_bitfield.mode(), Mode::Off);
+    access_bitfield.set_mode(Mode::Fast);
+    assert_eq!(access_bitfield.0, 0b10);
+    access_bitfield.set_byte0(0b01);
+    assert_eq!(access_bitfield.mode(), Mode::Slow);
+
+    // Invalid values are shown as numbers
+    access_bitfield.0 = 0b11;
+    assert_eq!(
+        format!("{:?}", access_bitfield),
+        "AccessBitfield { mode: 3, busy: false, start: false, irq: false, version: 0 }"
+    );
+}
+
+#[test]
+fn invalid_enum_writes_are_ignored() {
+    let mut access_bitfield = AccessBitfield::new();
+    access_bitfield.set_mode(Mode::Slow);
+    // The rest of the byte is still written
+    access_bitfield.set_byte0(0b1011);
+    assert_eq!(access_bitfield.mode(), Mode::Slow);
+    assert!(access_bitfield.start());
+}
+
+#[test]
+fn access_rules() {
+    let mut access_bitfield = AccessBitfield::from(0x1200);
+
+    // Read-only bits aren't changed by byte writes, but setters can change them
+    access_bitfield.set_byte0(0xE4);
+    access_bitfield.set_byte1(0xFF);
+    assert_eq!(access_bitfield.0, 0x1200);
+    access_bitfield.set_busy(true);
+    assert_eq!(access_bitfield.byte0(), 0b100);
+
+    // Write-only bits always read as 0
+    access_bitfield.set_byte0(0b1000);
+    assert!(access_bitfield.start());
+    assert_eq!(access_bitfield.byte0(), 0b100);
+
+    // Writing 1 clears write-one-to-clear bits and writing 0 leaves them alone
+    access_bitfield.set_irq(true);
+    access_bitfield.set_byte0(0);
+    assert!(access_bitfield.irq());
+    access_bitfield.set_byte0(0b10000);
+    assert!(!access_bitfield.irq());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde() {
+    let basic_bitfield = BasicBitfield::from(0xAAA1);
+    let json = serde_json::to_string(&basic_bitfield).unwrap();
+    assert_eq!(json, "43681");
+    let deserialized: BasicBitfield = serde_json::from_str(&json).unwrap();
+    assert_eq!(deserialized.0, 0xAAA1);
+}
+
 #[test]
 fn fail_tests() {
     let t = trybuild::TestCases::new();
@@ -1,22 +1,38 @@
 #[macro_use]
 mod instructions;
 mod arm;
+mod block_cache;
+mod debugger;
+pub mod disassembler;
+#[cfg(feature = "jit")]
+mod jit;
 mod registers;
 mod thumb;
+mod trace;
 
-use crate::hw::{AccessType, MemoryValue, HW};
+use crate::hw::{mem::CodePages, AccessType, MemoryValue, HW};
 use crate::{likely, num, unlikely};
-use registers::{Mode, RegValues};
+use block_cache::BlockCache;
+use debugger::Debugger;
+pub use debugger::{DebugStop, WatchKind};
+pub use registers::Mode;
+use registers::RegValues;
 
 pub struct ARM<const IS_ARM9: bool> {
     cycle: usize,
     regs: RegValues,
     instr_buffer: [u32; 2],
     next_access_type: AccessType,
+    data_abort_lr: Option<u32>,
+    debugger: Debugger,
 
     condition_lut: [bool; 256],
     arm_lut: [instructions::InstructionHandler<u32, IS_ARM9>; 4096],
     thumb_lut: [instructions::InstructionHandler<u16, IS_ARM9>; 256],
+    arm_blocks: BlockCache<u32, IS_ARM9>,
+    thumb_blocks: BlockCache<u16, IS_ARM9>,
+    #[cfg(feature = "jit")]
+    jit: jit::Jit<IS_ARM9>,
 }
 
 impl<const IS_ARM9: bool> ARM<IS_ARM9> {
@@ -34,10 +50,16 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
             },
             instr_buffer: [0; 2],
             next_access_type: AccessType::N,
+            data_abort_lr: None,
+            debugger: Debugger::new(),
 
             condition_lut: instructions::gen_condition_table(),
             arm_lut: arm::gen_lut(),
             thumb_lut: thumb::gen_lut(),
+            arm_blocks: BlockCache::new(),
+            thumb_blocks: BlockCache::new(),
+            #[cfg(feature = "jit")]
+            jit: jit::Jit::new(),
         };
         cpu.fill_arm_instr_buffer(hw);
         cpu
@@ -49,30 +71,161 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
 
     pub fn emulate(&mut self, hw: &mut HW, target: usize) {
         while self.cycle < target {
+            // The CPU can't access the bus while its DMA is transferring
+            if hw.dma_active(IS_ARM9) {
+                self.cycle = target;
+                return;
+            }
             self.handle_irq(hw);
             if self.is_halted(hw) {
                 self.cycle = target;
                 return;
             }
+            if unlikely(hw.code_pages.invalidated(IS_ARM9)) {
+                self.invalidate_blocks(hw);
+            }
 
-            if unlikely(self.regs.get_t()) {
-                self.emulate_thumb_instr(hw)
-            } else {
-                self.emulate_arm_instr(hw)
+            if IS_ARM9 && unlikely(!self.instr_access_allowed(hw)) {
+                let instr_addr = self.instr_addr();
+                warn!("ARM9 Prefetch Abort at 0x{:08X}", instr_addr);
+                // LR points to the instruction after the aborted one
+                self.abort(hw, instr_addr.wrapping_add(4), 0xC);
+                continue;
             }
+
+            let debugging = self.debugger_active();
+            if unlikely(debugging) && self.debug_start_instr() {
+                return;
+            }
+
+            // Compiled blocks don't stop for the debugger or tracing
+            if debugging || !self.run_compiled_block(hw, target) {
+                if unlikely(self.regs.get_t()) {
+                    self.emulate_thumb_instr(hw)
+                } else {
+                    self.emulate_arm_instr(hw)
+                }
+            }
+
+            if let Some(lr) = self.data_abort_lr.take() {
+                self.abort(hw, lr, 0x10);
+            }
+            if unlikely(debugging) && self.debug_finish_instr() {
+                return;
+            }
+        }
+    }
+
+    fn invalidate_blocks(&mut self, hw: &mut HW) {
+        let invalidated = hw.code_pages.take_invalidated(IS_ARM9);
+        if invalidated.all {
+            self.arm_blocks.clear();
+            self.thumb_blocks.clear();
+            #[cfg(feature = "jit")]
+            self.jit.clear();
         }
+        for chunk in invalidated.chunks {
+            self.arm_blocks.invalidate_chunk(chunk);
+            self.thumb_blocks.invalidate_chunk(chunk);
+            #[cfg(feature = "jit")]
+            self.jit.invalidate_chunk(chunk);
+        }
+    }
+
+    #[cfg(not(feature = "jit"))]
+    fn run_compiled_block(&mut self, _hw: &mut HW, _target: usize) -> bool {
+        false
+    }
+
+    // Decoded instructions would skip the emulated I-cache, so those are fetched one at a time
+    fn can_decode_block(hw: &HW, addr: u32) -> bool {
+        CodePages::cachable(addr)
+            && !hw.code_pages.self_modifying(IS_ARM9, addr)
+            && !(IS_ARM9 && hw.cp15.emulate_caches)
+    }
+
+    fn privileged(&self) -> bool {
+        self.regs.get_mode() != Mode::USR
+    }
+
+    // Address of the instruction that will be executed next
+    fn instr_addr(&self) -> u32 {
+        if self.regs.get_t() {
+            self.regs[15].wrapping_sub(2)
+        } else {
+            self.regs[15].wrapping_sub(4)
+        }
+    }
+
+    fn instr_access_allowed(&self, hw: &HW) -> bool {
+        hw.cp15
+            .code_access_allowed(self.privileged(), self.instr_addr())
+    }
+
+    fn data_access_allowed(&mut self, hw: &HW, is_write: bool, addr: u32) -> bool {
+        if likely(
+            hw.cp15
+                .data_access_allowed(self.privileged(), is_write, addr),
+        ) {
+            return true;
+        }
+        warn!("ARM9 Data Abort at 0x{:08X}", addr);
+        // Only the first abort of an instruction is taken
+        if self.data_abort_lr.is_none() {
+            self.data_abort_lr = Some(if self.regs.get_t() {
+                self.regs[15].wrapping_add(4)
+            } else {
+                self.regs[15]
+            });
+        }
+        false
+    }
+
+    // The ARM9 restores the base register on a data abort and doesn't load into registers, so a
+    // handler can fix the fault and retry the instruction
+    fn aborted(&self) -> bool {
+        IS_ARM9 && unlikely(self.data_abort_lr.is_some())
+    }
+
+    fn abort(&mut self, hw: &mut HW, lr: u32, vector: u32) {
+        self.regs.change_mode(Mode::ABT);
+        self.regs.set_lr(lr);
+        self.regs.set_t(false);
+        self.regs.set_i(true);
+        self.regs[15] = hw.cp15.interrupt_base() | vector;
+        self.fill_arm_instr_buffer(hw);
     }
 
     pub fn read<T: MemoryValue>(&mut self, hw: &mut HW, access_type: AccessType, addr: u32) -> T {
+        self.read_mem::<T, false>(hw, access_type, addr)
+    }
+
+    pub fn fetch<T: MemoryValue>(&mut self, hw: &mut HW, access_type: AccessType, addr: u32) -> T {
+        self.read_mem::<T, true>(hw, access_type, addr)
+    }
+
+    fn read_mem<T: MemoryValue, const IS_CODE: bool>(
+        &mut self,
+        hw: &mut HW,
+        access_type: AccessType,
+        addr: u32,
+    ) -> T {
         let value = if IS_ARM9 {
-            let value = hw.arm9_read::<T>(addr);
-            self.cycle += hw.arm9_get_access_time::<T>(self.next_access_type, addr);
+            if !IS_CODE && unlikely(!self.data_access_allowed(hw, false, addr)) {
+                return num::zero();
+            }
+            let (value, access_time) = hw.arm9_cpu_read::<T>(self.next_access_type, addr, IS_CODE);
+            self.add_arm9_access_time(access_time);
             value
         } else {
             let value = hw.arm7_read::<T>(addr);
             self.cycle += hw.arm7_get_access_time::<T>(self.next_access_type, addr);
             value
         };
+        if !IS_CODE && unlikely(self.debugger_active()) {
+            let watch_value = num::cast::<T, u32>(value).unwrap();
+            self.debug_watch(addr, std::mem::size_of::<T>(), false, watch_value);
+        }
         self.next_access_type = access_type;
         value
     }
@@ -84,9 +237,16 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         addr: u32,
         value: T,
     ) {
+        if unlikely(self.debugger_active()) {
+            let watch_value = num::cast::<T, u32>(value).unwrap();
+            self.debug_watch(addr, std::mem::size_of::<T>(), true, watch_value);
+        }
         if IS_ARM9 {
-            self.cycle += hw.arm9_get_access_time::<T>(self.next_access_type, addr);
-            hw.arm9_write::<T>(addr, value);
+            if unlikely(!self.data_access_allowed(hw, true, addr)) {
+                return;
+            }
+            let access_time = hw.arm9_cpu_write::<T>(self.next_access_type, addr, value);
+            self.add_arm9_access_time(access_time);
         } else {
             self.cycle += hw.arm7_get_access_time::<T>(self.next_access_type, addr);
             hw.arm7_write::<T>(addr, value);
@@ -94,11 +254,19 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         self.next_access_type = access_type;
     }
 
+    fn add_arm9_access_time(&mut self, access_time: usize) {
+        // Bus accesses have to wait for the next 33 MHz clock edge
+        if access_time > HW::ARM9_INTERNAL_ACCESS_TIME {
+            self.cycle += self.cycle & 0x1;
+        }
+        self.cycle += access_time;
+    }
+
     pub fn instruction_prefetch<T: MemoryValue>(&mut self, hw: &mut HW, access_type: AccessType) {
         // Internal Cycle merges with instruction prefetch
         // TODO: Increment PC here
         self.instr_buffer[1] =
-            num::cast::<T, u32>(self.read::<T>(hw, access_type, self.regs[15])).unwrap();
+            num::cast::<T, u32>(self.fetch::<T>(hw, access_type, self.regs[15])).unwrap();
     }
 
     pub fn internal(&mut self) {
@@ -114,16 +282,21 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         }
     }
 
-    pub fn handle_irq(&mut self, hw: &mut HW) {
-        let (interrupts_requested, interrupt_base) = if IS_ARM9 {
-            (hw.arm9_interrupts_requested(), hw.cp15.interrupt_base())
+    fn irq_pending(&self, hw: &mut HW) -> bool {
+        let interrupts_requested = if IS_ARM9 {
+            hw.arm9_interrupts_requested()
         } else {
-            (hw.arm7_interrupts_requested(), 0)
+            hw.arm7_interrupts_requested()
         };
         let use_i = IS_ARM9 || !hw.haltcnt.halted();
-        if likely((use_i && self.regs.get_i()) || !interrupts_requested) {
+        interrupts_requested && !(use_i && self.regs.get_i())
+    }
+
+    pub fn handle_irq(&mut self, hw: &mut HW) {
+        if likely(!self.irq_pending(hw)) {
             return;
         }
+        let interrupt_base = if IS_ARM9 { hw.cp15.interrupt_base() } else { 0 };
         if IS_ARM9 {
             hw.cp15.arm9_halted = false
         } else {
@@ -131,10 +304,10 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         }
         self.regs.change_mode(Mode::IRQ);
         let lr = if unlikely(self.regs.get_t()) {
-            self.read::<u16>(hw, AccessType::N, self.regs[15]);
+            self.fetch::<u16>(hw, AccessType::N, self.regs[15]);
             self.regs[15].wrapping_sub(2).wrapping_add(4)
         } else {
-            self.read::<u32>(hw, AccessType::N, self.regs[15]);
+            self.fetch::<u32>(hw, AccessType::N, self.regs[15]);
             self.regs[15].wrapping_sub(4).wrapping_add(4)
         };
         self.regs.set_lr(lr);
@@ -330,3 +503,149 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, ARM};
+    use crate::hw::HW;
+
+    const CODE_ADDR: u32 = 0x0200_0000;
+    // A 4 KB region without any access
+    const PROTECTED_ADDR: u32 = 0x0230_0000;
+
+    fn setup(thumb: bool, instr: u32) -> (ARM<true>, HW) {
+        let mut hw = HW::new_for_tests();
+        // Region 0 allows everything, and region 1 overrides it for PROTECTED_ADDR
+        hw.cp15.write(6, 0, 0, 0x3F);
+        hw.cp15.write(6, 0, 1, 0x3F);
+        hw.cp15.write(6, 1, 0, PROTECTED_ADDR | 11 << 1 | 1);
+        hw.cp15.write(5, 0, 2, 0x03);
+        hw.cp15.write(5, 0, 3, 0x03);
+        // Protection unit enabled with high exception vectors
+        hw.cp15.write(1, 0, 0, 1 << 13 | 1);
+        if thumb {
+            hw.arm9_write::<u16>(CODE_ADDR, instr as u16);
+        } else {
+            hw.arm9_write::<u32>(CODE_ADDR, instr);
+        }
+
+        let mut cpu = ARM::<true>::new(&mut hw, false);
+        cpu.regs[15] = CODE_ADDR;
+        if thumb {
+            cpu.regs.set_t(true);
+            cpu.fill_thumb_instr_buffer(&mut hw);
+        } else {
+            cpu.fill_arm_instr_buffer(&mut hw);
+        }
+        (cpu, hw)
+    }
+
+    fn step(cpu: &mut ARM<true>, hw: &mut HW) {
+        let target = cpu.cycle + 1;
+        cpu.emulate(hw, target);
+    }
+
+    // Runs a single instruction that's expected to take a data abort
+    fn run(cpu: &mut ARM<true>, hw: &mut HW) {
+        let target = cpu.cycle + 1;
+        cpu.emulate(hw, target);
+        assert_eq!(cpu.regs.get_mode(), Mode::ABT);
+        assert_eq!(cpu.instr_addr(), 0xFFFF_0010);
+    }
+
+    #[test]
+    fn aborted_ldr_keeps_destination() {
+        // ldr r0, [r0]
+        let (mut cpu, mut hw) = setup(false, 0xE590_0000);
+        cpu.regs[0] = PROTECTED_ADDR;
+        run(&mut cpu, &mut hw);
+        assert_eq!(cpu.regs[0], PROTECTED_ADDR);
+        assert_eq!(cpu.regs.lr(), CODE_ADDR + 8);
+    }
+
+    #[test]
+    fn aborted_ldr_skips_writeback() {
+        // ldr r1, [r0], #4
+        let (mut cpu, mut hw) = setup(false, 0xE490_1004);
+        cpu.regs[0] = PROTECTED_ADDR;
+        cpu.regs[1] = 0x1234;
+        run(&mut cpu, &mut hw);
+        assert_eq!(cpu.regs[0], PROTECTED_ADDR);
+        assert_eq!(cpu.regs[1], 0x1234);
+    }
+
+    #[test]
+    fn aborted_str_skips_writeback() {
+        // str r1, [r0, #4]!
+        let (mut cpu, mut hw) = setup(false, 0xE5A0_1004);
+        cpu.regs[0] = PROTECTED_ADDR;
+        run(&mut cpu, &mut hw);
+        assert_eq!(cpu.regs[0], PROTECTED_ADDR);
+    }
+
+    #[test]
+    fn aborted_ldm_keeps_registers() {
+        // ldmia r0!, {r1-r3}
+        let (mut cpu, mut hw) = setup(false, 0xE8B0_000E);
+        cpu.regs[0] = PROTECTED_ADDR;
+        for reg in 1..=3 {
+            cpu.regs[reg] = reg;
+        }
+        run(&mut cpu, &mut hw);
+        assert_eq!(cpu.regs[0], PROTECTED_ADDR);
+        for reg in 1..=3 {
+            assert_eq!(cpu.regs[reg], reg);
+        }
+    }
+
+    #[test]
+    fn aborted_swp_keeps_destination() {
+        // swp r1, r2, [r0]
+        let (mut cpu, mut hw) = setup(false, 0xE100_1092);
+        cpu.regs[0] = PROTECTED_ADDR;
+        cpu.regs[1] = 0x1234;
+        run(&mut cpu, &mut hw);
+        assert_eq!(cpu.regs[1], 0x1234);
+    }
+
+    #[test]
+    fn aborted_thumb_pop_keeps_sp() {
+        // pop {r0, r1}
+        let (mut cpu, mut hw) = setup(true, 0xBC03);
+        cpu.regs.set_sp(PROTECTED_ADDR);
+        run(&mut cpu, &mut hw);
+        cpu.regs.change_mode(Mode::SYS);
+        assert_eq!(cpu.regs.sp(), PROTECTED_ADDR);
+        assert_eq!(cpu.regs[0], 0);
+    }
+
+    #[test]
+    fn aborted_thumb_stmia_keeps_base() {
+        // stmia r0!, {r1, r2}
+        let (mut cpu, mut hw) = setup(true, 0xC006);
+        cpu.regs[0] = PROTECTED_ADDR;
+        run(&mut cpu, &mut hw);
+        assert_eq!(cpu.regs[0], PROTECTED_ADDR);
+    }
+
+    #[test]
+    fn emulated_icache_keeps_stale_code() {
+        // mov r0, #1; b CODE_ADDR
+        let (mut cpu, mut hw) = setup(false, 0xE3A0_0001);
+        hw.arm9_write::<u32>(CODE_ADDR + 4, 0xEAFF_FFFD);
+        // Region 0 is instruction cachable
+        hw.cp15.write(2, 0, 1, 0x01);
+        hw.cp15.write(1, 0, 0, 1 << 13 | 1 << 12 | 1);
+        hw.set_arm9_cache_emulation(true);
+        cpu.regs[15] = CODE_ADDR;
+        cpu.fill_arm_instr_buffer(&mut hw);
+
+        step(&mut cpu, &mut hw);
+        // mov r0, #2, which the I-cache doesn't see
+        hw.arm9_write::<u32>(CODE_ADDR, 0xE3A0_0002);
+        cpu.regs[0] = 0;
+        step(&mut cpu, &mut hw);
+        step(&mut cpu, &mut hw);
+        assert_eq!(cpu.regs[0], 1);
+    }
+}
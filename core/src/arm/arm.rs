@@ -1,33 +1,74 @@
-use super::{instructions::InstructionHandler, registers::Mode, ARM, HW};
+use super::{
+    block_cache::{BlockCache, CachedInstr},
+    instructions::InstructionHandler,
+    registers::Mode,
+    ARM, HW,
+};
 
-use crate::hw::AccessType;
+use crate::hw::{mem::CodePages, AccessType};
 use crate::likely;
 
 impl<const IS_ARM9: bool> ARM<IS_ARM9> {
     pub(super) fn fill_arm_instr_buffer(&mut self, hw: &mut HW) {
         self.regs[15] &= !0x3;
-        self.instr_buffer[0] = self.read::<u32>(hw, AccessType::S, self.regs[15] & !0x3);
+        self.instr_buffer[0] = self.fetch::<u32>(hw, AccessType::S, self.regs[15] & !0x3);
         self.regs[15] = self.regs[15].wrapping_add(4);
 
-        self.instr_buffer[1] = self.read::<u32>(hw, AccessType::S, self.regs[15] & !0x3);
+        self.instr_buffer[1] = self.fetch::<u32>(hw, AccessType::S, self.regs[15] & !0x3);
     }
 
-    pub(super) fn emulate_arm_instr(&mut self, hw: &mut HW) {
-        let instr = self.instr_buffer[0];
+    pub(super) fn arm_lut_index(instr: u32) -> usize {
+        ((instr as usize) >> 16 & 0xFF0) | ((instr as usize) >> 4 & 0xF)
+    }
+
+    fn next_arm_instr(&mut self, hw: &mut HW) -> CachedInstr<u32, IS_ARM9> {
+        let addr = self.regs[15].wrapping_sub(4);
+        if let Some(cached_instr) = self.arm_blocks.next(addr) {
+            return cached_instr;
+        }
+        if !ARM::<IS_ARM9>::can_decode_block(hw, addr) {
+            let instr = self.instr_buffer[0];
+            return (self.arm_lut[ARM::<IS_ARM9>::arm_lut_index(instr)], instr);
+        }
+        self.build_arm_block(hw, addr);
+        self.arm_blocks.next(addr).unwrap()
+    }
+
+    #[cold]
+    fn build_arm_block(&mut self, hw: &mut HW, addr: u32) {
+        let mut block = Vec::new();
+        let mut instr_addr = addr;
+        while block.len() < BlockCache::<u32, IS_ARM9>::MAX_BLOCK_LEN
+            && CodePages::same_chunk(addr, instr_addr)
         {
-            trace!("{:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} \
-            {:08X} {:08X} {:08X} {:08X} cpsr: {:08X} | {:08X}",
-            self.regs[0], self.regs[1], self.regs[2], self.regs[3], self.regs[4], self.regs[5], self.regs[6],
-            self.regs[7], self.regs[8], self.regs[9], self.regs[10], self.regs[11], self.regs[12],
-            self.regs[13], self.regs[14], self.regs[15], self.regs.cpsr(), instr);
+            let instr = if IS_ARM9 {
+                hw.arm9_read::<u32>(instr_addr)
+            } else {
+                hw.arm7_read::<u32>(instr_addr)
+            };
+            block.push((self.arm_lut[ARM::<IS_ARM9>::arm_lut_index(instr)], instr));
+            instr_addr = instr_addr.wrapping_add(4);
         }
+        let chunk = hw.code_pages.mark(IS_ARM9, addr);
+        self.arm_blocks.insert(addr, chunk, block);
+    }
+
+    pub(super) fn emulate_arm_instr(&mut self, hw: &mut HW) {
+        let (handler, instr) = self.next_arm_instr(hw);
+        self.execute_arm_instr(hw, handler, instr);
+    }
+
+    pub(super) fn execute_arm_instr(
+        &mut self,
+        hw: &mut HW,
+        handler: InstructionHandler<u32, IS_ARM9>,
+        instr: u32,
+    ) {
         self.instr_buffer[0] = self.instr_buffer[1];
         self.regs[15] = self.regs[15].wrapping_add(4);
 
         if likely(self.should_exec((instr >> 28) & 0xF)) {
-            self.arm_lut[((instr as usize) >> 16 & 0xFF0) | ((instr as usize) >> 4 & 0xF)](
-                self, hw, instr,
-            );
+            handler(self, hw, instr);
         } else {
             self.instruction_prefetch::<u32>(hw, AccessType::S);
         }
@@ -319,15 +360,8 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
     ) {
         assert_eq!(instr >> 26 & 0b11, 0b01);
         let shifted_reg_offset = I;
-        let pre_offset = P;
         let add_offset = U;
-        let transfer_byte = B;
-        let mut write_back = W || !pre_offset;
-        let load = L;
-        let base_reg = instr >> 16 & 0xF;
-        let base = self.regs[base_reg];
-        let src_dest_reg = instr >> 12 & 0xF;
-        self.instruction_prefetch::<u32>(hw, AccessType::N);
+        let base = self.regs[instr >> 16 & 0xF];
 
         let offset = if shifted_reg_offset {
             let shift = instr >> 7 & 0x1F;
@@ -340,67 +374,79 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         } else {
             instr & 0xFFF
         };
-
-        let mut exec = |addr| {
-            if load {
-                let access_type = if src_dest_reg == 15 {
-                    AccessType::N
-                } else {
-                    AccessType::S
-                };
-                let value = if transfer_byte {
-                    self.read::<u8>(hw, access_type, addr) as u32
-                } else {
-                    self.read::<u32>(hw, access_type, addr & !0x3)
-                        .rotate_right((addr & 0x3) * 8)
-                };
-                self.internal();
-                self.regs[src_dest_reg] = value;
-                if src_dest_reg == base_reg {
-                    write_back = false
-                }
-                if src_dest_reg == 15 {
-                    if IS_ARM9 && self.regs[15] & 0x1 != 0 {
-                        self.regs[15] -= 1;
-                        self.regs.set_t(true);
-                        self.fill_thumb_instr_buffer(hw);
-                    } else {
-                        self.fill_arm_instr_buffer(hw);
-                    }
-                }
-            } else {
-                let value = self.regs[src_dest_reg];
-                let value = if src_dest_reg == 15 {
-                    value.wrapping_add(4)
-                } else {
-                    value
-                };
-                if transfer_byte {
-                    self.write::<u8>(hw, AccessType::N, addr, value as u8);
-                } else {
-                    self.write::<u32>(hw, AccessType::N, addr & !0x3, value);
-                }
-            }
-        };
         let offset_applied = if add_offset {
             base.wrapping_add(offset)
         } else {
             base.wrapping_sub(offset)
         };
-        if pre_offset {
-            exec(offset_applied);
-            if write_back {
-                self.regs[base_reg] = offset_applied
-            }
-        } else {
+        if !P {
             // TOOD: Take into account privilege of access
             let force_non_privileged_access = instr >> 21 & 0x1 != 0;
             assert_eq!(force_non_privileged_access, false);
-            // Write back is not done if src_reg == base_reg
-            exec(base);
-            if write_back {
-                self.regs[base_reg] = offset_applied
+        }
+        self.single_data_transfer_at(hw, instr, offset_applied);
+    }
+
+    // Everything after calculating the offset address, which the JIT does natively
+    #[inline(always)]
+    pub(super) fn single_data_transfer_at(&mut self, hw: &mut HW, instr: u32, offset_applied: u32) {
+        let pre_offset = instr >> 24 & 0x1 != 0;
+        let transfer_byte = instr >> 22 & 0x1 != 0;
+        let mut write_back = instr >> 21 & 0x1 != 0 || !pre_offset;
+        let load = instr >> 20 & 0x1 != 0;
+        let base_reg = instr >> 16 & 0xF;
+        let src_dest_reg = instr >> 12 & 0xF;
+        self.instruction_prefetch::<u32>(hw, AccessType::N);
+        // Write back is not done if src_reg == base_reg
+        let addr = if pre_offset {
+            offset_applied
+        } else {
+            self.regs[base_reg]
+        };
+        if load {
+            let access_type = if src_dest_reg == 15 {
+                AccessType::N
+            } else {
+                AccessType::S
+            };
+            let value = if transfer_byte {
+                self.read::<u8>(hw, access_type, addr) as u32
+            } else {
+                self.read::<u32>(hw, access_type, addr & !0x3)
+                    .rotate_right((addr & 0x3) * 8)
+            };
+            self.internal();
+            if self.aborted() {
+                return;
             }
+            self.regs[src_dest_reg] = value;
+            if src_dest_reg == base_reg {
+                write_back = false
+            }
+            if src_dest_reg == 15 {
+                if IS_ARM9 && self.regs[15] & 0x1 != 0 {
+                    self.regs[15] -= 1;
+                    self.regs.set_t(true);
+                    self.fill_thumb_instr_buffer(hw);
+                } else {
+                    self.fill_arm_instr_buffer(hw);
+                }
+            }
+        } else {
+            let value = self.regs[src_dest_reg];
+            let value = if src_dest_reg == 15 {
+                value.wrapping_add(4)
+            } else {
+                value
+            };
+            if transfer_byte {
+                self.write::<u8>(hw, AccessType::N, addr, value as u8);
+            } else {
+                self.write::<u32>(hw, AccessType::N, addr & !0x3, value);
+            }
+        }
+        if write_back && !self.aborted() {
+            self.regs[base_reg] = offset_applied
         }
     }
 
@@ -471,6 +517,9 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
                     }
                 };
                 self.internal();
+                if self.aborted() {
+                    return;
+                }
                 self.regs[src_dest_reg] = value;
                 if src_dest_reg == 15 {
                     self.fill_arm_instr_buffer(hw)
@@ -492,6 +541,9 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
                         // LDRD
                         let value1 = self.read::<u32>(hw, AccessType::N, addr);
                         let value2 = self.read::<u32>(hw, AccessType::S, addr + 4);
+                        if self.aborted() {
+                            return;
+                        }
                         self.regs[src_dest_reg] = value1;
                         self.regs[src_dest_reg + 1] = value2;
                     }
@@ -512,14 +564,14 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         };
         if pre_offset {
             exec(offset_applied);
-            if write_back {
+            if write_back && !self.aborted() {
                 self.regs[base_reg] = offset_applied
             }
         } else {
             exec(base);
             assert_eq!(instr >> 24 & 0x1 != 0, false);
             // Write back is not done if src_reg == base_reg
-            if write_back {
+            if write_back && !self.aborted() {
                 self.regs[base_reg] = offset_applied
             }
         }
@@ -587,6 +639,9 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         let mut exec = |addr, reg, last_access| {
             if load {
                 let value = self.read::<u32>(hw, AccessType::S, addr);
+                if self.aborted() {
+                    return;
+                }
                 self.regs[reg] = value;
                 if !IS_ARM9 && write_back {
                     self.regs[base_reg] = final_addr;
@@ -659,7 +714,7 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
                 } else {
                     write_back
                 };
-                if write_back {
+                if write_back && !self.aborted() {
                     self.regs[base_reg] = final_addr
                 }
             }
@@ -681,16 +736,23 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
 
         self.instruction_prefetch::<u32>(hw, AccessType::N);
         let value = if byte {
-            let value = self.read::<u8>(hw, AccessType::N, base) as u32;
+            self.read::<u8>(hw, AccessType::N, base) as u32
+        } else {
+            self.read::<u32>(hw, AccessType::N, base & !0x3)
+                .rotate_right((base & 0x3) * 8)
+        };
+        // The write doesn't happen if the read aborted
+        if self.aborted() {
+            return;
+        }
+        if byte {
             self.write::<u8>(hw, AccessType::S, base, src as u8);
-            value
         } else {
-            let value = self
-                .read::<u32>(hw, AccessType::N, base & !0x3)
-                .rotate_right((base & 0x3) * 8);
             self.write::<u32>(hw, AccessType::S, base & !0x3, src);
-            value
-        };
+        }
+        if self.aborted() {
+            return;
+        }
         self.regs[dest_reg] = value;
         self.internal();
     }
@@ -709,13 +771,16 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
 
     // ARM.14: Coprocessor Data Operations (CDP)
     // ARM.15: Coprocessor Data Transfers (LDC,STC)
-    fn coprocessor(&mut self, _hw: &mut HW, _instr: u32) {
-        unimplemented!("Coprocessor not implemented!");
+    // Both are treated as NOPs instead of raising the undefined instruction exception
+    fn coprocessor(&mut self, hw: &mut HW, instr: u32) {
+        unimplemented_behavior!("Coprocessor Instruction 0x{:08X}", instr);
+        self.instruction_prefetch::<u32>(hw, AccessType::S);
     }
 
     // ARM.17: Undefined Instruction
-    fn undefined_instr_arm(&mut self, _hw: &mut HW, _instr: u32) {
-        unimplemented!("ARM.17: Undefined Instruction not implemented!");
+    fn undefined_instr_arm(&mut self, hw: &mut HW, instr: u32) {
+        unimplemented_behavior!("Undefined ARM Instruction 0x{:08X}", instr);
+        self.instruction_prefetch::<u32>(hw, AccessType::S);
     }
 }
 
@@ -835,6 +900,10 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
                 // TODO: Only update ITCM and DTCM portions
                 hw.init_arm9_page_tables()
             }
+            // Cache clean commands can write back dirty lines
+            if cp_src_dest_reg == 7 {
+                hw.write_back_arm9_dcache()
+            }
         }
     }
 
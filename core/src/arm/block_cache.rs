@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::rc::Rc;
+
+use super::instructions::InstructionHandler;
+
+pub(super) type CachedInstr<T, const IS_ARM9: bool> = (InstructionHandler<T, IS_ARM9>, T);
+
+// Runs of pre-decoded instructions keyed by the address of the first one
+pub(super) struct BlockCache<T: Copy, const IS_ARM9: bool> {
+    blocks: HashMap<u32, Rc<[CachedInstr<T, IS_ARM9>]>, BuildHasherDefault<AddrHasher>>,
+    chunk_blocks: HashMap<usize, Vec<u32>>,
+    // Position in the block currently being executed
+    cur_block: Rc<[CachedInstr<T, IS_ARM9>]>,
+    cur_start: u32,
+    cur_addr: u32,
+    cur_index: usize,
+}
+
+impl<T: Copy, const IS_ARM9: bool> BlockCache<T, IS_ARM9> {
+    pub const MAX_BLOCK_LEN: usize = 64;
+
+    pub fn new() -> Self {
+        BlockCache {
+            blocks: HashMap::default(),
+            chunk_blocks: HashMap::new(),
+            cur_block: Rc::new([]),
+            // Instructions are never at odd addresses
+            cur_start: 1,
+            cur_addr: 1,
+            cur_index: 0,
+        }
+    }
+
+    #[inline]
+    pub fn next(&mut self, addr: u32) -> Option<CachedInstr<T, IS_ARM9>> {
+        if addr != self.cur_addr || self.cur_index == self.cur_block.len() {
+            self.enter(addr)?;
+        }
+        let instr = self.cur_block[self.cur_index];
+        self.cur_index += 1;
+        self.cur_addr = addr.wrapping_add(std::mem::size_of::<T>() as u32);
+        Some(instr)
+    }
+
+    fn enter(&mut self, addr: u32) -> Option<()> {
+        // Loops usually jump back to the start of the current block
+        if addr != self.cur_start {
+            self.cur_block = self.blocks.get(&addr)?.clone();
+            self.cur_start = addr;
+        }
+        self.cur_index = 0;
+        Some(())
+    }
+
+    // Forces the next lookup to go through the map
+    fn reset_cursor(&mut self) {
+        self.cur_block = Rc::new([]);
+        self.cur_start = 1;
+        self.cur_addr = 1;
+    }
+
+    pub fn insert(&mut self, addr: u32, chunk: usize, block: Vec<CachedInstr<T, IS_ARM9>>) {
+        self.chunk_blocks.entry(chunk).or_default().push(addr);
+        self.blocks.insert(addr, block.into());
+        self.reset_cursor();
+    }
+
+    pub fn invalidate_chunk(&mut self, chunk: usize) {
+        if let Some(addrs) = self.chunk_blocks.remove(&chunk) {
+            for addr in addrs {
+                self.blocks.remove(&addr);
+            }
+        }
+        self.reset_cursor();
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.chunk_blocks.clear();
+        self.reset_cursor();
+    }
+}
+
+// Block lookups are hot, so SipHash is too slow
+#[derive(Default)]
+pub(super) struct AddrHasher(u64);
+
+impl Hasher for AddrHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _bytes: &[u8]) {
+        unreachable!()
+    }
+
+    fn write_u32(&mut self, addr: u32) {
+        self.0 = (addr as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    }
+}
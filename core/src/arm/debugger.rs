@@ -0,0 +1,260 @@
+use std::io::Write;
+use std::ops::Range;
+
+use super::{trace::Tracer, Mode, ARM};
+use crate::hw::HW;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugStop {
+    Breakpoint(u32),
+    // Kind of the access that triggered it, address and value
+    Watchpoint(WatchKind, u32, u32),
+    Step,
+}
+
+struct Watchpoint {
+    start: u32,
+    end: u32,
+    kind: WatchKind,
+    // Only accesses of this value trigger the watchpoint
+    value: Option<u32>,
+}
+
+pub struct Debugger {
+    // Checked before every instruction, so everything else is skipped when nothing is set
+    active: bool,
+    breakpoints: Vec<u32>,
+    watchpoints: Vec<Watchpoint>,
+    step: bool,
+    stop: Option<DebugStop>,
+    // Set when resuming from a breakpoint so it doesn't hit again immediately
+    resume_addr: Option<u32>,
+    pub(super) tracer: Option<Tracer>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            active: false,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            step: false,
+            stop: None,
+            resume_addr: None,
+            tracer: None,
+        }
+    }
+
+    fn update_active(&mut self) {
+        self.active = !self.breakpoints.is_empty()
+            || !self.watchpoints.is_empty()
+            || self.step
+            || self.stop.is_some()
+            || self.tracer.is_some();
+        if !self.active {
+            self.resume_addr = None;
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u32) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+        self.update_active();
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u32) {
+        self.breakpoints.retain(|&breakpoint| breakpoint != addr);
+        self.update_active();
+    }
+
+    pub fn breakpoints(&self) -> &[u32] {
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, addr: u32, len: u32, kind: WatchKind, value: Option<u32>) {
+        self.watchpoints.push(Watchpoint {
+            start: addr,
+            end: addr.wrapping_add(len.max(1) - 1),
+            kind,
+            value,
+        });
+        self.update_active();
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u32, len: u32, kind: WatchKind) {
+        let end = addr.wrapping_add(len.max(1) - 1);
+        self.watchpoints.retain(|watchpoint| {
+            (watchpoint.start, watchpoint.end, watchpoint.kind) != (addr, end, kind)
+        });
+        self.update_active();
+    }
+
+    // Stops after the next instruction
+    pub fn step(&mut self) {
+        self.step = true;
+        self.update_active();
+    }
+
+    pub fn cancel_step(&mut self) {
+        self.step = false;
+        self.update_active();
+    }
+
+    pub(super) fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+        self.update_active();
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stop.is_some()
+    }
+
+    pub fn take_stop(&mut self) -> Option<DebugStop> {
+        let stop = self.stop.take();
+        if let Some(DebugStop::Breakpoint(addr)) = stop {
+            self.resume_addr = Some(addr);
+        }
+        self.update_active();
+        stop
+    }
+
+    fn check_breakpoint(&mut self, addr: u32) -> bool {
+        if self.stop.is_some() {
+            return true;
+        }
+        if self.resume_addr.take() != Some(addr) && self.breakpoints.contains(&addr) {
+            self.stop = Some(DebugStop::Breakpoint(addr));
+            return true;
+        }
+        false
+    }
+
+    fn check_watchpoints(&mut self, addr: u32, size: u32, is_write: bool, value: u32) {
+        let end = addr.wrapping_add(size - 1);
+        let kind = if is_write {
+            WatchKind::Write
+        } else {
+            WatchKind::Read
+        };
+        let hit = self.watchpoints.iter().any(|watchpoint| {
+            (watchpoint.kind == kind || watchpoint.kind == WatchKind::Access)
+                && addr <= watchpoint.end
+                && end >= watchpoint.start
+                && watchpoint
+                    .value
+                    .is_none_or(|watch_value| watch_value == value)
+        });
+        // The first access of an instruction is the one reported
+        if hit && self.stop.is_none() {
+            self.stop = Some(DebugStop::Watchpoint(kind, addr, value));
+        }
+    }
+
+    fn finish_instr(&mut self) -> bool {
+        if self.step {
+            self.step = false;
+            if self.stop.is_none() {
+                self.stop = Some(DebugStop::Step);
+            }
+        }
+        self.stop.is_some()
+    }
+}
+
+impl<const IS_ARM9: bool> ARM<IS_ARM9> {
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    // Returns true if emulation should stop before the next instruction, otherwise traces it
+    pub(super) fn debug_start_instr(&mut self) -> bool {
+        let addr = self.instr_addr();
+        if self.debugger.check_breakpoint(addr) {
+            return true;
+        }
+        match &self.debugger.tracer {
+            Some(tracer) if tracer.finished(self.cycle) => self.debugger.set_tracer(None),
+            Some(_) => self.trace_instr(),
+            None => (),
+        }
+        false
+    }
+
+    // Traces instructions executed within cycles
+    pub fn start_trace(&mut self, writer: Box<dyn Write>, cycles: Range<usize>) {
+        self.debugger.set_tracer(Some(Tracer::new(writer, cycles)));
+    }
+
+    pub fn stop_trace(&mut self) {
+        self.debugger.set_tracer(None);
+    }
+
+    pub(super) fn debug_finish_instr(&mut self) -> bool {
+        self.debugger.finish_instr()
+    }
+
+    pub(super) fn debug_watch(&mut self, addr: u32, size: usize, is_write: bool, value: u32) {
+        self.debugger
+            .check_watchpoints(addr, size as u32, is_write, value)
+    }
+
+    pub(super) fn debugger_active(&self) -> bool {
+        self.debugger.active
+    }
+
+    // PC is reported as the address of the next instruction
+    pub fn reg(&self, reg: u32) -> u32 {
+        if reg == 15 {
+            self.instr_addr()
+        } else {
+            self.regs[reg]
+        }
+    }
+
+    pub fn set_reg(&mut self, hw: &mut HW, reg: u32, value: u32) {
+        self.regs[reg] = value;
+        if reg == 15 {
+            if self.regs.get_t() {
+                self.fill_thumb_instr_buffer(hw)
+            } else {
+                self.fill_arm_instr_buffer(hw)
+            }
+        }
+    }
+
+    pub fn mode_regs(&self, mode: Mode) -> [u32; 15] {
+        self.regs.mode_regs(mode)
+    }
+
+    pub fn mode_spsr(&self, mode: Mode) -> Option<u32> {
+        self.regs.mode_spsr(mode)
+    }
+
+    pub fn cpsr(&self) -> u32 {
+        self.regs.cpsr()
+    }
+
+    pub fn set_cpsr(&mut self, hw: &mut HW, value: u32) {
+        let (thumb, pc) = (self.regs.get_t(), self.reg(15));
+        self.regs.save_banked();
+        *self.regs.cpsr_mut() = value;
+        self.regs.update_cpsr_mode();
+        self.regs.load_banked(self.regs.get_mode());
+        if self.regs.get_t() != thumb {
+            // Keep the next instruction the same when switching between ARM and THUMB
+            self.set_reg(hw, 15, pc);
+        }
+    }
+}
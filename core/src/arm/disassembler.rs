@@ -0,0 +1,779 @@
+// Decodes instructions into ARM assembly syntax
+// Instructions the CPU doesn't implement are shown as "undefined", so ARMv5TE-only
+// instructions are only decoded for the ARM9
+
+const CONDITIONS: [&str; 16] = [
+    "eq", "ne", "cs", "cc", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "", "nv",
+];
+const SHIFTS: [&str; 4] = ["lsl", "lsr", "asr", "ror"];
+
+pub fn disassemble_arm(is_arm9: bool, addr: u32, instr: u32) -> String {
+    let cond = CONDITIONS[(instr >> 28) as usize];
+    if instr >> 28 == 0xF {
+        return disassemble_arm_unconditional(is_arm9, addr, instr);
+    }
+
+    if instr & 0x0FFF_FFD0 == 0x012F_FF10 {
+        // ARM.3: Branch and Exchange (BX, BLX)
+        let link = instr >> 5 & 0x1 != 0;
+        if link && !is_arm9 {
+            return undefined();
+        }
+        let name = if link { "blx" } else { "bx" };
+        format!("{}{} {}", name, cond, reg(instr))
+    } else if instr & 0x0FC0_00F0 == 0x0000_0090 {
+        // ARM.7: Multiply and Multiply-Accumulate (MUL, MLA)
+        let s = flags(instr);
+        if instr >> 21 & 0x1 != 0 {
+            format!(
+                "mla{}{} {}, {}, {}, {}",
+                s,
+                cond,
+                reg(instr >> 16),
+                reg(instr),
+                reg(instr >> 8),
+                reg(instr >> 12)
+            )
+        } else {
+            format!(
+                "mul{}{} {}, {}, {}",
+                s,
+                cond,
+                reg(instr >> 16),
+                reg(instr),
+                reg(instr >> 8)
+            )
+        }
+    } else if instr & 0x0F80_00F0 == 0x0080_0090 {
+        // ARM.8: Multiply Long and Multiply-Accumulate Long (MULL, MLAL)
+        let sign = if instr >> 22 & 0x1 != 0 { "s" } else { "u" };
+        let op = if instr >> 21 & 0x1 != 0 {
+            "mlal"
+        } else {
+            "mull"
+        };
+        format!(
+            "{}{}{}{} {}, {}, {}, {}",
+            sign,
+            op,
+            flags(instr),
+            cond,
+            reg(instr >> 12),
+            reg(instr >> 16),
+            reg(instr),
+            reg(instr >> 8)
+        )
+    } else if instr & 0x0FB0_0FF0 == 0x0100_0090 {
+        // ARM.12: Single Data Swap (SWP)
+        let byte = if instr >> 22 & 0x1 != 0 { "b" } else { "" };
+        format!(
+            "swp{}{} {}, {}, [{}]",
+            byte,
+            cond,
+            reg(instr >> 12),
+            reg(instr),
+            reg(instr >> 16)
+        )
+    } else if instr & 0x0E00_0090 == 0x0000_0090 && instr >> 5 & 0x3 != 0 {
+        disassemble_halfword_transfer(is_arm9, addr, instr, cond)
+    } else if instr & 0x0FF0_0FF0 == 0x0160_0F10 {
+        // ARM.X: Count Leading Zeros (CLZ)
+        if !is_arm9 {
+            return undefined();
+        }
+        format!("clz{} {}, {}", cond, reg(instr >> 12), reg(instr))
+    } else if instr & 0x0F90_0FF0 == 0x0100_0050 {
+        // ARM.X: Saturated Arithmetic (QADD, QSUB, QDADD, QDSUB)
+        if !is_arm9 {
+            return undefined();
+        }
+        let name = ["qadd", "qsub", "qdadd", "qdsub"][(instr >> 21 & 0x3) as usize];
+        format!(
+            "{}{} {}, {}, {}",
+            name,
+            cond,
+            reg(instr >> 12),
+            reg(instr),
+            reg(instr >> 16)
+        )
+    } else if instr & 0x0FF0_00F0 == 0x0120_0070 {
+        // ARM.X: Breakpoint (BKPT)
+        if !is_arm9 || instr >> 28 != 0xE {
+            return undefined();
+        }
+        format!("bkpt #0x{:X}", instr >> 4 & 0xFFF0 | instr & 0xF)
+    } else if instr & 0x0F90_0090 == 0x0100_0080 {
+        disassemble_signed_half_mul(is_arm9, instr, cond)
+    } else if instr & 0x0DB0_0000 == 0x0100_0000 || instr & 0x0DB0_0000 == 0x0120_0000 {
+        disassemble_psr_transfer(instr, cond)
+    } else if instr & 0x0C00_0000 == 0x0000_0000 {
+        disassemble_data_proc(instr, cond)
+    } else if instr & 0x0E00_0010 == 0x0600_0010 {
+        undefined()
+    } else if instr & 0x0C00_0000 == 0x0400_0000 {
+        disassemble_single_data_transfer(addr, instr, cond)
+    } else if instr & 0x0E00_0000 == 0x0800_0000 {
+        disassemble_block_data_transfer(instr, cond)
+    } else if instr & 0x0E00_0000 == 0x0A00_0000 {
+        // ARM.4: Branch and Branch with Link (B, BL)
+        let name = if instr >> 24 & 0x1 != 0 { "bl" } else { "b" };
+        let offset = ((instr << 8) as i32 >> 6) as u32;
+        format!(
+            "{}{} 0x{:08X}",
+            name,
+            cond,
+            addr.wrapping_add(8).wrapping_add(offset)
+        )
+    } else if instr & 0x0F00_0000 == 0x0F00_0000 {
+        // ARM.11: Software Interrupt (SWI)
+        format!("swi{} #0x{:X}", cond, instr & 0xFF_FFFF)
+    } else {
+        disassemble_coprocessor(is_arm9, instr, cond)
+    }
+}
+
+// Encodings with the condition field set to NV are only used by ARMv5 instructions
+fn disassemble_arm_unconditional(is_arm9: bool, addr: u32, instr: u32) -> String {
+    if !is_arm9 {
+        undefined()
+    } else if instr & 0x0E00_0000 == 0x0A00_0000 {
+        // ARM.4: Branch with Link and Exchange (BLX)
+        let offset = ((instr << 8) as i32 >> 6) as u32 | (instr >> 23 & 0x2);
+        format!("blx 0x{:08X}", addr.wrapping_add(8).wrapping_add(offset))
+    } else if instr & 0x0D70_F000 == 0x0550_F000 {
+        // Preload Data (PLD) has no effect without an L2 cache, but is still valid
+        format!("pld {}", address_mode(addr, instr, true))
+    } else {
+        undefined()
+    }
+}
+
+// ARM.5: Data Processing
+fn disassemble_data_proc(instr: u32, cond: &str) -> String {
+    let opcode = instr >> 21 & 0xF;
+    let name = [
+        "and", "eor", "sub", "rsb", "add", "adc", "sbc", "rsc", "tst", "teq", "cmp", "cmn", "orr",
+        "mov", "bic", "mvn",
+    ][opcode as usize];
+    let op2 = if instr >> 25 & 0x1 != 0 {
+        let imm = (instr & 0xFF).rotate_right((instr >> 8 & 0xF) * 2);
+        format!("#0x{:X}", imm)
+    } else {
+        shifted_reg(instr)
+    };
+    match opcode {
+        // The flags are always set
+        0x8..=0xB => format!("{}{} {}, {}", name, cond, reg(instr >> 16), op2),
+        0xD | 0xF => format!(
+            "{}{}{} {}, {}",
+            name,
+            flags(instr),
+            cond,
+            reg(instr >> 12),
+            op2
+        ),
+        _ => format!(
+            "{}{}{} {}, {}, {}",
+            name,
+            flags(instr),
+            cond,
+            reg(instr >> 12),
+            reg(instr >> 16),
+            op2
+        ),
+    }
+}
+
+// ARM.6: PSR Transfer (MRS, MSR)
+fn disassemble_psr_transfer(instr: u32, cond: &str) -> String {
+    let psr = if instr >> 22 & 0x1 != 0 {
+        "spsr"
+    } else {
+        "cpsr"
+    };
+    if instr >> 21 & 0x1 == 0 {
+        return format!("mrs{} {}, {}", cond, reg(instr >> 12), psr);
+    }
+    let fields: String = ["f", "s", "x", "c"]
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| instr >> (19 - i) & 0x1 != 0)
+        .map(|(_, field)| *field)
+        .collect();
+    let operand = if instr >> 25 & 0x1 != 0 {
+        format!(
+            "#0x{:X}",
+            (instr & 0xFF).rotate_right((instr >> 8 & 0xF) * 2)
+        )
+    } else {
+        reg(instr).to_string()
+    };
+    format!("msr{} {}_{}, {}", cond, psr, fields, operand)
+}
+
+// ARM.X: Signed Halfword Multiply (SMLAxy, SMLAWy, SMULWy, SMLALxy, SMULxy)
+fn disassemble_signed_half_mul(is_arm9: bool, instr: u32, cond: &str) -> String {
+    if !is_arm9 {
+        return undefined();
+    }
+    let x = if instr >> 5 & 0x1 != 0 { "t" } else { "b" };
+    let y = if instr >> 6 & 0x1 != 0 { "t" } else { "b" };
+    let (rd, rn, rm, rs) = (
+        reg(instr >> 16),
+        reg(instr >> 12),
+        reg(instr),
+        reg(instr >> 8),
+    );
+    match instr >> 21 & 0x3 {
+        0 => format!("smla{}{}{} {}, {}, {}, {}", x, y, cond, rd, rm, rs, rn),
+        1 if instr >> 5 & 0x1 == 0 => format!("smlaw{}{} {}, {}, {}, {}", y, cond, rd, rm, rs, rn),
+        1 => format!("smulw{}{} {}, {}, {}", y, cond, rd, rm, rs),
+        2 => format!("smlal{}{}{} {}, {}, {}, {}", x, y, cond, rn, rd, rm, rs),
+        3 => format!("smul{}{}{} {}, {}, {}", x, y, cond, rd, rm, rs),
+        _ => unreachable!(),
+    }
+}
+
+// ARM.9: Single Data Transfer (LDR, STR)
+fn disassemble_single_data_transfer(addr: u32, instr: u32, cond: &str) -> String {
+    let name = if instr >> 20 & 0x1 != 0 { "ldr" } else { "str" };
+    let byte = if instr >> 22 & 0x1 != 0 { "b" } else { "" };
+    // Post-indexing with write back forces a user mode access
+    let user = if instr >> 24 & 0x1 == 0 && instr >> 21 & 0x1 != 0 {
+        "t"
+    } else {
+        ""
+    };
+    format!(
+        "{}{}{}{} {}, {}",
+        name,
+        byte,
+        user,
+        cond,
+        reg(instr >> 12),
+        address_mode(addr, instr, true)
+    )
+}
+
+// ARM.10: Halfword, Doubleword, and Signed Data Transfer
+fn disassemble_halfword_transfer(is_arm9: bool, addr: u32, instr: u32, cond: &str) -> String {
+    let load = instr >> 20 & 0x1 != 0;
+    let name = match (load, instr >> 5 & 0x3) {
+        (false, 1) => "strh",
+        (false, 2) if is_arm9 => "ldrd",
+        (false, 3) if is_arm9 => "strd",
+        (true, 1) => "ldrh",
+        (true, 2) => "ldrsb",
+        (true, 3) => "ldrsh",
+        _ => return undefined(),
+    };
+    format!(
+        "{}{} {}, {}",
+        name,
+        cond,
+        reg(instr >> 12),
+        address_mode(addr, instr, false)
+    )
+}
+
+// ARM.11: Block Data Transfer (LDM, STM)
+fn disassemble_block_data_transfer(instr: u32, cond: &str) -> String {
+    let name = if instr >> 20 & 0x1 != 0 { "ldm" } else { "stm" };
+    let mode = ["da", "ia", "db", "ib"][(instr >> 23 & 0x3) as usize];
+    let write_back = if instr >> 21 & 0x1 != 0 { "!" } else { "" };
+    let user = if instr >> 22 & 0x1 != 0 { "^" } else { "" };
+    format!(
+        "{}{}{} {}{}, {}{}",
+        name,
+        mode,
+        cond,
+        reg(instr >> 16),
+        write_back,
+        reg_list(instr & 0xFFFF),
+        user
+    )
+}
+
+// ARM.13: Coprocessor Instructions (CDP, LDC, STC, MCR, MRC, MCRR, MRRC)
+fn disassemble_coprocessor(is_arm9: bool, instr: u32, cond: &str) -> String {
+    let coprocessor = instr >> 8 & 0xF;
+    if instr & 0x0FE0_0000 == 0x0C40_0000 {
+        if !is_arm9 {
+            return undefined();
+        }
+        let name = if instr >> 20 & 0x1 != 0 {
+            "mrrc"
+        } else {
+            "mcrr"
+        };
+        format!(
+            "{}{} p{}, {}, {}, {}, c{}",
+            name,
+            cond,
+            coprocessor,
+            instr >> 4 & 0xF,
+            reg(instr >> 12),
+            reg(instr >> 16),
+            instr & 0xF
+        )
+    } else if instr & 0x0E00_0000 == 0x0C00_0000 {
+        let name = if instr >> 20 & 0x1 != 0 { "ldc" } else { "stc" };
+        let long = if instr >> 22 & 0x1 != 0 { "l" } else { "" };
+        let offset = (instr & 0xFF) * 4;
+        let sign = if instr >> 23 & 0x1 != 0 { "" } else { "-" };
+        let rn = reg(instr >> 16);
+        let address = match (instr >> 24 & 0x1 != 0, instr >> 21 & 0x1 != 0) {
+            (true, write_back) => format!(
+                "[{}, #{}0x{:X}]{}",
+                rn,
+                sign,
+                offset,
+                if write_back { "!" } else { "" }
+            ),
+            (false, true) => format!("[{}], #{}0x{:X}", rn, sign, offset),
+            (false, false) => format!("[{}], {{0x{:X}}}", rn, instr & 0xFF),
+        };
+        format!(
+            "{}{}{} p{}, c{}, {}",
+            name,
+            long,
+            cond,
+            coprocessor,
+            instr >> 12 & 0xF,
+            address
+        )
+    } else if instr >> 4 & 0x1 == 0 {
+        format!(
+            "cdp{} p{}, {}, c{}, c{}, c{}, {}",
+            cond,
+            coprocessor,
+            instr >> 20 & 0xF,
+            instr >> 12 & 0xF,
+            instr >> 16 & 0xF,
+            instr & 0xF,
+            instr >> 5 & 0x7
+        )
+    } else {
+        let name = if instr >> 20 & 0x1 != 0 { "mrc" } else { "mcr" };
+        format!(
+            "{}{} p{}, {}, {}, c{}, c{}, {}",
+            name,
+            cond,
+            coprocessor,
+            instr >> 21 & 0x7,
+            reg(instr >> 12),
+            instr >> 16 & 0xF,
+            instr & 0xF,
+            instr >> 5 & 0x7
+        )
+    }
+}
+
+// BL and BLX are split into two instructions, so next_instr is used to show the target
+pub fn disassemble_thumb(is_arm9: bool, addr: u32, instr: u16, next_instr: u16) -> String {
+    let instr = instr as u32;
+    let (rd, rs) = (reg(instr & 0x7), reg(instr >> 3 & 0x7));
+    match instr >> 11 {
+        // THUMB.2: add/subtract
+        0b00011 => {
+            let name = if instr >> 9 & 0x1 != 0 {
+                "subs"
+            } else {
+                "adds"
+            };
+            let operand = if instr >> 10 & 0x1 != 0 {
+                format!("#0x{:X}", instr >> 6 & 0x7)
+            } else {
+                reg(instr >> 6 & 0x7).to_string()
+            };
+            format!("{} {}, {}, {}", name, rd, rs, operand)
+        }
+        // THUMB.1: move shifted register
+        0b00000..=0b00010 => {
+            let op = instr >> 11 & 0x3;
+            let offset = match instr >> 6 & 0x1F {
+                0 if op != 0 => 32,
+                offset => offset,
+            };
+            if op == 0 && offset == 0 {
+                format!("movs {}, {}", rd, rs)
+            } else {
+                format!("{}s {}, {}, #{}", SHIFTS[op as usize], rd, rs, offset)
+            }
+        }
+        // THUMB.3: move/compare/add/subtract immediate
+        0b00100..=0b00111 => {
+            let name = ["movs", "cmp", "adds", "subs"][(instr >> 11 & 0x3) as usize];
+            format!("{} {}, #0x{:X}", name, reg(instr >> 8 & 0x7), instr & 0xFF)
+        }
+        0b01000 if instr >> 10 & 0x1 == 0 => {
+            // THUMB.4: ALU operations
+            let name = [
+                "ands", "eors", "lsls", "lsrs", "asrs", "adcs", "sbcs", "rors", "tst", "rsbs",
+                "cmp", "cmn", "orrs", "muls", "bics", "mvns",
+            ][(instr >> 6 & 0xF) as usize];
+            match name {
+                "rsbs" => format!("rsbs {}, {}, #0", rd, rs),
+                "muls" => format!("muls {}, {}, {}", rd, rs, rd),
+                _ => format!("{} {}, {}", name, rd, rs),
+            }
+        }
+        0b01000 => {
+            // THUMB.5: Hi register operations/branch exchange
+            let rd = reg(instr >> 4 & 0x8 | instr & 0x7);
+            let rs = reg(instr >> 3 & 0xF);
+            match instr >> 8 & 0x3 {
+                0 => format!("add {}, {}", rd, rs),
+                1 => format!("cmp {}, {}", rd, rs),
+                2 => format!("mov {}, {}", rd, rs),
+                _ if is_arm9 && instr >> 7 & 0x1 != 0 => format!("blx {}", rs),
+                _ => format!("bx {}", rs),
+            }
+        }
+        // THUMB.6: load PC-relative
+        0b01001 => {
+            let target = (addr.wrapping_add(4) & !0x3).wrapping_add((instr & 0xFF) * 4);
+            format!(
+                "ldr {}, [pc, #0x{:X}] ; 0x{:08X}",
+                reg(instr >> 8 & 0x7),
+                (instr & 0xFF) * 4,
+                target
+            )
+        }
+        0b01010..=0b01011 => {
+            let name = if instr >> 9 & 0x1 == 0 {
+                // THUMB.7: load/store with register offset
+                ["str", "strb", "ldr", "ldrb"][(instr >> 10 & 0x3) as usize]
+            } else {
+                // THUMB.8: load/store sign-extended byte/halfword
+                ["strh", "ldrsb", "ldrh", "ldrsh"][(instr >> 10 & 0x3) as usize]
+            };
+            format!("{} {}, [{}, {}]", name, rd, rs, reg(instr >> 6 & 0x7))
+        }
+        // THUMB.9: load/store with immediate offset
+        0b01100..=0b01111 => {
+            let byte = instr >> 12 & 0x1 != 0;
+            let name = match (instr >> 11 & 0x1 != 0, byte) {
+                (false, false) => "str",
+                (false, true) => "strb",
+                (true, false) => "ldr",
+                (true, true) => "ldrb",
+            };
+            let offset = (instr >> 6 & 0x1F) << if byte { 0 } else { 2 };
+            format!("{} {}, {}", name, rd, imm_address(rs, offset))
+        }
+        // THUMB.10: load/store halfword
+        0b10000..=0b10001 => {
+            let name = if instr >> 11 & 0x1 != 0 {
+                "ldrh"
+            } else {
+                "strh"
+            };
+            let offset = (instr >> 6 & 0x1F) << 1;
+            format!("{} {}, {}", name, rd, imm_address(rs, offset))
+        }
+        // THUMB.11: load/store SP-relative
+        0b10010..=0b10011 => {
+            let name = if instr >> 11 & 0x1 != 0 { "ldr" } else { "str" };
+            let offset = (instr & 0xFF) * 4;
+            format!(
+                "{} {}, {}",
+                name,
+                reg(instr >> 8 & 0x7),
+                imm_address("sp", offset)
+            )
+        }
+        // THUMB.12: get relative address
+        0b10100..=0b10101 => {
+            let base = if instr >> 11 & 0x1 != 0 { "sp" } else { "pc" };
+            format!(
+                "add {}, {}, #0x{:X}",
+                reg(instr >> 8 & 0x7),
+                base,
+                (instr & 0xFF) * 4
+            )
+        }
+        0b10110..=0b10111 => match instr >> 8 & 0xF {
+            // THUMB.13: add offset to stack pointer
+            0b0000 => {
+                let name = if instr >> 7 & 0x1 != 0 { "sub" } else { "add" };
+                format!("{} sp, #0x{:X}", name, (instr & 0x7F) * 4)
+            }
+            // THUMB.14: push/pop registers
+            0b0100 | 0b0101 => format!("push {}", reg_list(instr & 0xFF | (instr & 0x100) << 6)),
+            0b1100 | 0b1101 => format!("pop {}", reg_list(instr & 0xFF | (instr & 0x100) << 7)),
+            // THUMB.17: breakpoint
+            0b1110 if is_arm9 => format!("bkpt #0x{:X}", instr & 0xFF),
+            _ => undefined(),
+        },
+        // THUMB.15: multiple load/store
+        0b11000..=0b11001 => {
+            let name = if instr >> 11 & 0x1 != 0 {
+                "ldmia"
+            } else {
+                "stmia"
+            };
+            format!(
+                "{} {}!, {}",
+                name,
+                reg(instr >> 8 & 0x7),
+                reg_list(instr & 0xFF)
+            )
+        }
+        0b11010..=0b11011 => match instr >> 8 & 0xF {
+            0xE => undefined(),
+            // THUMB.17: software interrupt
+            0xF => format!("swi #0x{:X}", instr & 0xFF),
+            // THUMB.16: conditional branch
+            cond => {
+                let offset = (instr as u8 as i8 as i32 * 2) as u32;
+                format!(
+                    "b{} 0x{:08X}",
+                    CONDITIONS[cond as usize],
+                    addr.wrapping_add(4).wrapping_add(offset)
+                )
+            }
+        },
+        // THUMB.18: unconditional branch
+        0b11100 => {
+            let offset = (((instr << 21) as i32) >> 20) as u32;
+            format!("b 0x{:08X}", addr.wrapping_add(4).wrapping_add(offset))
+        }
+        // THUMB.19: long branch with link
+        0b11110 => {
+            let next_instr = next_instr as u32;
+            let exchange = match next_instr >> 11 {
+                0b11111 => false,
+                0b11101 if is_arm9 => true,
+                // The halves are only shown as one instruction when they're next to each other
+                _ => {
+                    let offset = (instr << 21) as i32 >> 9;
+                    let sign = if offset < 0 { "-" } else { "" };
+                    return format!("bl (prefix) #{}0x{:X}", sign, offset.unsigned_abs());
+                }
+            };
+            let offset = ((instr << 21) as i32 >> 9) as u32 | (next_instr & 0x7FF) << 1;
+            let target = addr.wrapping_add(4).wrapping_add(offset);
+            if exchange {
+                format!("blx 0x{:08X}", target & !0x3)
+            } else {
+                format!("bl 0x{:08X}", target)
+            }
+        }
+        0b11111 => format!("bl (suffix) #0x{:X}", (instr & 0x7FF) << 1),
+        0b11101 if is_arm9 && instr & 0x1 == 0 => {
+            format!("blx (suffix) #0x{:X}", (instr & 0x7FF) << 1)
+        }
+        _ => undefined(),
+    }
+}
+
+fn undefined() -> String {
+    "undefined".to_string()
+}
+
+fn flags(instr: u32) -> &'static str {
+    if instr >> 20 & 0x1 != 0 {
+        "s"
+    } else {
+        ""
+    }
+}
+
+fn reg(reg: u32) -> &'static str {
+    [
+        "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp",
+        "lr", "pc",
+    ][(reg & 0xF) as usize]
+}
+
+fn reg_list(regs: u32) -> String {
+    let mut list = Vec::new();
+    let mut reg_num = 0;
+    while reg_num < 16 {
+        if regs >> reg_num & 0x1 == 0 {
+            reg_num += 1;
+            continue;
+        }
+        let start = reg_num;
+        while reg_num < 16 && regs >> reg_num & 0x1 != 0 {
+            reg_num += 1;
+        }
+        // Ranges are only used for the numbered registers
+        let end = reg_num - 1;
+        if end - start >= 2 && end <= 12 {
+            list.push(format!("{}-{}", reg(start), reg(end)));
+        } else {
+            list.extend((start..=end).map(|reg_num| reg(reg_num).to_string()));
+        }
+    }
+    format!("{{{}}}", list.join(", "))
+}
+
+// Register shifted by an immediate or a register
+fn shifted_reg(instr: u32) -> String {
+    let shift_type = instr >> 5 & 0x3;
+    let rm = reg(instr);
+    if instr >> 4 & 0x1 != 0 {
+        return format!(
+            "{}, {} {}",
+            rm,
+            SHIFTS[shift_type as usize],
+            reg(instr >> 8)
+        );
+    }
+    match (shift_type, instr >> 7 & 0x1F) {
+        (0, 0) => rm.to_string(),
+        (3, 0) => format!("{}, rrx", rm),
+        (1, 0) | (2, 0) => format!("{}, {} #32", rm, SHIFTS[shift_type as usize]),
+        (_, shift) => format!("{}, {} #{}", rm, SHIFTS[shift_type as usize], shift),
+    }
+}
+
+fn imm_address(base: &str, offset: u32) -> String {
+    if offset == 0 {
+        format!("[{}]", base)
+    } else {
+        format!("[{}, #0x{:X}]", base, offset)
+    }
+}
+
+// Addressing for LDR/STR when word_transfer is set and LDRH/STRH/LDRSB/LDRSH/LDRD/STRD otherwise
+fn address_mode(addr: u32, instr: u32, word_transfer: bool) -> String {
+    let pre_index = instr >> 24 & 0x1 != 0;
+    let sign = if instr >> 23 & 0x1 != 0 { "" } else { "-" };
+    let write_back = pre_index && instr >> 21 & 0x1 != 0;
+    let rn = reg(instr >> 16);
+    let imm_offset = if word_transfer {
+        (instr >> 25 & 0x1 == 0).then_some(instr & 0xFFF)
+    } else {
+        (instr >> 22 & 0x1 != 0).then_some(instr >> 4 & 0xF0 | instr & 0xF)
+    };
+    let offset = match imm_offset {
+        Some(0) => None,
+        Some(imm) => Some(format!("#{}0x{:X}", sign, imm)),
+        None if word_transfer => Some(format!("{}{}", sign, shifted_reg(instr & !0x10))),
+        None => Some(format!("{}{}", sign, reg(instr))),
+    };
+    let address = match (pre_index, offset) {
+        (true, None) => format!("[{}]", rn),
+        (true, Some(offset)) => {
+            format!("[{}, {}]{}", rn, offset, if write_back { "!" } else { "" })
+        }
+        (false, None) => format!("[{}], #0", rn),
+        (false, Some(offset)) => format!("[{}], {}", rn, offset),
+    };
+    // Show the address of literal pool loads
+    match imm_offset {
+        Some(imm) if instr >> 16 & 0xF == 15 && pre_index && !write_back => {
+            let base = addr.wrapping_add(8);
+            let target = if sign.is_empty() {
+                base.wrapping_add(imm)
+            } else {
+                base.wrapping_sub(imm)
+            };
+            format!("{} ; 0x{:08X}", address, target)
+        }
+        _ => address,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: u32 = 0x0200_0000;
+
+    #[test]
+    fn arm() {
+        for (instr, text) in [
+            (0xE3A0_0001, "mov r0, #0x1"),
+            (0xE091_0002, "adds r0, r1, r2"),
+            (0xE1A0_0100, "mov r0, r0, lsl #2"),
+            (0x1A00_0000, "bne 0x02000008"),
+            (0xEB00_0002, "bl 0x02000010"),
+            (0xE12F_FF1E, "bx lr"),
+            (0xE010_0291, "muls r0, r1, r2"),
+            (0xE081_0392, "umull r0, r1, r2, r3"),
+            (0xE10F_0000, "mrs r0, cpsr"),
+            (0xE121_F000, "msr cpsr_c, r0"),
+            (0xE59F_0010, "ldr r0, [pc, #0x10] ; 0x02000018"),
+            (0xE111_00B2, "ldrh r0, [r1, -r2]"),
+            (0xE92D_40F0, "stmdb sp!, {r4-r7, lr}"),
+            (0xEF00_0006, "swi #0x6"),
+            (0xEE01_0F10, "mcr p15, 0, r0, c1, c0, 0"),
+            (0xEE19_0F31, "mrc p15, 0, r0, c9, c1, 1"),
+        ] {
+            assert_eq!(disassemble_arm(true, ADDR, instr), text);
+            assert_eq!(disassemble_arm(false, ADDR, instr), text);
+        }
+    }
+
+    #[test]
+    fn arm_v5te() {
+        for (instr, text) in [
+            (0xFA00_0000, "blx 0x02000008"),
+            (0xFB00_0000, "blx 0x0200000A"),
+            (0xE12F_FF33, "blx r3"),
+            (0xE16F_0F11, "clz r0, r1"),
+            (0xE102_0051, "qadd r0, r1, r2"),
+            (0xE162_0051, "qdsub r0, r1, r2"),
+            (0xE100_3281, "smlabb r0, r1, r2, r3"),
+            (0xE100_32A1, "smlatb r0, r1, r2, r3"),
+            (0xE120_3281, "smlawb r0, r1, r2, r3"),
+            (0xE120_02A1, "smulwb r0, r1, r2"),
+            (0xE141_0283, "smlalbb r0, r1, r3, r2"),
+            (0xE160_02C1, "smulbt r0, r1, r2"),
+            (0xE1C0_20D8, "ldrd r2, [r0, #0x8]"),
+            (0xE1C0_20F8, "strd r2, [r0, #0x8]"),
+            (0xE120_0070, "bkpt #0x0"),
+            (0xF5D0_F000, "pld [r0]"),
+        ] {
+            assert_eq!(disassemble_arm(true, ADDR, instr), text);
+            assert_eq!(disassemble_arm(false, ADDR, instr), "undefined");
+        }
+    }
+
+    #[test]
+    fn thumb() {
+        for (addr, instr, next_instr, text) in [
+            (ADDR, 0x2001, 0, "movs r0, #0x1"),
+            (ADDR, 0x1888, 0, "adds r0, r1, r2"),
+            (ADDR, 0x00C8, 0, "lsls r0, r1, #3"),
+            (ADDR, 0x4770, 0, "bx lr"),
+            (ADDR, 0xB510, 0, "push {r4, lr}"),
+            (ADDR, 0xBD10, 0, "pop {r4, pc}"),
+            (ADDR + 2, 0x4801, 0, "ldr r0, [pc, #0x4] ; 0x02000008"),
+            (ADDR, 0xD0FE, 0, "beq 0x02000000"),
+            (ADDR, 0xDF05, 0, "swi #0x5"),
+            (ADDR, 0xF000, 0xF802, "bl 0x02000008"),
+            (ADDR, 0xF7FF, 0xFFFE, "bl 0x02000000"),
+            (ADDR, 0xF800, 0, "bl (suffix) #0x0"),
+            (ADDR, 0xF801, 0, "bl (suffix) #0x2"),
+            (ADDR, 0xF001, 0x4770, "bl (prefix) #0x1000"),
+            (ADDR, 0xF7FF, 0x4770, "bl (prefix) #-0x1000"),
+        ] {
+            assert_eq!(disassemble_thumb(true, addr, instr, next_instr), text);
+            assert_eq!(disassemble_thumb(false, addr, instr, next_instr), text);
+        }
+    }
+
+    #[test]
+    fn thumb_v5te() {
+        for (addr, instr, next_instr, text, arm7_text) in [
+            (ADDR, 0x4798, 0, "blx r3", "bx r3"),
+            (ADDR, 0xBE01, 0, "bkpt #0x1", "undefined"),
+            (
+                ADDR + 2,
+                0xF000,
+                0xE802,
+                "blx 0x02000008",
+                "bl (prefix) #0x0",
+            ),
+            (ADDR, 0xE802, 0, "blx (suffix) #0x4", "undefined"),
+        ] {
+            assert_eq!(disassemble_thumb(true, addr, instr, next_instr), text);
+            assert_eq!(disassemble_thumb(false, addr, instr, next_instr), arm7_text);
+        }
+    }
+}
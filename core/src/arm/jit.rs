@@ -0,0 +1,912 @@
+#[cfg(not(target_arch = "x86_64"))]
+compile_error!("The JIT only supports x86-64");
+
+mod emitter;
+
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use std::io;
+
+use memmap::{Mmap, MmapMut};
+
+use super::{
+    block_cache::{AddrHasher, BlockCache},
+    instructions::InstructionHandler,
+    ARM,
+};
+use crate::hw::{mem::CodePages, AccessType, HW};
+use emitter::{AluOp, Emitter, Label, Reg, ShiftOp};
+
+type CompiledBlock<const IS_ARM9: bool> = extern "sysv64" fn(&mut ARM<IS_ARM9>, &mut HW);
+type Helper<const IS_ARM9: bool> =
+    extern "sysv64" fn(&mut ARM<IS_ARM9>, &mut HW, u32, u32, usize) -> bool;
+
+// Compiled blocks call into the interpreter handlers for everything they can't translate,
+// so timing is identical to the interpreter
+pub(super) struct Jit<const IS_ARM9: bool> {
+    // Everything is interpreted if executable memory isn't available
+    code: Option<CodeBuffer>,
+    arm_blocks: HashMap<u32, CompiledBlock<IS_ARM9>, BuildHasherDefault<AddrHasher>>,
+    thumb_blocks: HashMap<u32, CompiledBlock<IS_ARM9>, BuildHasherDefault<AddrHasher>>,
+    chunk_blocks: HashMap<usize, Vec<u32>>,
+    // Blocks stop once the CPU reaches this cycle
+    target: usize,
+}
+
+impl<const IS_ARM9: bool> Jit<IS_ARM9> {
+    pub fn new() -> Self {
+        let code = CodeBuffer::new()
+            .map_err(|err| warn!("Couldn't allocate JIT code, interpreting instead: {}", err))
+            .ok();
+        Jit {
+            code,
+            arm_blocks: HashMap::default(),
+            thumb_blocks: HashMap::default(),
+            chunk_blocks: HashMap::new(),
+            target: 0,
+        }
+    }
+
+    fn block(&self, thumb: bool, addr: u32) -> Option<CompiledBlock<IS_ARM9>> {
+        if thumb {
+            self.thumb_blocks.get(&addr).copied()
+        } else {
+            self.arm_blocks.get(&addr).copied()
+        }
+    }
+
+    fn insert(
+        &mut self,
+        thumb: bool,
+        addr: u32,
+        chunk: usize,
+        code: Vec<u8>,
+    ) -> Option<CompiledBlock<IS_ARM9>> {
+        let pushed = match self.code.as_mut()?.push(&code) {
+            Ok(None) => {
+                self.clear();
+                self.code.as_mut()?.push(&code)
+            }
+            pushed => pushed,
+        };
+        let ptr = match pushed {
+            Ok(ptr) => ptr?,
+            Err(err) => {
+                warn!("Couldn't write JIT code, interpreting instead: {}", err);
+                self.clear();
+                self.code = None;
+                return None;
+            }
+        };
+        // Safety: The code was generated by Emitter, which follows the sysv64 ABI
+        let block = unsafe { std::mem::transmute::<*const u8, CompiledBlock<IS_ARM9>>(ptr) };
+        self.chunk_blocks.entry(chunk).or_default().push(addr);
+        if thumb {
+            self.thumb_blocks.insert(addr, block);
+        } else {
+            self.arm_blocks.insert(addr, block);
+        }
+        Some(block)
+    }
+
+    // Code memory is only reclaimed once the whole buffer is full
+    pub fn invalidate_chunk(&mut self, chunk: usize) {
+        if let Some(addrs) = self.chunk_blocks.remove(&chunk) {
+            for addr in addrs {
+                self.arm_blocks.remove(&addr);
+                self.thumb_blocks.remove(&addr);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.arm_blocks.clear();
+        self.thumb_blocks.clear();
+        self.chunk_blocks.clear();
+        if let Some(code) = self.code.as_mut() {
+            code.clear();
+        }
+    }
+}
+
+// An ARM data processing instruction, which the Thumb ALU instructions are also translated to
+struct DataProc {
+    opcode: u32,
+    rd: u32,
+    rn: Operand,
+    op2: Operand,
+    // Applied to op2 the same way as an ARM shift by immediate
+    shift: Option<(u32, u32)>,
+    // Carry out of a rotated immediate
+    imm_carry: Option<bool>,
+    set_flags: bool,
+}
+
+impl DataProc {
+    fn logical(&self) -> bool {
+        matches!(self.opcode, 0x0 | 0x1 | 0x8 | 0x9 | 0xC..=0xF)
+    }
+}
+
+// PC reads are known at compile time
+#[derive(Clone, Copy)]
+enum Operand {
+    Reg(u32),
+    Imm(u32),
+}
+
+impl Operand {
+    fn reg(reg: u32, pc: u32) -> Self {
+        if reg == 15 {
+            Operand::Imm(pc)
+        } else {
+            Operand::Reg(reg)
+        }
+    }
+}
+
+impl<const IS_ARM9: bool> ARM<IS_ARM9> {
+    // Returns false if the next instruction has to be interpreted
+    pub(super) fn run_compiled_block(&mut self, hw: &mut HW, target: usize) -> bool {
+        let thumb = self.regs.get_t();
+        let addr = self.instr_addr();
+        if self.jit.code.is_none() || !ARM::<IS_ARM9>::can_decode_block(hw, addr) {
+            return false;
+        }
+        let block = match self.jit.block(thumb, addr) {
+            Some(block) => block,
+            None => match self.compile_block(hw, thumb, addr) {
+                Some(block) => block,
+                None => return false,
+            },
+        };
+        self.jit.target = target;
+        block(self, hw);
+        true
+    }
+
+    fn compile_block(
+        &mut self,
+        hw: &mut HW,
+        thumb: bool,
+        addr: u32,
+    ) -> Option<CompiledBlock<IS_ARM9>> {
+        let mut emitter = Emitter::new();
+        let instr_size = if thumb { 2 } else { 4 };
+        let mut instr_addr = addr;
+        let mut len = 0;
+        while len < BlockCache::<u32, IS_ARM9>::MAX_BLOCK_LEN
+            && CodePages::same_chunk(addr, instr_addr)
+        {
+            // PC points two instructions ahead while executing
+            let next_pc = instr_addr.wrapping_add(2 * instr_size);
+            if thumb {
+                let instr = if IS_ARM9 {
+                    hw.arm9_read::<u16>(instr_addr)
+                } else {
+                    hw.arm7_read::<u16>(instr_addr)
+                };
+                self.compile_thumb_instr(&mut emitter, instr, next_pc);
+            } else {
+                let instr = if IS_ARM9 {
+                    hw.arm9_read::<u32>(instr_addr)
+                } else {
+                    hw.arm7_read::<u32>(instr_addr)
+                };
+                self.compile_arm_instr(&mut emitter, instr, next_pc);
+            }
+            instr_addr = instr_addr.wrapping_add(instr_size);
+            len += 1;
+        }
+        let chunk = hw.code_pages.mark(IS_ARM9, addr);
+        self.jit.insert(thumb, addr, chunk, emitter.finish())
+    }
+
+    fn compile_arm_instr(&self, emitter: &mut Emitter, instr: u32, next_pc: u32) {
+        let finish: Helper<IS_ARM9> = finish_native_arm;
+        let condition = instr >> 28;
+        if condition == 0xF {
+            // ARMv5 unconditional instructions
+        } else if let Some(data_proc) = ARM::<IS_ARM9>::decode_arm_data_proc(instr, next_pc) {
+            let skip = self.emit_condition(emitter, condition);
+            self.emit_data_proc(emitter, &data_proc);
+            if let Some(skip) = skip {
+                emitter.bind(skip);
+            }
+            emitter.call_helper(finish as usize, instr, next_pc, 0);
+            return;
+        } else if instr >> 25 & 0x7 == 0b101 {
+            // B and BL
+            let skip = self.emit_condition(emitter, condition);
+            if instr >> 24 & 0x1 != 0 {
+                emitter.store_imm(self.reg_offset(14), next_pc.wrapping_sub(4));
+            }
+            let offset = ((instr << 8) as i32 >> 6) as u32;
+            let branch: Helper<IS_ARM9> = branch_arm;
+            emitter.call_helper(branch as usize, next_pc.wrapping_add(offset), next_pc, 0);
+            if let Some(skip) = skip {
+                emitter.bind(skip);
+                emitter.call_helper(finish as usize, instr, next_pc, 0);
+            }
+            return;
+        } else if ARM::<IS_ARM9>::native_single_data_transfer(instr) {
+            let skip = self.emit_condition(emitter, condition);
+            // The offset address is calculated in edx
+            self.load_operand(emitter, Reg::Edx, Operand::reg(instr >> 16 & 0xF, next_pc));
+            let op = if instr >> 23 & 0x1 != 0 {
+                AluOp::Add
+            } else {
+                AluOp::Sub
+            };
+            if instr >> 25 & 0x1 != 0 {
+                self.load_operand(emitter, Reg::Ecx, Operand::Reg(instr & 0xF));
+                self.emit_shift(emitter, instr >> 5 & 0x3, instr >> 7 & 0x1F, false);
+                emitter.alu(op, Reg::Edx, Reg::Ecx);
+            } else {
+                emitter.alu_imm(op, Reg::Edx, instr & 0xFFF);
+            }
+            let transfer: Helper<IS_ARM9> = single_data_transfer;
+            emitter.call_helper_with_edx(transfer as usize, next_pc, instr as usize);
+            if let Some(skip) = skip {
+                let end = emitter.jump();
+                emitter.bind(skip);
+                emitter.call_helper(finish as usize, instr, next_pc, 0);
+                emitter.bind(end);
+            }
+            return;
+        }
+        let handler = self.arm_lut[ARM::<IS_ARM9>::arm_lut_index(instr)];
+        let helper: Helper<IS_ARM9> = execute_arm;
+        emitter.call_helper(helper as usize, instr, next_pc, handler as usize);
+    }
+
+    fn compile_thumb_instr(&self, emitter: &mut Emitter, instr: u16, next_pc: u32) {
+        let finish: Helper<IS_ARM9> = finish_native_thumb;
+        let branch: Helper<IS_ARM9> = branch_thumb;
+        if let Some(data_proc) = ARM::<IS_ARM9>::decode_thumb_alu(instr, next_pc) {
+            self.emit_data_proc(emitter, &data_proc);
+            emitter.call_helper(finish as usize, instr as u32, next_pc, 0);
+        } else if instr >> 12 == 0xD && instr >> 8 & 0xF < 0xE {
+            // Conditional branch
+            let skip = self.emit_condition(emitter, (instr >> 8 & 0xF) as u32);
+            let offset = (instr as i8 as u32).wrapping_mul(2);
+            emitter.call_helper(branch as usize, next_pc.wrapping_add(offset), next_pc, 0);
+            if let Some(skip) = skip {
+                emitter.bind(skip);
+                emitter.call_helper(finish as usize, instr as u32, next_pc, 0);
+            }
+        } else if instr >> 11 == 0b11100 {
+            // Unconditional branch
+            let offset = (((instr as u32) << 21) as i32 >> 20) as u32;
+            emitter.call_helper(branch as usize, next_pc.wrapping_add(offset), next_pc, 0);
+        } else if instr >> 11 == 0b11110 {
+            // First half of BL
+            let offset = (((instr as u32) << 21) as i32 >> 9) as u32;
+            emitter.store_imm(self.reg_offset(14), next_pc.wrapping_add(offset));
+            emitter.call_helper(finish as usize, instr as u32, next_pc, 0);
+        } else {
+            let handler = self.thumb_lut[(instr >> 8) as usize];
+            let helper: Helper<IS_ARM9> = execute_thumb;
+            emitter.call_helper(helper as usize, instr as u32, next_pc, handler as usize);
+        }
+    }
+
+    // Data processing that doesn't write PC or shift by a register, which takes an extra cycle
+    fn decode_arm_data_proc(instr: u32, pc: u32) -> Option<DataProc> {
+        let immediate = instr >> 25 & 0x1 != 0;
+        let opcode = instr >> 21 & 0xF;
+        let set_flags = instr >> 20 & 0x1 != 0;
+        let rd = instr >> 12 & 0xF;
+        if instr >> 26 & 0x3 != 0
+            || (!immediate && instr >> 4 & 0x1 != 0)
+            // PSR transfers and the other miscellaneous instructions
+            || (opcode & 0xC == 0x8 && !set_flags)
+            || rd == 15
+        {
+            return None;
+        }
+        let (op2, shift, imm_carry) = if immediate {
+            let rotate = (instr >> 8 & 0xF) * 2;
+            let imm = (instr & 0xFF).rotate_right(rotate);
+            (
+                Operand::Imm(imm),
+                None,
+                (rotate != 0).then_some(imm >> 31 != 0),
+            )
+        } else {
+            let shift = (instr >> 5 & 0x3, instr >> 7 & 0x1F);
+            (Operand::reg(instr & 0xF, pc), Some(shift), None)
+        };
+        Some(DataProc {
+            opcode,
+            rd,
+            rn: Operand::reg(instr >> 16 & 0xF, pc),
+            op2,
+            shift,
+            imm_carry,
+            set_flags,
+        })
+    }
+
+    // LDR, STR, LDRB and STRB that don't load PC or write back to it
+    fn native_single_data_transfer(instr: u32) -> bool {
+        let pre_offset = instr >> 24 & 0x1 != 0;
+        let write_back = instr >> 21 & 0x1 != 0;
+        let base_reg = instr >> 16 & 0xF;
+        let reg_offset = instr >> 25 & 0x1 != 0;
+        instr >> 26 & 0x3 == 0b01
+            && !(reg_offset && (instr >> 4 & 0x1 != 0 || instr & 0xF == 15))
+            // LDRT and STRT
+            && (pre_offset || !write_back)
+            && (base_reg != 15 || (pre_offset && !write_back))
+            && instr >> 12 & 0xF != 15
+    }
+
+    // Thumb instructions that map to ARM data processing, leaving out register shifts and MUL
+    fn decode_thumb_alu(instr: u16, pc: u32) -> Option<DataProc> {
+        let instr = instr as u32;
+        let low_reg = |bit: u32| Operand::Reg(instr >> bit & 0x7);
+        let data_proc = |opcode, rd, rn, op2| DataProc {
+            opcode,
+            rd,
+            rn,
+            op2,
+            shift: None,
+            imm_carry: None,
+            set_flags: true,
+        };
+        if instr >> 11 == 0b00011 {
+            // Add/subtract
+            let op2 = if instr >> 10 & 0x1 != 0 {
+                Operand::Imm(instr >> 6 & 0x7)
+            } else {
+                low_reg(6)
+            };
+            let opcode = if instr >> 9 & 0x1 != 0 { 0x2 } else { 0x4 };
+            Some(data_proc(opcode, instr & 0x7, low_reg(3), op2))
+        } else if instr >> 13 == 0b000 {
+            // Move shifted register
+            Some(DataProc {
+                shift: Some((instr >> 11 & 0x3, instr >> 6 & 0x1F)),
+                ..data_proc(0xD, instr & 0x7, Operand::Imm(0), low_reg(3))
+            })
+        } else if instr >> 13 == 0b001 {
+            // Move/compare/add/subtract immediate
+            let opcode = [0xD, 0xA, 0x4, 0x2][(instr >> 11 & 0x3) as usize];
+            let rd = instr >> 8 & 0x7;
+            Some(data_proc(
+                opcode,
+                rd,
+                Operand::Reg(rd),
+                Operand::Imm(instr & 0xFF),
+            ))
+        } else if instr >> 10 == 0b010000 {
+            let rd = instr & 0x7;
+            let opcode = match instr >> 6 & 0xF {
+                // NEG is RSBS Rd, Rs, #0
+                0x9 => return Some(data_proc(0x3, rd, low_reg(3), Operand::Imm(0))),
+                opcode @ (0x0 | 0x1 | 0x5 | 0x6 | 0x8 | 0xA..=0xC | 0xE | 0xF) => opcode,
+                _ => return None,
+            };
+            Some(data_proc(opcode, rd, Operand::Reg(rd), low_reg(3)))
+        } else if instr >> 10 == 0b010001 && instr >> 8 & 0x3 != 0b11 {
+            // Hi register ADD, CMP and MOV
+            let rd = (instr >> 7 & 0x1) << 3 | instr & 0x7;
+            let rs = Operand::reg(instr >> 3 & 0xF, pc);
+            let (opcode, set_flags) = match instr >> 8 & 0x3 {
+                0b00 => (0x4, false),
+                0b01 => (0xA, true),
+                _ => (0xD, false),
+            };
+            // Even CMP refills the pipeline when Rd is PC
+            if rd == 15 {
+                return None;
+            }
+            Some(DataProc {
+                set_flags,
+                ..data_proc(opcode, rd, Operand::reg(rd, pc), rs)
+            })
+        } else {
+            None
+        }
+    }
+
+    // Jumps to the returned label if the condition fails
+    fn emit_condition(&self, emitter: &mut Emitter, condition: u32) -> Option<Label> {
+        if condition == 0xE {
+            return None;
+        }
+        // Same lookup as should_exec
+        emitter.load(Reg::Eax, self.cpsr_offset());
+        emitter.shift(ShiftOp::Shr, Reg::Eax, 24);
+        emitter.alu_imm(AluOp::And, Reg::Eax, 0xF0);
+        emitter.alu_imm(AluOp::Or, Reg::Eax, condition);
+        emitter.load_byte_indexed(Reg::Eax, self.condition_lut_offset(), Reg::Eax);
+        Some(emitter.jump_if_zero(Reg::Eax))
+    }
+
+    fn emit_data_proc(&self, emitter: &mut Emitter, data_proc: &DataProc) {
+        let cpsr = self.cpsr_offset();
+        let logical = data_proc.logical();
+        // op2 goes in ecx, op1 in eax and the shifter carry in edi
+        self.load_operand(emitter, Reg::Ecx, data_proc.op2);
+        let shifter_carry = match data_proc.shift {
+            Some((shift_type, shift)) => {
+                self.emit_shift(emitter, shift_type, shift, data_proc.set_flags && logical)
+            }
+            None => false,
+        };
+        if !matches!(data_proc.opcode, 0xD | 0xF) {
+            self.load_operand(emitter, Reg::Eax, data_proc.rn);
+        }
+
+        // ARM's carry is the inverse of x86's borrow
+        let mut borrow = false;
+        match data_proc.opcode {
+            0x0 | 0x8 => emitter.alu(AluOp::And, Reg::Eax, Reg::Ecx),
+            0x1 | 0x9 => emitter.alu(AluOp::Xor, Reg::Eax, Reg::Ecx),
+            0x2 | 0xA => {
+                emitter.alu(AluOp::Sub, Reg::Eax, Reg::Ecx);
+                borrow = true;
+            }
+            0x3 => {
+                emitter.alu(AluOp::Sub, Reg::Ecx, Reg::Eax);
+                emitter.mov(Reg::Eax, Reg::Ecx);
+                borrow = true;
+            }
+            0x4 | 0xB => emitter.alu(AluOp::Add, Reg::Eax, Reg::Ecx),
+            0x5 => {
+                emitter.carry_from_bit(cpsr, 29);
+                emitter.alu(AluOp::Adc, Reg::Eax, Reg::Ecx);
+            }
+            0x6 => {
+                emitter.carry_from_bit(cpsr, 29);
+                emitter.complement_carry();
+                emitter.alu(AluOp::Sbb, Reg::Eax, Reg::Ecx);
+                borrow = true;
+            }
+            0x7 => {
+                emitter.carry_from_bit(cpsr, 29);
+                emitter.complement_carry();
+                emitter.alu(AluOp::Sbb, Reg::Ecx, Reg::Eax);
+                emitter.mov(Reg::Eax, Reg::Ecx);
+                borrow = true;
+            }
+            0xC => emitter.alu(AluOp::Or, Reg::Eax, Reg::Ecx),
+            0xD => emitter.mov(Reg::Eax, Reg::Ecx),
+            0xE => {
+                emitter.not(Reg::Ecx);
+                emitter.alu(AluOp::And, Reg::Eax, Reg::Ecx);
+            }
+            0xF => {
+                emitter.not(Reg::Ecx);
+                emitter.mov(Reg::Eax, Reg::Ecx);
+            }
+            _ => unreachable!(),
+        }
+
+        if data_proc.set_flags {
+            // The new flags are built in ecx
+            let mask = if logical {
+                emitter.is_zero(Reg::Edx, Reg::Eax);
+                emitter.shift(ShiftOp::Shl, Reg::Edx, 30);
+                emitter.mov(Reg::Ecx, Reg::Eax);
+                emitter.alu_imm(AluOp::And, Reg::Ecx, 0x8000_0000);
+                emitter.alu(AluOp::Or, Reg::Ecx, Reg::Edx);
+                if shifter_carry {
+                    emitter.alu(AluOp::Or, Reg::Ecx, Reg::Edi);
+                    0xE000_0000
+                } else if let Some(carry) = data_proc.imm_carry {
+                    emitter.alu_imm(AluOp::Or, Reg::Ecx, (carry as u32) << 29);
+                    0xE000_0000
+                } else {
+                    0xC000_0000
+                }
+            } else {
+                emitter.flags(Reg::Edx);
+                // N and Z
+                emitter.mov(Reg::Ecx, Reg::Edx);
+                emitter.alu_imm(
+                    AluOp::And,
+                    Reg::Ecx,
+                    1 << Emitter::SIGN_FLAG | 1 << Emitter::ZERO_FLAG,
+                );
+                emitter.shift(ShiftOp::Shl, Reg::Ecx, 30 - Emitter::ZERO_FLAG);
+                // C
+                emitter.mov(Reg::Esi, Reg::Edx);
+                emitter.alu_imm(AluOp::And, Reg::Esi, 1 << Emitter::CARRY_FLAG);
+                emitter.shift(ShiftOp::Shl, Reg::Esi, 29 - Emitter::CARRY_FLAG);
+                emitter.alu(AluOp::Or, Reg::Ecx, Reg::Esi);
+                if borrow {
+                    emitter.alu_imm(AluOp::Xor, Reg::Ecx, 1 << 29);
+                }
+                // V
+                emitter.alu_imm(AluOp::And, Reg::Edx, 1 << Emitter::OVERFLOW_FLAG);
+                emitter.shift(ShiftOp::Shl, Reg::Edx, 28 - Emitter::OVERFLOW_FLAG);
+                emitter.alu(AluOp::Or, Reg::Ecx, Reg::Edx);
+                0xF000_0000
+            };
+            emitter.load(Reg::Edx, cpsr);
+            emitter.alu_imm(AluOp::And, Reg::Edx, !mask);
+            emitter.alu(AluOp::Or, Reg::Edx, Reg::Ecx);
+            emitter.store(Reg::Edx, cpsr);
+        }
+
+        if data_proc.opcode & 0xC != 0x8 {
+            emitter.store(Reg::Eax, self.reg_offset(data_proc.rd));
+        }
+    }
+
+    // Shifts ecx like an ARM shift by immediate and returns true if the carry was put in edi
+    fn emit_shift(&self, emitter: &mut Emitter, shift_type: u32, shift: u32, carry: bool) -> bool {
+        if shift_type == 0 && shift == 0 {
+            // LSL #0 leaves the carry alone
+            return false;
+        }
+        if carry {
+            let carry_bit = match (shift_type, shift) {
+                (0, shift) => 32 - shift,
+                // LSR #32 and ASR #32
+                (1 | 2, 0) => 31,
+                // RRX
+                (3, 0) => 0,
+                (_, shift) => shift - 1,
+            };
+            emitter.mov(Reg::Edi, Reg::Ecx);
+            if carry_bit != 0 {
+                emitter.shift(ShiftOp::Shr, Reg::Edi, carry_bit);
+            }
+            emitter.alu_imm(AluOp::And, Reg::Edi, 0x1);
+            emitter.shift(ShiftOp::Shl, Reg::Edi, 29);
+        }
+        match (shift_type, shift) {
+            (0, shift) => emitter.shift(ShiftOp::Shl, Reg::Ecx, shift),
+            (1, 0) => emitter.mov_imm(Reg::Ecx, 0),
+            (1, shift) => emitter.shift(ShiftOp::Shr, Reg::Ecx, shift),
+            (2, 0) => emitter.shift(ShiftOp::Sar, Reg::Ecx, 31),
+            (2, shift) => emitter.shift(ShiftOp::Sar, Reg::Ecx, shift),
+            (3, 0) => {
+                emitter.load(Reg::Esi, self.cpsr_offset());
+                emitter.alu_imm(AluOp::And, Reg::Esi, 1 << 29);
+                emitter.shift(ShiftOp::Shl, Reg::Esi, 2);
+                emitter.shift(ShiftOp::Shr, Reg::Ecx, 1);
+                emitter.alu(AluOp::Or, Reg::Ecx, Reg::Esi);
+            }
+            (_, shift) => emitter.shift(ShiftOp::Ror, Reg::Ecx, shift),
+        }
+        carry
+    }
+
+    fn load_operand(&self, emitter: &mut Emitter, reg: Reg, operand: Operand) {
+        match operand {
+            Operand::Reg(src) => emitter.load(reg, self.reg_offset(src)),
+            Operand::Imm(imm) => emitter.mov_imm(reg, imm),
+        }
+    }
+
+    fn offset_of<T>(&self, field: &T) -> u32 {
+        (field as *const T as usize - self as *const Self as usize) as u32
+    }
+
+    fn reg_offset(&self, reg: u32) -> u32 {
+        self.offset_of(&self.regs[reg])
+    }
+
+    fn cpsr_offset(&self) -> u32 {
+        self.offset_of(self.regs.cpsr_ref())
+    }
+
+    fn condition_lut_offset(&self) -> u32 {
+        self.offset_of(&self.condition_lut)
+    }
+
+    // Performs the checks the emulate loop does before each instruction
+    fn can_continue_block(&mut self, hw: &mut HW, thumb: bool, next_pc: u32) -> bool {
+        self.cycle < self.jit.target
+            && !hw.dma_active(IS_ARM9)
+            && self.regs[15] == next_pc
+            && self.regs.get_t() == thumb
+            && self.data_abort_lr.is_none()
+            && !hw.code_pages.invalidated(IS_ARM9)
+            && !self.is_halted(hw)
+            && !self.irq_pending(hw)
+            && (!IS_ARM9 || self.instr_access_allowed(hw))
+    }
+}
+
+extern "sysv64" fn execute_arm<const IS_ARM9: bool>(
+    cpu: &mut ARM<IS_ARM9>,
+    hw: &mut HW,
+    instr: u32,
+    next_pc: u32,
+    handler: usize,
+) -> bool {
+    // Safety: compile_arm_instr passes an InstructionHandler<u32, IS_ARM9>
+    let handler =
+        unsafe { std::mem::transmute::<usize, InstructionHandler<u32, IS_ARM9>>(handler) };
+    cpu.execute_arm_instr(hw, handler, instr);
+    cpu.can_continue_block(hw, false, next_pc)
+}
+
+extern "sysv64" fn execute_thumb<const IS_ARM9: bool>(
+    cpu: &mut ARM<IS_ARM9>,
+    hw: &mut HW,
+    instr: u32,
+    next_pc: u32,
+    handler: usize,
+) -> bool {
+    // Safety: compile_thumb_instr passes an InstructionHandler<u16, IS_ARM9>
+    let handler =
+        unsafe { std::mem::transmute::<usize, InstructionHandler<u16, IS_ARM9>>(handler) };
+    cpu.execute_thumb_instr(hw, handler, instr as u16);
+    cpu.can_continue_block(hw, true, next_pc)
+}
+
+// The native code has already written the result, so only the pipeline is left
+extern "sysv64" fn finish_native_arm<const IS_ARM9: bool>(
+    cpu: &mut ARM<IS_ARM9>,
+    hw: &mut HW,
+    _instr: u32,
+    next_pc: u32,
+    _handler: usize,
+) -> bool {
+    cpu.instr_buffer[0] = cpu.instr_buffer[1];
+    cpu.regs[15] = cpu.regs[15].wrapping_add(4);
+    cpu.instruction_prefetch::<u32>(hw, AccessType::S);
+    cpu.can_continue_block(hw, false, next_pc)
+}
+
+extern "sysv64" fn finish_native_thumb<const IS_ARM9: bool>(
+    cpu: &mut ARM<IS_ARM9>,
+    hw: &mut HW,
+    _instr: u32,
+    next_pc: u32,
+    _handler: usize,
+) -> bool {
+    cpu.instr_buffer[0] = cpu.instr_buffer[1];
+    cpu.regs[15] = cpu.regs[15].wrapping_add(2);
+    cpu.instruction_prefetch::<u16>(hw, AccessType::S);
+    cpu.can_continue_block(hw, true, next_pc)
+}
+
+// Branches always end the block
+extern "sysv64" fn branch_arm<const IS_ARM9: bool>(
+    cpu: &mut ARM<IS_ARM9>,
+    hw: &mut HW,
+    target: u32,
+    _next_pc: u32,
+    _handler: usize,
+) -> bool {
+    cpu.instr_buffer[0] = cpu.instr_buffer[1];
+    cpu.regs[15] = cpu.regs[15].wrapping_add(4);
+    cpu.instruction_prefetch::<u32>(hw, AccessType::N);
+    cpu.regs[15] = target;
+    cpu.fill_arm_instr_buffer(hw);
+    false
+}
+
+extern "sysv64" fn branch_thumb<const IS_ARM9: bool>(
+    cpu: &mut ARM<IS_ARM9>,
+    hw: &mut HW,
+    target: u32,
+    _next_pc: u32,
+    _handler: usize,
+) -> bool {
+    cpu.instr_buffer[0] = cpu.instr_buffer[1];
+    cpu.regs[15] = cpu.regs[15].wrapping_add(2);
+    cpu.instruction_prefetch::<u16>(hw, AccessType::N);
+    cpu.regs[15] = target;
+    cpu.fill_thumb_instr_buffer(hw);
+    false
+}
+
+// The native code calculated the offset address
+extern "sysv64" fn single_data_transfer<const IS_ARM9: bool>(
+    cpu: &mut ARM<IS_ARM9>,
+    hw: &mut HW,
+    offset_applied: u32,
+    next_pc: u32,
+    instr: usize,
+) -> bool {
+    cpu.instr_buffer[0] = cpu.instr_buffer[1];
+    cpu.regs[15] = cpu.regs[15].wrapping_add(4);
+    cpu.single_data_transfer_at(hw, instr as u32, offset_applied);
+    cpu.can_continue_block(hw, false, next_pc)
+}
+
+// Executable memory is only made writable while a block is being copied in
+struct CodeBuffer {
+    mem: Option<Mmap>,
+    len: usize,
+}
+
+impl CodeBuffer {
+    const SIZE: usize = 32 * 1024 * 1024;
+
+    fn new() -> io::Result<Self> {
+        Ok(CodeBuffer {
+            mem: Some(MmapMut::map_anon(CodeBuffer::SIZE)?.make_exec()?),
+            len: 0,
+        })
+    }
+
+    // Returns None if the buffer is full
+    fn push(&mut self, code: &[u8]) -> io::Result<Option<*const u8>> {
+        if self.len + code.len() > CodeBuffer::SIZE {
+            return Ok(None);
+        }
+        let mut mem = match self.mem.take() {
+            Some(mem) => mem.make_mut()?,
+            None => return Ok(None),
+        };
+        mem[self.len..self.len + code.len()].copy_from_slice(code);
+        let mem = mem.make_exec()?;
+        let ptr = mem[self.len..].as_ptr();
+        self.mem = Some(mem);
+        self.len += code.len();
+        Ok(Some(ptr))
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE_ADDR: u32 = 0x0200_0000;
+    const DATA_ADDR: u32 = 0x0220_0000;
+    const DATA_SIZE: u32 = 0x2200;
+    const CASES: usize = 4000;
+
+    // Xorshift, so failures can be reproduced
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+    }
+
+    struct System {
+        cpu: ARM<true>,
+        hw: HW,
+    }
+
+    impl System {
+        fn new() -> Self {
+            let mut hw = HW::new_for_tests();
+            for addr in (DATA_ADDR..DATA_ADDR + DATA_SIZE).step_by(4) {
+                hw.arm9_write::<u32>(addr, addr.wrapping_mul(0x9E37_79B9));
+            }
+            let cpu = ARM::new(&mut hw, false);
+            System { cpu, hw }
+        }
+
+        fn load(&mut self, thumb: bool, addr: u32, instr: u32, regs: &[u32; 15], flags: u32) {
+            let System { cpu, hw } = self;
+            if thumb {
+                hw.arm9_write::<u16>(addr, instr as u16);
+            } else {
+                hw.arm9_write::<u32>(addr, instr);
+            }
+            cpu.invalidate_blocks(hw);
+            for (reg, &value) in regs.iter().enumerate() {
+                cpu.regs[reg as u32] = value;
+            }
+            let cpsr = cpu.regs.cpsr_mut();
+            *cpsr = *cpsr & 0x0FFF_FFFF | flags;
+            cpu.regs[15] = addr;
+            cpu.regs.set_t(thumb);
+            if thumb {
+                cpu.fill_thumb_instr_buffer(hw);
+            } else {
+                cpu.fill_arm_instr_buffer(hw);
+            }
+        }
+
+        fn data(&self) -> Vec<u32> {
+            (DATA_ADDR..DATA_ADDR + DATA_SIZE)
+                .step_by(4)
+                .map(|addr| self.hw.arm9_peek::<u32>(addr))
+                .collect()
+        }
+    }
+
+    // Runs every instruction on both the interpreter and the JIT and compares the results
+    fn compare(thumb: bool, gen_instr: impl Fn(&mut Rng) -> Option<(u32, [u32; 15])>) {
+        let mut interpreter = System::new();
+        let mut jit = System::new();
+        let mut rng = Rng(0x1234_5678);
+        let mut cases = 0;
+        while cases < CASES {
+            let Some((instr, regs)) = gen_instr(&mut rng) else {
+                continue;
+            };
+            let flags = rng.next() & 0xF000_0000;
+            // Every case gets its own chunk, so the code isn't treated as self-modifying
+            let addr = CODE_ADDR + cases as u32 * 0x100;
+            interpreter.load(thumb, addr, instr, &regs, flags);
+            jit.load(thumb, addr, instr, &regs, flags);
+
+            if thumb {
+                interpreter.cpu.emulate_thumb_instr(&mut interpreter.hw);
+            } else {
+                interpreter.cpu.emulate_arm_instr(&mut interpreter.hw);
+            }
+            // The block stops after the first instruction
+            let target = jit.cpu.cycle + 1;
+            assert!(jit.cpu.run_compiled_block(&mut jit.hw, target));
+
+            let context =
+                format!("0x{instr:08X} at 0x{addr:08X} with {regs:08X?} and flags 0x{flags:08X}");
+            assert_eq!(interpreter.cpu.regs, jit.cpu.regs, "{context}");
+            assert_eq!(interpreter.cpu.cycle, jit.cpu.cycle, "{context}");
+            assert_eq!(
+                interpreter.cpu.instr_buffer, jit.cpu.instr_buffer,
+                "{context}"
+            );
+            if !thumb && instr >> 26 & 0x3 == 0b01 {
+                assert!(interpreter.data() == jit.data(), "{context}");
+            }
+            cases += 1;
+        }
+    }
+
+    fn random_regs(rng: &mut Rng) -> [u32; 15] {
+        // Small values hit the special cases of shifts and flags more often
+        [(); 15].map(|_| match rng.next() % 4 {
+            0 => rng.next() & 0x3F,
+            1 => rng.next() | 0xFFFF_FFC0,
+            _ => rng.next(),
+        })
+    }
+
+    #[test]
+    fn arm_matches_interpreter() {
+        compare(false, |rng| {
+            let instr = rng.next();
+            let mut regs = random_regs(rng);
+            if ARM::<true>::native_single_data_transfer(instr) {
+                // Keep accesses inside the data area
+                let instr = instr & !(0x7F << 5) | (rng.next() % 3) << 7;
+                let base_reg = instr >> 16 & 0xF;
+                for value in regs.iter_mut() {
+                    *value &= 0xFF;
+                }
+                if base_reg != 15 {
+                    regs[base_reg as usize] = DATA_ADDR + 0x1000 + (rng.next() & 0xFC);
+                }
+                let offset_reg = instr & 0xF;
+                if instr >> 25 & 0x1 != 0 && offset_reg == base_reg {
+                    return None;
+                }
+                Some((instr, regs))
+            } else if instr >> 28 != 0xF
+                && (ARM::<true>::decode_arm_data_proc(instr, 0).is_some()
+                    || instr >> 25 & 0x7 == 0b101)
+            {
+                Some((instr, regs))
+            } else {
+                None
+            }
+        });
+    }
+
+    #[test]
+    fn thumb_matches_interpreter() {
+        compare(true, |rng| {
+            let instr = rng.next() as u16;
+            let native = ARM::<true>::decode_thumb_alu(instr, 0).is_some()
+                || (instr >> 12 == 0xD && instr >> 8 & 0xF < 0xE)
+                || instr >> 11 == 0b11100
+                || instr >> 11 == 0b11110;
+            native.then(|| (instr as u32, random_regs(rng)))
+        });
+    }
+}
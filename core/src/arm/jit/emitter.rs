@@ -0,0 +1,210 @@
+// Minimal x86-64 encoder for the instructions compiled blocks need
+pub struct Emitter {
+    code: Vec<u8>,
+    exit_jumps: Vec<usize>,
+}
+
+// Only registers that can be encoded without a REX prefix
+#[derive(Clone, Copy, PartialEq)]
+pub enum Reg {
+    Eax = 0,
+    Ecx = 1,
+    Edx = 2,
+    Esi = 6,
+    Edi = 7,
+}
+
+#[derive(Clone, Copy)]
+pub enum AluOp {
+    Add = 0,
+    Or = 1,
+    Adc = 2,
+    Sbb = 3,
+    And = 4,
+    Sub = 5,
+    Xor = 6,
+}
+
+#[derive(Clone, Copy)]
+pub enum ShiftOp {
+    Ror = 1,
+    Shl = 4,
+    Shr = 5,
+    Sar = 7,
+}
+
+// A forward jump that still has to be pointed at its destination
+pub struct Label(usize);
+
+impl Emitter {
+    // Bit positions in RFLAGS
+    pub const CARRY_FLAG: u32 = 0;
+    pub const ZERO_FLAG: u32 = 6;
+    pub const SIGN_FLAG: u32 = 7;
+    pub const OVERFLOW_FLAG: u32 = 11;
+
+    pub fn new() -> Self {
+        let mut emitter = Emitter {
+            code: Vec::new(),
+            exit_jumps: Vec::new(),
+        };
+        // The CPU and HW pointers live in callee-saved registers for the whole block
+        emitter.emit(&[0x53]); // push rbx
+        emitter.emit(&[0x41, 0x54]); // push r12
+        emitter.emit(&[0x48, 0x83, 0xEC, 0x08]); // sub rsp, 8
+        emitter.emit(&[0x48, 0x89, 0xFB]); // mov rbx, rdi
+        emitter.emit(&[0x49, 0x89, 0xF4]); // mov r12, rsi
+        emitter
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn modrm_reg(reg: u8, rm: Reg) -> u8 {
+        0xC0 | reg << 3 | rm as u8
+    }
+
+    // [rbx + disp32]
+    fn modrm_cpu(reg: u8) -> u8 {
+        0x80 | reg << 3 | 0x3
+    }
+
+    // Calls helper(cpu, hw, arg0, arg1, arg2) and leaves the block if it returns false
+    pub fn call_helper(&mut self, helper: usize, arg0: u32, arg1: u32, arg2: usize) {
+        self.mov_imm(Reg::Edx, arg0);
+        self.call_helper_with_edx(helper, arg1, arg2);
+    }
+
+    // Same as call_helper, but arg0 is whatever was computed in edx
+    pub fn call_helper_with_edx(&mut self, helper: usize, arg1: u32, arg2: usize) {
+        self.emit(&[0x48, 0x89, 0xDF]); // mov rdi, rbx
+        self.emit(&[0x4C, 0x89, 0xE6]); // mov rsi, r12
+        self.mov_imm(Reg::Ecx, arg1);
+        self.emit(&[0x49, 0xB8]); // mov r8, imm64
+        self.emit(&(arg2 as u64).to_le_bytes());
+        self.emit(&[0x48, 0xB8]); // mov rax, imm64
+        self.emit(&(helper as u64).to_le_bytes());
+        self.emit(&[0xFF, 0xD0]); // call rax
+        self.emit(&[0x84, 0xC0]); // test al, al
+        self.emit(&[0x0F, 0x84]); // jz exit
+        self.exit_jumps.push(self.code.len());
+        self.emit(&[0; 4]);
+    }
+
+    // Offsets are relative to the CPU pointer
+    pub fn load(&mut self, reg: Reg, offset: u32) {
+        self.emit(&[0x8B, Emitter::modrm_cpu(reg as u8)]); // mov reg, [rbx + disp32]
+        self.emit(&offset.to_le_bytes());
+    }
+
+    pub fn store(&mut self, reg: Reg, offset: u32) {
+        self.emit(&[0x89, Emitter::modrm_cpu(reg as u8)]); // mov [rbx + disp32], reg
+        self.emit(&offset.to_le_bytes());
+    }
+
+    pub fn store_imm(&mut self, offset: u32, imm: u32) {
+        self.emit(&[0xC7, Emitter::modrm_cpu(0)]); // mov dword [rbx + disp32], imm32
+        self.emit(&offset.to_le_bytes());
+        self.emit(&imm.to_le_bytes());
+    }
+
+    pub fn mov_imm(&mut self, reg: Reg, imm: u32) {
+        self.emit(&[0xB8 + reg as u8]); // mov reg, imm32
+        self.emit(&imm.to_le_bytes());
+    }
+
+    // Doesn't change the flags
+    pub fn mov(&mut self, dest: Reg, src: Reg) {
+        self.emit(&[0x89, Emitter::modrm_reg(src as u8, dest)]); // mov dest, src
+    }
+
+    pub fn alu(&mut self, op: AluOp, dest: Reg, src: Reg) {
+        // op dest, src
+        self.emit(&[(op as u8) << 3 | 0x01, Emitter::modrm_reg(src as u8, dest)]);
+    }
+
+    pub fn alu_imm(&mut self, op: AluOp, dest: Reg, imm: u32) {
+        self.emit(&[0x81, Emitter::modrm_reg(op as u8, dest)]); // op dest, imm32
+        self.emit(&imm.to_le_bytes());
+    }
+
+    pub fn shift(&mut self, op: ShiftOp, reg: Reg, amount: u32) {
+        debug_assert!(amount > 0 && amount < 32);
+        self.emit(&[0xC1, Emitter::modrm_reg(op as u8, reg), amount as u8]); // op reg, imm8
+    }
+
+    pub fn not(&mut self, reg: Reg) {
+        self.emit(&[0xF7, Emitter::modrm_reg(2, reg)]); // not reg
+    }
+
+    // Sets the x86 carry flag to a bit of a value in the CPU
+    pub fn carry_from_bit(&mut self, offset: u32, bit: u32) {
+        self.emit(&[0x0F, 0xBA, Emitter::modrm_cpu(4)]); // bt dword [rbx + disp32], imm8
+        self.emit(&offset.to_le_bytes());
+        self.emit(&[bit as u8]);
+    }
+
+    pub fn complement_carry(&mut self) {
+        self.emit(&[0xF5]); // cmc
+    }
+
+    // Copies RFLAGS into reg
+    pub fn flags(&mut self, reg: Reg) {
+        self.emit(&[0x9C]); // pushfq
+        self.emit(&[0x58 + reg as u8]); // pop reg
+    }
+
+    // dest = (src == 0) as u32
+    pub fn is_zero(&mut self, dest: Reg, src: Reg) {
+        // esi and edi don't have byte registers without a REX prefix
+        debug_assert!(dest != Reg::Esi && dest != Reg::Edi);
+        self.emit(&[0x85, Emitter::modrm_reg(src as u8, src)]); // test src, src
+        self.emit(&[0x0F, 0x94, Emitter::modrm_reg(0, dest)]); // sete dest8
+        self.emit(&[0x0F, 0xB6, Emitter::modrm_reg(dest as u8, dest)]); // movzx dest, dest8
+    }
+
+    // dest = cpu[offset + index] as u32, where index is a byte offset
+    pub fn load_byte_indexed(&mut self, dest: Reg, offset: u32, index: Reg) {
+        // movzx dest, byte [rbx + index + disp32]
+        self.emit(&[
+            0x0F,
+            0xB6,
+            0x84 | (dest as u8) << 3,
+            (index as u8) << 3 | 0x3,
+        ]);
+        self.emit(&offset.to_le_bytes());
+    }
+
+    // Jumps to the returned label if reg is zero
+    pub fn jump_if_zero(&mut self, reg: Reg) -> Label {
+        self.emit(&[0x85, Emitter::modrm_reg(reg as u8, reg)]); // test reg, reg
+        self.emit(&[0x0F, 0x84]); // jz rel32
+        let label = Label(self.code.len());
+        self.emit(&[0; 4]);
+        label
+    }
+
+    pub fn jump(&mut self) -> Label {
+        self.emit(&[0xE9]); // jmp rel32
+        let label = Label(self.code.len());
+        self.emit(&[0; 4]);
+        label
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        let rel = (self.code.len() - (label.0 + 4)) as u32;
+        self.code[label.0..label.0 + 4].copy_from_slice(&rel.to_le_bytes());
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        for jump in std::mem::take(&mut self.exit_jumps) {
+            self.bind(Label(jump));
+        }
+        self.emit(&[0x48, 0x83, 0xC4, 0x08]); // add rsp, 8
+        self.emit(&[0x41, 0x5C]); // pop r12
+        self.emit(&[0x5B]); // pop rbx
+        self.emit(&[0xC3]); // ret
+        self.code
+    }
+}
@@ -12,7 +12,7 @@ pub enum Mode {
 }
 
 bitfield! {
-    #[derive(Debug, PartialEq, Clone, Copy)]
+    #[derive(PartialEq, Clone, Copy)]
     struct StatusRegBits: u32 {
         n: bool @ 31,
         z: bool @ 30,
@@ -73,9 +73,10 @@ pub struct RegValues {
     svc: [u32; 2], // R13 and R14
     und: [u32; 2], // R13 and R14
     irq: [u32; 2], // R13 and R14
+    abt: [u32; 2], // R13 and R14
     fiq: [u32; 7], // R8-R14
     cpsr: StatusReg,
-    spsr: [StatusReg; 4], // SVC, IRQ, IRQ
+    spsr: [StatusReg; 5], // SVC, UND, IRQ, FIQ, ABT
 }
 
 impl RegValues {
@@ -85,9 +86,10 @@ impl RegValues {
             svc: [0; 2], // R13 and R14
             und: [0; 2], // R13 and R14
             irq: [0; 2], // R13 and R14
+            abt: [0; 2], // R13 and R14
             fiq: [0; 7], // R8-R14
             cpsr: StatusReg::reset(),
-            spsr: [StatusReg::reset(); 4], // SVC, UND, IRQ, FIQ
+            spsr: [StatusReg::reset(); 5], // SVC, UND, IRQ, FIQ, ABT
         };
         regs[15] = if IS_ARM9 { 0xFFFF_0000 } else { 0x0 };
         regs
@@ -145,7 +147,7 @@ impl RegValues {
             Mode::UND => &mut self.und,
             Mode::IRQ => &mut self.irq,
             Mode::FIQ => &mut self.fiq,
-            Mode::ABT => unreachable!(), // Unused modes (hopefully)
+            Mode::ABT => &mut self.abt,
         };
         let start = 15 - banked.len();
         banked.swap_with_slice(&mut self.regs[start..15]);
@@ -159,7 +161,7 @@ impl RegValues {
             Mode::UND => &mut self.und,
             Mode::IRQ => &mut self.irq,
             Mode::FIQ => &mut self.fiq,
-            Mode::ABT => unreachable!(), // Unused modes (hopefully)
+            Mode::ABT => &mut self.abt,
         };
         let start = 15 - banked.len();
         self.regs[start..15].swap_with_slice(banked);
@@ -171,7 +173,7 @@ impl RegValues {
             Mode::UND => self.spsr[1].bits.0,
             Mode::IRQ => self.spsr[2].bits.0,
             Mode::FIQ => self.spsr[3].bits.0,
-            Mode::ABT => unreachable!(), // Unused modes (hopefully)
+            Mode::ABT => self.spsr[4].bits.0,
             _ => self.cpsr.bits.0,
         }
     }
@@ -183,7 +185,7 @@ impl RegValues {
             Mode::UND => &mut self.spsr[1].bits.0,
             Mode::IRQ => &mut self.spsr[2].bits.0,
             Mode::FIQ => &mut self.spsr[3].bits.0,
-            Mode::ABT => unreachable!(), // Unused modes (hopefully)
+            Mode::ABT => &mut self.spsr[4].bits.0,
             _ => &mut self.cpsr.bits.0,
         }
     }
@@ -194,7 +196,7 @@ impl RegValues {
             Mode::UND => &mut self.spsr[1].update_mode(),
             Mode::IRQ => &mut self.spsr[2].update_mode(),
             Mode::FIQ => &mut self.spsr[3].update_mode(),
-            Mode::ABT => unreachable!(), // Unused modes (hopefully)
+            Mode::ABT => &mut self.spsr[4].update_mode(),
             _ => &mut self.cpsr.update_mode(),
         };
     }
@@ -208,10 +210,37 @@ impl RegValues {
         &mut self.cpsr.bits.0
     }
 
+    // Compiled blocks only change the flags through this
+    #[cfg(feature = "jit")]
+    pub fn cpsr_ref(&self) -> &u32 {
+        &self.cpsr.bits.0
+    }
+
     pub fn update_cpsr_mode(&mut self) {
         self.cpsr.update_mode();
     }
 
+    // R0-R14 as seen from mode
+    pub fn mode_regs(&self, mode: Mode) -> [u32; 15] {
+        let mut regs = self.clone();
+        regs.save_banked();
+        regs.cpsr.set_mode(mode);
+        regs.load_banked(mode);
+        regs.regs[..15].try_into().unwrap()
+    }
+
+    pub fn mode_spsr(&self, mode: Mode) -> Option<u32> {
+        let index = match mode {
+            Mode::SVC => 0,
+            Mode::UND => 1,
+            Mode::IRQ => 2,
+            Mode::FIQ => 3,
+            Mode::ABT => 4,
+            Mode::USR | Mode::SYS => return None,
+        };
+        Some(self.spsr[index].bits.0)
+    }
+
     pub fn sp(&self) -> u32 {
         self.regs[13]
     }
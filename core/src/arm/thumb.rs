@@ -1,32 +1,68 @@
-use super::{instructions::InstructionHandler, registers::Mode, ARM, HW};
+use super::{
+    block_cache::{BlockCache, CachedInstr},
+    instructions::InstructionHandler,
+    registers::Mode,
+    ARM, HW,
+};
 
-use crate::hw::AccessType;
+use crate::hw::{mem::CodePages, AccessType};
 
 impl<const IS_ARM9: bool> ARM<IS_ARM9> {
     pub(super) fn fill_thumb_instr_buffer(&mut self, hw: &mut HW) {
         self.regs[15] &= !0x1;
-        self.instr_buffer[0] = self.read::<u16>(hw, AccessType::S, self.regs[15] & !0x1) as u32;
+        self.instr_buffer[0] = self.fetch::<u16>(hw, AccessType::S, self.regs[15] & !0x1) as u32;
         self.regs[15] = self.regs[15].wrapping_add(2);
 
-        self.instr_buffer[1] = self.read::<u16>(hw, AccessType::S, self.regs[15] & !0x1) as u32;
+        self.instr_buffer[1] = self.fetch::<u16>(hw, AccessType::S, self.regs[15] & !0x1) as u32;
     }
 
-    pub(super) fn emulate_thumb_instr(&mut self, hw: &mut HW) {
-        let instr = self.instr_buffer[0] as u16;
+    fn next_thumb_instr(&mut self, hw: &mut HW) -> CachedInstr<u16, IS_ARM9> {
+        let addr = self.regs[15].wrapping_sub(2);
+        if let Some(cached_instr) = self.thumb_blocks.next(addr) {
+            return cached_instr;
+        }
+        if !ARM::<IS_ARM9>::can_decode_block(hw, addr) {
+            let instr = self.instr_buffer[0] as u16;
+            return (self.thumb_lut[(instr >> 8) as usize], instr);
+        }
+        self.build_thumb_block(hw, addr);
+        self.thumb_blocks.next(addr).unwrap()
+    }
+
+    #[cold]
+    fn build_thumb_block(&mut self, hw: &mut HW, addr: u32) {
+        let mut block = Vec::new();
+        let mut instr_addr = addr;
+        while block.len() < BlockCache::<u16, IS_ARM9>::MAX_BLOCK_LEN
+            && CodePages::same_chunk(addr, instr_addr)
         {
-            trace!("{:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} \
-            {:08X} {:08X} {:08X} {:08X} cpsr: {:08X} | {}",
-            self.regs[0], self.regs[1], self.regs[2], self.regs[3], self.regs[4], self.regs[5], self.regs[6],
-            self.regs[7], self.regs[8], self.regs[9], self.regs[10], self.regs[11], self.regs[12],
-            self.regs[13], self.regs[14], self.regs[15], self.regs.cpsr(),
-            if instr & 0b1111_1000_0000_0000 == 0b1111_0000_0000_0000 {
-                format!("{:04X}{:04X}", instr, self.instr_buffer[1])
-            } else { format!("    {:04X}", instr) });
+            let instr = if IS_ARM9 {
+                hw.arm9_read::<u16>(instr_addr)
+            } else {
+                hw.arm7_read::<u16>(instr_addr)
+            };
+            block.push((self.thumb_lut[(instr >> 8) as usize], instr));
+            instr_addr = instr_addr.wrapping_add(2);
         }
+        let chunk = hw.code_pages.mark(IS_ARM9, addr);
+        self.thumb_blocks.insert(addr, chunk, block);
+    }
+
+    pub(super) fn emulate_thumb_instr(&mut self, hw: &mut HW) {
+        let (handler, instr) = self.next_thumb_instr(hw);
+        self.execute_thumb_instr(hw, handler, instr);
+    }
+
+    pub(super) fn execute_thumb_instr(
+        &mut self,
+        hw: &mut HW,
+        handler: InstructionHandler<u16, IS_ARM9>,
+        instr: u16,
+    ) {
         self.instr_buffer[0] = self.instr_buffer[1];
         self.regs[15] = self.regs[15].wrapping_add(2);
 
-        self.thumb_lut[(instr >> 8) as usize](self, hw, instr);
+        handler(self, hw, instr);
     }
 
     // THUMB.1: move shifted register
@@ -200,8 +236,10 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         let value = self
             .read::<u32>(hw, AccessType::N, addr & !0x3)
             .rotate_right((addr & 0x3) * 8);
-        self.regs[dest_reg] = value;
         self.internal();
+        if !self.aborted() {
+            self.regs[dest_reg] = value;
+        }
     }
 
     // THUMB.7: load/store with register offset
@@ -226,8 +264,10 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
                 self.read::<u32>(hw, AccessType::S, addr & !0x3)
                     .rotate_right((addr & 0x3) * 8) // LDR
             };
-            self.regs[src_dest_reg] = value;
             self.internal();
+            if !self.aborted() {
+                self.regs[src_dest_reg] = value;
+            }
         } else {
             // Store
             if opcode & 0b01 != 0 {
@@ -278,8 +318,10 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
                     _ => unreachable!(),
                 }
             };
-            self.regs[src_dest_reg] = value;
             self.internal();
+            if !self.aborted() {
+                self.regs[src_dest_reg] = value;
+            }
         }
     }
 
@@ -303,8 +345,10 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
                 self.read::<u32>(hw, AccessType::S, addr & !0x3)
                     .rotate_right((addr & 0x3) * 8)
             };
-            self.regs[src_dest_reg] = value;
             self.internal();
+            if !self.aborted() {
+                self.regs[src_dest_reg] = value;
+            }
         } else {
             let value = self.regs[src_dest_reg];
             // Is access width 1? Probably not, could be just bug in prev version
@@ -338,8 +382,10 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
             } else {
                 value.rotate_right((addr & 0x1) * 8)
             };
-            self.regs[src_dest_reg] = value;
             self.internal();
+            if !self.aborted() {
+                self.regs[src_dest_reg] = value;
+            }
         } else {
             self.write::<u16>(
                 hw,
@@ -366,8 +412,10 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
             let value = self
                 .read::<u32>(hw, AccessType::S, addr & !0x3)
                 .rotate_right((addr & 0x3) * 8);
-            self.regs[src_dest_reg] = value;
             self.internal();
+            if !self.aborted() {
+                self.regs[src_dest_reg] = value;
+            }
         } else {
             self.write::<u32>(hw, AccessType::N, addr & !0x3, self.regs[src_dest_reg]);
         }
@@ -420,7 +468,9 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
             let mut sp = self.regs.sp();
             let mut stack_pop = |sp, last_access, reg: u32| {
                 let value = self.read::<u32>(hw, AccessType::S, sp);
-                self.regs[reg] = value;
+                if !self.aborted() {
+                    self.regs[reg] = value;
+                }
                 if last_access {
                     self.internal()
                 }
@@ -437,6 +487,11 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
             if pc_lr {
                 stack_pop(sp, true, 15);
                 sp += 4;
+            }
+            if self.aborted() {
+                return;
+            }
+            if pc_lr {
                 self.next_access_type = AccessType::N;
                 if !IS_ARM9 || self.regs[15] & 0x1 != 0 {
                     self.regs[15] &= !0x1;
@@ -473,6 +528,9 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
                 sp += 4
             }
             assert_eq!(initial_sp, sp);
+            if self.aborted() {
+                self.regs.set_sp(initial_sp);
+            }
         }
     }
 
@@ -485,7 +543,8 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         assert_eq!(instr >> 12, 0b1100);
         let load = L;
         let base_reg = (RB2 as u32) << 2 | (RB1 as u32) << 1 | (RB0 as u32);
-        let mut base = self.regs[base_reg];
+        let original_base = self.regs[base_reg];
+        let mut base = original_base;
         let base_offset = base & 0x3;
         base -= base_offset;
         let mut r_list = (instr & 0xFF) as u8;
@@ -502,7 +561,9 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
             base = base.wrapping_add(4);
             if load {
                 let value = self.read::<u32>(hw, AccessType::S, addr);
-                self.regs[reg] = value;
+                if !self.aborted() {
+                    self.regs[reg] = value;
+                }
                 if last_access {
                     self.internal()
                 }
@@ -547,7 +608,9 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         if !load {
             self.regs[15] = self.regs[15].wrapping_sub(2)
         }
-        if write_back {
+        if self.aborted() {
+            self.regs[base_reg] = original_base
+        } else if write_back {
             self.regs[base_reg] = base + base_offset
         }
     }
@@ -637,8 +700,10 @@ impl<const IS_ARM9: bool> ARM<IS_ARM9> {
         }
     }
 
-    fn undefined_instr_thumb(&mut self, _hw: &mut HW, _instr: u16) {
-        panic!("Undefined Thumb Instruction!")
+    // Treated as a NOP instead of raising the undefined instruction exception
+    fn undefined_instr_thumb(&mut self, hw: &mut HW, instr: u16) {
+        unimplemented_behavior!("Undefined Thumb Instruction 0x{:04X}", instr);
+        self.instruction_prefetch::<u16>(hw, AccessType::S);
     }
 }
 
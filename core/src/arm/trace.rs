@@ -0,0 +1,68 @@
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+
+use super::{disassembler, ARM};
+
+// Writes a line per executed instruction in the format
+// <cycle> <pc> <opcode> <r0> ... <r14> <cpsr> ; <disassembly>
+// The cycle is decimal and in the CPU's own clock, everything else is hex
+// THUMB opcodes are 4 digits, and r15 is left out since it's the same as pc
+// Anything after the ; is only for reading and isn't meant to be compared
+pub(super) struct Tracer {
+    writer: BufWriter<Box<dyn Write>>,
+    cycles: Range<usize>,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>, cycles: Range<usize>) -> Self {
+        Tracer {
+            writer: BufWriter::new(writer),
+            cycles,
+        }
+    }
+
+    pub fn finished(&self, cycle: usize) -> bool {
+        cycle >= self.cycles.end
+    }
+}
+
+impl<const IS_ARM9: bool> ARM<IS_ARM9> {
+    pub(super) fn trace_instr(&mut self) {
+        match &self.debugger.tracer {
+            Some(tracer) if tracer.cycles.contains(&self.cycle) => (),
+            _ => return,
+        }
+        let pc = self.instr_addr();
+        let instr = self.instr_buffer[0];
+        let (opcode, disassembly) = if self.regs.get_t() {
+            let next_instr = self.instr_buffer[1] as u16;
+            (
+                format!("{:04X}", instr as u16),
+                disassembler::disassemble_thumb(IS_ARM9, pc, instr as u16, next_instr),
+            )
+        } else {
+            (
+                format!("{:08X}", instr),
+                disassembler::disassemble_arm(IS_ARM9, pc, instr),
+            )
+        };
+        let regs: String = (0..15)
+            .map(|reg| format!(" {:08X}", self.regs[reg]))
+            .collect();
+        let writer = &mut self.debugger.tracer.as_mut().unwrap().writer;
+        let result = writeln!(
+            writer,
+            "{} {:08X} {}{} {:08X} ; {}",
+            self.cycle,
+            pc,
+            opcode,
+            regs,
+            self.regs.cpsr(),
+            disassembly
+        );
+        if let Err(e) = result {
+            warn!("Stopping trace: {}", e);
+            self.debugger.set_tracer(None);
+        }
+    }
+}
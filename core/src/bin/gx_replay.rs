@@ -0,0 +1,110 @@
+// Renders a frame written by NDS::record_geometry_frame without emulating the CPUs
+// The result is written as a PPM image, or compared against a previously written one so that
+// recordings can be used as regression fixtures
+
+use std::fs::{self, File};
+use std::io::BufReader;
+
+use nds_core::nds::replay_geometry;
+
+const WIDTH: usize = 256;
+const HEIGHT: usize = 192;
+
+fn main() {
+    let args: Vec<_> = std::env::args().collect();
+    let mut recording_path = None;
+    let mut output_path = None;
+    let mut compare_path = None;
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--output" => match arg_iter.next() {
+                Some(path) => output_path = Some(path),
+                None => usage(&args[0]),
+            },
+            "--compare" => match arg_iter.next() {
+                Some(path) => compare_path = Some(path),
+                None => usage(&args[0]),
+            },
+            _ if recording_path.is_none() => recording_path = Some(arg),
+            _ => usage(&args[0]),
+        }
+    }
+    let recording_path = match recording_path {
+        Some(path) if output_path.is_some() || compare_path.is_some() => path,
+        _ => usage(&args[0]),
+    };
+
+    let pixels = match File::open(recording_path)
+        .and_then(|file| replay_geometry(&mut BufReader::new(file)))
+    {
+        Ok(pixels) => pixels,
+        Err(e) => {
+            eprintln!("Unable to replay {}: {}", recording_path, e);
+            std::process::exit(2);
+        }
+    };
+    let image = to_ppm(&pixels);
+
+    if let Some(path) = output_path {
+        if let Err(e) = fs::write(path, &image) {
+            eprintln!("Unable to write {}: {}", path, e);
+            std::process::exit(2);
+        }
+    }
+    if let Some(path) = compare_path {
+        let expected = match fs::read(path) {
+            Ok(expected) => expected,
+            Err(e) => {
+                eprintln!("Unable to read {}: {}", path, e);
+                std::process::exit(2);
+            }
+        };
+        if expected.len() != image.len() || !expected.starts_with(&header()) {
+            eprintln!("{} isn't a {}x{} PPM image", path, WIDTH, HEIGHT);
+            std::process::exit(2);
+        }
+        let start = header().len();
+        let mismatches = image[start..]
+            .chunks(3)
+            .zip(expected[start..].chunks(3))
+            .enumerate()
+            .filter(|(_, (actual, expected))| actual != expected)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        match mismatches.first() {
+            None => println!("Frame matches {}", path),
+            Some(first) => {
+                println!(
+                    "{} pixels differ from {}, first at ({}, {})",
+                    mismatches.len(),
+                    path,
+                    first % WIDTH,
+                    first / WIDTH,
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn header() -> Vec<u8> {
+    format!("P6\n{} {}\n255\n", WIDTH, HEIGHT).into_bytes()
+}
+
+fn to_ppm(pixels: &[u16]) -> Vec<u8> {
+    let upscale = |component: u16| ((component & 0x1F) << 3 | (component & 0x1F) >> 2) as u8;
+    let mut image = header();
+    for pixel in pixels.iter() {
+        image.extend([upscale(*pixel), upscale(*pixel >> 5), upscale(*pixel >> 10)]);
+    }
+    image
+}
+
+fn usage(program: &str) -> ! {
+    println!(
+        "Usage: {} <recording> [--output <image.ppm>] [--compare <image.ppm>]",
+        program
+    );
+    std::process::exit(2);
+}
@@ -0,0 +1,102 @@
+// Inspects and converts ROM dumps
+// Dumps can have an encrypted secure area like on a card, a decrypted one like most dumpers write,
+// or a destroyed one if the BIOS failed to decrypt it. The KEY1 tables come from the ARM7 BIOS.
+
+use std::fs;
+
+use nds_core::nds::Error;
+use nds_core::rom::{CrcCheck, RomTools};
+
+fn main() {
+    let args: Vec<_> = std::env::args().collect();
+    let mut bios7_path = "ROMs/bios7.bin";
+    let mut output_path = None;
+    let mut positional = Vec::new();
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--bios7" => match arg_iter.next() {
+                Some(path) => bios7_path = path,
+                None => usage(&args[0]),
+            },
+            "--output" => match arg_iter.next() {
+                Some(path) => output_path = Some(path),
+                None => usage(&args[0]),
+            },
+            _ => positional.push(arg.as_str()),
+        }
+    }
+    let (command, rom_path) = match positional[..] {
+        [command, rom_path] => (command, rom_path),
+        _ => usage(&args[0]),
+    };
+    let modify: fn(&mut RomTools, &mut [u8]) -> Result<(), Error> = match command {
+        "info" => |_, _| Ok(()),
+        "decrypt" => RomTools::decrypt_secure_area,
+        "encrypt" => RomTools::encrypt_secure_area,
+        "fix-crc" => RomTools::fix_secure_area_crc,
+        _ => usage(&args[0]),
+    };
+    if command != "info" && output_path.is_none() {
+        usage(&args[0]);
+    }
+
+    let read = |path: &str| match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", path, e);
+            std::process::exit(2);
+        }
+    };
+    let mut rom_tools = RomTools::new(&read(bios7_path)).unwrap_or_else(|e| fail(e));
+    let mut rom = read(rom_path);
+
+    if let Err(e) = modify(&mut rom_tools, &mut rom) {
+        fail(e);
+    }
+    if let Some(path) = output_path {
+        if let Err(e) = fs::write(path, &rom) {
+            eprintln!("Unable to write {}: {}", path, e);
+            std::process::exit(2);
+        }
+    }
+
+    let checks = rom_tools.check(&rom).unwrap_or_else(|e| fail(e));
+    println!("Secure area: {:?}", rom_tools.secure_area_state(&rom));
+    let mut valid = print_check("Header CRC", Some(checks.header));
+    valid &= print_check("Logo CRC", Some(checks.logo));
+    valid &= print_check("Secure area CRC", checks.secure_area);
+    if !checks.nintendo_logo {
+        println!("Logo isn't the Nintendo logo, so consoles won't boot it");
+    }
+    if !valid {
+        std::process::exit(1);
+    }
+}
+
+// A missing check can't be verified, so it doesn't count as a failure
+fn print_check(name: &str, check: Option<CrcCheck>) -> bool {
+    match check {
+        Some(check) if check.valid() => println!("{}: {:04X}", name, check.stored),
+        Some(check) => println!(
+            "{}: {:04X}, should be {:04X}",
+            name, check.stored, check.expected
+        ),
+        None => println!("{}: can't be checked", name),
+    }
+    check.map_or(true, |check| check.valid())
+}
+
+fn fail(e: Error) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(2);
+}
+
+fn usage(program: &str) -> ! {
+    println!(
+        "Usage: {} <info|decrypt|encrypt|fix-crc> <rom> [--bios7 <bios7.bin>] [--output <rom>]",
+        program
+    );
+    println!("decrypt, encrypt and fix-crc write the result to --output");
+    std::process::exit(2);
+}
@@ -0,0 +1,109 @@
+// Finds the first instruction where two traces diverge
+// Traces are in the format written by NDS::start_trace, and other emulators can be compared
+// by writing the same columns. Columns missing from a line aren't compared.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+const COLUMNS: [&str; 19] = [
+    "cycle", "pc", "opcode", "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10",
+    "r11", "r12", "sp", "lr", "cpsr",
+];
+
+fn main() {
+    let args: Vec<_> = std::env::args().collect();
+    let mut paths = Vec::new();
+    let mut ignored = Vec::new();
+    let mut context = 5;
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--ignore" => match arg_iter.next() {
+                Some(column) if COLUMNS.contains(&column.as_str()) => ignored.push(column.clone()),
+                _ => usage(&args[0]),
+            },
+            "--context" => match arg_iter.next().and_then(|lines| lines.parse().ok()) {
+                Some(lines) => context = lines,
+                None => usage(&args[0]),
+            },
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 2 {
+        usage(&args[0]);
+    }
+
+    let open = |path: &String| match File::open(path) {
+        Ok(file) => BufReader::new(file).lines(),
+        Err(e) => {
+            eprintln!("Unable to open {}: {}", path, e);
+            std::process::exit(2);
+        }
+    };
+    let (mut lines_a, mut lines_b) = (open(paths[0]), open(paths[1]));
+    let mut previous = VecDeque::new();
+    let mut line_num = 0;
+    loop {
+        line_num += 1;
+        let (line_a, line_b) = match (lines_a.next(), lines_b.next()) {
+            (Some(Ok(line_a)), Some(Ok(line_b))) => (line_a, line_b),
+            (None, None) => {
+                println!("Traces match for {} instructions", line_num - 1);
+                return;
+            }
+            (Some(Err(e)), _) | (_, Some(Err(e))) => {
+                eprintln!("Unable to read trace: {}", e);
+                std::process::exit(2);
+            }
+            (None, Some(_)) => finish_early(paths[0], line_num - 1),
+            (Some(_), None) => finish_early(paths[1], line_num - 1),
+        };
+
+        let differing: Vec<_> = columns(&line_a)
+            .zip(columns(&line_b))
+            .zip(COLUMNS)
+            .filter(|((a, b), name)| a != b && !ignored.iter().any(|column| column == name))
+            .map(|(_, name)| name)
+            .collect();
+        if !differing.is_empty() {
+            println!(
+                "Traces diverge at line {}: {}",
+                line_num,
+                differing.join(", ")
+            );
+            for (prev_line_num, line) in previous.iter() {
+                println!("  {:>8} {}", prev_line_num, line);
+            }
+            println!("a {:>8} {}", line_num, line_a);
+            println!("b {:>8} {}", line_num, line_b);
+            std::process::exit(1);
+        }
+
+        if context > 0 {
+            if previous.len() == context {
+                previous.pop_front();
+            }
+            previous.push_back((line_num, line_a));
+        }
+    }
+}
+
+fn finish_early(path: &str, instrs: usize) -> ! {
+    println!("{} ends after {} instructions", path, instrs);
+    std::process::exit(1);
+}
+
+// Everything after a ; is a comment
+fn columns(line: &str) -> std::str::SplitWhitespace<'_> {
+    line.split(';').next().unwrap_or("").split_whitespace()
+}
+
+fn usage(program: &str) -> ! {
+    println!(
+        "Usage: {} <trace A> <trace B> [--ignore <column>]... [--context <lines>]",
+        program
+    );
+    println!("Columns: {}", COLUMNS.join(" "));
+    std::process::exit(2);
+}
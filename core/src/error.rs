@@ -0,0 +1,83 @@
+use std::{fmt, io};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileKind {
+    Bios7,
+    Bios9,
+    Firmware,
+    Rom,
+    Save,
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FileKind::Bios7 => "ARM7 BIOS",
+            FileKind::Bios9 => "ARM9 BIOS",
+            FileKind::Firmware => "firmware",
+            FileKind::Rom => "ROM",
+            FileKind::Save => "save file",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    // Required input that wasn't given to the builder
+    Missing(FileKind),
+    Io {
+        file: FileKind,
+        source: io::Error,
+    },
+    // Sizes are in bytes
+    WrongSize {
+        file: FileKind,
+        size: usize,
+        expected: usize,
+    },
+    TooSmall {
+        file: FileKind,
+        size: usize,
+        min: usize,
+    },
+    InvalidRom(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Missing(file) => write!(f, "No {} was given", file),
+            Error::Io { file, source } => write!(f, "Couldn't access {}: {}", file, source),
+            Error::WrongSize {
+                file,
+                size,
+                expected,
+            } => write!(
+                f,
+                "{} is 0x{:X} bytes but should be 0x{:X}",
+                file, size, expected
+            ),
+            Error::TooSmall { file, size, min } => write!(
+                f,
+                "{} is 0x{:X} bytes but should be at least 0x{:X}",
+                file, size, min
+            ),
+            Error::InvalidRom(reason) => write!(f, "Invalid ROM: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    pub(crate) fn io(file: FileKind) -> impl FnOnce(io::Error) -> Error {
+        move |source| Error::Io { file, source }
+    }
+}
@@ -0,0 +1,566 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::nds::{Cpu, StopReason, WatchKind, NDS};
+
+// GDB Remote Serial Protocol server on localhost
+// The ARM9 is thread 1 and the ARM7 is thread 2
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    buffer: Vec<u8>,
+    running: bool,
+    // Thread selected by Hg for register accesses
+    reg_cpu: Cpu,
+    // Thread selected by Hc for stepping
+    step_cpu: Cpu,
+    // CPU to stop after one instruction once emulation resumes
+    pending_step: Option<Cpu>,
+    last_stop: (Cpu, Signal),
+}
+
+#[derive(Clone, Copy)]
+enum Signal {
+    Interrupt,
+    Trap(StopReason),
+}
+
+impl GdbServer {
+    const PACKET_SIZE: usize = 0x1000;
+    // Each byte takes two hex digits in the reply
+    const MAX_READ_LEN: u32 = GdbServer::PACKET_SIZE as u32 / 2;
+    // r0-r15, f0-f7, fps and cpsr in the legacy ARM layout
+    const NUM_REGS: u32 = 26;
+    const CPSR_REG: u32 = 25;
+
+    pub fn new(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        info!("GDB server listening on 127.0.0.1:{}", port);
+        Ok(GdbServer {
+            listener,
+            client: None,
+            buffer: Vec::new(),
+            running: true,
+            reg_cpu: Cpu::ARM9,
+            step_cpu: Cpu::ARM9,
+            pending_step: None,
+            last_stop: (Cpu::ARM9, Signal::Interrupt),
+        })
+    }
+
+    // Accepts a client and services its requests
+    pub fn poll(&mut self, nds: &mut NDS) {
+        self.accept();
+        if let Err(e) = self.handle_packets(nds) {
+            warn!("GDB connection closed: {}", e);
+            self.disconnect();
+        }
+    }
+
+    // The client decides when the CPUs run while it's attached
+    pub fn attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // Emulates a frame unless the client has the CPUs stopped
+    pub fn emulate_frame(&mut self, nds: &mut NDS) {
+        if !self.running {
+            return;
+        }
+        // A step can take more than one frame if the CPU is halted
+        let stop = match self.pending_step {
+            Some(cpu) => nds.step_instruction(cpu),
+            None => nds.emulate_frame(),
+        };
+        let cpu = match stop {
+            StopReason::FrameRendered => return,
+            StopReason::Breakpoint { cpu, .. }
+            | StopReason::Watchpoint { cpu, .. }
+            | StopReason::Step(cpu) => cpu,
+            StopReason::Halted => self.reg_cpu,
+        };
+        if self.client.is_some() {
+            self.stop(cpu, Signal::Trap(stop));
+        }
+    }
+
+    fn accept(&mut self) {
+        if self.client.is_some() {
+            return;
+        }
+        match self.listener.accept() {
+            Ok((stream, addr)) => {
+                info!("GDB connected from {}", addr);
+                self.client = Some(stream);
+                self.buffer.clear();
+                // GDB expects the target to be stopped when it attaches
+                self.running = false;
+                self.last_stop = (Cpu::ARM9, Signal::Interrupt);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(e) => warn!("Unable to accept GDB connection: {}", e),
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.client = None;
+        self.pending_step = None;
+        self.running = true;
+    }
+
+    fn handle_packets(&mut self, nds: &mut NDS) -> io::Result<()> {
+        let client = match self.client.as_mut() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        client.set_nonblocking(true)?;
+        let mut bytes = [0; 0x1000];
+        loop {
+            match client.read(&mut bytes) {
+                Ok(0) => return Err(ErrorKind::ConnectionAborted.into()),
+                Ok(len) => self.buffer.extend_from_slice(&bytes[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        client.set_nonblocking(false)?;
+
+        while !self.buffer.is_empty() && self.client.is_some() {
+            match self.buffer[0] {
+                b'+' | b'-' => {
+                    self.buffer.remove(0);
+                }
+                // Ctrl-C
+                0x03 => {
+                    self.buffer.remove(0);
+                    if self.running {
+                        self.stop(self.step_cpu, Signal::Interrupt);
+                    }
+                }
+                b'$' => {
+                    let end = match self.buffer.iter().position(|&byte| byte == b'#') {
+                        Some(end) if self.buffer.len() >= end + 3 => end,
+                        // Wait for the rest of the packet
+                        _ => break,
+                    };
+                    let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+                    let checksum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                    // The client sends the packet again after a NAK
+                    if checksum != Some(GdbServer::checksum(&packet[1..end])) {
+                        self.write_raw(b"-")?;
+                        continue;
+                    }
+                    let data = String::from_utf8_lossy(&packet[1..end]).into_owned();
+                    self.write_raw(b"+")?;
+                    if let Some(reply) = self.handle_packet(nds, &data) {
+                        self.send(&reply)?;
+                    }
+                }
+                _ => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns None for packets that resume emulation since they are answered when it stops
+    fn handle_packet(&mut self, nds: &mut NDS, packet: &str) -> Option<String> {
+        // The packet may contain replacement characters from invalid UTF-8
+        let split = packet.chars().next().map_or(0, char::len_utf8);
+        let (command, args) = packet.split_at(split);
+        Some(match command {
+            "?" => self.stop_reply(),
+            "q" => self.handle_query(args),
+            "H" => {
+                if let Some(cpu) = GdbServer::parse_thread(args.get(1..).unwrap_or("")) {
+                    if args.starts_with('g') {
+                        self.reg_cpu = cpu
+                    } else {
+                        self.step_cpu = cpu
+                    }
+                }
+                "OK".to_string()
+            }
+            "T" => match u32::from_str_radix(args, 16) {
+                Ok(1) | Ok(2) => "OK".to_string(),
+                _ => "E01".to_string(),
+            },
+            "g" => (0..GdbServer::NUM_REGS)
+                .map(|reg| self.read_reg(nds, reg))
+                .collect(),
+            "G" => {
+                let mut pos = 0;
+                for reg in 0..GdbServer::NUM_REGS {
+                    let len = GdbServer::reg_size(reg) * 2;
+                    if let Some(hex) = args.get(pos..pos + len) {
+                        self.write_reg(nds, reg, hex);
+                    }
+                    pos += len;
+                }
+                "OK".to_string()
+            }
+            "p" => match u32::from_str_radix(args, 16) {
+                Ok(reg) if reg < GdbServer::NUM_REGS => self.read_reg(nds, reg),
+                _ => "E01".to_string(),
+            },
+            "P" => match args.split_once('=') {
+                Some((reg, hex)) => match u32::from_str_radix(reg, 16) {
+                    Ok(reg) if reg < GdbServer::NUM_REGS => {
+                        self.write_reg(nds, reg, hex);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                },
+                None => "E01".to_string(),
+            },
+            "m" => match GdbServer::parse_range(args) {
+                // GDB accepts shorter replies and asks for the rest separately
+                Some((addr, len)) => (0..len.min(GdbServer::MAX_READ_LEN))
+                    .map(|i| {
+                        let value = nds.peek_cpu_mem(self.reg_cpu, addr.wrapping_add(i));
+                        format!("{:02x}", value)
+                    })
+                    .collect(),
+                None => "E01".to_string(),
+            },
+            "M" => match args
+                .split_once(':')
+                .and_then(|(range, hex)| Some((GdbServer::parse_range(range)?, hex)))
+            {
+                Some(((addr, len), hex)) => {
+                    let bytes = GdbServer::decode_hex(hex);
+                    for (i, value) in bytes.into_iter().take(len as usize).enumerate() {
+                        nds.write_cpu_mem(self.reg_cpu, addr.wrapping_add(i as u32), value);
+                    }
+                    "OK".to_string()
+                }
+                None => "E01".to_string(),
+            },
+            "c" => {
+                self.resume(nds, None);
+                return None;
+            }
+            "s" => {
+                self.resume(nds, Some(self.step_cpu));
+                return None;
+            }
+            "v" => return self.handle_v(nds, args),
+            "Z" | "z" => self.handle_breakpoint(nds, command == "Z", args),
+            "D" => {
+                self.send("OK").ok();
+                self.disconnect();
+                return None;
+            }
+            "k" => {
+                self.disconnect();
+                return None;
+            }
+            _ => String::new(),
+        })
+    }
+
+    fn handle_query(&self, args: &str) -> String {
+        let name = args.split([':', ',']).next().unwrap_or("");
+        match name {
+            "Supported" => format!("PacketSize={:x};vContSupported+", GdbServer::PACKET_SIZE),
+            "Attached" => "1".to_string(),
+            "C" => format!("QC{:x}", GdbServer::thread_id(self.reg_cpu)),
+            "fThreadInfo" => "m1,2".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            "ThreadExtraInfo" => {
+                let name = match args.split(',').nth(1).and_then(GdbServer::parse_thread) {
+                    Some(Cpu::ARM7) => "ARM7",
+                    _ => "ARM9",
+                };
+                GdbServer::encode_hex(name.as_bytes())
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn handle_v(&mut self, nds: &mut NDS, args: &str) -> Option<String> {
+        if args == "Cont?" {
+            return Some("vCont;c;C;s;S".to_string());
+        }
+        let actions = match args.strip_prefix("Cont;") {
+            Some(actions) => actions,
+            None => return Some(String::new()),
+        };
+        // Only one CPU can be stepped at a time, the other one keeps running
+        let step_cpu = actions.split(';').find_map(|action| {
+            let (action, thread) = action.split_once(':').unwrap_or((action, "-1"));
+            if action.starts_with('s') || action.starts_with('S') {
+                Some(GdbServer::parse_thread(thread).unwrap_or(self.step_cpu))
+            } else {
+                None
+            }
+        });
+        self.resume(nds, step_cpu);
+        None
+    }
+
+    fn handle_breakpoint(&mut self, nds: &mut NDS, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let kind = fields.next();
+        let addr = fields
+            .next()
+            .and_then(|addr| u32::from_str_radix(addr, 16).ok());
+        let len = fields
+            .next()
+            .and_then(|len| u32::from_str_radix(len, 16).ok());
+        let (addr, len) = match (addr, len) {
+            (Some(addr), Some(len)) => (addr, len),
+            _ => return "E01".to_string(),
+        };
+        let watch_kind = match kind {
+            // Software and hardware breakpoints are handled the same way
+            Some("0") | Some("1") => None,
+            Some("2") => Some(WatchKind::Write),
+            Some("3") => Some(WatchKind::Read),
+            Some("4") => Some(WatchKind::Access),
+            _ => return String::new(),
+        };
+        // Breakpoints go on the thread selected with Hg, but are removed from both CPUs in case
+        // another thread has been selected since
+        if insert {
+            match watch_kind {
+                None => nds.add_breakpoint(self.reg_cpu, addr & !0x1),
+                Some(kind) => nds.add_watchpoint(self.reg_cpu, addr, len, kind, None),
+            }
+        } else {
+            for cpu in [Cpu::ARM9, Cpu::ARM7] {
+                match watch_kind {
+                    None => nds.remove_breakpoint(cpu, addr & !0x1),
+                    Some(kind) => nds.remove_watchpoint(cpu, addr, len, kind),
+                }
+            }
+        }
+        "OK".to_string()
+    }
+
+    // Continuing also gets past a halt, so the debugger can be used to skip unimplemented behavior
+    fn resume(&mut self, nds: &mut NDS, step_cpu: Option<Cpu>) {
+        nds.resume();
+        self.pending_step = step_cpu;
+        self.running = true;
+    }
+
+    fn stop(&mut self, cpu: Cpu, signal: Signal) {
+        self.running = false;
+        self.pending_step = None;
+        self.last_stop = (cpu, signal);
+        self.reg_cpu = cpu;
+        self.step_cpu = cpu;
+        let reply = self.stop_reply();
+        if let Err(e) = self.send(&reply) {
+            warn!("GDB connection closed: {}", e);
+            self.disconnect();
+        }
+    }
+
+    fn stop_reply(&self) -> String {
+        let (cpu, signal) = self.last_stop;
+        let thread = GdbServer::thread_id(cpu);
+        match signal {
+            Signal::Interrupt => format!("T02thread:{:02x};", thread),
+            Signal::Trap(StopReason::Watchpoint { kind, addr, .. }) => {
+                let name = match kind {
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Write => "watch",
+                    WatchKind::Access => "awatch",
+                };
+                format!("T05{}:{:08x};thread:{:02x};", name, addr, thread)
+            }
+            // Reported as SIGABRT since the game did something that isn't emulated
+            Signal::Trap(StopReason::Halted) => format!("T06thread:{:02x};", thread),
+            Signal::Trap(_) => format!("T05thread:{:02x};", thread),
+        }
+    }
+
+    fn reg_size(reg: u32) -> usize {
+        match reg {
+            16..=23 => 12,
+            _ => 4,
+        }
+    }
+
+    fn read_reg(&self, nds: &mut NDS, reg: u32) -> String {
+        let value = match reg {
+            0..=15 => nds.cpu_reg(self.reg_cpu, reg),
+            GdbServer::CPSR_REG => nds.cpu_reg(self.reg_cpu, 16),
+            // There's no FPA, so those are always zero
+            _ => 0,
+        };
+        let mut bytes = value.to_le_bytes().to_vec();
+        bytes.resize(GdbServer::reg_size(reg), 0);
+        GdbServer::encode_hex(&bytes)
+    }
+
+    fn write_reg(&self, nds: &mut NDS, reg: u32, hex: &str) {
+        let mut bytes = GdbServer::decode_hex(hex);
+        bytes.resize(4, 0);
+        let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        match reg {
+            0..=15 => nds.set_cpu_reg(self.reg_cpu, reg, value),
+            GdbServer::CPSR_REG => nds.set_cpu_reg(self.reg_cpu, 16, value),
+            _ => (),
+        }
+    }
+
+    fn thread_id(cpu: Cpu) -> u32 {
+        match cpu {
+            Cpu::ARM9 => 1,
+            Cpu::ARM7 => 2,
+        }
+    }
+
+    // Returns None for "any thread"
+    fn parse_thread(thread: &str) -> Option<Cpu> {
+        match i64::from_str_radix(thread, 16) {
+            Ok(1) => Some(Cpu::ARM9),
+            Ok(2) => Some(Cpu::ARM7),
+            _ => None,
+        }
+    }
+
+    fn parse_range(range: &str) -> Option<(u32, u32)> {
+        let (addr, len) = range.split_once(',')?;
+        Some((
+            u32::from_str_radix(addr, 16).ok()?,
+            u32::from_str_radix(len, 16).ok()?,
+        ))
+    }
+
+    fn encode_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn decode_hex(hex: &str) -> Vec<u8> {
+        hex.as_bytes()
+            .chunks_exact(2)
+            .filter_map(|digits| {
+                let digits = std::str::from_utf8(digits).ok()?;
+                u8::from_str_radix(digits, 16).ok()
+            })
+            .collect()
+    }
+
+    fn checksum(data: &[u8]) -> u8 {
+        data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = GdbServer::checksum(data.as_bytes());
+        let packet = format!("${}#{:02x}", data, checksum);
+        self.write_raw(packet.as_bytes())
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.client.as_mut() {
+            Some(client) => client.write_all(bytes),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nds::NDSBuilder;
+
+    fn test_nds() -> NDS {
+        NDSBuilder::new()
+            .bios7(vec![0; 0x4000])
+            .bios9(vec![0; 0x1000])
+            .firmware(vec![0; 0x40000])
+            .rom(vec![0; 0x8000])
+            .build()
+            .unwrap()
+    }
+
+    // Sends raw bytes from a client and checks what the server replies
+    fn exchange(
+        server: &mut GdbServer,
+        nds: &mut NDS,
+        client: &mut TcpStream,
+        bytes: &[u8],
+        expected: &[u8],
+    ) {
+        client.write_all(bytes).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        server.poll(nds);
+        let mut reply = vec![0; expected.len()];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(reply, expected);
+    }
+
+    #[test]
+    fn packets_with_bad_checksums_are_rejected() {
+        let mut nds = test_nds();
+        let mut server = GdbServer::new(0).unwrap();
+        let mut client = TcpStream::connect(server.listener.local_addr().unwrap()).unwrap();
+        client
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        server.poll(&mut nds);
+        assert!(server.attached());
+
+        exchange(&mut server, &mut nds, &mut client, b"$?#00", b"-");
+        exchange(&mut server, &mut nds, &mut client, b"$?#3g", b"-");
+        exchange(
+            &mut server,
+            &mut nds,
+            &mut client,
+            b"$?#3f",
+            b"+$T02thread:01;#04",
+        );
+    }
+
+    #[test]
+    fn breakpoints_go_on_the_selected_thread() {
+        let mut nds = test_nds();
+        let mut server = GdbServer::new(0).unwrap();
+        server.handle_packet(&mut nds, "Hg2");
+        server.handle_packet(&mut nds, "Z0,2000001,2");
+        assert_eq!(nds.breakpoints(Cpu::ARM7), [0x0200_0000]);
+        assert!(nds.breakpoints(Cpu::ARM9).is_empty());
+
+        server.handle_packet(&mut nds, "Hg1");
+        server.handle_packet(&mut nds, "z0,2000001,2");
+        assert!(nds.breakpoints(Cpu::ARM7).is_empty());
+    }
+
+    #[test]
+    fn decode_hex_ignores_non_ascii() {
+        assert_eq!(GdbServer::decode_hex("12ab"), [0x12, 0xAB]);
+        assert_eq!(GdbServer::decode_hex("12\u{FFFD}"), [0x12]);
+        assert_eq!(GdbServer::decode_hex("1\u{FFFD}"), []);
+    }
+
+    #[test]
+    fn memory_reads_fit_in_a_packet() {
+        let mut nds = test_nds();
+        let mut server = GdbServer::new(0).unwrap();
+        let reply = server.handle_packet(&mut nds, "m2000000,ffffffff").unwrap();
+        assert_eq!(reply.len(), GdbServer::PACKET_SIZE);
+        let reply = server.handle_packet(&mut nds, "m2000000,4").unwrap();
+        assert_eq!(reply.len(), 8);
+    }
+
+    #[test]
+    fn non_ascii_packets_are_rejected() {
+        let mut nds = test_nds();
+        let mut server = GdbServer::new(0).unwrap();
+        let packet = String::from_utf8_lossy(b"\xFFg\xFE");
+        assert_eq!(server.handle_packet(&mut nds, &packet).as_deref(), Some(""));
+        let packet = String::from_utf8_lossy(b"Hg\xFF");
+        server.handle_packet(&mut nds, &packet);
+        let packet = String::from_utf8_lossy(b"M2000000,2:\xFF\xFE");
+        server.handle_packet(&mut nds, &packet);
+    }
+}
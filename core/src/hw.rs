@@ -10,26 +10,40 @@ mod rtc;
 mod scheduler;
 mod spi;
 mod spu;
+mod storage;
 mod timers;
+mod wifi;
 
 use std::convert::TryInto;
-use std::fs::File;
+use std::io::Write;
 
+use crate::error::{Error, FileKind};
 use crate::unlikely;
 use cartridge::Cartridge;
-pub use gpu::{EngineA, EngineB, GPU};
+pub use cartridge::{crc16, CrcCheck, RomChecks, RomTools, SecureAreaState};
+pub use gpu::debug::{OAMEntry, OBJAffine, OBJMode};
+pub use gpu::{
+    replay_geometry, EngineA, EngineB, Polygon, PolygonAttributes, PolygonMode,
+    TexCoordTransformationMode, TextureFormat, TextureParams, Vertex, GPU,
+};
 use interrupt_controller::{InterruptController, InterruptRequest};
 use ipc::IPC;
 pub use keypad::Key;
 use keypad::Keypad;
 use math::{Div, Sqrt};
-pub use mem::{AccessType, MemoryValue};
-use mem::{CP15, EXMEM, HALTCNT, POWCNT2, WRAMCNT};
+pub use mem::{
+    AccessType, IOMapEntry, IORegisterField, IORegisterGroup, IORegisterInfo, MemoryValue,
+    ARM7_IO_MAP, ARM9_IO_MAP,
+};
+use mem::{CodePages, CP15, EXMEM, HALTCNT, POWCNT2, WRAMCNT};
 use rtc::RTC;
 use scheduler::Scheduler;
 use spi::SPI;
 use spu::SPU;
+pub use storage::{FileStorage, MemoryStorage, Storage};
 use timers::Timers;
+use wifi::WiFi;
+pub use wifi::{LocalHub, LocalLink, UdpLink, WiFiLink};
 
 pub struct HW {
     // Memory
@@ -44,6 +58,7 @@ pub struct HW {
     shared_wram: Vec<u8>,
     arm7_page_table: Vec<*mut u8>,
     arm9_page_table: Vec<*mut u8>,
+    pub code_pages: CodePages,
     // Devices
     pub gpu: GPU,
     spu: SPU,
@@ -55,6 +70,7 @@ pub struct HW {
     ipc: IPC,
     rtc: RTC,
     spi: SPI,
+    wifi: WiFi,
     // Registers
     wramcnt: WRAMCNT,
     powcnt2: POWCNT2,
@@ -75,17 +91,31 @@ impl HW {
     const MAIN_MEM_SIZE: usize = 0x40_0000;
     const IWRAM_SIZE: usize = 0x1_0000;
     const SHARED_WRAM_SIZE: usize = 0x8000;
+    const BIOS7_SIZE: usize = 0x4000;
+    const BIOS9_SIZE: usize = 0x1000;
 
     pub fn new(
         bios7: Vec<u8>,
         bios9: Vec<u8>,
-        firmware_file: File,
+        firmware: Box<dyn Storage>,
         rom: Vec<u8>,
-        save_file: File,
+        save: Box<dyn Storage>,
         direct_boot: bool,
-    ) -> Self {
+    ) -> Result<Self, Error> {
+        for (file, bios, expected) in [
+            (FileKind::Bios7, &bios7, HW::BIOS7_SIZE),
+            (FileKind::Bios9, &bios9, HW::BIOS9_SIZE),
+        ] {
+            if bios.len() != expected {
+                return Err(Error::WrongSize {
+                    file,
+                    size: bios.len(),
+                    expected,
+                });
+            }
+        }
         let mut scheduler = Scheduler::new();
-        let cartridge = Cartridge::new(rom, save_file, &bios7);
+        let cartridge = Cartridge::new(rom, save, &bios7)?;
         let mut hw = HW {
             // Memory
             cp15: CP15::new(),
@@ -99,6 +129,7 @@ impl HW {
             shared_wram: vec![0; HW::SHARED_WRAM_SIZE],
             arm7_page_table: vec![std::ptr::null_mut(); HW::ARM7_PAGE_TABLE_SIZE],
             arm9_page_table: vec![std::ptr::null_mut(); HW::ARM9_PAGE_TABLE_SIZE],
+            code_pages: CodePages::new(),
             // Devices
             gpu: GPU::new(&mut scheduler),
             spu: SPU::new(&mut scheduler),
@@ -109,7 +140,8 @@ impl HW {
             timers: [Timers::new(false), Timers::new(true)],
             ipc: IPC::new(),
             rtc: RTC::new(),
-            spi: SPI::new(firmware_file),
+            spi: SPI::new(firmware)?,
+            wifi: WiFi::new(),
             // Registesr
             wramcnt: WRAMCNT::new(3),
             powcnt2: POWCNT2::new(),
@@ -125,12 +157,27 @@ impl HW {
         };
         hw.init_arm7_page_tables();
         hw.init_arm9_page_tables();
-        if direct_boot {
+        Ok(if direct_boot {
+            hw.cartridge.direct_boot();
             hw.init_mem()
         } else {
             hw.cartridge.encrypt_secure_area();
             hw
-        }
+        })
+    }
+
+    // Blank BIOSes, firmware and ROM for tests that drive the hardware directly
+    #[cfg(test)]
+    pub fn new_for_tests() -> Self {
+        HW::new(
+            vec![0; HW::BIOS7_SIZE],
+            vec![0; HW::BIOS9_SIZE],
+            Box::new(MemoryStorage::new(vec![0; SPI::FIRMWARE_SIZE])),
+            vec![0; 0x200],
+            Box::new(MemoryStorage::default()),
+            false,
+        )
+        .unwrap()
     }
 
     pub fn clock_until(&mut self, target: usize) {
@@ -173,6 +220,14 @@ impl HW {
         self.spi.release_screen();
     }
 
+    pub fn save_data(&self) -> &[u8] {
+        self.cartridge.save_data()
+    }
+
+    pub fn firmware_data(&self) -> &[u8] {
+        self.spi.firmware_data()
+    }
+
     pub fn render_palettes(
         &self,
         extended: bool,
@@ -233,6 +288,36 @@ impl HW {
         }
     }
 
+    pub fn frame_polygons(&self) -> &[Polygon] {
+        self.gpu.engine3d.frame_polygons()
+    }
+
+    pub fn frame_vertices(&self) -> &[Vertex] {
+        self.gpu.engine3d.frame_vertices()
+    }
+
+    pub fn render_texture(&self, polygon_i: usize) -> (Vec<u16>, usize, usize) {
+        self.gpu.engine3d.render_texture(&self.gpu.vram, polygon_i)
+    }
+
+    pub fn record_geometry_frame(&mut self, writer: Box<dyn Write>) {
+        self.gpu.engine3d.record_frame(writer)
+    }
+
+    pub fn oam_entry(&self, engine: Engine, obj_i: usize) -> OAMEntry {
+        match engine {
+            Engine::A => self.gpu.engine_a.oam_entry(obj_i),
+            Engine::B => self.gpu.engine_b.oam_entry(obj_i),
+        }
+    }
+
+    pub fn render_obj(&self, engine: Engine, obj_i: usize) -> (Vec<u16>, usize, usize) {
+        match engine {
+            Engine::A => self.gpu.engine_a.render_obj(&self.gpu.vram, obj_i),
+            Engine::B => self.gpu.engine_b.render_obj(&self.gpu.vram, obj_i),
+        }
+    }
+
     pub fn render_tiles(
         &self,
         engine: Engine,
@@ -1,21 +1,26 @@
 mod backup;
 mod header;
 mod key1_encryption;
+mod key2_encryption;
+mod secure_area;
 
 use std::collections::VecDeque;
 use std::convert::TryInto;
-use std::fs::File;
 use std::ops::Range;
 
 use super::{
     dma,
     interrupt_controller::InterruptRequest,
     scheduler::{Event, Scheduler},
-    HW,
+    Storage, HW,
 };
+use crate::error::{Error, FileKind};
 
-use header::Header;
+use header::{Header, UnitCode};
 use key1_encryption::Key1Encryption;
+use key2_encryption::Key2Encryption;
+
+pub use secure_area::{crc16, CrcCheck, RomChecks, RomTools, SecureAreaState};
 
 pub(super) use backup::{Backup, Flash}; // For Firmware
 
@@ -24,10 +29,15 @@ pub struct Cartridge {
     header: Header,
     rom: Vec<u8>,
     key1_encryption: Key1Encryption,
+    mode: Mode,
+    // The card's KEY2 state, which is set up by a KEY1 command
+    card_key2: Option<Key2Encryption>,
     // Registers
     pub spicnt: SPICNT,
     romctrl: ROMCTRL,
     command: [u8; 8],
+    seeds: [u64; 2],
+    key2: Key2Encryption,
     cur_game_card_word: u32,
     // Data Transfer
     rom_bytes_left: usize,
@@ -37,66 +47,90 @@ pub struct Cartridge {
 }
 
 impl Cartridge {
-    const DESTROYED_SECURE_AREA_ID: u32 = 0xE7FFDEFF;
-    const DECRYPTED_SECURE_AREA_ID: &'static str = "encryObj";
-    const SECURE_AREA_RANGE: Range<usize> = 0x4000..0x8000;
-    const SECURE_AREA_SIZE: usize = 0x800;
-
-    pub fn new(rom: Vec<u8>, save_file: File, bios7: &[u8]) -> Self {
+    const HEADER_SIZE: usize = 0x200;
+
+    pub fn new(rom: Vec<u8>, save: Box<dyn Storage>, bios7: &[u8]) -> Result<Self, Error> {
+        if rom.len() < Self::HEADER_SIZE {
+            return Err(Error::TooSmall {
+                file: FileKind::Rom,
+                size: rom.len(),
+                min: Self::HEADER_SIZE,
+            });
+        }
         let header = Header::new(&rom);
-        let backup = <dyn Backup>::detect_type(&header, save_file);
+        let in_rom = |offset: u32, size: u32| {
+            (offset as usize)
+                .checked_add(size as usize)
+                .is_some_and(|end| end <= rom.len())
+        };
+        if !in_rom(header.arm9_rom_offset, header.arm9_size) {
+            return Err(Error::InvalidRom("ARM9 binary is outside of the ROM"));
+        }
+        if !in_rom(header.arm7_rom_offset, header.arm7_size) {
+            return Err(Error::InvalidRom("ARM7 binary is outside of the ROM"));
+        }
+        let backup = <dyn Backup>::detect_type(&header, save).map_err(Error::io(FileKind::Save))?;
 
-        Cartridge {
-            chip_id: 0x000_00FC2u32, // TODO: Actually Calculate
+        Ok(Cartridge {
+            chip_id: Cartridge::calc_chip_id(&header, rom.len()),
             header,
             rom,
             key1_encryption: Key1Encryption::new(bios7),
+            mode: Mode::Raw,
+            card_key2: None,
             // Registers
             spicnt: SPICNT::new(),
             romctrl: ROMCTRL::new(),
             command: [0; 8],
+            seeds: [0; 2],
+            key2: Key2Encryption::default(),
             cur_game_card_word: 0,
             // Data Transfer
             rom_bytes_left: 0,
             game_card_words: VecDeque::new(),
             backup,
-        }
+        })
     }
 
-    pub fn encrypt_secure_area(&mut self) {
-        let start = self.header.arm9_rom_offset as usize;
-        if !Self::SECURE_AREA_RANGE.contains(&start) {
-            return;
-        }
+    fn calc_chip_id(header: &Header, rom_len: usize) -> u32 {
+        // The ROM maker can't be told from the header, so use Macronix like most retail cards
+        let manufacturer = 0xC2;
+        // Dumps are often trimmed, so the capacity in the header is used when it's bigger
+        let size = 0x2_0000usize
+            .checked_shl(header.device_capacity as u32)
+            .unwrap_or(0)
+            .max(rom_len.next_power_of_two());
+        let size_mb = size >> 20;
+        let size_id = if size_mb <= 128 {
+            size_mb.max(1) - 1
+        } else {
+            0x100 - (size_mb >> 8).min(0x10)
+        };
+        // Cards of 1GB and up use a newer protocol
+        let new_protocol = size_mb >= 0x400;
+        let dsi = matches!(header.unit_code, UnitCode::Both | UnitCode::DSi);
+        (new_protocol as u32) << 31 | (dsi as u32) << 30 | (size_id as u32) << 8 | manufacturer
+    }
 
-        let secure_area_range = || start..start + Self::SECURE_AREA_SIZE;
-        let secure_area = &mut self.rom[secure_area_range()];
-        let secure_area_32: &[u32] = bytemuck::cast_slice(&secure_area);
-        // Check secure area exists
-        for i in 0..3 {
-            if secure_area_32[i] != Self::DESTROYED_SECURE_AREA_ID {
-                return;
-            }
-        }
-        if secure_area[0xC] != 0xFF || secure_area[0xD] != 0xDE {
-            return;
-        }
+    // Leaves the card how the BIOS does after booting it
+    pub fn direct_boot(&mut self) {
+        // The BIOS picks mmmnnn randomly and both sides use it, so any value works
+        let mmmnnn = 0;
+        self.seeds = [
+            Key2Encryption::seed0(mmmnnn, self.header.encryption_seed),
+            Key2Encryption::SEED1,
+        ];
+        self.key2 = Key2Encryption::new(self.seeds[0], self.seeds[1]);
+        self.card_key2 = Some(self.key2);
+        self.mode = Mode::Key2;
+    }
 
-        // First 8 bytes over secure area is overwritten by BIOS after decryption, so put correct string
-        secure_area[..0x8].copy_from_slice(Self::DECRYPTED_SECURE_AREA_ID.as_bytes());
-        let secure_area_32: &mut [u32] =
-            bytemuck::cast_slice_mut(&mut self.rom[secure_area_range()]);
-        // Level 3 for entire secure area
-        self.key1_encryption
-            .init_key_code(self.header.game_code, 3, 2);
-        for chunk in secure_area_32.chunks_exact_mut(2) {
-            self.key1_encryption.encrypt(chunk);
+    // Most dumps have the secure area decrypted, but the BIOS expects it to be encrypted
+    pub fn encrypt_secure_area(&mut self) {
+        let mut rom_tools = RomTools::with_encryption(self.key1_encryption.clone());
+        if rom_tools.secure_area_state(&self.rom) == SecureAreaState::Decrypted {
+            rom_tools.encrypt_secure_area(&mut self.rom).unwrap();
         }
-        // Level 2 for first 8 bytes (first 8 bytes encrypted twice)
-        self.key1_encryption
-            .init_key_code(self.header.game_code, 2, 2);
-        self.key1_encryption.encrypt(&mut secure_area_32[..0x8]);
-        self.key1_encryption.in_use = false;
     }
 
     pub fn run_command(&mut self, scheduler: &mut Scheduler, is_arm9: bool) {
@@ -120,10 +154,32 @@ impl Cartridge {
         self.romctrl.data_word_ready = false;
         self.game_card_words.clear();
 
-        if self.key1_encryption.in_use {
-            self.run_encrypted_command();
-        } else {
-            self.run_unencrypted_command();
+        // The console encrypts commands and the card decrypts them, and the reverse for data
+        let mut command = self.command;
+        // KEY2 starts with the data after the command that activates it
+        let card_encrypts_data = self.card_key2.is_some();
+        if self.romctrl.key2_encrypt_cmd {
+            self.key2.apply(&mut command);
+        }
+        match self.mode {
+            Mode::Raw => self.run_raw_command(command),
+            Mode::Key1 => self.run_key1_command(command),
+            Mode::Key2 => {
+                if let Some(card_key2) = self.card_key2.as_mut() {
+                    card_key2.apply(&mut command);
+                }
+                self.run_key2_command(command)
+            }
+        }
+        if let Some(card_key2) = self.card_key2.as_mut().filter(|_| card_encrypts_data) {
+            for word in self.game_card_words.iter_mut() {
+                *word = card_key2.apply_word(*word);
+            }
+        }
+        if self.romctrl.key2_encrypt_data {
+            for word in self.game_card_words.iter_mut() {
+                *word = self.key2.apply_word(*word);
+            }
         }
 
         // TODO: Take into account WR bit
@@ -146,21 +202,33 @@ impl Cartridge {
 
     fn copy_rom(&mut self, range: Range<usize>) {
         for addr in range.step_by(4) {
-            self.game_card_words.push_back(u32::from_le_bytes(
-                self.rom[addr..addr + 4].try_into().unwrap(),
-            ));
+            self.game_card_words.push_back(self.rom_word(addr));
+        }
+    }
+
+    // Dumps are often trimmed, and the trimmed part is 0xFF
+    fn rom_word(&self, addr: usize) -> u32 {
+        match self.rom.get(addr..addr + 4) {
+            Some(word) => u32::from_le_bytes(word.try_into().unwrap()),
+            None => 0xFFFF_FFFF,
+        }
+    }
+
+    fn check_unused_params(command: &[u8; 8], start: usize) {
+        if command[start..].iter().any(|&byte| byte != 0) {
+            unimplemented_behavior!("Cartridge Command with unused parameters: {:X?}", command);
         }
     }
 
-    pub fn run_encrypted_command(&mut self) {
+    fn run_key1_command(&mut self, mut command: [u8; 8]) {
         // Command is in given as big endian, but the decryption works with little endian
-        self.command.reverse();
+        command.reverse();
         self.key1_encryption
-            .decrypt(bytemuck::cast_slice_mut(&mut self.command));
-        self.command.reverse();
+            .decrypt(bytemuck::cast_slice_mut(&mut command));
+        command.reverse();
 
         // TODO: Verify command parameters
-        match self.command[0] >> 4 {
+        match command[0] >> 4 {
             0x1 => {
                 // 0x910 dummy bytes and 4 bytes of chip id
                 // But making them all chip id works anyway
@@ -169,23 +237,33 @@ impl Cartridge {
                 }
             }
             0x2 => {
-                let addr = (self.command[2] as usize & 0xF0) << (12 - 4)
-                    | (self.command[1] as usize) << 8
-                    | self.command[0] as usize & 0x0F;
-                assert!((0x4000..=0x7000).contains(&addr));
-                assert_eq!(addr & 0xFFF, 0);
-                assert_eq!(self.rom_bytes_left, 0x1000);
+                let addr = (command[2] as usize & 0xF0) << (12 - 4)
+                    | (command[1] as usize) << 8
+                    | command[0] as usize & 0x0F;
+                if !(0x4000..=0x7000).contains(&addr)
+                    || addr & 0xFFF != 0
+                    || self.rom_bytes_left != 0x1000
+                {
+                    unimplemented_behavior!(
+                        "Secure Area Read of 0x{:X} bytes at 0x{:X}",
+                        self.rom_bytes_left,
+                        addr
+                    );
+                }
                 self.copy_rom(addr..addr + self.rom_bytes_left);
             }
             0x4 => {
+                // 4llllmmmnnnkkkkk
+                let mmmnnn = (u64::from_be_bytes(command) >> 20) as u32;
+                let seed0 = Key2Encryption::seed0(mmmnnn, self.header.encryption_seed);
                 // Endless stream of HIGH-Z bytes?
                 for _ in 0..self.rom_bytes_left / 4 {
                     self.game_card_words.push_back(0xFFFF_FFFF);
                 }
+                self.card_key2 = Some(Key2Encryption::new(seed0, Key2Encryption::SEED1));
             }
             0xA => {
-                // TODO: Add 3rd mode instead of using same mode for boot and key2 encrypted
-                self.key1_encryption.in_use = false;
+                self.mode = Mode::Key2;
                 // 0x910 dummy bytes followed by KEY2 encrypted 0s
                 // Making them all 0s works
                 for _ in 0..self.rom_bytes_left / 4 {
@@ -193,10 +271,7 @@ impl Cartridge {
                 }
             }
             _ => {
-                warn!(
-                    "Unimplemented Encrypted Cartridge Command: {:X?}",
-                    self.command
-                );
+                warn!("Unimplemented KEY1 Cartridge Command: {:X?}", command);
                 for _ in 0..self.rom_bytes_left / 4 {
                     self.game_card_words.push_back(0);
                 }
@@ -204,25 +279,48 @@ impl Cartridge {
         }
     }
 
-    pub fn run_unencrypted_command(&mut self) {
-        match self.command[0] {
+    fn run_raw_command(&mut self, command: [u8; 8]) {
+        match command[0] {
             0x00 => {
-                for byte in self.command[1..].iter() {
-                    assert_eq!(*byte, 0)
+                Cartridge::check_unused_params(&command, 1);
+                // The first 4 KB are repeated
+                for addr in (0..self.rom_bytes_left).step_by(4) {
+                    self.game_card_words.push_back(self.rom_word(addr & 0xFFF));
                 }
-                assert!(self.rom_bytes_left < 0x1000); // TODO: Support
-                self.copy_rom(0..self.rom_bytes_left);
             }
             0x3C => {
                 self.key1_encryption
                     .init_key_code(self.header.game_code, 2, 2);
+                self.mode = Mode::Key1;
             }
-            0xB7 => {
-                for byte in self.command[5..].iter() {
-                    assert_eq!(*byte, 0)
+            0x90 => {
+                // Chip ID is repeated
+                for _ in 0..self.rom_bytes_left / 4 {
+                    self.game_card_words.push_back(self.chip_id);
                 }
-                let addr = u32::from_be_bytes(self.command[1..=4].try_into().unwrap()) as usize;
-                assert!(addr + self.rom_bytes_left < self.rom.len()); // TODO: Handle mirroring later
+            }
+            0x9F => {
+                // Endless stream of HIGH-Z bytes
+                Cartridge::check_unused_params(&command, 1);
+                for _ in 0..self.rom_bytes_left / 4 {
+                    self.game_card_words.push_back(0xFFFF_FFFF);
+                }
+            }
+            _ => {
+                warn!("Unimplemented Raw Cartridge Command: {:X?}", command);
+                for _ in 0..self.rom_bytes_left / 4 {
+                    self.game_card_words.push_back(0);
+                }
+            }
+        };
+    }
+
+    fn run_key2_command(&mut self, command: [u8; 8]) {
+        match command[0] {
+            0xB7 => {
+                Cartridge::check_unused_params(&command, 5);
+                // TODO: Mirror addresses past the chip's capacity
+                let addr = u32::from_be_bytes(command[1..=4].try_into().unwrap()) as usize;
                 let addr = if addr < 0x8000 {
                     0x8000 + (addr & 0x1FFF)
                 } else {
@@ -241,34 +339,14 @@ impl Cartridge {
                 }
             }
             0xB8 => {
-                for byte in self.command[1..].iter() {
-                    assert_eq!(*byte, 0)
-                }
+                Cartridge::check_unused_params(&command, 1);
                 // Chip ID is repeated
                 for _ in 0..self.rom_bytes_left / 4 {
                     self.game_card_words.push_back(self.chip_id);
                 }
             }
-            0x90 => {
-                // Chip ID is repeated
-                for _ in 0..self.rom_bytes_left / 4 {
-                    self.game_card_words.push_back(self.chip_id);
-                }
-            }
-            0x9F => {
-                // Endless stream of HIGH-Z bytes
-                for byte in self.command[1..].iter() {
-                    assert_eq!(*byte, 0)
-                }
-                for _ in 0..self.rom_bytes_left / 4 {
-                    self.game_card_words.push_back(0xFFFF_FFFF);
-                }
-            }
             _ => {
-                warn!(
-                    "Unimplemented Unencrypted Cartridge Command: {:X?}",
-                    self.command
-                );
+                warn!("Unimplemented KEY2 Cartridge Command: {:X?}", command);
                 for _ in 0..self.rom_bytes_left / 4 {
                     self.game_card_words.push_back(0);
                 }
@@ -344,11 +422,31 @@ impl Cartridge {
         byte: usize,
         value: u8,
     ) {
-        if self.romctrl.write(has_access, byte, value) {
+        let start = self.romctrl.write(has_access, byte, value);
+        if self.romctrl.key2_apply_seed {
+            self.romctrl.key2_apply_seed = false;
+            self.key2 = Key2Encryption::new(self.seeds[0], self.seeds[1]);
+        }
+        if start {
             self.run_command(scheduler, is_arm9)
         }
     }
 
+    // Seed 0 and 1 low words are at 0x0 and 0x4, and their upper 7 bits are at 0x8 and 0xA
+    pub fn write_seed(&mut self, has_access: bool, byte: usize, value: u8) {
+        if !has_access {
+            warn!("No Write Access to ROM Seed");
+            return;
+        }
+        let (seed_i, shift) = match byte {
+            0x0..=0x7 => (byte / 4, byte % 4 * 8),
+            0x8..=0xB => ((byte - 8) / 2, 32 + byte % 2 * 8),
+            _ => unreachable!(),
+        };
+        let seed = &mut self.seeds[seed_i];
+        *seed = (*seed & !(0xFF << shift) | (value as u64) << shift) & Key2Encryption::SEED_MASK;
+    }
+
     pub fn chip_id(&self) -> u32 {
         self.chip_id
     }
@@ -358,6 +456,9 @@ impl Cartridge {
     pub fn header(&self) -> &Header {
         &self.header
     }
+    pub fn save_data(&self) -> &[u8] {
+        self.backup.data()
+    }
 
     fn transfer_byte_time(&self) -> usize {
         if self.romctrl.transfer_clk_rate {
@@ -392,6 +493,13 @@ impl HW {
     }
 }
 
+#[derive(Clone, Copy)]
+enum Mode {
+    Raw,
+    Key1,
+    Key2,
+}
+
 pub struct SPICNT {
     // Registers
     baudrate: u8,
@@ -456,7 +564,7 @@ impl SPICNT {
 pub struct ROMCTRL {
     key1_gap1_len: u16,
     key2_encrypt_data: bool,
-    _key2_apply_seed: bool,
+    key2_apply_seed: bool,
     key1_gap2_len: u8,
     key2_encrypt_cmd: bool,
     data_word_ready: bool,
@@ -473,7 +581,7 @@ impl ROMCTRL {
         ROMCTRL {
             key1_gap1_len: 0,
             key2_encrypt_data: false,
-            _key2_apply_seed: false,
+            key2_apply_seed: false,
             key1_gap2_len: 0,
             key2_encrypt_cmd: false,
             data_word_ready: false,
@@ -526,9 +634,9 @@ impl ROMCTRL {
         match byte {
             0 => self.key1_gap1_len = self.key1_gap1_len & !0xFF | value as u16,
             1 => {
-                self.key1_gap1_len = self.key1_gap1_len & !0x1F00 | (value as u16 & 0x1F) << 4;
+                self.key1_gap1_len = self.key1_gap1_len & !0x1F00 | (value as u16 & 0x1F) << 8;
                 self.key2_encrypt_data = value >> 5 & 0x1 != 0;
-                self._key2_apply_seed = value >> 7 & 0x1 != 0;
+                self.key2_apply_seed = value >> 7 & 0x1 != 0;
             }
             2 => {
                 self.key1_gap2_len = value & 0x3F;
@@ -548,3 +656,43 @@ impl ROMCTRL {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(unit_code: u8, device_capacity: u8) -> Header {
+        let mut rom = vec![0; 0x200];
+        rom[0x012] = unit_code;
+        rom[0x014] = device_capacity;
+        Header::new(&rom)
+    }
+
+    // Sizes up to 128MB are (N + 1)MB, and bigger ones are (0x100 - N) * 256MB
+    #[test]
+    fn chip_ids_encode_the_card_size() {
+        const MB: usize = 1 << 20;
+        for (device_capacity, rom_len, chip_id) in [
+            (0, 0x200, 0x0000_00C2),
+            (6, 8 * MB, 0x0000_07C2),
+            // Trimmed dumps use the capacity in the header
+            (9, 8 * MB, 0x0000_3FC2),
+            // And untrimmed ones are used when the header is too small
+            (0, 3 * MB, 0x0000_03C2),
+            (10, 128 * MB, 0x0000_7FC2),
+            (11, 256 * MB, 0x0000_FFC2),
+            (12, 512 * MB, 0x0000_FEC2),
+            // 1GB cards use the newer protocol
+            (13, 1024 * MB, 0x8000_FCC2),
+        ] {
+            let header = header(0, device_capacity);
+            assert_eq!(Cartridge::calc_chip_id(&header, rom_len), chip_id);
+        }
+    }
+
+    #[test]
+    fn chip_ids_flag_dsi_cards() {
+        assert_eq!(Cartridge::calc_chip_id(&header(2, 7), 0x200), 0x4000_0FC2);
+        assert_eq!(Cartridge::calc_chip_id(&header(3, 7), 0x200), 0x4000_0FC2);
+    }
+}
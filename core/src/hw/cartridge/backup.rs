@@ -3,10 +3,10 @@ mod flash;
 mod game_db;
 mod no_backup;
 
-use memmap::{MmapMut, MmapOptions};
-use std::{fs::File, io::Write};
+use std::io;
 
 use super::Header;
+use crate::hw::Storage;
 
 use eeprom::{EEPROMNormal, EEPROMSmall, EEPROM};
 pub use flash::Flash;
@@ -15,34 +15,31 @@ use no_backup::NoBackup;
 pub trait Backup {
     fn read(&self) -> u8;
     fn write(&mut self, hold: bool, value: u8);
+    fn data(&self) -> &[u8];
 }
 
 impl dyn Backup {
-    pub fn detect_type(header: &Header, save_file: File) -> Box<dyn Backup> {
+    pub fn detect_type(header: &Header, save: Box<dyn Storage>) -> io::Result<Box<dyn Backup>> {
         if let Some(pos) = <dyn Backup>::GAME_DB
             .iter()
             .position(|game_info| game_info.game_code == header.game_code)
         {
             let game_info = &<dyn Backup>::GAME_DB[pos];
             let sram_size = <dyn Backup>::SRAM_SIZES[game_info.sram_type];
-            match game_info.sram_type {
-                1 => Box::new(EEPROM::<EEPROMSmall>::new(save_file, sram_size)),
-                2..=4 => Box::new(EEPROM::<EEPROMNormal>::new(save_file, sram_size)),
-                5..=8 => Box::new(Flash::new_backup(save_file, sram_size)),
-                _ => todo!(),
-            }
+            Ok(match game_info.sram_type {
+                0 => Box::new(NoBackup::new()),
+                1 => Box::new(EEPROM::<EEPROMSmall>::new(save, sram_size)?),
+                2..=4 => Box::new(EEPROM::<EEPROMNormal>::new(save, sram_size)?),
+                5..=8 => Box::new(Flash::new_backup(save, sram_size)?),
+                sram_type => {
+                    // NAND saves and types the DB doesn't know
+                    warn!("Unsupported save type 0x{:X}", sram_type);
+                    Box::new(NoBackup::new())
+                }
+            })
         } else {
             warn!("Game not found in DB!");
-            Box::new(NoBackup::new())
+            Ok(Box::new(NoBackup::new()))
         }
     }
-
-    fn mmap(save_file: File, default_val: u8, size: usize) -> MmapMut {
-        let mut save_file = save_file;
-        if save_file.metadata().unwrap().len() as usize != size {
-            save_file.write_all(&vec![default_val; size]).unwrap();
-        }
-
-        unsafe { MmapOptions::new().map_mut(&save_file).unwrap() }
-    }
 }
@@ -1,12 +1,12 @@
-use memmap::MmapMut;
-use std::fs::File;
+use std::io;
 use std::marker::PhantomData;
 
 use super::Backup;
+use crate::hw::Storage;
 
 pub struct EEPROM<T: EEPROMType> {
     eeprom_type: PhantomData<T>,
-    mem: MmapMut,
+    mem: Box<dyn Storage>,
 
     mode: Mode,
     value: u8,
@@ -16,17 +16,18 @@ pub struct EEPROM<T: EEPROMType> {
 }
 
 impl<T: EEPROMType> EEPROM<T> {
-    pub fn new(save_file: File, size: usize) -> EEPROM<T> {
-        EEPROM {
+    pub fn new(mut mem: Box<dyn Storage>, size: usize) -> io::Result<EEPROM<T>> {
+        mem.init(size, 0)?;
+        Ok(EEPROM {
             eeprom_type: PhantomData,
-            mem: <dyn Backup>::mmap(save_file, 0, size),
+            mem,
 
             mode: Mode::ReadCommand,
             value: 0,
             // Status Reg
             write_enable: false,
             write_protect: WriteProtect::None,
-        }
+        })
     }
 
     fn set_command(&mut self, command: Command) -> Mode {
@@ -41,9 +42,10 @@ impl<T: EEPROMType> EEPROM<T> {
 
     fn handle_command(&mut self, command: Command, value: u8) -> Mode {
         match command {
+            // Bytes written while reading are ignored, and addresses wrap around the chip
             Command::RD(0, addr) => {
-                assert_eq!(value, 0);
-                self.value = self.mem[addr];
+                let mem = self.mem.data();
+                self.value = mem[addr % mem.len()];
                 Mode::HandleCommand(Command::RD(0, addr + 1))
             }
             Command::RD(addr_bytes_left, addr) => {
@@ -52,7 +54,9 @@ impl<T: EEPROMType> EEPROM<T> {
 
             Command::WR(0, addr) => {
                 if self.write_enable {
-                    self.mem[addr] = value
+                    let mem = self.mem.data_mut();
+                    let len = mem.len();
+                    mem[addr % len] = value
                 }
                 Mode::HandleCommand(Command::WR(0, addr + 1))
             }
@@ -61,7 +65,6 @@ impl<T: EEPROMType> EEPROM<T> {
             }
 
             Command::RDSR => {
-                assert_eq!(value, 0);
                 // TODO: Figure out Write in Progress needs to be emulated
                 let low_nibble = (self.write_protect as u8) << 2 | (self.write_enable as u8) << 1;
                 // TODO: Figure out what SWRD Status Register is
@@ -83,19 +86,32 @@ impl<T: EEPROMType> Backup for EEPROM<T> {
     fn write(&mut self, hold: bool, value: u8) {
         self.mode = match self.mode {
             Mode::ReadCommand if value == 0 => return,
-            Mode::ReadCommand => self.set_command(Command::get::<T>(value)),
+            Mode::ReadCommand => match Command::get::<T>(value) {
+                Some(command) => self.set_command(command),
+                None => {
+                    unimplemented_behavior!("{} EEPROM Command: 0x{:X}", T::debug_str(), value);
+                    Mode::Ignore
+                }
+            },
             Mode::HandleCommand(command) => self.handle_command(command, value),
+            Mode::Ignore => Mode::Ignore,
         };
         if !hold {
             self.mode = Mode::ReadCommand
         }
     }
+
+    fn data(&self) -> &[u8] {
+        self.mem.data()
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 enum Mode {
     ReadCommand,
     HandleCommand(Command),
+    // Skips the rest of an unknown command
+    Ignore,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -107,8 +123,8 @@ enum Command {
 }
 
 impl Command {
-    fn get<T: EEPROMType>(value: u8) -> Self {
-        match value {
+    fn get<T: EEPROMType>(value: u8) -> Option<Self> {
+        Some(match value {
             0x02 if T::is_small() => Command::WR(1, 0), // WRLO
             0x03 if T::is_small() => Command::RD(1, 0), // RDLO
             0x02 => Command::WR(2, 0),
@@ -117,8 +133,8 @@ impl Command {
             0x06 => Command::WREN,
             0x0A if T::is_small() => Command::WR(1, 1), // WRHI
             0x0B if T::is_small() => Command::RD(1, 1), // RDHI
-            _ => unimplemented!("{} EEPROM Command: 0x{:X}", T::debug_str(), value),
-        }
+            _ => return None,
+        })
     }
 }
 
@@ -1,10 +1,10 @@
-use memmap::MmapMut;
-use std::fs::File;
+use std::io;
 
 use super::Backup;
+use crate::hw::Storage;
 
 pub struct Flash {
-    mem: MmapMut,
+    mem: Box<dyn Storage>,
 
     mode: Mode,
     value: u8,
@@ -13,18 +13,19 @@ pub struct Flash {
 }
 
 impl Flash {
-    pub fn new_backup(save_file: File, size: usize) -> Self {
-        Flash {
-            mem: <dyn Backup>::mmap(save_file, 0xFF, size),
+    pub fn new_backup(mut mem: Box<dyn Storage>, size: usize) -> io::Result<Self> {
+        mem.init(size, 0xFF)?;
+        Ok(Flash {
+            mem,
 
             mode: Mode::ReadInstr,
             value: 0,
             // Status Reg
             write_enable: false,
-        }
+        })
     }
 
-    pub fn new_firmware(mem: MmapMut) -> Self {
+    pub fn new_firmware(mem: Box<dyn Storage>) -> Self {
         Flash {
             mem,
 
@@ -50,9 +51,10 @@ impl Flash {
         match instr {
             Instr::IR => unreachable!(),
 
+            // Bytes written while reading are ignored, and addresses wrap around the chip
             Instr::READ(0, addr) => {
-                assert_eq!(value, 0);
-                self.value = self.mem[addr];
+                let mem = self.mem.data();
+                self.value = mem[addr % mem.len()];
                 Mode::HandleInstr(Instr::READ(0, addr + 1))
             }
             Instr::READ(addr_bytes_left, addr) => {
@@ -60,7 +62,6 @@ impl Flash {
             }
 
             Instr::RDSR => {
-                assert_eq!(value, 0);
                 // TODO: Figure out if in Progress needs to be emulated
                 self.value = (self.write_enable as u8) << 1;
                 Mode::ReadInstr
@@ -69,8 +70,10 @@ impl Flash {
             Instr::WREN => unreachable!(),
 
             Instr::PW(0, addr) => {
-                self.value = self.mem[addr];
-                self.mem[addr] = value;
+                let mem = self.mem.data_mut();
+                let addr = addr % mem.len();
+                self.value = mem[addr];
+                mem[addr] = value;
                 Mode::HandleInstr(Instr::PW(0, addr + 1))
             }
             Instr::PW(addr_bytes_left, addr) => {
@@ -91,19 +94,32 @@ impl Backup for Flash {
 
     fn write(&mut self, hold: bool, value: u8) {
         self.mode = match self.mode {
-            Mode::ReadInstr => self.set_instr(Instr::get(value)),
+            Mode::ReadInstr => match Instr::get(value) {
+                Some(instr) => self.set_instr(instr),
+                None => {
+                    unimplemented_behavior!("Flash Instr: 0x{:X}", value);
+                    Mode::Ignore
+                }
+            },
             Mode::HandleInstr(instr) => self.handle_instr(instr, value),
+            Mode::Ignore => Mode::Ignore,
         };
         if !hold {
             self.mode = Mode::ReadInstr
         }
     }
+
+    fn data(&self) -> &[u8] {
+        self.mem.data()
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 enum Mode {
     ReadInstr,
     HandleInstr(Instr),
+    // Skips the rest of an unknown instruction
+    Ignore,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -116,15 +132,15 @@ enum Instr {
 }
 
 impl Instr {
-    fn get(value: u8) -> Self {
-        match value {
+    fn get(value: u8) -> Option<Self> {
+        Some(match value {
             0x00 => Instr::IR,
             0x08 => Instr::IR,
             0x03 => Instr::READ(3, 0),
             0x05 => Instr::RDSR,
             0x06 => Instr::WREN,
             0x0A => Instr::PW(3, 0),
-            _ => unimplemented!("Flash Instr: 0x{:X}", value),
-        }
+            _ => return None,
+        })
     }
 }
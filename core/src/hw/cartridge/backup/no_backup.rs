@@ -7,6 +7,9 @@ impl Backup for NoBackup {
         0
     }
     fn write(&mut self, _hold: bool, _value: u8) {}
+    fn data(&self) -> &[u8] {
+        &[]
+    }
 }
 
 impl NoBackup {
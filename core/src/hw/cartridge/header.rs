@@ -51,7 +51,7 @@ pub struct Header {
 }
 
 impl Header {
-    pub fn new(rom: &Vec<u8>) -> Header {
+    pub fn new(rom: &[u8]) -> Header {
         Header {
             game_title: rom[0x000..0x00C].try_into().unwrap(),
             game_code: u32::from_le_bytes(rom[0x00C..0x010].try_into().unwrap()),
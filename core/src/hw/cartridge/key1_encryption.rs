@@ -1,7 +1,7 @@
 use std::convert::TryInto;
 
+#[derive(Clone)]
 pub struct Key1Encryption {
-    pub in_use: bool,
     key_buf: [u32; Key1Encryption::KEY_TABLE_SIZE],
     original_key_buf: [u32; Key1Encryption::KEY_TABLE_SIZE],
 }
@@ -14,7 +14,6 @@ impl Key1Encryption {
             .try_into()
             .unwrap();
         Key1Encryption {
-            in_use: false,
             key_buf: original_key_buf,
             original_key_buf,
         }
@@ -22,7 +21,6 @@ impl Key1Encryption {
 
     // Modulo should be div by 4 before passing in
     pub fn init_key_code(&mut self, id_code: u32, level: u32, modulo: u32) {
-        self.in_use = true;
         self.key_buf = self.original_key_buf;
 
         let mut key_code = [id_code, id_code / 2, id_code * 2];
@@ -0,0 +1,94 @@
+// Two 39 bit LFSRs that are XORed into every byte sent over the bus while KEY2 is enabled
+// The console and the card each have their own copy, which stay in sync as they see the same bytes
+#[derive(Clone, Copy, Default)]
+pub struct Key2Encryption {
+    x: u64,
+    y: u64,
+}
+
+impl Key2Encryption {
+    pub const SEED_MASK: u64 = 0x7F_FFFF_FFFF;
+    pub const SEED1: u64 = 0x5C_879B_9B05;
+    // Selected by the encryption seed in the header
+    const SEED_BYTES: [u8; 8] = [0xE8, 0x4D, 0x5A, 0xB1, 0x17, 0x8F, 0x99, 0xD5];
+
+    pub fn new(seed0: u64, seed1: u64) -> Self {
+        // Seeds are loaded with their bits reversed
+        let reverse = |seed: u64| (seed & Self::SEED_MASK).reverse_bits() >> (64 - 39);
+        Key2Encryption {
+            x: reverse(seed0),
+            y: reverse(seed1),
+        }
+    }
+
+    // mmmnnn is given to the card in the KEY1 command that activates KEY2
+    pub fn seed0(mmmnnn: u32, encryption_seed: u8) -> u64 {
+        (mmmnnn as u64 & 0xFF_FFFF) << 15
+            | 0x6000
+            | Self::SEED_BYTES[encryption_seed as usize & 0x7] as u64
+    }
+
+    // Encryption and decryption are the same
+    pub fn apply(&mut self, bytes: &mut [u8]) {
+        for byte in bytes.iter_mut() {
+            let x = self.x;
+            let y = self.y;
+            self.x = (((x >> 5 ^ x >> 17 ^ x >> 18 ^ x >> 31) & 0xFF) + (x << 8)) & Self::SEED_MASK;
+            self.y = (((y >> 5 ^ y >> 23 ^ y >> 18 ^ y >> 31) & 0xFF) + (y << 8)) & Self::SEED_MASK;
+            *byte ^= (self.x ^ self.y) as u8;
+        }
+    }
+
+    pub fn apply_word(&mut self, word: u32) -> u32 {
+        let mut bytes = word.to_le_bytes();
+        self.apply(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed0_combines_mmmnnn_with_the_seed_byte() {
+        assert_eq!(Key2Encryption::seed0(0, 0), 0x00_0000_60E8);
+        assert_eq!(Key2Encryption::seed0(0x12_3456, 3), 0x09_1A2B_60B1);
+        // Only 24 bits of mmmnnn and 3 bits of the encryption seed are used
+        assert_eq!(Key2Encryption::seed0(0xFFFF_FFFF, 0xFF), 0x7F_FFFF_E0D5);
+    }
+
+    // The first bytes XORed in after loading the seeds, as given by GBATEK's pseudocode
+    #[test]
+    fn streams_match_reference_values() {
+        for (mmmnnn, encryption_seed, stream) in [
+            (0, 0, [0x91, 0xD6, 0x91, 0x21, 0xC8, 0x71, 0xF9, 0x6A]),
+            (
+                0x12_3456,
+                3,
+                [0x46, 0x0D, 0xCD, 0xFD, 0xA4, 0x44, 0x77, 0x52],
+            ),
+            (
+                0xFF_FFFF,
+                7,
+                [0x92, 0x2F, 0x71, 0x50, 0x37, 0x71, 0xD6, 0x2B],
+            ),
+        ] {
+            let seed0 = Key2Encryption::seed0(mmmnnn, encryption_seed);
+            let mut key2 = Key2Encryption::new(seed0, Key2Encryption::SEED1);
+            let mut bytes = [0; 8];
+            key2.apply(&mut bytes);
+            assert_eq!(bytes, stream);
+        }
+    }
+
+    #[test]
+    fn applying_twice_restores_the_data() {
+        let seed0 = Key2Encryption::seed0(0x12_3456, 3);
+        let mut encrypt = Key2Encryption::new(seed0, Key2Encryption::SEED1);
+        let mut decrypt = encrypt;
+        let word = encrypt.apply_word(0xDEAD_BEEF);
+        assert_ne!(word, 0xDEAD_BEEF);
+        assert_eq!(decrypt.apply_word(word), 0xDEAD_BEEF);
+    }
+}
@@ -0,0 +1,254 @@
+use std::ops::Range;
+
+use super::header::Header;
+use super::key1_encryption::Key1Encryption;
+use crate::error::{Error, FileKind};
+
+const ID: &[u8; 8] = b"encryObj";
+// The BIOS overwrites the ID with this after decrypting the secure area, and overwrites all of
+// it when decryption fails
+const DESTROYED_ID: u32 = 0xE7FF_DEFF;
+const RANGE: Range<usize> = 0x4000..0x8000;
+const SIZE: usize = 0x800;
+const HEADER_CRC_RANGE: Range<usize> = 0x000..0x15E;
+const LOGO_CRC: u16 = 0xCF56;
+const SECURE_AREA_CRC_ADDR: usize = 0x06C;
+const HEADER_CRC_ADDR: usize = 0x15E;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SecureAreaState {
+    // Homebrew doesn't have one
+    Absent,
+    // How it's stored on a card
+    Encrypted,
+    // How the BIOS leaves it, which is how most dumps have it
+    Decrypted,
+    // The BIOS failed to decrypt it, so the code is gone
+    Destroyed,
+    Unknown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CrcCheck {
+    pub stored: u16,
+    pub expected: u16,
+}
+
+impl CrcCheck {
+    pub fn valid(&self) -> bool {
+        self.stored == self.expected
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RomChecks {
+    pub header: CrcCheck,
+    pub logo: CrcCheck,
+    // Consoles only boot cards with the Nintendo logo
+    pub nintendo_logo: bool,
+    // Only known when the secure area can be encrypted
+    pub secure_area: Option<CrcCheck>,
+}
+
+// Converts dumps between secure area states and checks their CRCs, using the KEY1 tables from the
+// ARM7 BIOS
+pub struct RomTools {
+    key1_encryption: Key1Encryption,
+}
+
+impl RomTools {
+    pub fn new(bios7: &[u8]) -> Result<Self, Error> {
+        if bios7.len() != crate::hw::HW::BIOS7_SIZE {
+            return Err(Error::WrongSize {
+                file: FileKind::Bios7,
+                size: bios7.len(),
+                expected: crate::hw::HW::BIOS7_SIZE,
+            });
+        }
+        Ok(RomTools::with_encryption(Key1Encryption::new(bios7)))
+    }
+
+    pub(super) fn with_encryption(key1_encryption: Key1Encryption) -> Self {
+        RomTools { key1_encryption }
+    }
+
+    pub fn secure_area_state(&mut self, rom: &[u8]) -> SecureAreaState {
+        let (header, secure_area) = match split(rom) {
+            Some((header, range)) => (header, &rom[range]),
+            None => return SecureAreaState::Absent,
+        };
+        let words = to_words(secure_area);
+        if words.iter().all(|&word| word == DESTROYED_ID) {
+            SecureAreaState::Destroyed
+        } else if secure_area.starts_with(ID) || is_decrypted(&words) {
+            SecureAreaState::Decrypted
+        } else {
+            let mut id = [words[0], words[1]];
+            self.decrypt_id(header.game_code, &mut id);
+            if from_words(&id) == ID {
+                SecureAreaState::Encrypted
+            } else {
+                SecureAreaState::Unknown
+            }
+        }
+    }
+
+    pub fn encrypt_secure_area(&mut self, rom: &mut [u8]) -> Result<(), Error> {
+        self.expect_state(rom, SecureAreaState::Decrypted)?;
+        let (header, range) = split(rom).unwrap();
+        let words = self.encrypt(header.game_code, &rom[range.clone()]);
+        rom[range].copy_from_slice(&from_words(&words));
+        Ok(())
+    }
+
+    pub fn decrypt_secure_area(&mut self, rom: &mut [u8]) -> Result<(), Error> {
+        self.expect_state(rom, SecureAreaState::Encrypted)?;
+        let (header, range) = split(rom).unwrap();
+        let mut words = to_words(&rom[range.clone()]);
+        self.decrypt_id(header.game_code, &mut words[..2]);
+        self.key1_encryption.init_key_code(header.game_code, 3, 2);
+        for chunk in words.chunks_exact_mut(2) {
+            self.key1_encryption.decrypt(chunk);
+        }
+        // Matches what the BIOS leaves behind
+        words[0] = DESTROYED_ID;
+        words[1] = DESTROYED_ID;
+        rom[range].copy_from_slice(&from_words(&words));
+        Ok(())
+    }
+
+    // The CRC covers the encrypted secure area and everything after it up to 0x8000
+    pub fn secure_area_crc(&mut self, rom: &[u8]) -> Result<u16, Error> {
+        let (header, range) = split(rom).ok_or(Error::InvalidRom("ROM has no secure area"))?;
+        let secure_area = match self.secure_area_state(rom) {
+            SecureAreaState::Encrypted => rom[range.clone()].to_vec(),
+            SecureAreaState::Decrypted => {
+                from_words(&self.encrypt(header.game_code, &rom[range.clone()]))
+            }
+            _ => return Err(Error::InvalidRom("Secure area can't be encrypted")),
+        };
+        Ok(crc16(
+            &[&secure_area[..], &rom[range.end..RANGE.end]].concat(),
+        ))
+    }
+
+    // Also updates the header CRC, which covers the secure area CRC
+    pub fn fix_secure_area_crc(&mut self, rom: &mut [u8]) -> Result<(), Error> {
+        let crc = self.secure_area_crc(rom)?;
+        write_u16(rom, SECURE_AREA_CRC_ADDR, crc);
+        write_u16(rom, HEADER_CRC_ADDR, crc16(&rom[HEADER_CRC_RANGE]));
+        Ok(())
+    }
+
+    pub fn check(&mut self, rom: &[u8]) -> Result<RomChecks, Error> {
+        let header = read_header(rom)?;
+        Ok(RomChecks {
+            header: CrcCheck {
+                stored: header.header_checksum,
+                expected: crc16(&rom[HEADER_CRC_RANGE]),
+            },
+            logo: CrcCheck {
+                stored: header.nintendo_logo_checksum,
+                expected: crc16(&header.nintendo_logo),
+            },
+            nintendo_logo: crc16(&header.nintendo_logo) == LOGO_CRC,
+            secure_area: self.secure_area_crc(rom).ok().map(|expected| CrcCheck {
+                stored: header.secure_area_checksum,
+                expected,
+            }),
+        })
+    }
+
+    fn expect_state(&mut self, rom: &[u8], expected: SecureAreaState) -> Result<(), Error> {
+        let state = self.secure_area_state(rom);
+        if state == expected {
+            return Ok(());
+        }
+        Err(Error::InvalidRom(match (state, expected) {
+            (SecureAreaState::Absent, _) => "ROM has no secure area",
+            (SecureAreaState::Destroyed, _) => "Secure area was destroyed",
+            (_, SecureAreaState::Encrypted) => "Secure area isn't encrypted",
+            _ => "Secure area isn't decrypted",
+        }))
+    }
+
+    fn encrypt(&mut self, game_code: u32, secure_area: &[u8]) -> Vec<u32> {
+        let mut words = to_words(secure_area);
+        // The ID is overwritten by the BIOS after decryption, so put it back
+        words[..2].copy_from_slice(&to_words(ID));
+        // Level 3 for the entire secure area
+        self.key1_encryption.init_key_code(game_code, 3, 2);
+        for chunk in words.chunks_exact_mut(2) {
+            self.key1_encryption.encrypt(chunk);
+        }
+        // Level 2 for the ID, so it's encrypted twice
+        self.key1_encryption.init_key_code(game_code, 2, 2);
+        self.key1_encryption.encrypt(&mut words[..2]);
+        words
+    }
+
+    fn decrypt_id(&mut self, game_code: u32, id: &mut [u32]) {
+        self.key1_encryption.init_key_code(game_code, 2, 2);
+        self.key1_encryption.decrypt(id);
+        self.key1_encryption.init_key_code(game_code, 3, 2);
+        self.key1_encryption.decrypt(id);
+    }
+}
+
+// The BIOS only overwrites the ID, and SDK secure areas start with more undefined instructions
+fn is_decrypted(words: &[u32]) -> bool {
+    words[..3].iter().all(|&word| word == DESTROYED_ID) && words[3] as u16 == 0xDEFF
+}
+
+fn read_header(rom: &[u8]) -> Result<Header, Error> {
+    if rom.len() < super::Cartridge::HEADER_SIZE {
+        return Err(Error::TooSmall {
+            file: FileKind::Rom,
+            size: rom.len(),
+            min: super::Cartridge::HEADER_SIZE,
+        });
+    }
+    Ok(Header::new(rom))
+}
+
+// Returns the secure area's range if the ROM has one
+fn split(rom: &[u8]) -> Option<(Header, Range<usize>)> {
+    let header = read_header(rom).ok()?;
+    let start = header.arm9_rom_offset as usize;
+    if RANGE.contains(&start) && start + SIZE <= RANGE.end && RANGE.end <= rom.len() {
+        Some((header, start..start + SIZE))
+    } else {
+        None
+    }
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF;
+    for byte in data.iter() {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 0x1 != 0 {
+                crc >> 1 ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn write_u16(rom: &mut [u8], addr: usize, value: u16) {
+    rom[addr..addr + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+// ROMs aren't necessarily aligned, so the encryption works on copies
+fn to_words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect()
+}
+
+fn from_words(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
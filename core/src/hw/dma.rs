@@ -8,6 +8,8 @@ use super::{
 pub struct Controller {
     channels: [Channel; 4],
     pub by_type: [Vec<usize>; Occasion::num()],
+    // Set while a DMATransfer event is scheduled, which is when the DMA owns the CPU's bus
+    transferring: bool,
 }
 
 impl Controller {
@@ -20,9 +22,17 @@ impl Controller {
                 Channel::new(is_nds9, 3),
             ],
             by_type: Default::default(), // TODO: Use ArrayVec or smth maybe?
+            transferring: false,
         }
     }
 
+    // Lower channels have priority, so a higher channel is preempted until they finish
+    fn active_channel(&self) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|channel| channel.remaining != 0)
+    }
+
     pub fn read(&self, channel: usize, addr: u32) -> u8 {
         self.channels[channel].read((addr & 0xFF) as usize)
     }
@@ -44,6 +54,10 @@ impl Controller {
                 self.by_type[new_start_timing as usize].push(channel);
             }
         }
+        if prev_enable && !new_enable {
+            // Disabling a channel aborts the transfer that's in progress
+            self.channels[channel].remaining = 0;
+        }
         if !prev_enable && new_enable {
             let channel = &mut self.channels[channel];
             channel.latch();
@@ -65,6 +79,11 @@ impl Controller {
                     Event::CheckGeometryCommandFIFO,
                     HW::check_geometry_command_fifo_handler,
                 ),
+                // Only a GBA cartridge can request these, and the GBA slot is always empty
+                Occasion::GBACartridge => unimplemented_behavior!(
+                    "ARM{} GBA Cartridge DMA",
+                    if channel.is_nds9 { 9 } else { 7 }
+                ),
                 _ => (),
             }
         }
@@ -92,96 +111,153 @@ impl std::ops::IndexMut<usize> for Controller {
 }
 
 impl HW {
+    pub fn dma_active(&self, is_nds9: bool) -> bool {
+        self.dmas[is_nds9 as usize].transferring
+    }
+
     fn on_dma(&mut self, event: Event) {
         let (is_nds9, num) = match event {
             Event::DMA(is_nds9, num) => (is_nds9, num),
             _ => unreachable!(),
         };
-        if self.dmas[is_nds9 as usize][num].cnt.transfer_32 {
+        let i = is_nds9 as usize;
+        let channel = &mut self.dmas[i][num];
+        // Occasions that happen while the channel is still transferring are missed
+        if channel.remaining != 0 {
+            return;
+        }
+        let addr_mask = if channel.cnt.transfer_32 { 0x3 } else { 0x1 };
+        channel.sad_latch &= !addr_mask;
+        channel.dad_latch &= !addr_mask;
+        channel.remaining = channel.count_latch;
+        info!(
+            "Running {:?} ARM{} DMA{}: Writing {} values to {:08X} from {:08X}, size: {}",
+            channel.cnt.start_timing,
+            if is_nds9 { 9 } else { 7 },
+            num,
+            channel.count_latch,
+            channel.dad_latch,
+            channel.sad_latch,
+            if channel.cnt.transfer_32 { 32 } else { 16 }
+        );
+        if !self.dmas[i].transferring {
+            self.dmas[i].transferring = true;
+            self.scheduler
+                .run_now(Event::DMATransfer(is_nds9), HW::on_dma_transfer);
+        }
+    }
+
+    // Transfers units of the highest priority channel until the next event so that other
+    // occasions can preempt it, then reschedules itself after the cycles the units took
+    fn on_dma_transfer(&mut self, event: Event) {
+        let is_nds9 = match event {
+            Event::DMATransfer(is_nds9) => is_nds9,
+            _ => unreachable!(),
+        };
+        let i = is_nds9 as usize;
+        let num = match self.dmas[i].active_channel() {
+            Some(num) => num,
+            None => {
+                self.dmas[i].transferring = false;
+                return;
+            }
+        };
+        let until_next_event = self.cycle_at_next_event() - self.cycle();
+        // Writes to a full geometry command FIFO wait until it has room
+        if is_nds9 && self.gpu.bus_stalled() {
+            self.scheduler.schedule(
+                Event::DMATransfer(is_nds9),
+                HW::on_dma_transfer,
+                std::cmp::max(until_next_event, 1),
+            );
+            return;
+        }
+
+        let cycles = if self.dmas[i][num].cnt.transfer_32 {
             if is_nds9 {
                 self.run_dma::<_, _, _, _, true>(
                     num,
+                    until_next_event * 2,
                     &HW::arm9_get_access_time::<u32>,
                     &HW::arm9_read::<u32>,
                     &HW::arm9_write::<u32>,
-                );
+                )
             } else {
                 self.run_dma::<_, _, _, _, false>(
                     num,
+                    until_next_event,
                     &HW::arm7_get_access_time::<u32>,
                     &HW::arm7_read::<u32>,
                     &HW::arm7_write::<u32>,
-                );
+                )
             }
         } else {
             if is_nds9 {
                 self.run_dma::<_, _, _, _, true>(
                     num,
+                    until_next_event * 2,
                     &HW::arm9_get_access_time::<u16>,
                     &HW::arm9_read::<u16>,
                     &HW::arm9_write::<u16>,
-                );
+                )
             } else {
                 self.run_dma::<_, _, _, _, false>(
                     num,
+                    until_next_event,
                     &HW::arm7_get_access_time::<u16>,
                     &HW::arm7_read::<u16>,
                     &HW::arm7_write::<u16>,
-                );
+                )
             }
-        }
+        };
+        // ARM9 access times are in ARM9 cycles, which run at twice the bus clock
+        let cycles = if is_nds9 { cycles.div_ceil(2) } else { cycles };
+        // TODO: Don't halt CPU if PC is in TCM
+        self.scheduler
+            .schedule(Event::DMATransfer(is_nds9), HW::on_dma_transfer, cycles);
     }
 
+    // Runs at least one unit and returns the number of cycles taken
     fn run_dma<A, R, W, T: MemoryValue, const IS_NDS9: bool>(
         &mut self,
         num: usize,
+        budget: usize,
         access_time_fn: A,
         read_fn: R,
         write_fn: W,
-    ) where
+    ) -> usize
+    where
         A: Fn(&mut HW, AccessType, u32) -> usize,
         R: Fn(&mut HW, u32) -> T,
         W: Fn(&mut HW, u32, T),
     {
         let i = IS_NDS9 as usize;
-        let channel = &mut self.dmas[i][num];
-        let count = channel.count_latch;
+        let channel = &self.dmas[i][num];
+        let mut remaining = channel.remaining;
         let mut src_addr = channel.sad_latch;
         let mut dest_addr = channel.dad_latch;
         let src_addr_ctrl = channel.cnt.src_addr_ctrl;
         let dest_addr_ctrl = channel.cnt.dest_addr_ctrl;
-        let transfer_32 = channel.cnt.transfer_32;
-        let irq = channel.cnt.irq;
-        channel.cnt.enable = channel.cnt.start_timing != Occasion::Immediate && channel.cnt.repeat;
-        info!(
-            "Running {:?} ARM{} DMA{}: Writing {} values to {:08X} from {:08X}, size: {}",
-            channel.cnt.start_timing,
-            if IS_NDS9 { 9 } else { 7 },
-            num,
-            count,
-            dest_addr,
-            src_addr,
-            if transfer_32 { 32 } else { 16 }
-        );
+        let addr_change = if channel.cnt.transfer_32 { 4 } else { 2 };
+
+        if src_addr_ctrl == 3 {
+            unimplemented_behavior!("DMA Source Address Control 3");
+        }
 
-        let (addr_change, addr_mask) = if transfer_32 { (4, 0x3) } else { (2, 0x1) };
-        src_addr &= !addr_mask;
-        dest_addr &= !addr_mask;
-        let mut first = true;
-        let original_dest_addr = dest_addr;
-        let mut _cycles_passed = 0;
-        for _ in 0..count {
-            let cycle_type = if first { AccessType::N } else { AccessType::S };
-            _cycles_passed += access_time_fn(self, cycle_type, src_addr);
-            _cycles_passed += access_time_fn(self, cycle_type, dest_addr);
+        // A transfer that was preempted restarts with nonsequential accesses
+        let mut cycle_type = AccessType::N;
+        let mut cycles_passed = 0;
+        loop {
+            cycles_passed += access_time_fn(self, cycle_type, src_addr);
+            cycles_passed += access_time_fn(self, cycle_type, dest_addr);
             let value = read_fn(self, src_addr);
             write_fn(self, dest_addr, value);
 
             src_addr = match src_addr_ctrl {
                 0 => src_addr.wrapping_add(addr_change),
                 1 => src_addr.wrapping_sub(addr_change),
-                2 => src_addr,
-                _ => panic!("Invalid DMA Source Address Control!"),
+                2 | 3 => src_addr,
+                _ => unreachable!(),
             };
             dest_addr = match dest_addr_ctrl {
                 0 | 3 => dest_addr.wrapping_add(addr_change),
@@ -189,25 +265,44 @@ impl HW {
                 2 => dest_addr,
                 _ => unreachable!(),
             };
-            first = false;
+            cycle_type = AccessType::S;
+            remaining -= 1;
+            if remaining == 0
+                || cycles_passed >= budget
+                || (IS_NDS9 && self.gpu.bus_stalled())
+                // The write may have disabled the channel
+                || self.dmas[i][num].remaining == 0
+            {
+                break;
+            }
         }
+
         let channel = &mut self.dmas[i][num];
+        if channel.remaining == 0 {
+            return cycles_passed;
+        }
+        channel.remaining = remaining;
         channel.sad_latch = src_addr;
         channel.dad_latch = dest_addr;
-        // if channel.cnt.enable { channel.count_latch = channel.count.count as u32 } // Only reload Count - TODO: Why?
-        if dest_addr_ctrl == 3 {
-            channel.dad_latch = original_dest_addr
+        if remaining == 0 {
+            self.finish_dma(i, num);
+            cycles_passed += 2; // 2 I cycles
         }
-        _cycles_passed += 2; // 2 I cycles
+        cycles_passed
+    }
 
-        if !channel.cnt.enable {
+    fn finish_dma(&mut self, i: usize, num: usize) {
+        let channel = &mut self.dmas[i][num];
+        // if channel.cnt.enable { channel.count_latch = channel.count.count as u32 } // Only reload Count - TODO: Why?
+        if channel.cnt.dest_addr_ctrl == 3 {
+            channel.dad_latch = channel.dad.addr & channel.dad.mask
+        }
+        let irq = channel.cnt.irq;
+        if channel.cnt.start_timing == Occasion::Immediate || !channel.cnt.repeat {
+            channel.cnt.enable = false;
             self.dmas[i].disable(num)
         }
 
-        // TODO: Don't halt CPU if PC is in TCM
-        // TODO: Add this back - Removed because it broke when CPU synchronization was made looser
-        // self.clock(_cycles_passed);
-
         if irq {
             let interrupt = match num {
                 0 => InterruptRequest::DMA0,
@@ -253,6 +348,8 @@ pub struct Channel {
     pub sad_latch: u32,
     pub dad_latch: u32,
     pub count_latch: u32,
+    // Units left in the transfer that's in progress
+    remaining: u32,
 
     pub cnt: Control,
     sad: Address,
@@ -267,6 +364,7 @@ impl Channel {
             sad_latch: 0,
             dad_latch: 0,
             count_latch: 0,
+            remaining: 0,
 
             cnt: Control::new(is_nds9, num),
             sad: Address::new(if is_nds9 {
@@ -292,7 +390,7 @@ impl Channel {
 
     pub fn latch(&mut self) {
         self.sad_latch = self.sad.addr & self.sad.mask;
-        self.dad_latch = self.dad.addr & self.sad.mask;
+        self.dad_latch = self.dad.addr & self.dad.mask;
         let count = self.cnt.count & self.cnt.count_mask;
         self.count_latch = if count == 0 {
             self.cnt.count_mask + 1
@@ -379,19 +477,13 @@ impl Occasion {
                 0 => Occasion::Immediate,
                 1 => Occasion::VBlank,
                 2 => Occasion::HBlank,
-                3 => {
-                    warn!("ARM9 Start Of Display DMA not implemented!");
-                    Occasion::StartOfDisplay
-                }
+                3 => Occasion::StartOfDisplay,
                 4 => {
                     warn!("ARM9 Main Memory Display DMA not implemented!");
                     Occasion::MainMemoryDisplay
                 }
                 5 => Occasion::DSCartridge,
-                6 => {
-                    warn!("ARM9 GBA Cartridge DMA not implemented!");
-                    Occasion::GBACartridge
-                }
+                6 => Occasion::GBACartridge,
                 7 => Occasion::GeometryCommandFIFO,
                 _ => unreachable!(),
             }
@@ -403,14 +495,8 @@ impl Occasion {
                     Occasion::VBlank
                 }
                 2 => Occasion::DSCartridge,
-                3 if dma_num % 2 == 0 => {
-                    warn!("ARM7 WirelessInterrupt DMA not implemented!");
-                    Occasion::WirelessInterrupt
-                }
-                3 => {
-                    warn!("ARM7 GBA Cartridge DMA not implemented!");
-                    Occasion::GBACartridge
-                }
+                3 if dma_num % 2 == 0 => Occasion::WirelessInterrupt,
+                3 => Occasion::GBACartridge,
                 _ => unreachable!(),
             }
         }
@@ -525,3 +611,104 @@ impl IORegister for Address {
         HW::write_byte_to_value(&mut self.addr, byte, value);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: u32 = 0x0200_0000;
+    const DEST: u32 = 0x0210_0000;
+    // Last word written by a full 0x4000 unit transfer to DEST
+    const LAST: u32 = DEST + 4 * 0x3FFF;
+
+    // Starts an immediate 32 bit ARM7 transfer that keeps reading the same word
+    fn start(hw: &mut HW, num: u32, src: u32, dest: u32, count: u32) {
+        let base = 0x0400_00B0 + 12 * num;
+        hw.arm7_write::<u32>(base, src);
+        hw.arm7_write::<u32>(base + 4, dest);
+        hw.arm7_write::<u32>(base + 8, 0x8500_0000 | count);
+    }
+
+    fn setup() -> HW {
+        let mut hw = HW::new_for_tests();
+        hw.arm7_write::<u32>(SRC, 0x1111_1111);
+        hw.arm7_write::<u32>(SRC + 4, 0x2222_2222);
+        hw
+    }
+
+    // Steps from event to event like the CPUs do, so transfers get to reschedule themselves
+    fn run_for(hw: &mut HW, cycles: usize) {
+        let target = hw.cycle() + cycles;
+        while hw.cycle() < target {
+            let next = hw.cycle_at_next_event().min(target);
+            hw.clock_until(next);
+        }
+    }
+
+    #[test]
+    fn lower_channels_preempt_higher_ones() {
+        let mut hw = setup();
+        start(&mut hw, 1, SRC, DEST, 0);
+        run_for(&mut hw, 100);
+        assert_eq!(hw.arm7_read::<u32>(DEST), 0x1111_1111);
+        assert_eq!(hw.arm7_read::<u32>(LAST), 0);
+
+        // DMA0 overwrites the end of DMA1's destination before DMA1 gets there
+        start(&mut hw, 0, SRC + 4, LAST, 2);
+        run_for(&mut hw, 1_000_000);
+        assert_eq!(hw.arm7_read::<u32>(LAST), 0x1111_1111);
+        assert_eq!(hw.arm7_read::<u32>(LAST + 4), 0x2222_2222);
+        assert!(!hw.dma_active(false));
+    }
+
+    #[test]
+    fn higher_channels_wait_for_lower_ones() {
+        let mut hw = setup();
+        start(&mut hw, 0, SRC, DEST, 0);
+        run_for(&mut hw, 100);
+        assert_eq!(hw.arm7_read::<u32>(LAST), 0);
+
+        // DMA1 only starts once DMA0 is done, so it has the last word
+        start(&mut hw, 1, SRC + 4, LAST, 2);
+        run_for(&mut hw, 1_000_000);
+        assert_eq!(hw.arm7_read::<u32>(LAST), 0x2222_2222);
+        assert_eq!(hw.arm7_read::<u32>(LAST + 4), 0x2222_2222);
+    }
+
+    #[test]
+    fn destination_latch_uses_destination_mask() {
+        // ARM7 DMA0 can only read from internal memory, and DMA3 can only write to it
+        let mut channel = Channel::new(false, 0);
+        channel.sad.addr = 0x0FFF_FFF0;
+        channel.dad.addr = 0x0FFF_FFF0;
+        channel.latch();
+        assert_eq!(
+            (channel.sad_latch, channel.dad_latch),
+            (0x07FF_FFF0, 0x0FFF_FFF0)
+        );
+
+        let mut channel = Channel::new(false, 3);
+        channel.sad.addr = 0x0FFF_FFF0;
+        channel.dad.addr = 0x0FFF_FFF0;
+        channel.latch();
+        assert_eq!(
+            (channel.sad_latch, channel.dad_latch),
+            (0x0FFF_FFF0, 0x07FF_FFF0)
+        );
+    }
+
+    #[test]
+    fn repeats_reload_the_destination() {
+        let mut hw = HW::new_for_tests();
+        let channel = &mut hw.dmas[1][0];
+        channel.dad.addr = 0x1234_5678;
+        channel.dad_latch = 0x0200_0100;
+        channel.cnt.dest_addr_ctrl = 3;
+        channel.cnt.repeat = true;
+        channel.cnt.start_timing = Occasion::VBlank;
+        channel.cnt.enable = true;
+        hw.finish_dma(1, 0);
+        assert_eq!(hw.dmas[1][0].dad_latch, 0x0234_5678);
+        assert!(hw.dmas[1][0].cnt.enable);
+    }
+}
@@ -11,8 +11,11 @@ use crate::hw::{
     HW,
 };
 
-pub use engine2d::Engine2D;
-pub use engine3d::Engine3D;
+pub use engine2d::{BGControl, Engine2D, Offset};
+pub use engine3d::{
+    replay_geometry, Engine3D, Polygon, PolygonAttributes, PolygonMode, TexCoordTransformationMode,
+    TextureFormat, TextureParams, Vertex,
+};
 pub use registers::{DISPSTATFlags, DISPCAPCNT, DISPSTAT, POWCNT1};
 pub use vram::VRAM;
 
@@ -123,7 +126,7 @@ impl GPU {
         let src_a_range = start_addr..start_addr + width;
         let mut src_b = [0; 2 * GPU::WIDTH];
         if self.dispcapcnt.src_b_fifo {
-            todo!()
+            unimplemented_behavior!("Display Capture from Main Memory Display FIFO");
         } else {
             let offset = 2 * start_addr
                 + if self.engine_a.dispcnt.display_mode == DisplayMode::Mode2 {
@@ -184,6 +187,10 @@ impl GPU {
         rendered_frame
     }
 
+    pub fn engine_a_on_top(&self) -> bool {
+        self.powcnt1.contains(POWCNT1::TOP_A)
+    }
+
     pub fn get_screens(&self) -> [&Vec<u16>; 2] {
         if self.powcnt1.contains(POWCNT1::TOP_A) {
             [&self.engine_a.pixels(), &self.engine_b.pixels()]
@@ -223,6 +230,10 @@ impl HW {
             });
         }
 
+        if self.gpu.vcount < GPU::HEIGHT as u16 {
+            self.run_dmas_single(dma::Occasion::StartOfDisplay, true);
+        }
+
         let vcount = self.gpu.vcount;
         self.check_dispstats(&mut |dispstat, interrupts| {
             if dispstat.contains(DISPSTATFlags::VBLANK_IRQ_ENABLE)
@@ -1,4 +1,46 @@
-use super::{engine2d::BGMode, Engine2D, EngineType, GPU, VRAM};
+use super::{
+    engine2d::{BGMode, DISPCNTFlags, RotationScalingParameter},
+    Engine2D, EngineType, GPU, VRAM,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OBJMode {
+    Normal,
+    SemiTransparent,
+    Window,
+    Bitmap,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OBJAffine {
+    pub index: usize,
+    pub double_size: bool,
+    // PA, PB, PC and PD
+    pub params: [f64; 4],
+}
+
+// A decoded OAM entry
+#[derive(Clone, Copy, Debug)]
+pub struct OAMEntry {
+    pub x: i16,
+    pub y: u8,
+    pub width: usize,
+    pub height: usize,
+    // Only possible for non-affine OBJs
+    pub disabled: bool,
+    pub affine: Option<OBJAffine>,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub mode: OBJMode,
+    pub mosaic: bool,
+    pub bpp8: bool,
+    pub extended_palette: bool,
+    pub priority: u8,
+    pub palette: usize,
+    pub tile_num: usize,
+    // Address in OBJ VRAM of the top left pixel
+    pub addr: usize,
+}
 
 impl GPU {
     pub fn render_palettes<F: Fn(usize) -> u16>(
@@ -251,3 +293,165 @@ impl<E: EngineType> Engine2D<E> {
         }
     }
 }
+
+impl<E: EngineType> Engine2D<E> {
+    pub fn oam_entry(&self, obj_i: usize) -> OAMEntry {
+        let attr = |i: usize| {
+            u16::from_le_bytes([self.oam[obj_i * 8 + i * 2], self.oam[obj_i * 8 + i * 2 + 1]])
+        };
+        let attrs = [attr(0), attr(1), attr(2)];
+        let obj_shape = (attrs[0] >> 14 & 0x3) as usize;
+        let obj_size = (attrs[1] >> 14 & 0x3) as usize;
+        // Reserved shape, treated as the first until known otherwise
+        let (width, height) = Engine2D::<E>::OBJ_SIZES[obj_size][obj_shape.min(2)];
+        let affine = attrs[0] >> 8 & 0x1 != 0;
+        let double_size_or_disable = attrs[0] >> 9 & 0x1 != 0;
+        let mode = match attrs[0] >> 10 & 0x3 {
+            0 => OBJMode::Normal,
+            1 => OBJMode::SemiTransparent,
+            2 => OBJMode::Window,
+            3 => OBJMode::Bitmap,
+            _ => unreachable!(),
+        };
+        let bpp8 = attrs[0] >> 13 & 0x1 != 0;
+        let x = (attrs[1] & 0x1FF) as i16;
+        let tile_num = (attrs[2] & 0x3FF) as usize;
+        OAMEntry {
+            x: if x & 0x100 != 0 { x - 0x200 } else { x },
+            y: attrs[0] as u8,
+            width: width as usize,
+            height: height as usize,
+            disabled: !affine && double_size_or_disable,
+            affine: if affine {
+                let index = (attrs[1] >> 9 & 0x1F) as usize;
+                let mut params = [0.0; 4];
+                for (i, param) in params.iter_mut().enumerate() {
+                    let addr = index * 0x20 + i * 8 + 6;
+                    *param = RotationScalingParameter::get_float_from_u16(u16::from_le_bytes([
+                        self.oam[addr],
+                        self.oam[addr + 1],
+                    ]));
+                }
+                Some(OBJAffine {
+                    index,
+                    double_size: double_size_or_disable,
+                    params,
+                })
+            } else {
+                None
+            },
+            flip_x: !affine && attrs[1] >> 12 & 0x1 != 0,
+            flip_y: !affine && attrs[1] >> 13 & 0x1 != 0,
+            mode,
+            mosaic: attrs[0] >> 12 & 0x1 != 0,
+            bpp8,
+            extended_palette: bpp8 && self.dispcnt.contains(DISPCNTFlags::OBJ_EXTENDED_PALETTES),
+            priority: (attrs[2] >> 10 & 0x3) as u8,
+            palette: (attrs[2] >> 12 & 0xF) as usize,
+            tile_num,
+            addr: self
+                .obj_pixel_addr(mode, bpp8, tile_num, width as usize, 0, 0)
+                .unwrap_or(0),
+        }
+    }
+
+    // Same addressing as render_objs_line, None if the bitmap mapping is reserved
+    fn obj_pixel_addr(
+        &self,
+        mode: OBJMode,
+        bpp8: bool,
+        tile_num: usize,
+        width: usize,
+        x: usize,
+        y: usize,
+    ) -> Option<usize> {
+        if mode == OBJMode::Bitmap {
+            let (start_addr, bitmap_width) = if self.dispcnt.contains(DISPCNTFlags::BITMAP_OBJ_1D) {
+                if self.dispcnt.contains(DISPCNTFlags::BITMAP_OBJ_SQUARE) {
+                    return None;
+                }
+                let boundary = if self.dispcnt.contains(DISPCNTFlags::BITMAP_OBJ_1D_BOUND) {
+                    256
+                } else {
+                    128
+                };
+                (tile_num * boundary, width)
+            } else {
+                let (mask_x, bitmap_width) =
+                    if self.dispcnt.contains(DISPCNTFlags::BITMAP_OBJ_SQUARE) {
+                        (0x1F, 256)
+                    } else {
+                        (0x0F, 128)
+                    };
+                (
+                    (tile_num & mask_x) * 0x10 + (tile_num & !mask_x) * 0x80,
+                    bitmap_width,
+                )
+            };
+            Some(start_addr + 2 * (y * bitmap_width + x))
+        } else {
+            let bit_depth = if bpp8 { 8 } else { 4 };
+            let (boundary, tile_offset) = if self.dispcnt.contains(DISPCNTFlags::TILE_OBJ_1D) {
+                (
+                    32 << self.dispcnt.tile_obj_1d_bound,
+                    y / 8 * width / 8 + x / 8,
+                )
+            } else {
+                (32, y / 8 * 0x80 / bit_depth + x / 8)
+            };
+            Some(boundary * tile_num + tile_offset * bit_depth * 8)
+        }
+    }
+
+    // Renders the OBJ as stored in VRAM, without flipping or affine transformation
+    pub fn render_obj(&self, vram: &VRAM, obj_i: usize) -> (Vec<u16>, usize, usize) {
+        let entry = self.oam_entry(obj_i);
+        let (width, height) = (entry.width, entry.height);
+        let mut pixels = vec![0; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let addr = match self.obj_pixel_addr(
+                    entry.mode,
+                    entry.bpp8,
+                    entry.tile_num,
+                    width,
+                    x,
+                    y,
+                ) {
+                    Some(addr) => addr,
+                    None => continue,
+                };
+                pixels[y * width + x] = if entry.mode == OBJMode::Bitmap {
+                    let color = vram.get_obj::<E, u16>(addr);
+                    if color & 0x8000 == 0 {
+                        continue;
+                    }
+                    color
+                } else {
+                    let bit_depth = if entry.bpp8 { 8 } else { 4 };
+                    let (palette_num, color_num) = Engine2D::<E>::get_color_from_tile(
+                        vram,
+                        VRAM::get_obj::<E, u8>,
+                        addr,
+                        false,
+                        false,
+                        bit_depth,
+                        x % 8,
+                        y % 8,
+                        entry.palette,
+                    );
+                    if color_num == 0 {
+                        continue;
+                    }
+                    0x8000
+                        | if entry.extended_palette {
+                            vram.get_obj_ext_pal::<E>(entry.palette * 256 + color_num)
+                        } else {
+                            self.obj_palettes()[palette_num * 16 + color_num]
+                        }
+                };
+            }
+        }
+        (pixels, width, height)
+    }
+}
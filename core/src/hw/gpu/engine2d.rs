@@ -1,6 +1,8 @@
 mod registers;
 
-pub use registers::{BGMode, DisplayMode};
+pub use registers::{
+    BGControl, BGMode, DISPCNTFlags, DisplayMode, Offset, RotationScalingParameter,
+};
 
 use super::{Engine3D, EngineType, GPU, VRAM};
 use crate::hw::{mem::IORegister, Scheduler};
@@ -91,7 +93,7 @@ impl<E: EngineType> Engine2D<E> {
         }
     }
 
-    const OBJ_SIZES: [[(i16, u16); 3]; 4] = [
+    pub(super) const OBJ_SIZES: [[(i16, u16); 3]; 4] = [
         [(8, 8), (16, 8), (8, 16)],
         [(16, 16), (32, 8), (8, 32)],
         [(32, 32), (32, 16), (16, 32)],
@@ -117,7 +119,7 @@ impl<E: EngineType> Engine2D<E> {
                     self.set_pixel(vcount, dot_x, color);
                 }
             }
-            DisplayMode::Mode3 => todo!(),
+            DisplayMode::Mode3 => unimplemented_behavior!("Main Memory Display Mode"),
         }
     }
 
@@ -212,7 +214,7 @@ impl<E: EngineType> Engine2D<E> {
                 }
                 self.process_lines(vcount, 0, 3);
             }
-            BGMode::Mode6 => todo!(),
+            BGMode::Mode6 => unimplemented_behavior!("BG Mode 6"),
         }
     }
 
@@ -28,7 +28,10 @@ impl BGMode {
             4 => Mode4,
             5 => Mode5,
             6 => Mode6,
-            _ => panic!("Invalid BG Mode!"),
+            _ => {
+                unimplemented_behavior!("BG Mode {}", bits);
+                Mode0
+            }
         }
     }
 }
@@ -3,13 +3,22 @@ use std::collections::VecDeque;
 use super::{InterruptRequest, Scheduler, GPU};
 use crate::hw::mem::IORegister;
 
+mod debug;
 mod geometry;
 mod math;
+mod recording;
 mod registers;
 mod rendering;
 
+pub use geometry::{Polygon, Vertex};
+pub use recording::replay_geometry;
+pub use registers::{
+    PolygonAttributes, PolygonMode, TexCoordTransformationMode, TextureFormat, TextureParams,
+};
+
 use geometry::*;
 use math::{FixedPoint, Matrix};
+use recording::Recorder;
 use registers::*;
 use rendering::FrameBufferPixel;
 
@@ -57,6 +66,9 @@ pub struct Engine3D {
     cur_poly_verts: Vec<Vertex>,
     vertices: Vec<Vertex>,
     polygons: Vec<Polygon>,
+    // Last rendered frame
+    frame_vertices: Vec<Vertex>,
+    frame_polygons: Vec<Polygon>,
     original_verts: Vec<(Matrix, [FixedPoint; 3])>,
     // Lighting
     lights: [Light; 4],
@@ -69,6 +81,8 @@ pub struct Engine3D {
     tex_coord: [i16; 2],     // 1 + 11 + 4 fixed point
     // Toon
     toon_table: [Color; 0x20],
+    // Debugging
+    recorder: Option<Recorder>,
 }
 
 impl Engine3D {
@@ -119,6 +133,8 @@ impl Engine3D {
             cur_poly_verts: Vec::with_capacity(10),
             vertices: Vec::new(),
             polygons: Vec::new(),
+            frame_vertices: Vec::new(),
+            frame_polygons: Vec::new(),
             original_verts: Vec::new(),
             // Lighting
             lights: [Light::new(); 4],
@@ -131,6 +147,8 @@ impl Engine3D {
             tex_coord: [0; 2],     // 1 + 11 + 4 fixed point
             // Toon
             toon_table: [Color::new5(0, 0, 0); 0x20],
+            // Debugging
+            recorder: None,
         }
     }
 
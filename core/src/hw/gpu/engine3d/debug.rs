@@ -0,0 +1,30 @@
+use super::{super::VRAM, Engine3D, Polygon, TextureFormat, Vertex};
+
+impl Engine3D {
+    // Polygons and vertices of the last rendered frame, after transformation and clipping
+    pub fn frame_polygons(&self) -> &[Polygon] {
+        &self.frame_polygons
+    }
+
+    pub fn frame_vertices(&self) -> &[Vertex] {
+        &self.frame_vertices
+    }
+
+    // Decodes the whole texture of a polygon, ignoring its vertex colors
+    pub fn render_texture(&self, vram: &VRAM, polygon_i: usize) -> (Vec<u16>, usize, usize) {
+        let polygon = &self.frame_polygons[polygon_i];
+        if let TextureFormat::NoTexture = polygon.tex_params.format {
+            return (Vec::new(), 0, 0);
+        }
+        let (width, height) = (polygon.tex_params.size_s, polygon.tex_params.size_t);
+        let mut pixels = vec![0; width * height];
+        for t in 0..height {
+            for s in 0..width {
+                if let Some(color) = Engine3D::get_tex_color(vram, polygon, s as i32, t as i32) {
+                    pixels[t * width + s] = color.as_u16();
+                }
+            }
+        }
+        (pixels, width, height)
+    }
+}
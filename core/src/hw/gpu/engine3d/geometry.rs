@@ -24,6 +24,9 @@ impl Engine3D {
         if !self.polygons_submitted {
             while let Some(entry) = self.gxfifo.pop_front() {
                 self.exec_command(entry);
+                if self.recorder.is_some() {
+                    self.record_command(entry);
+                }
                 if self.polygons_submitted {
                     break;
                 }
@@ -56,96 +59,96 @@ impl Engine3D {
         match command_entry.command {
             NOP => (),
             MtxMode => self.mtx_mode = MatrixMode::from(param as u8 & 0x3),
+            // Stack overflows and underflows only set the error flag
             MtxPush => match self.mtx_mode {
                 MatrixMode::Proj => {
-                    self.proj_stack[self.proj_stack_sp as usize] = self.cur_proj;
-                    self.proj_stack_sp += 1;
-                    assert!(self.proj_stack_sp <= 1);
+                    if self.proj_stack_sp >= 1 {
+                        self.gxstat.mat_stack_error = true;
+                    } else {
+                        self.proj_stack[0] = self.cur_proj;
+                        self.proj_stack_sp += 1;
+                    }
                 }
                 MatrixMode::Pos | MatrixMode::PosVec => {
-                    self.pos_stack[self.pos_vec_stack_sp as usize] = self.cur_pos;
-                    self.vec_stack[self.pos_vec_stack_sp as usize] = self.cur_vec;
-                    self.pos_vec_stack_sp += 1;
-                    assert!(self.pos_vec_stack_sp <= 31);
+                    if self.pos_vec_stack_sp >= 31 {
+                        self.gxstat.mat_stack_error = true;
+                    } else {
+                        self.pos_stack[self.pos_vec_stack_sp as usize] = self.cur_pos;
+                        self.vec_stack[self.pos_vec_stack_sp as usize] = self.cur_vec;
+                        self.pos_vec_stack_sp += 1;
+                    }
                 }
                 MatrixMode::Texture => {
-                    self.tex_stack[self.tex_stack_sp as usize] = self.cur_tex;
-                    self.tex_stack_sp += 1;
-                    assert!(self.tex_stack_sp <= 31);
+                    if self.tex_stack_sp >= 1 {
+                        self.gxstat.mat_stack_error = true;
+                    } else {
+                        self.tex_stack[0] = self.cur_tex;
+                        self.tex_stack_sp += 1;
+                    }
                 }
             },
-            MtxPop => {
-                let offset = param & 0x3F;
-                let offset = if offset & 0x20 != 0 {
-                    0xC0 | offset
-                } else {
-                    offset
-                } as i8;
-                match self.mtx_mode {
-                    MatrixMode::Proj => {
+            MtxPop => match self.mtx_mode {
+                MatrixMode::Proj => {
+                    if self.proj_stack_sp == 0 {
+                        self.gxstat.mat_stack_error = true;
+                    } else {
                         self.proj_stack_sp -= 1;
-                        assert!(self.proj_stack_sp < 1);
-                        self.cur_proj = self.proj_stack[self.proj_stack_sp as usize];
+                        self.cur_proj = self.proj_stack[0];
                         self.calc_clip_mat();
                     }
-                    MatrixMode::Pos | MatrixMode::PosVec => {
-                        self.pos_vec_stack_sp = (self.pos_vec_stack_sp as i8 - offset) as u8;
-                        assert!(self.pos_vec_stack_sp < 31);
+                }
+                MatrixMode::Pos | MatrixMode::PosVec => {
+                    // The offset is a signed 6 bit number, and the pointer wraps within 6 bits
+                    let offset = ((param as u8) << 2) as i8 >> 2;
+                    self.pos_vec_stack_sp = (self.pos_vec_stack_sp as i8 - offset) as u8 & 0x3F;
+                    if self.pos_vec_stack_sp >= 31 {
+                        self.gxstat.mat_stack_error = true;
+                    } else {
                         self.cur_pos = self.pos_stack[self.pos_vec_stack_sp as usize];
                         self.calc_clip_mat();
                         self.cur_vec = self.vec_stack[self.pos_vec_stack_sp as usize];
                     }
-                    MatrixMode::Texture => {
-                        self.tex_stack_sp = (self.tex_stack_sp as i8 - offset) as u8;
-                        assert!(self.tex_stack_sp < 31);
-                        self.cur_tex = self.tex_stack[self.tex_stack_sp as usize];
-                    }
-                }
-            }
-            MtxStore => {
-                let index = param & 0x3F;
-                if index == 31 {
-                    self.gxstat.mat_stack_error = true
                 }
-                match self.mtx_mode {
-                    MatrixMode::Proj => {
-                        assert!(index <= 1);
-                        self.proj_stack[0] = self.cur_proj;
-                    }
-                    MatrixMode::Pos | MatrixMode::PosVec => {
-                        assert!(index <= 31);
-                        self.pos_stack[index as usize] = self.cur_pos;
-                        self.vec_stack[index as usize] = self.cur_vec;
+                MatrixMode::Texture => {
+                    if self.tex_stack_sp == 0 {
+                        self.gxstat.mat_stack_error = true;
+                    } else {
+                        self.tex_stack_sp -= 1;
+                        self.cur_tex = self.tex_stack[0];
                     }
-                    MatrixMode::Texture => {
-                        assert!(index <= 31);
-                        self.tex_stack[index as usize] = self.cur_tex;
+                }
+            },
+            // The projection and texture stacks only have one entry, so the index is ignored
+            MtxStore => match self.mtx_mode {
+                MatrixMode::Proj => self.proj_stack[0] = self.cur_proj,
+                MatrixMode::Pos | MatrixMode::PosVec => {
+                    let index = param as usize & 0x1F;
+                    if index >= 31 {
+                        self.gxstat.mat_stack_error = true;
+                    } else {
+                        self.pos_stack[index] = self.cur_pos;
+                        self.vec_stack[index] = self.cur_vec;
                     }
                 }
-            }
-            MtxRestore => {
-                let index = param & 0x3F;
-                if index == 31 {
-                    self.gxstat.mat_stack_error = true
+                MatrixMode::Texture => self.tex_stack[0] = self.cur_tex,
+            },
+            MtxRestore => match self.mtx_mode {
+                MatrixMode::Proj => {
+                    self.cur_proj = self.proj_stack[0];
+                    self.calc_clip_mat();
                 }
-                match self.mtx_mode {
-                    MatrixMode::Proj => {
-                        assert!(index <= 1);
-                        self.cur_proj = self.proj_stack[0];
-                        self.calc_clip_mat();
-                    }
-                    MatrixMode::Pos | MatrixMode::PosVec => {
-                        assert!(index <= 31);
-                        self.cur_pos = self.pos_stack[index as usize];
+                MatrixMode::Pos | MatrixMode::PosVec => {
+                    let index = param as usize & 0x1F;
+                    if index >= 31 {
+                        self.gxstat.mat_stack_error = true;
+                    } else {
+                        self.cur_pos = self.pos_stack[index];
                         self.calc_clip_mat();
-                        self.cur_vec = self.vec_stack[index as usize];
-                    }
-                    MatrixMode::Texture => {
-                        assert!(index <= 31);
-                        self.cur_tex = self.tex_stack[index as usize];
+                        self.cur_vec = self.vec_stack[index];
                     }
                 }
-            }
+                MatrixMode::Texture => self.cur_tex = self.tex_stack[0],
+            },
             MtxIdentity => self.apply_cur_mat(Matrix::set_identity, true),
             MtxLoad4x4 => self.apply_cur_mat(Matrix::load4x4, true),
             MtxLoad4x3 => self.apply_cur_mat(Matrix::load4x3, true),
@@ -599,9 +602,9 @@ impl Engine3D {
         let mut w_size = 0;
         for vert in self.cur_poly_verts.iter() {
             let w = vert.clip_coords[3].raw() as u32;
-            while w >> w_size != 0 {
+            // Negative W only comes from broken matrices, so just avoid overflowing
+            while w_size < 32 && w >> w_size != 0 {
                 w_size += 4;
-                assert!(w_size < 32);
             }
         }
         let (mut bot, mut top) = (0, 191);
@@ -610,7 +613,8 @@ impl Engine3D {
             let w = vert.clip_coords[3].raw();
             let vert = Vertex {
                 screen_coords: self.viewport.screen_coords(&vert.clip_coords),
-                z_depth: ((((z * 0x4000 / w as i64) + 0x3FFF) * 0x200) & 0xFFFFFF) as u32,
+                z_depth: ((((z * 0x4000).checked_div(w as i64).unwrap_or(0) + 0x3FFF) * 0x200)
+                    & 0xFFFFFF) as u32,
                 normalized_w: if w_size < 16 {
                     w << (16 - w_size)
                 } else {
@@ -854,7 +858,7 @@ impl GeometryCommand {
         }
     }
 
-    fn from_byte(value: u8) -> Self {
+    pub(super) fn from_byte(value: u8) -> Self {
         use GeometryCommand::*;
         match value {
             0x00 => NOP,
@@ -946,8 +950,8 @@ impl GeometryCommand {
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct GeometryCommandEntry {
-    command: GeometryCommand,
-    param: u32,
+    pub(super) command: GeometryCommand,
+    pub(super) param: u32,
 }
 
 impl GeometryCommandEntry {
@@ -956,6 +960,7 @@ impl GeometryCommandEntry {
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum MatrixMode {
     Proj = 0,
     Pos = 1,
@@ -988,6 +993,17 @@ impl Light {
             color: [0, 0, 0],
         }
     }
+
+    // Parameters for LightVector and LightColor, assuming an identity directional matrix
+    pub(super) fn params(&self, index: usize) -> (u32, u32) {
+        let index = (index as u32) << 30;
+        let direction = |i: usize| (self.direction[i].raw() as u32 >> 3) & 0x3FF;
+        let color = |i: usize| self.color[i] as u32;
+        (
+            index | direction(2) << 20 | direction(1) << 10 | direction(0),
+            index | color(2) << 10 | color(1) << 5 | color(0),
+        )
+    }
 }
 
 pub struct Material {
@@ -1011,6 +1027,28 @@ impl Material {
         }
     }
 
+    // Parameters for DifAmb, SpeEmi and Shininess
+    pub(super) fn params(&self) -> (u32, u32, Vec<u32>) {
+        let rgb = |color: &[i32; 3]| (color[2] << 10 | color[1] << 5 | color[0]) as u32;
+        let shininess = self
+            .shininess
+            .chunks(4)
+            .map(|bytes| {
+                bytes
+                    .iter()
+                    .rev()
+                    .fold(0, |word, byte| word << 8 | *byte as u8 as u32)
+            })
+            .collect();
+        (
+            rgb(&self.ambient) << 16 | rgb(&self.diffuse),
+            rgb(&self.emission) << 16
+                | (self.use_shininess_table as u32) << 15
+                | rgb(&self.specular),
+            shininess,
+        )
+    }
+
     pub fn set_dif_amb(&mut self, val: u32) -> bool {
         self.diffuse = [
             (val >> 0 & 0x1F) as i32,
@@ -1163,3 +1201,53 @@ pub struct Polygon {
     pub is_front: bool,
     pub original_verts: Vec<(Matrix, [FixedPoint; 3])>,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::hw::HW;
+
+    const MTX_MODE: u32 = 0x0400_0440;
+    const MTX_PUSH: u32 = 0x0400_0444;
+    const MTX_POP: u32 = 0x0400_0448;
+    const MTX_STORE: u32 = 0x0400_044C;
+    const MTX_RESTORE: u32 = 0x0400_0450;
+    const GXSTAT: u32 = 0x0400_0600;
+
+    fn run_commands(commands: &[(u32, u32)]) -> HW {
+        let mut hw = HW::new_for_tests();
+        for &(addr, param) in commands {
+            hw.arm9_write::<u32>(addr, param);
+        }
+        hw.clock_until(hw.cycle() + 0x1000);
+        hw
+    }
+
+    fn stack_error(hw: &mut HW) -> bool {
+        hw.arm9_read::<u32>(GXSTAT) & 1 << 15 != 0
+    }
+
+    #[test]
+    fn out_of_range_store_sets_stack_error() {
+        for command in [MTX_STORE, MTX_RESTORE] {
+            for index in [31, 63] {
+                let mut hw = run_commands(&[(MTX_MODE, 1), (command, index)]);
+                assert!(stack_error(&mut hw));
+            }
+            // Only 5 bits of the index are used
+            let mut hw = run_commands(&[(MTX_MODE, 1), (command, 40)]);
+            assert!(!stack_error(&mut hw));
+        }
+    }
+
+    #[test]
+    fn stack_overflow_and_underflow_set_stack_error() {
+        let mut hw = run_commands(&[(MTX_MODE, 0), (MTX_PUSH, 0), (MTX_PUSH, 0)]);
+        assert!(stack_error(&mut hw));
+        let mut hw = run_commands(&[(MTX_MODE, 3), (MTX_POP, 1)]);
+        assert!(stack_error(&mut hw));
+        let mut hw = run_commands(&[(MTX_MODE, 1), (MTX_POP, 1)]);
+        assert!(stack_error(&mut hw));
+        let mut hw = run_commands(&[(MTX_MODE, 1), (MTX_PUSH, 0), (MTX_POP, 1)]);
+        assert!(!stack_error(&mut hw));
+    }
+}
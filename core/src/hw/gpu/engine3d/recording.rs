@@ -0,0 +1,286 @@
+use std::io::{self, Read, Write};
+
+use super::{
+    super::VRAM, math::Matrix, Engine3D, GeometryCommand, GeometryCommandEntry, InterruptRequest,
+    MatrixMode, Scheduler, GPU,
+};
+use crate::hw::mem::IORegister;
+
+// A frame's geometry commands along with the registers and VRAM needed to render it
+// Format (little endian): magic, registers, texture and texture palette VRAM, then
+// the number of commands followed by each command byte and parameter
+struct GeometryRecording {
+    disp3dcnt: u16,
+    clear_color: u32,
+    clear_depth: u16,
+    toon_table: [u16; 0x20],
+    textures: Vec<u8>,
+    textures_pal: Vec<u8>,
+    commands: Vec<GeometryCommandEntry>,
+}
+
+impl GeometryRecording {
+    const MAGIC: &'static [u8; 8] = b"NDSGX\0\0\x01";
+    const TEXTURES_LEN: usize = 4 * 0x2_0000;
+    const TEXTURES_PAL_LEN: usize = 6 * 0x4000;
+
+    fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(GeometryRecording::MAGIC)?;
+        writer.write_all(&self.disp3dcnt.to_le_bytes())?;
+        writer.write_all(&self.clear_color.to_le_bytes())?;
+        writer.write_all(&self.clear_depth.to_le_bytes())?;
+        for color in self.toon_table.iter() {
+            writer.write_all(&color.to_le_bytes())?;
+        }
+        writer.write_all(&self.textures)?;
+        writer.write_all(&self.textures_pal)?;
+        writer.write_all(&(self.commands.len() as u32).to_le_bytes())?;
+        for entry in self.commands.iter() {
+            writer.write_all(&[entry.command as u8])?;
+            writer.write_all(&entry.param.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    fn read(reader: &mut dyn Read) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != GeometryRecording::MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a geometry recording",
+            ));
+        }
+        fn read_bytes<const N: usize>(reader: &mut dyn Read) -> io::Result<[u8; N]> {
+            let mut bytes = [0; N];
+            reader.read_exact(&mut bytes)?;
+            Ok(bytes)
+        }
+        let disp3dcnt = u16::from_le_bytes(read_bytes(reader)?);
+        let clear_color = u32::from_le_bytes(read_bytes(reader)?);
+        let clear_depth = u16::from_le_bytes(read_bytes(reader)?);
+        let mut toon_table = [0; 0x20];
+        for color in toon_table.iter_mut() {
+            *color = u16::from_le_bytes(read_bytes(reader)?);
+        }
+        let mut textures = vec![0; GeometryRecording::TEXTURES_LEN];
+        reader.read_exact(&mut textures)?;
+        let mut textures_pal = vec![0; GeometryRecording::TEXTURES_PAL_LEN];
+        reader.read_exact(&mut textures_pal)?;
+        let num_commands = u32::from_le_bytes(read_bytes(reader)?);
+        let mut commands = Vec::new();
+        for _ in 0..num_commands {
+            let command = GeometryCommand::from_byte(read_bytes::<1>(reader)?[0]);
+            let param = u32::from_le_bytes(read_bytes(reader)?);
+            commands.push(GeometryCommandEntry::new(command, param));
+        }
+        Ok(GeometryRecording {
+            disp3dcnt,
+            clear_color,
+            clear_depth,
+            toon_table,
+            textures,
+            textures_pal,
+            commands,
+        })
+    }
+}
+
+pub(super) struct Recorder {
+    writer: Box<dyn Write>,
+    state: RecorderState,
+}
+
+enum RecorderState {
+    // Commands are recorded starting with the next frame
+    Waiting,
+    Recording(Vec<GeometryCommandEntry>),
+    // VRAM and registers are captured once the frame is rendered
+    Submitted(Vec<GeometryCommandEntry>),
+}
+
+impl Engine3D {
+    pub fn record_frame(&mut self, writer: Box<dyn Write>) {
+        self.recorder = Some(Recorder {
+            writer,
+            state: RecorderState::Waiting,
+        });
+    }
+
+    pub(super) fn record_command(&mut self, entry: GeometryCommandEntry) {
+        let mut recorder = self.recorder.take().unwrap();
+        let swapped = entry.command == GeometryCommand::SwapBuffers;
+        recorder.state = match recorder.state {
+            RecorderState::Waiting if swapped => RecorderState::Recording(self.state_commands()),
+            RecorderState::Recording(mut commands) => {
+                commands.push(entry);
+                if swapped {
+                    RecorderState::Submitted(commands)
+                } else {
+                    RecorderState::Recording(commands)
+                }
+            }
+            state => state,
+        };
+        self.recorder = Some(recorder);
+    }
+
+    pub(super) fn finish_recording(&mut self, vram: &VRAM) {
+        let (mut writer, commands) = match self.recorder.take() {
+            Some(Recorder {
+                writer,
+                state: RecorderState::Submitted(commands),
+            }) => (writer, commands),
+            recorder => {
+                self.recorder = recorder;
+                return;
+            }
+        };
+        let recording = GeometryRecording {
+            disp3dcnt: u16::from_le_bytes([self.disp3dcnt.read(0), self.disp3dcnt.read(1)]),
+            clear_color: self.clear_color.raw(),
+            clear_depth: self.clear_depth.raw(),
+            toon_table: self.toon_table.map(|color| color.as_u16()),
+            textures: (0..GeometryRecording::TEXTURES_LEN)
+                .map(|addr| vram.get_textures(addr))
+                .collect(),
+            textures_pal: (0..GeometryRecording::TEXTURES_PAL_LEN)
+                .map(|addr| vram.get_textures_pal(addr))
+                .collect(),
+            commands,
+        };
+        if let Err(e) = recording.write(&mut writer) {
+            warn!("Unable to write geometry recording: {}", e);
+        }
+    }
+
+    // Commands that recreate the current geometry engine state in a reset engine
+    // Vertex state that carries over between frames, such as unfinished strips, isn't restored
+    fn state_commands(&self) -> Vec<GeometryCommandEntry> {
+        use GeometryCommand::*;
+        let mut commands = Vec::new();
+        let mut push = |command, params: &[u32]| {
+            for param in params {
+                commands.push(GeometryCommandEntry::new(command, *param))
+            }
+        };
+        let load =
+            |matrix: &Matrix| -> Vec<u32> { (0..16).map(|i| matrix[i].raw() as u32).collect() };
+
+        // Light directions are transformed by the directional matrix, so restore them while
+        // it's still the identity matrix
+        for (i, light) in self.lights.iter().enumerate() {
+            let (direction, color) = light.params(i);
+            push(LightVector, &[direction]);
+            push(LightColor, &[color]);
+        }
+        let (dif_amb, spe_emi, shininess) = self.material.params();
+        push(DifAmb, &[dif_amb]);
+        push(SpeEmi, &[spe_emi]);
+        push(Shininess, &shininess);
+
+        // Stack pointers, then stacks, then current matrices
+        let stack_pointers = [
+            (MatrixMode::Proj, self.proj_stack_sp),
+            (MatrixMode::Texture, self.tex_stack_sp),
+            (MatrixMode::Pos, self.pos_vec_stack_sp),
+        ];
+        for (mode, stack_sp) in stack_pointers {
+            push(MtxMode, &[mode as u32]);
+            for _ in 0..stack_sp {
+                push(MtxPush, &[0]);
+            }
+        }
+        push(MtxMode, &[MatrixMode::Proj as u32]);
+        push(MtxLoad4x4, &load(&self.proj_stack[0]));
+        push(MtxStore, &[0]);
+        push(MtxMode, &[MatrixMode::Texture as u32]);
+        push(MtxLoad4x4, &load(&self.tex_stack[0]));
+        push(MtxStore, &[0]);
+        for i in 0..self.pos_stack.len() {
+            push(MtxMode, &[MatrixMode::PosVec as u32]);
+            push(MtxLoad4x4, &load(&self.vec_stack[i]));
+            push(MtxMode, &[MatrixMode::Pos as u32]);
+            push(MtxLoad4x4, &load(&self.pos_stack[i]));
+            push(MtxStore, &[i as u32]);
+        }
+        push(MtxMode, &[MatrixMode::Proj as u32]);
+        push(MtxLoad4x4, &load(&self.cur_proj));
+        push(MtxMode, &[MatrixMode::Texture as u32]);
+        push(MtxLoad4x4, &load(&self.cur_tex));
+        push(MtxMode, &[MatrixMode::PosVec as u32]);
+        push(MtxLoad4x4, &load(&self.cur_vec));
+        push(MtxMode, &[MatrixMode::Pos as u32]);
+        push(MtxLoad4x4, &load(&self.cur_pos));
+        push(MtxMode, &[self.mtx_mode as u32]);
+
+        push(Viewport, &[self.viewport.raw()]);
+        push(PolygonAttr, &[self.polygon_attrs.raw()]);
+        push(TexImageParam, &[self.tex_params.raw()]);
+        push(PlttBase, &[(self.palette_base / 16) as u32]);
+        push(Color, &[self.color.as_u16() as u32]);
+        let tex_coord = self.raw_tex_coord.map(|coord| coord as u16 as u32);
+        push(TexCoord, &[tex_coord[1] << 16 | tex_coord[0]]);
+        commands
+    }
+}
+
+// Renders a recorded frame without the rest of the system, returning its BGR555 pixels
+pub fn replay_geometry(reader: &mut dyn Read) -> io::Result<Vec<u16>> {
+    let recording = GeometryRecording::read(reader)?;
+    let mut scheduler = Scheduler::new();
+    let mut interrupts = InterruptRequest::empty();
+
+    // Map banks A-D to the texture slots and E-G to the texture palette slots
+    let mut vram = VRAM::new();
+    let banks = [
+        (0, 0x0680_0000, 0x83),
+        (1, 0x0682_0000, 0x8B),
+        (2, 0x0684_0000, 0x93),
+        (3, 0x0686_0000, 0x9B),
+    ];
+    for (index, addr, cnt) in banks {
+        let data = &recording.textures[index * 0x2_0000..(index + 1) * 0x2_0000];
+        load_bank(&mut vram, index, addr, cnt, data);
+    }
+    let pal_banks = [
+        (4, 0x0688_0000, 0x83, 0x0..0x1_0000),
+        (5, 0x0689_0000, 0x93, 0x1_0000..0x1_4000),
+        (6, 0x0689_4000, 0x9B, 0x1_4000..0x1_8000),
+    ];
+    for (index, addr, cnt, range) in pal_banks {
+        load_bank(&mut vram, index, addr, cnt, &recording.textures_pal[range]);
+    }
+
+    let mut engine = Engine3D::new();
+    for (i, byte) in recording.disp3dcnt.to_le_bytes().into_iter().enumerate() {
+        engine.disp3dcnt.write(&mut scheduler, i, byte);
+    }
+    let mut write_register = |addr: u32, bytes: &[u8]| {
+        for (i, byte) in bytes.iter().enumerate() {
+            engine.write_register(&mut interrupts, &mut scheduler, addr + i as u32, *byte);
+        }
+    };
+    write_register(0x0400_0350, &recording.clear_color.to_le_bytes());
+    write_register(0x0400_0354, &recording.clear_depth.to_le_bytes());
+    for (i, color) in recording.toon_table.iter().enumerate() {
+        write_register(0x0400_0380 + 2 * i as u32, &color.to_le_bytes());
+    }
+
+    for entry in recording.commands {
+        engine.gxfifo.push_back(entry);
+        engine.exec_commands(&mut interrupts);
+    }
+    engine.render(&vram);
+    Ok((0..GPU::WIDTH * GPU::HEIGHT)
+        .map(|i| engine.pixel_color(i))
+        .collect())
+}
+
+fn load_bank(vram: &mut VRAM, index: usize, addr: u32, cnt: u8, data: &[u8]) {
+    vram.write_vram_cnt(index, 0x80);
+    for (i, byte) in data.iter().enumerate() {
+        vram.arm9_write(addr + i as u32, *byte);
+    }
+    vram.write_vram_cnt(index, cnt);
+}
@@ -104,7 +104,10 @@ impl From<u8> for CommandFifoIRQ {
             0 => CommandFifoIRQ::Never,
             1 => CommandFifoIRQ::LessHalf,
             2 => CommandFifoIRQ::Empty,
-            3 => panic!("Reserved Command FIFO IRQ"),
+            3 => {
+                unimplemented_behavior!("Reserved Command FIFO IRQ");
+                CommandFifoIRQ::Never
+            }
             _ => unreachable!(),
         }
     }
@@ -205,6 +208,15 @@ impl ClearColor {
             polygon_id: 0,
         }
     }
+
+    pub fn raw(&self) -> u32 {
+        (self.polygon_id as u32) << 24
+            | (self.a as u32) << 16
+            | (self.fog as u32) << 15
+            | (self.b as u32) << 10
+            | (self.g as u32) << 5
+            | self.r as u32
+    }
 }
 
 impl IORegister for ClearColor {
@@ -242,6 +254,10 @@ impl ClearDepth {
     pub fn depth(&self) -> u32 {
         (self.depth as u32) * 0x200 + 0x1FF
     }
+
+    pub fn raw(&self) -> u16 {
+        self.depth
+    }
 }
 
 impl IORegister for ClearDepth {
@@ -306,9 +322,22 @@ impl TextureParams {
         self.color0_transparent = value >> 29 & 0x1 != 0;
         self.coord_transformation_mode = TexCoordTransformationMode::from(value >> 30 & 0x3);
     }
+
+    pub fn raw(&self) -> u32 {
+        (self.coord_transformation_mode as u32) << 30
+            | (self.color0_transparent as u32) << 29
+            | (self.format as u32) << 26
+            | self.size_t_shift.saturating_sub(3) << 23
+            | self.size_s_shift.saturating_sub(3) << 20
+            | (self.flip_t as u32) << 19
+            | (self.flip_s as u32) << 18
+            | (self.repeat_t as u32) << 17
+            | (self.repeat_s as u32) << 16
+            | (self.vram_offset >> 3) as u32
+    }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum TextureFormat {
     NoTexture = 0,
     A3I5 = 1,
@@ -336,7 +365,7 @@ impl From<u32> for TextureFormat {
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TexCoordTransformationMode {
     None = 0,
     TexCoord = 1,
@@ -404,9 +433,26 @@ impl PolygonAttributes {
         self.alpha = (value >> 16 & 0x1F) as u8;
         self.polygon_id = (value >> 24 & 0x3F) as u8;
     }
+
+    pub fn raw(&self) -> u32 {
+        (self.polygon_id as u32) << 24
+            | (self.alpha as u32) << 16
+            | (self.fog_enable as u32) << 15
+            | (self.depth_test_eq as u32) << 14
+            | (self.render_1dot_behind_depth as u32) << 13
+            | (self.render_far_plane_intersecting as u32) << 12
+            | (self.set_depth_translucent as u32) << 11
+            | (self.render_front as u32) << 7
+            | (self.render_back as u32) << 6
+            | (self.mode as u32) << 4
+            | (self.lights_enabled[3] as u32) << 3
+            | (self.lights_enabled[2] as u32) << 2
+            | (self.lights_enabled[1] as u32) << 1
+            | self.lights_enabled[0] as u32
+    }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PolygonMode {
     Modulation = 0,
     Decal = 1,
@@ -480,6 +526,10 @@ impl Viewport {
         assert!(self.height as usize <= GPU::HEIGHT);
     }
 
+    pub fn raw(&self) -> u32 {
+        (self.y2 as u32) << 24 | (self.x2 as u32) << 16 | (self.y1 as u32) << 8 | self.x1 as u32
+    }
+
     pub fn screen_coords(&self, clip_coords: &Vec4) -> [u32; 2] {
         let w = clip_coords[3].raw();
         if w == 0 {
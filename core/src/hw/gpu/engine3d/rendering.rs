@@ -31,8 +31,14 @@ impl Engine3D {
             pixel.depth = self.clear_depth.depth();
         }
 
-        assert!(!self.frame_params.w_buffer); // TODO: Implement W-Buffer
-        assert!(!self.disp3dcnt.alpha_test); // TODO: Implement alpha test
+        // TODO: Implement W-Buffer
+        if self.frame_params.w_buffer {
+            unimplemented_behavior!("3D W-Buffer");
+        }
+        // TODO: Implement alpha test
+        if self.disp3dcnt.alpha_test {
+            unimplemented_behavior!("3D Alpha Test");
+        }
 
         let disp3dcnt = &self.disp3dcnt;
         let toon_table = &self.toon_table;
@@ -58,21 +64,24 @@ impl Engine3D {
                 PolygonMode::Shadow => {
                     tex_color.unwrap_or_else(|| FrameBufferColor::new5(Color::new5(0, 0, 0), 0))
                 }
-                _ => todo!(),
+                PolygonMode::Decal => {
+                    unimplemented_behavior!("Decal Polygons");
+                    tex_color.unwrap_or(vert_color)
+                }
             }
         };
 
         let vertices = &self.vertices;
         let frame_buffer = &mut self.frame_buffer;
-        let mut render = |polygon: Polygon| {
+        let mut render = |polygon: &Polygon| {
             let vertices = &vertices[polygon.start_vert..polygon.end_vert];
-            Self::render_polygon(disp3dcnt, blend, &polygon, vertices, frame_buffer);
+            Self::render_polygon(disp3dcnt, blend, polygon, vertices, frame_buffer);
         };
 
         if disp3dcnt.alpha_blending {
-            let (opaque, translucent): (Vec<Polygon>, Vec<Polygon>) = self
+            let (opaque, translucent): (Vec<&Polygon>, Vec<&Polygon>) = self
                 .polygons
-                .drain(..)
+                .iter()
                 .partition(|polygon| polygon.attrs.alpha == 0x1F);
 
             for polygon in opaque {
@@ -82,11 +91,19 @@ impl Engine3D {
                 render(polygon)
             }
         } else {
-            for polygon in self.polygons.drain(..) {
+            for polygon in self.polygons.iter() {
                 render(polygon)
             }
         }
 
+        if self.recorder.is_some() {
+            self.finish_recording(vram);
+        }
+
+        // Kept around for debugging
+        std::mem::swap(&mut self.polygons, &mut self.frame_polygons);
+        std::mem::swap(&mut self.vertices, &mut self.frame_vertices);
+        self.polygons.clear();
         self.vertices.clear();
         self.gxstat.geometry_engine_busy = false;
         self.polygons_submitted = false;
@@ -275,7 +292,12 @@ impl Engine3D {
         }
     }
 
-    fn get_tex_color(vram: &VRAM, polygon: &Polygon, s: i32, t: i32) -> Option<FrameBufferColor> {
+    pub(super) fn get_tex_color(
+        vram: &VRAM,
+        polygon: &Polygon,
+        s: i32,
+        t: i32,
+    ) -> Option<FrameBufferColor> {
         let vram_offset = polygon.tex_params.vram_offset;
         let pal_offset = polygon.palette_base;
         let size = (
@@ -778,7 +800,7 @@ impl FrameBufferPixel {
 }
 
 #[derive(Clone, Copy)]
-struct FrameBufferColor {
+pub(super) struct FrameBufferColor {
     color: Color,
     a: u8,
 }
@@ -338,6 +338,36 @@ impl VRAM {
         }
     }
 
+    // Where addr is in the first bank mapped there, as the address LCDC mode shows it at
+    // This identifies the memory behind VRAM addresses regardless of how it's mapped
+    pub fn arm9_lcdc_addr(&self, addr: u32) -> Option<u32> {
+        let index = addr as usize / VRAM::MAPPING_LEN;
+        let mapping = match addr as usize & 0x00E0_0000 {
+            VRAM::ENGINE_A_BG_OFFSET => &self.engine_a_bg[index & VRAM::ENGINE_A_BG_MASK],
+            VRAM::ENGINE_B_BG_OFFSET => &self.engine_b_bg[index & VRAM::ENGINE_B_BG_MASK],
+            VRAM::ENGINE_A_OBJ_OFFSET => &self.engine_a_obj[index & VRAM::ENGINE_A_OBJ_MASK],
+            VRAM::ENGINE_B_OBJ_OFFSET => &self.engine_b_obj[index & VRAM::ENGINE_B_OBJ_MASK],
+            _ => self
+                .lcdc
+                .get((addr as usize & 0xF_C000) / VRAM::MAPPING_LEN)?,
+        };
+        mapping
+            .first()
+            .map(|bank| VRAM::lcdc_addr(*bank, addr as usize))
+    }
+
+    pub fn arm7_lcdc_addr(&self, addr: u32) -> Option<u32> {
+        let index = (addr as usize / VRAM::BANKS_LEN[VRAM::BANK_C]) % 2;
+        self.arm7_wram[index]
+            .first()
+            .map(|bank| VRAM::lcdc_addr(*bank, addr as usize))
+    }
+
+    fn lcdc_addr(bank: Bank, addr: usize) -> u32 {
+        let bank = bank as usize;
+        (VRAM::LCDC_OFFSETS[bank] + (addr & (VRAM::BANKS_LEN[bank] - 1))) as u32
+    }
+
     pub fn get_lcdc_bank(&self, bank: u8) -> Option<&Vec<u8>> {
         if self.lcdc_enabled[bank as usize] {
             Some(&self.banks[bank as usize])
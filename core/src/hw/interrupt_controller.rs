@@ -44,6 +44,7 @@ bitflags! {
         const GAME_CARD_TRANSFER_COMPLETION = 1 << 19;
         const GAME_CARD_IREQ_MC = 1 << 20;
         const GEOMETRY_COMMAND_FIFO = 1 << 21;
+        const WIFI = 1 << 24;
     }
 }
 
@@ -75,6 +76,7 @@ bitflags! {
         const GAME_CARD_TRANSFER_COMPLETION = 1 << 19;
         const GAME_CARD_IREQ_MC = 1 << 20;
         const GEOMETRY_COMMAND_FIFO = 1 << 21; // TODO: Don't include for interrupts7
+        const WIFI = 1 << 24;
     }
 }
 
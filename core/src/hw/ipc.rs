@@ -115,7 +115,9 @@ impl IPC {
         if !recv_cnt.enable {
             return (*prev_value, InterruptRequest::empty());
         }
-        assert!(send_cnt.enable); // TODO: Figure out behavior
+        if !send_cnt.enable {
+            unimplemented_behavior!("IPC FIFO Receive with Sending Side Disabled");
+        }
         let interrupt = if let Some(value) = recv_fifo.pop_front() {
             *prev_value = value;
             if send_cnt.enable && send_cnt.send_fifo_empty_irq && recv_fifo.is_empty() {
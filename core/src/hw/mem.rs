@@ -1,10 +1,22 @@
+#[macro_use]
+mod io_map;
+#[macro_use]
+mod io_registers;
 pub mod arm7;
 pub mod arm9;
+mod cache;
+mod code_pages;
 pub mod cp15;
 
 use super::{Scheduler, HW};
 use crate::num::{self, cast::FromPrimitive, NumCast, PrimInt, Unsigned};
+pub use arm7::ARM7_IO_MAP;
+pub use arm9::ARM9_IO_MAP;
+use cache::Cache;
+pub use code_pages::{CodeLayout, CodePages};
 pub use cp15::CP15;
+pub use io_map::IOMapEntry;
+pub use io_registers::{IORegisterField, IORegisterGroup, IORegisterInfo};
 use std::mem::size_of;
 use std::ops::BitOrAssign;
 
@@ -63,6 +75,16 @@ impl HW {
         }
     }
 
+    // TODO: Replace with const generic
+    fn gba_slot_timings(&self, is_arm9: bool, addr: u32) -> RegionTimings {
+        let cnt = &self.exmem.gba[is_arm9 as usize];
+        if addr >> 24 == 0xA {
+            cnt.ram_timings()
+        } else {
+            cnt.rom_timings()
+        }
+    }
+
     pub(super) fn read_mem<T: MemoryValue>(mem: &[u8], addr: u32) -> T {
         unsafe { *(&mem[addr as usize] as *const u8 as *const T) }
     }
@@ -73,6 +95,26 @@ impl HW {
         }
     }
 
+    // Called whenever the memory map changes, which makes every decoded block stale
+    pub(in crate::hw) fn remap_code_pages(&mut self) {
+        self.code_pages.remap(CodeLayout {
+            itcm: self.cp15.itcm_range(),
+            dtcm: self.cp15.dtcm_range(),
+            shared_wram: [
+                (self.wramcnt.arm7_offset, self.wramcnt.arm7_mask),
+                (self.wramcnt.arm9_offset, self.wramcnt.arm9_mask),
+            ],
+            vram: [
+                (0..CodePages::VRAM_SLOTS[0])
+                    .map(|i| self.gpu.vram.arm7_lcdc_addr(i * CodePages::VRAM_SLOT_LEN))
+                    .collect(),
+                (0..CodePages::VRAM_SLOTS[1])
+                    .map(|i| self.gpu.vram.arm9_lcdc_addr(i * CodePages::VRAM_SLOT_LEN))
+                    .collect(),
+            ],
+        });
+    }
+
     fn read_from_bytes<T: MemoryValue, F: Fn(&D, u32) -> u8, D>(
         device: &D,
         read_fn: &F,
@@ -130,6 +172,34 @@ pub enum AccessType {
     S,
 }
 
+// Waitstates of a memory region in 33 MHz bus cycles
+#[derive(Clone, Copy)]
+pub(super) struct RegionTimings {
+    bus_width: usize,
+    n: usize,
+    s: usize,
+}
+
+impl RegionTimings {
+    pub(super) const MAIN_MEM: RegionTimings = RegionTimings::new(16, 8, 1);
+    pub(super) const BUS16: RegionTimings = RegionTimings::new(16, 1, 1);
+    pub(super) const BUS32: RegionTimings = RegionTimings::new(32, 1, 1);
+
+    const fn new(bus_width: usize, n: usize, s: usize) -> Self {
+        RegionTimings { bus_width, n, s }
+    }
+
+    pub(super) fn access_time<T: MemoryValue>(&self, access_type: AccessType) -> usize {
+        let first = match access_type {
+            AccessType::N => self.n,
+            AccessType::S => self.s,
+        };
+        // Accesses wider than the bus are split into sequential accesses
+        let accesses = (8 * size_of::<T>() / self.bus_width).max(1);
+        first + (accesses - 1) * self.s
+    }
+}
+
 pub trait IORegister {
     fn read(&self, byte: usize) -> u8;
     fn write(&mut self, scheduler: &mut Scheduler, byte: usize, value: u8);
@@ -197,6 +267,22 @@ impl ExMemGBA {
         }
     }
 
+    const N_ACCESS_TIMES: [usize; 4] = [10, 8, 6, 18];
+    const S_ACCESS_TIMES: [usize; 2] = [6, 4];
+
+    fn rom_timings(&self) -> RegionTimings {
+        RegionTimings::new(
+            16,
+            ExMemGBA::N_ACCESS_TIMES[self.rom_n_access_time as usize],
+            ExMemGBA::S_ACCESS_TIMES[self.rom_s_access_time as usize],
+        )
+    }
+
+    fn ram_timings(&self) -> RegionTimings {
+        let access_time = ExMemGBA::N_ACCESS_TIMES[self.sram_access_time as usize];
+        RegionTimings::new(8, access_time, access_time)
+    }
+
     pub fn read(&self) -> u8 {
         self.phi << 5
             | self.rom_s_access_time << 4
@@ -312,7 +398,7 @@ impl IORegister for POWCNT2 {
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum HaltMode {
     None = 0,
     GBA = 1,
@@ -360,6 +446,8 @@ impl IORegister for HALTCNT {
     fn write(&mut self, _scheduler: &mut Scheduler, byte: usize, value: u8) {
         assert_eq!(byte, 0);
         self.mode = HaltMode::from_bits(value >> 6);
-        assert!(self.mode != HaltMode::GBA && self.mode != HaltMode::Sleep); // TODO: Implement
+        if self.mode == HaltMode::GBA || self.mode == HaltMode::Sleep {
+            unimplemented_behavior!("{:?} Mode", self.mode);
+        }
     }
 }
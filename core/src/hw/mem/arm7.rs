@@ -1,6 +1,8 @@
 mod io;
 
-use super::{AccessType, IORegister, MemoryValue, HW};
+pub use io::ARM7_IO_MAP;
+
+use super::{AccessType, IORegister, MemoryValue, RegionTimings, HW};
 use crate::{num, unlikely};
 use std::mem::size_of;
 
@@ -25,19 +27,45 @@ impl HW {
                     warn!("Reading from Unmapped ARM7 Shared WRAM: 0x{:X}", addr);
                     HW::read_mem(&self.iwram, addr & HW::IWRAM_MASK)
                 }
+                MemoryRegion::IO => self.arm7_read_io(addr),
+                MemoryRegion::Unknown => {
+                    warn!("Reading from Unknown 0x{:08X}", addr);
+                    num::zero()
+                }
+                // The GBA slot is always empty
+                _ => self.arm7_peek(addr),
+            }
+        }
+    }
+
+    // Reads without side effects for debuggers
+    // IO registers that change when read and unmapped memory read as 0
+    pub fn arm7_peek<T: MemoryValue>(&self, addr: u32) -> T {
+        let page_table_ptr = self.arm7_page_table[addr as usize >> HW::ARM7_PAGE_SHIFT];
+        if !page_table_ptr.is_null() {
+            unsafe {
+                let slice = std::slice::from_raw_parts(page_table_ptr, HW::ARM7_PAGE_SIZE);
+                HW::read_mem(slice, addr & HW::ARM7_PAGE_TABLE_MASK)
+            }
+        } else {
+            match MemoryRegion::from_addr(addr) {
+                MemoryRegion::SharedWRAM if self.wramcnt.arm7_mask == 0 => {
+                    HW::read_mem(&self.iwram, addr & HW::IWRAM_MASK)
+                }
                 MemoryRegion::SharedWRAM => HW::read_mem(
                     &self.shared_wram,
                     self.wramcnt.arm7_offset + (addr & self.wramcnt.arm7_mask),
                 ),
-                MemoryRegion::IO => self.arm7_read_io(addr),
+                MemoryRegion::IO => self.arm7_peek_io(addr),
                 MemoryRegion::VRAM => self.gpu.vram.arm7_read(addr),
                 MemoryRegion::GBAROM => self.read_gba_rom(false, addr),
-                MemoryRegion::GBARAM => todo!(),
+                MemoryRegion::GBARAM | MemoryRegion::Unknown => num::zero(),
             }
         }
     }
 
     pub fn arm7_write<T: MemoryValue>(&mut self, addr: u32, value: T) {
+        self.code_pages.write(false, addr);
         let page_table_ptr = self.arm7_page_table[addr as usize >> HW::ARM7_PAGE_SHIFT];
         if !page_table_ptr.is_null() {
             if unlikely(addr < self.bios7.len() as u32) {
@@ -60,8 +88,8 @@ impl HW {
                 ),
                 MemoryRegion::IO => self.arm7_write_io(addr, value),
                 MemoryRegion::VRAM => self.gpu.vram.arm7_write(addr, value),
-                MemoryRegion::GBAROM => (),
-                MemoryRegion::GBARAM => todo!(),
+                MemoryRegion::GBAROM | MemoryRegion::GBARAM => (),
+                MemoryRegion::Unknown => warn!("Writing to Unknown 0x{:08X} = 0x{:X}", addr, value),
             }
         }
     }
@@ -75,6 +103,13 @@ impl HW {
         }
     }
 
+    fn arm7_peek_io<T: MemoryValue>(&self, addr: u32) -> T {
+        let value = (0..size_of::<T>() as u32).fold(0, |value, i| {
+            value | (self.arm7_peek_io8(addr + i).unwrap_or(0) as u32) << (8 * i)
+        });
+        num::cast::<u32, T>(value).unwrap()
+    }
+
     fn arm7_write_io<T: MemoryValue>(&mut self, addr: u32, value: T) {
         match size_of::<T>() {
             1 => self.arm7_write_io8(addr, num::cast::<T, u8>(value).unwrap()),
@@ -86,14 +121,20 @@ impl HW {
 
     pub fn arm7_get_access_time<T: MemoryValue>(
         &mut self,
-        _access_type: AccessType,
-        _addr: u32,
+        access_type: AccessType,
+        addr: u32,
     ) -> usize {
-        // TODO: Use accurate timings
-        1
+        let timings = match addr >> 24 {
+            0x2 => RegionTimings::MAIN_MEM,
+            0x6 => RegionTimings::BUS16,
+            0x8..=0xA => self.gba_slot_timings(false, addr),
+            _ => RegionTimings::BUS32,
+        };
+        timings.access_time::<T>(access_type)
     }
 
     pub fn init_arm7_page_tables(&mut self) {
+        self.remap_code_pages();
         Self::map_page_table(
             &mut self.arm7_page_table,
             HW::ARM7_PAGE_SHIFT,
@@ -137,6 +178,7 @@ pub enum ARM7MemoryRegion {
     VRAM,
     GBAROM,
     GBARAM,
+    Unknown,
 }
 
 impl ARM7MemoryRegion {
@@ -148,7 +190,7 @@ impl ARM7MemoryRegion {
             0x6 => VRAM,
             0x8 | 0x9 => GBAROM,
             0xA => GBARAM,
-            _ => todo!(),
+            _ => Unknown,
         }
     }
 }
@@ -1,88 +1,431 @@
 use super::{IORegister, HW};
+use crate::hw::mem::io_map::{is_sorted, registers_fit, IOMapEntry};
+use crate::hw::mem::io_registers::*;
+
+pub const ARM7_IO_MAP: &[IOMapEntry] = &[
+    io!("DISPSTAT", 0x0400_0004, 2, "GPU", |hw| hw.gpu.dispstats[0], fields: DISPSTAT),
+    io!(
+        "VCOUNT",
+        0x0400_0006,
+        2,
+        "GPU",
+        |hw, addr| (hw.gpu.vcount >> (8 * (addr & 0x1))) as u8,
+        |_, _, _| (), // VCOUNT is read only
+        fields: VCOUNT
+    ),
+    io!(
+        "DMA0",
+        0x0400_00B0,
+        12,
+        "DMA",
+        |hw, addr| hw.dmas[0].read(0, addr - 0xB0),
+        |hw, addr, value| hw.dmas[0].write(0, &mut hw.scheduler, addr - 0xB0, value),
+        registers: [
+            register!("DMA0SAD", 0x0400_00B0, 4, NO_FIELDS),
+            register!("DMA0DAD", 0x0400_00B4, 4, NO_FIELDS),
+            register!("DMA0CNT", 0x0400_00B8, 4, ARM7_DMACNT),
+        ]
+    ),
+    io!(
+        "DMA1",
+        0x0400_00BC,
+        12,
+        "DMA",
+        |hw, addr| hw.dmas[0].read(1, addr - 0xBC),
+        |hw, addr, value| hw.dmas[0].write(1, &mut hw.scheduler, addr - 0xBC, value),
+        registers: [
+            register!("DMA1SAD", 0x0400_00BC, 4, NO_FIELDS),
+            register!("DMA1DAD", 0x0400_00C0, 4, NO_FIELDS),
+            register!("DMA1CNT", 0x0400_00C4, 4, ARM7_DMACNT),
+        ]
+    ),
+    io!(
+        "DMA2",
+        0x0400_00C8,
+        12,
+        "DMA",
+        |hw, addr| hw.dmas[0].read(2, addr - 0xC8),
+        |hw, addr, value| hw.dmas[0].write(2, &mut hw.scheduler, addr - 0xC8, value),
+        registers: [
+            register!("DMA2SAD", 0x0400_00C8, 4, NO_FIELDS),
+            register!("DMA2DAD", 0x0400_00CC, 4, NO_FIELDS),
+            register!("DMA2CNT", 0x0400_00D0, 4, ARM7_DMACNT),
+        ]
+    ),
+    io!(
+        "DMA3",
+        0x0400_00D4,
+        12,
+        "DMA",
+        |hw, addr| hw.dmas[0].read(3, addr - 0xD4),
+        |hw, addr, value| hw.dmas[0].write(3, &mut hw.scheduler, addr - 0xD4, value),
+        registers: [
+            register!("DMA3SAD", 0x0400_00D4, 4, NO_FIELDS),
+            register!("DMA3DAD", 0x0400_00D8, 4, NO_FIELDS),
+            register!("DMA3CNT", 0x0400_00DC, 4, ARM7_DMACNT),
+        ]
+    ),
+    io!(
+        "TM0CNT",
+        0x0400_0100,
+        4,
+        "Timers",
+        |hw, addr| hw.timers[0][0].read(&hw.scheduler, addr as usize % 4),
+        |hw, addr, value| hw.timers[0][0].write(&mut hw.scheduler, addr as usize % 4, value),
+        fields: TMCNT
+    ),
+    io!(
+        "TM1CNT",
+        0x0400_0104,
+        4,
+        "Timers",
+        |hw, addr| hw.timers[0][1].read(&hw.scheduler, addr as usize % 4),
+        |hw, addr, value| hw.timers[0][1].write(&mut hw.scheduler, addr as usize % 4, value),
+        fields: TMCNT
+    ),
+    io!(
+        "TM2CNT",
+        0x0400_0108,
+        4,
+        "Timers",
+        |hw, addr| hw.timers[0][2].read(&hw.scheduler, addr as usize % 4),
+        |hw, addr, value| hw.timers[0][2].write(&mut hw.scheduler, addr as usize % 4, value),
+        fields: TMCNT
+    ),
+    io!(
+        "TM3CNT",
+        0x0400_010C,
+        4,
+        "Timers",
+        |hw, addr| hw.timers[0][3].read(&hw.scheduler, addr as usize % 4),
+        |hw, addr, value| hw.timers[0][3].write(&mut hw.scheduler, addr as usize % 4, value),
+        fields: TMCNT
+    ),
+    io!(
+        "KEYINPUT",
+        0x0400_0130,
+        2,
+        "Keypad",
+        |hw| hw.keypad.keyinput,
+        fields: KEYINPUT
+    ),
+    io!("KEYCNT", 0x0400_0132, 2, "Keypad", |hw| hw.keypad.keycnt, fields: KEYCNT),
+    io!(
+        "RCNT",
+        0x0400_0134,
+        2,
+        "Debug",
+        |_, _| 0, // TODO: Debug RCNT
+        |_, _, _| ()
+    ),
+    io!(
+        "EXTKEYIN",
+        0x0400_0136,
+        2,
+        "Keypad",
+        |hw| hw.keypad.extkeyin,
+        fields: EXTKEYIN
+    ),
+    io!("RTC", 0x0400_0138, 2, "RTC", |hw| hw.rtc, fields: RTC),
+    io!(
+        "IPCSYNC",
+        0x0400_0180,
+        4,
+        "IPC",
+        |hw, addr| hw.ipc.read_sync7(addr as usize % 4),
+        |hw, addr, value| hw.interrupts[1].request |= hw.ipc.write_sync7(addr as usize % 4, value),
+        fields: IPCSYNC
+    ),
+    io!(
+        "IPCFIFOCNT",
+        0x0400_0184,
+        4,
+        "IPC",
+        |hw, addr| hw.ipc.read_fifocnt7(addr as usize % 4),
+        |hw, addr, value| {
+            hw.interrupts[0].request |= hw.ipc.write_fifocnt7(addr as usize % 4, value)
+        },
+        fields: IPCFIFOCNT
+    ),
+    io!(
+        "AUXSPICNT",
+        0x0400_01A0,
+        2,
+        "Cartridge",
+        |hw, addr| {
+            let has_access = hw.exmem.nds_arm7_access;
+            hw.cartridge.spicnt.read(has_access, addr as usize % 2)
+        },
+        |hw, addr, value| {
+            let has_access = hw.exmem.nds_arm7_access;
+            hw.cartridge
+                .spicnt
+                .write(has_access, addr as usize % 2, value)
+        },
+        fields: AUXSPICNT
+    ),
+    io!(
+        "AUXSPIDATA",
+        0x0400_01A2,
+        2,
+        "Cartridge",
+        |hw, addr| match addr & 0x1 {
+            0 => hw.cartridge.read_spi_data(hw.exmem.nds_arm7_access),
+            _ => 0, // Upper byte of AUXSPIDATA is always 0
+        },
+        |hw, addr, value| {
+            // TODO: Does writing the upper byte do anything?
+            if addr & 0x1 == 0 {
+                hw.cartridge.write_spi_data(hw.exmem.nds_arm7_access, value);
+            }
+        }
+    ),
+    io!(
+        "ROMCTRL",
+        0x0400_01A4,
+        4,
+        "Cartridge",
+        |hw, addr| hw
+            .cartridge
+            .read_romctrl(hw.exmem.nds_arm7_access, addr as usize % 4),
+        |hw, addr, value| hw.cartridge.write_romctrl(
+            &mut hw.scheduler,
+            false,
+            hw.exmem.nds_arm7_access,
+            addr as usize % 4,
+            value,
+        ),
+        fields: ROMCTRL
+    ),
+    io!(
+        "CARD_COMMAND",
+        0x0400_01A8,
+        8,
+        "Cartridge",
+        |_, _| 0, // Write only
+        |hw, addr, value| {
+            let has_access = hw.exmem.nds_arm7_access;
+            hw.cartridge
+                .write_command(has_access, addr as usize % 8, value)
+        }
+    ),
+    io!(
+        "ROMSEED",
+        0x0400_01B0,
+        0xC,
+        "Cartridge",
+        |_, _| 0, // Write only
+        |hw, addr, value| hw.cartridge.write_seed(
+            hw.exmem.nds_arm7_access,
+            (addr - 0x0400_01B0) as usize,
+            value,
+        ),
+        registers: [
+            register!("ROMSEED0_L", 0x0400_01B0, 4, NO_FIELDS),
+            register!("ROMSEED1_L", 0x0400_01B4, 4, NO_FIELDS),
+            register!("ROMSEED0_H", 0x0400_01B8, 2, NO_FIELDS),
+            register!("ROMSEED1_H", 0x0400_01BA, 2, NO_FIELDS),
+        ]
+    ),
+    io!(
+        "SPICNT",
+        0x0400_01C0,
+        2,
+        "SPI",
+        |hw, addr| hw.spi.read_cnt(addr as usize % 2),
+        |hw, addr, value| hw
+            .spi
+            .write_cnt(&mut hw.scheduler, addr as usize % 2, value),
+        fields: SPICNT
+    ),
+    // SPI bug makes upper 8 bits always 0
+    io!(
+        "SPIDATA",
+        0x0400_01C2,
+        2,
+        "SPI",
+        |hw, addr| match addr & 0x1 {
+            0 => hw.spi.read_data(),
+            _ => 0,
+        },
+        |hw, addr, value| {
+            if addr & 0x1 == 0 {
+                hw.spi.write_data(value);
+            }
+        }
+    ),
+    io!(
+        "EXMEMSTAT",
+        0x0400_0204,
+        2,
+        "Memory",
+        |hw, addr| match addr & 0x1 {
+            0 => hw.exmem.read_arm7(),
+            _ => hw.exmem.read_common(),
+        },
+        |hw, addr, value| {
+            // Upper bits are read-only for ARM7
+            if addr & 0x1 == 0 {
+                hw.exmem.write_arm7(value);
+            }
+        },
+        fields: EXMEMCNT
+    ),
+    io!(
+        "WIFIWAITCNT",
+        0x0400_0206,
+        2,
+        "Wi-Fi",
+        |hw, addr| match addr & 0x1 {
+            0 => hw.wifi.read_waitcnt(),
+            _ => 0,
+        },
+        |hw, addr, value| {
+            if addr & 0x1 == 0 {
+                hw.wifi.write_waitcnt(value);
+            }
+        },
+        fields: WIFIWAITCNT
+    ),
+    io!(
+        "IME",
+        0x0400_0208,
+        4,
+        "Interrupts",
+        |hw| hw.interrupts[0].master_enable,
+        fields: IME
+    ),
+    io!(
+        "IE",
+        0x0400_0210,
+        4,
+        "Interrupts",
+        |hw| hw.interrupts[0].enable,
+        fields: INTERRUPTS
+    ),
+    io!(
+        "IF",
+        0x0400_0214,
+        4,
+        "Interrupts",
+        |hw| hw.interrupts[0].request,
+        fields: INTERRUPTS
+    ),
+    io!(
+        "WRAMSTAT",
+        0x0400_0241,
+        1,
+        "Memory",
+        |hw, _| hw.wramcnt.read(0),
+        |_, _, _| (), // WRAMCNT is read-only
+        fields: WRAMCNT
+    ),
+    io!(
+        "POSTFLG",
+        0x0400_0300,
+        1,
+        "System",
+        |hw, _| hw.postflg7,
+        |hw, _, value| hw.postflg7 |= value & 0x1, // Should only be written to during boot
+        fields: POSTFLG
+    ),
+    io!("HALTCNT", 0x0400_0301, 1, "System", |hw| hw.haltcnt, fields: HALTCNT),
+    io!("POWCNT2", 0x0400_0304, 4, "System", |hw| hw.powcnt2, fields: POWCNT2),
+    io!(
+        "Sound",
+        0x0400_0400,
+        0x120,
+        "SPU",
+        |hw, addr| hw.spu.read(addr as usize & 0xFFF),
+        |hw, addr, value| hw
+            .spu
+            .write(&mut hw.scheduler, addr as usize & 0xFFF, value),
+        registers: [
+            register!("SOUND0CNT", 0x0400_0400, 4, SOUNDCNT),
+            register!("SOUND1CNT", 0x0400_0410, 4, SOUNDCNT),
+            register!("SOUND2CNT", 0x0400_0420, 4, SOUNDCNT),
+            register!("SOUND3CNT", 0x0400_0430, 4, SOUNDCNT),
+            register!("SOUND4CNT", 0x0400_0440, 4, SOUNDCNT),
+            register!("SOUND5CNT", 0x0400_0450, 4, SOUNDCNT),
+            register!("SOUND6CNT", 0x0400_0460, 4, SOUNDCNT),
+            register!("SOUND7CNT", 0x0400_0470, 4, SOUNDCNT),
+            register!("SOUND8CNT", 0x0400_0480, 4, SOUNDCNT),
+            register!("SOUND9CNT", 0x0400_0490, 4, SOUNDCNT),
+            register!("SOUND10CNT", 0x0400_04A0, 4, SOUNDCNT),
+            register!("SOUND11CNT", 0x0400_04B0, 4, SOUNDCNT),
+            register!("SOUND12CNT", 0x0400_04C0, 4, SOUNDCNT),
+            register!("SOUND13CNT", 0x0400_04D0, 4, SOUNDCNT),
+            register!("SOUND14CNT", 0x0400_04E0, 4, SOUNDCNT),
+            register!("SOUND15CNT", 0x0400_04F0, 4, SOUNDCNT),
+            register!("SOUNDCNT", 0x0400_0500, 2, MASTER_SOUNDCNT),
+            register!("SOUNDBIAS", 0x0400_0504, 2, SOUNDBIAS),
+            register!("SNDCAP0CNT", 0x0400_0508, 1, SNDCAPCNT),
+            register!("SNDCAP1CNT", 0x0400_0509, 1, SNDCAPCNT),
+        ]
+    ),
+    // Wi-Fi registers are 16 bits wide, so wider accesses are handled separately
+    io!(
+        "Wi-Fi",
+        0x0480_0000,
+        0x10000,
+        "Wi-Fi",
+        |hw, addr| (hw.wifi.peek(&hw.scheduler, addr) >> (8 * (addr & 0x1))) as u8,
+        |_, addr, value| warn!(
+            "Ignoring 8-bit ARM7 Wi-Fi Write 0x{:08X} = {:02X}",
+            addr, value
+        ),
+        registers: [
+            register!("W_ID", 0x0480_8000, 2, NO_FIELDS),
+            register!("W_MODE_RST", 0x0480_8004, 2, W_MODE_RST),
+            register!("W_IF", 0x0480_8010, 2, W_IRQS),
+            register!("W_IE", 0x0480_8012, 2, W_IRQS),
+            register!("W_POWERSTATE", 0x0480_803C, 2, W_POWERSTATE),
+            register!("W_TXBUF_BEACON", 0x0480_8080, 2, W_TXBUF_LOC),
+            register!("W_BEACONINT", 0x0480_808C, 2, NO_FIELDS),
+            register!("W_TXBUF_CMD", 0x0480_8090, 2, W_TXBUF_LOC),
+            register!("W_TXBUF_LOC1", 0x0480_80A0, 2, W_TXBUF_LOC),
+            register!("W_TXBUF_LOC2", 0x0480_80A4, 2, W_TXBUF_LOC),
+            register!("W_TXBUF_LOC3", 0x0480_80A8, 2, W_TXBUF_LOC),
+            register!("W_TXREQ_READ", 0x0480_80B0, 2, W_TXSLOTS),
+            register!("W_TXBUSY", 0x0480_80B6, 2, W_TXSLOTS),
+            register!("W_TXSTAT", 0x0480_80B8, 2, W_TXSTAT),
+            register!("W_US_COUNTCNT", 0x0480_80E8, 2, W_ENABLE),
+            register!("W_US_COMPARECNT", 0x0480_80EA, 2, W_ENABLE),
+            register!("W_US_COMPARE", 0x0480_80F0, 8, NO_FIELDS),
+            register!("W_US_COUNT", 0x0480_80F8, 8, NO_FIELDS),
+            register!("W_RF_STATUS", 0x0480_8214, 2, NO_FIELDS),
+        ]
+    ),
+];
+
+const _: () = assert!(is_sorted(ARM7_IO_MAP) && registers_fit(ARM7_IO_MAP));
 
 impl HW {
     pub(super) fn arm7_read_io8(&self, addr: u32) -> u8 {
+        self.read_io8(ARM7_IO_MAP, "ARM7", addr)
+    }
+
+    // Returns None for unknown registers
+    pub(super) fn arm7_peek_io8(&self, addr: u32) -> Option<u8> {
+        self.peek_io8(ARM7_IO_MAP, addr)
+    }
+
+    pub(super) fn arm7_read_io16(&mut self, addr: u32) -> u16 {
         match addr {
-            0x0400_0004 => self.gpu.dispstats[0].read(0),
-            0x0400_0005 => self.gpu.dispstats[0].read(1),
-            0x0400_0006 => (self.gpu.vcount >> 0) as u8,
-            0x0400_0007 => (self.gpu.vcount >> 8) as u8,
-            0x0400_00B0..=0x0400_00BB => self.dmas[0].read(0, addr - 0xB0),
-            0x0400_00BC..=0x0400_00C7 => self.dmas[0].read(1, addr - 0xBC),
-            0x0400_00C8..=0x0400_00D3 => self.dmas[0].read(2, addr - 0xC8),
-            0x0400_00D4..=0x0400_00DF => self.dmas[0].read(3, addr - 0xD4),
-            0x0400_0100..=0x0400_0103 => self.timers[0][0].read(&self.scheduler, addr as usize % 4),
-            0x0400_0104..=0x0400_0107 => self.timers[0][1].read(&self.scheduler, addr as usize % 4),
-            0x0400_0108..=0x0400_010B => self.timers[0][2].read(&self.scheduler, addr as usize % 4),
-            0x0400_010C..=0x0400_010F => self.timers[0][3].read(&self.scheduler, addr as usize % 4),
-            0x0400_0130 => self.keypad.keyinput.read(0),
-            0x0400_0131 => self.keypad.keyinput.read(1),
-            0x0400_0132 => self.keypad.keycnt.read(0),
-            0x0400_0133 => self.keypad.keycnt.read(1),
-            0x0400_0134..=0x0400_0135 => 0, // TODO: Debug RCNT
-            0x0400_0136 => self.keypad.extkeyin.read(0),
-            0x0400_0137 => self.keypad.extkeyin.read(1),
-            0x0400_0138 => self.rtc.read(0),
-            0x0400_0139 => self.rtc.read(1),
-            0x0400_0180 => self.ipc.read_sync7(0),
-            0x0400_0181 => self.ipc.read_sync7(1),
-            0x0400_0182 => self.ipc.read_sync7(2),
-            0x0400_0183 => self.ipc.read_sync7(3),
-            0x0400_0184 => self.ipc.read_fifocnt7(0),
-            0x0400_0185 => self.ipc.read_fifocnt7(1),
-            0x0400_0186 => self.ipc.read_fifocnt7(2),
-            0x0400_0187 => self.ipc.read_fifocnt7(3),
-            0x0400_01A0 => self.cartridge.spicnt.read(self.exmem.nds_arm7_access, 0),
-            0x0400_01A1 => self.cartridge.spicnt.read(self.exmem.nds_arm7_access, 1),
-            0x0400_01A2 => self.cartridge.read_spi_data(self.exmem.nds_arm7_access),
-            0x0400_01A3 => 0, // Upper byte of AUXSPIDATA is always 0
-            0x0400_01A4 => self.cartridge.read_romctrl(self.exmem.nds_arm7_access, 0),
-            0x0400_01A5 => self.cartridge.read_romctrl(self.exmem.nds_arm7_access, 1),
-            0x0400_01A6 => self.cartridge.read_romctrl(self.exmem.nds_arm7_access, 2),
-            0x0400_01A7 => self.cartridge.read_romctrl(self.exmem.nds_arm7_access, 3),
-            0x0400_01C0 => self.spi.read_cnt(0),
-            0x0400_01C1 => self.spi.read_cnt(1),
-            0x0400_01C2 => self.spi.read_data(),
-            0x0400_01C3 => 0, // SPI bug makes upper 8 bits always 0
-            0x0400_0204 => self.exmem.read_arm7(),
-            0x0400_0205 => self.exmem.read_common(),
-            0x0400_0208 => self.interrupts[0].master_enable.read(0),
-            0x0400_0209 => self.interrupts[0].master_enable.read(1),
-            0x0400_020A => self.interrupts[0].master_enable.read(2),
-            0x0400_020B => self.interrupts[0].master_enable.read(3),
-            0x0400_0210 => self.interrupts[0].enable.read(0),
-            0x0400_0211 => self.interrupts[0].enable.read(1),
-            0x0400_0212 => self.interrupts[0].enable.read(2),
-            0x0400_0213 => self.interrupts[0].enable.read(3),
-            0x0400_0214 => self.interrupts[0].request.read(0),
-            0x0400_0215 => self.interrupts[0].request.read(1),
-            0x0400_0216 => self.interrupts[0].request.read(2),
-            0x0400_0217 => self.interrupts[0].request.read(3),
-            0x0400_0241 => self.wramcnt.read(0),
-            0x0400_0300 => self.postflg7,
-            0x0400_0301 => self.haltcnt.read(0),
-            0x0400_0304 => self.powcnt2.read(0),
-            0x0400_0305 => self.powcnt2.read(1),
-            0x0400_0306 => self.powcnt2.read(2),
-            0x0400_0307 => self.powcnt2.read(3),
-            0x0400_0400..=0x0400_051F => self.spu.read(addr as usize & 0xFFF),
-            0x0480_4000..=0x0480_5FFF => 0, // TODO: WiFi RAM
-            0x0480_8000..=0x0480_8FFF => 0, // TOOD: WiFi Registers
+            0x0480_0000..=0x0480_FFFF => self.read_wifi(addr),
             _ => {
-                warn!("Ignoring ARM7 IO Register Read at 0x{:08X}", addr);
-                0
+                (self.arm7_read_io8(addr) as u16) << 0 | (self.arm7_read_io8(addr + 1) as u16) << 8
             }
         }
     }
 
-    pub(super) fn arm7_read_io16(&self, addr: u32) -> u16 {
-        (self.arm7_read_io8(addr) as u16) << 0 | (self.arm7_read_io8(addr + 1) as u16) << 8
-    }
-
     pub(super) fn arm7_read_io32(&mut self, addr: u32) -> u32 {
         match addr {
             0x0410_0000 => self.ipc_fifo_recv(false),
             0x0410_0010 => self.read_game_card(false),
+            0x0480_0000..=0x0480_FFFF => {
+                (self.read_wifi(addr) as u32) << 0 | (self.read_wifi(addr + 2) as u32) << 16
+            }
             _ => {
                 (self.arm7_read_io8(addr) as u32) << 0
                     | (self.arm7_read_io8(addr + 1) as u32) << 8
@@ -93,182 +436,26 @@ impl HW {
     }
 
     pub(super) fn arm7_write_io8(&mut self, addr: u32, value: u8) {
-        match addr {
-            0x0400_0004 => self.gpu.dispstats[0].write(&mut self.scheduler, 0, value),
-            0x0400_0005 => self.gpu.dispstats[0].write(&mut self.scheduler, 1, value),
-            0x0400_0006 => (), // VCOUNT is read only
-            0x0400_0007 => (), // VCOUNT is read only
-            0x0400_00B0..=0x0400_00BB => {
-                self.dmas[0].write(0, &mut self.scheduler, addr - 0xB0, value)
-            }
-            0x0400_00BC..=0x0400_00C7 => {
-                self.dmas[0].write(1, &mut self.scheduler, addr - 0xBC, value)
-            }
-            0x0400_00C8..=0x0400_00D3 => {
-                self.dmas[0].write(2, &mut self.scheduler, addr - 0xC8, value)
-            }
-            0x0400_00D4..=0x0400_00DF => {
-                self.dmas[0].write(3, &mut self.scheduler, addr - 0xD4, value)
-            }
-            0x0400_0100..=0x0400_0103 => {
-                self.timers[0][0].write(&mut self.scheduler, addr as usize % 4, value)
-            }
-            0x0400_0104..=0x0400_0107 => {
-                self.timers[0][1].write(&mut self.scheduler, addr as usize % 4, value)
-            }
-            0x0400_0108..=0x0400_010B => {
-                self.timers[0][2].write(&mut self.scheduler, addr as usize % 4, value)
-            }
-            0x0400_010C..=0x0400_010F => {
-                self.timers[0][3].write(&mut self.scheduler, addr as usize % 4, value)
-            }
-            0x0400_0134..=0x0400_0135 => (), // TODO: Debug RCNT
-            0x0400_0136 => self.keypad.extkeyin.write(&mut self.scheduler, 0, value),
-            0x0400_0137 => self.keypad.extkeyin.write(&mut self.scheduler, 1, value),
-            0x0400_0138 => self.rtc.write(&mut self.scheduler, 0, value),
-            0x0400_0139 => self.rtc.write(&mut self.scheduler, 1, value),
-            0x0400_0180 => self.interrupts[1].request |= self.ipc.write_sync7(0, value),
-            0x0400_0181 => self.interrupts[1].request |= self.ipc.write_sync7(1, value),
-            0x0400_0182 => self.interrupts[1].request |= self.ipc.write_sync7(2, value),
-            0x0400_0183 => self.interrupts[1].request |= self.ipc.write_sync7(3, value),
-            0x0400_0184 => self.interrupts[0].request |= self.ipc.write_fifocnt7(0, value),
-            0x0400_0185 => self.interrupts[0].request |= self.ipc.write_fifocnt7(1, value),
-            0x0400_0186 => self.interrupts[0].request |= self.ipc.write_fifocnt7(2, value),
-            0x0400_0187 => self.interrupts[0].request |= self.ipc.write_fifocnt7(3, value),
-            0x0400_01A0 => self
-                .cartridge
-                .spicnt
-                .write(self.exmem.nds_arm7_access, 0, value),
-            0x0400_01A1 => self
-                .cartridge
-                .spicnt
-                .write(self.exmem.nds_arm7_access, 1, value),
-            0x0400_01A2 => self
-                .cartridge
-                .write_spi_data(self.exmem.nds_arm7_access, value),
-            0x0400_01A3 => (), // TODO: Does this write do anything?
-            0x0400_01A4 => self.cartridge.write_romctrl(
-                &mut self.scheduler,
-                false,
-                self.exmem.nds_arm7_access,
-                0,
-                value,
-            ),
-            0x0400_01A5 => self.cartridge.write_romctrl(
-                &mut self.scheduler,
-                false,
-                self.exmem.nds_arm7_access,
-                1,
-                value,
-            ),
-            0x0400_01A6 => self.cartridge.write_romctrl(
-                &mut self.scheduler,
-                false,
-                self.exmem.nds_arm7_access,
-                2,
-                value,
-            ),
-            0x0400_01A7 => self.cartridge.write_romctrl(
-                &mut self.scheduler,
-                false,
-                self.exmem.nds_arm7_access,
-                3,
-                value,
-            ),
-            0x0400_01A8 => self
-                .cartridge
-                .write_command(self.exmem.nds_arm7_access, 0, value),
-            0x0400_01A9 => self
-                .cartridge
-                .write_command(self.exmem.nds_arm7_access, 1, value),
-            0x0400_01AA => self
-                .cartridge
-                .write_command(self.exmem.nds_arm7_access, 2, value),
-            0x0400_01AB => self
-                .cartridge
-                .write_command(self.exmem.nds_arm7_access, 3, value),
-            0x0400_01AC => self
-                .cartridge
-                .write_command(self.exmem.nds_arm7_access, 4, value),
-            0x0400_01AD => self
-                .cartridge
-                .write_command(self.exmem.nds_arm7_access, 5, value),
-            0x0400_01AE => self
-                .cartridge
-                .write_command(self.exmem.nds_arm7_access, 6, value),
-            0x0400_01AF => self
-                .cartridge
-                .write_command(self.exmem.nds_arm7_access, 7, value),
-            0x0400_01C0 => self.spi.write_cnt(&mut self.scheduler, 0, value),
-            0x0400_01C1 => self.spi.write_cnt(&mut self.scheduler, 1, value),
-            0x0400_01C2 => self.spi.write_data(value),
-            0x0400_01C3 => (), // SPI bug makes upper 8 bits always 0
-            0x0400_0204 => self.exmem.write_arm7(value),
-            0x0400_0205 => (), // Upper bits are read-only for ARM7
-            0x0400_0208 => self.interrupts[0]
-                .master_enable
-                .write(&mut self.scheduler, 0, value),
-            0x0400_0209 => self.interrupts[0]
-                .master_enable
-                .write(&mut self.scheduler, 1, value),
-            0x0400_020A => self.interrupts[0]
-                .master_enable
-                .write(&mut self.scheduler, 2, value),
-            0x0400_020B => self.interrupts[0]
-                .master_enable
-                .write(&mut self.scheduler, 3, value),
-            0x0400_0210 => self.interrupts[0]
-                .enable
-                .write(&mut self.scheduler, 0, value),
-            0x0400_0211 => self.interrupts[0]
-                .enable
-                .write(&mut self.scheduler, 1, value),
-            0x0400_0212 => self.interrupts[0]
-                .enable
-                .write(&mut self.scheduler, 2, value),
-            0x0400_0213 => self.interrupts[0]
-                .enable
-                .write(&mut self.scheduler, 3, value),
-            0x0400_0214 => self.interrupts[0]
-                .request
-                .write(&mut self.scheduler, 0, value),
-            0x0400_0215 => self.interrupts[0]
-                .request
-                .write(&mut self.scheduler, 1, value),
-            0x0400_0216 => self.interrupts[0]
-                .request
-                .write(&mut self.scheduler, 2, value),
-            0x0400_0217 => self.interrupts[0]
-                .request
-                .write(&mut self.scheduler, 3, value),
-            0x0400_0241 => (),                           // WRAMCNT is read-only
-            0x0400_0300 => self.postflg7 |= value & 0x1, // Should only be written to during boot
-            0x0400_0301 => self.haltcnt.write(&mut self.scheduler, 0, value),
-            0x0400_0304 => self.powcnt2.write(&mut self.scheduler, 0, value),
-            0x0400_0305 => self.powcnt2.write(&mut self.scheduler, 1, value),
-            0x0400_0306 => self.powcnt2.write(&mut self.scheduler, 2, value),
-            0x0400_0307 => self.powcnt2.write(&mut self.scheduler, 3, value),
-            0x0400_0400..=0x0400_051F => {
-                self.spu
-                    .write(&mut self.scheduler, addr as usize & 0xFFF, value)
-            }
-            0x0480_4000..=0x0480_5FFF => (), // TODO: WiFi RAM
-            0x0480_8000..=0x0480_8FFF => (), // TOOD: WiFi Registers
-            _ => warn!(
-                "Ignoring ARM7 IO Register Write 0x{:08X} = {:02X}",
-                addr, value
-            ),
-        }
+        self.write_io8(ARM7_IO_MAP, "ARM7", addr, value)
     }
 
     pub(super) fn arm7_write_io16(&mut self, addr: u32, value: u16) {
-        self.arm7_write_io8(addr + 0, (value >> 0) as u8);
-        self.arm7_write_io8(addr + 1, (value >> 8) as u8);
+        match addr {
+            0x0480_0000..=0x0480_FFFF => self.write_wifi(addr, value),
+            _ => {
+                self.arm7_write_io8(addr + 0, (value >> 0) as u8);
+                self.arm7_write_io8(addr + 1, (value >> 8) as u8);
+            }
+        }
     }
 
     pub(super) fn arm7_write_io32(&mut self, addr: u32, value: u32) {
         match addr {
             0x0400_0188 => self.ipc_fifo_send(true, value),
+            0x0480_0000..=0x0480_FFFF => {
+                self.write_wifi(addr, value as u16);
+                self.write_wifi(addr + 2, (value >> 16) as u16);
+            }
             _ => {
                 self.arm7_write_io8(addr + 0, (value >> 0) as u8);
                 self.arm7_write_io8(addr + 1, (value >> 8) as u8);
@@ -1,6 +1,8 @@
 mod io;
 
-use super::{AccessType, IORegister, MemoryValue, HW};
+pub use io::ARM9_IO_MAP;
+
+use super::{AccessType, Cache, IORegister, MemoryValue, RegionTimings, HW};
 use crate::hw::gpu::{Engine2D, EngineType, GPU};
 use crate::{num, unlikely};
 use std::mem::size_of;
@@ -26,11 +28,34 @@ impl HW {
                     warn!("Reading from Unmapped ARM9 Shared WRAM: 0x{:X}", addr);
                     num::zero()
                 }
+                MemoryRegion::IO => self.arm9_read_io(addr),
+                MemoryRegion::Unknown => {
+                    warn!("Reading from Unknown 0x{:08X}", addr);
+                    num::zero()
+                }
+                // The GBA slot is always empty
+                _ => self.arm9_peek(addr),
+            }
+        }
+    }
+
+    // Reads without side effects for debuggers
+    // IO registers that change when read and unmapped memory read as 0
+    pub fn arm9_peek<T: MemoryValue>(&self, addr: u32) -> T {
+        let page_table_ptr = self.arm9_page_table[addr as usize >> HW::ARM9_PAGE_SHIFT];
+        if !page_table_ptr.is_null() {
+            unsafe {
+                let slice = std::slice::from_raw_parts(page_table_ptr, HW::ARM9_PAGE_SIZE);
+                HW::read_mem(slice, addr & HW::ARM9_PAGE_TABLE_MASK)
+            }
+        } else {
+            match MemoryRegion::from_addr(addr) {
+                MemoryRegion::SharedWRAM if self.wramcnt.arm9_mask == 0 => num::zero(),
                 MemoryRegion::SharedWRAM => HW::read_mem(
                     &self.shared_wram,
                     self.wramcnt.arm9_offset + (addr & self.wramcnt.arm9_mask),
                 ),
-                MemoryRegion::IO => self.arm9_read_io(addr),
+                MemoryRegion::IO => self.arm9_peek_io(addr),
                 MemoryRegion::Palette if addr & 0x7FFF < 0x400 => HW::read_from_bytes(
                     &self.gpu.engine_a,
                     &Engine2D::read_palette_ram,
@@ -49,16 +74,13 @@ impl HW {
                     HW::read_mem(&self.gpu.engine_b.oam, addr & GPU::OAM_MASK as u32)
                 }
                 MemoryRegion::GBAROM => self.read_gba_rom(true, addr),
-                MemoryRegion::GBARAM => todo!(),
-                MemoryRegion::Unknown => {
-                    warn!("Reading from Unknown 0x{:08X}", addr);
-                    num::zero()
-                }
+                MemoryRegion::GBARAM | MemoryRegion::Unknown => num::zero(),
             }
         }
     }
 
     pub fn arm9_write<T: MemoryValue>(&mut self, addr: u32, value: T) {
+        self.code_pages.write(true, addr);
         let page_table_ptr = self.arm9_page_table[addr as usize >> HW::ARM9_PAGE_SHIFT];
         if !page_table_ptr.is_null() {
             if unlikely(addr >> 16 == 0xFFFF) {
@@ -95,8 +117,7 @@ impl HW {
                     addr & GPU::OAM_MASK as u32,
                     value,
                 ),
-                MemoryRegion::GBAROM => (),
-                MemoryRegion::GBARAM => todo!(),
+                MemoryRegion::GBAROM | MemoryRegion::GBARAM => (),
                 MemoryRegion::Unknown => warn!("Writing to Unknown 0x{:08X} = 0x{:X}", addr, value),
             }
         }
@@ -111,6 +132,13 @@ impl HW {
         }
     }
 
+    fn arm9_peek_io<T: MemoryValue>(&self, addr: u32) -> T {
+        let value = (0..size_of::<T>() as u32).fold(0, |value, i| {
+            value | (self.arm9_peek_io8(addr + i).unwrap_or(0) as u32) << (8 * i)
+        });
+        num::cast::<u32, T>(value).unwrap()
+    }
+
     fn arm9_write_io<T: MemoryValue>(&mut self, addr: u32, value: T) {
         match size_of::<T>() {
             1 => self.arm9_write_io8(addr, num::cast::<T, u8>(value).unwrap()),
@@ -120,16 +148,113 @@ impl HW {
         }
     }
 
+    // TCM and cache hits don't go over the bus
+    pub const ARM9_INTERNAL_ACCESS_TIME: usize = 1;
+
+    // Returns ARM9 cycles, which run at twice the speed of the bus
     pub fn arm9_get_access_time<T: MemoryValue>(
         &mut self,
-        _access_type: AccessType,
-        _addr: u32,
+        access_type: AccessType,
+        addr: u32,
+    ) -> usize {
+        if self.cp15.itcm_range().contains(&addr) || self.cp15.dtcm_range().contains(&addr) {
+            return HW::ARM9_INTERNAL_ACCESS_TIME;
+        }
+        let timings = match addr >> 24 {
+            0x2 => RegionTimings::MAIN_MEM,
+            0x5 | 0x6 => RegionTimings::BUS16,
+            0x8..=0xA => self.gba_slot_timings(true, addr),
+            _ => RegionTimings::BUS32,
+        };
+        2 * timings.access_time::<T>(access_type)
+    }
+
+    // CPU accesses go through the caches, unlike DMA
+    pub fn arm9_cpu_read<T: MemoryValue>(
+        &mut self,
+        access_type: AccessType,
+        addr: u32,
+        is_code: bool,
+    ) -> (T, usize) {
+        if !self.cp15.cachable(is_code, addr) {
+            let value = self.arm9_read(addr);
+            return (value, self.arm9_get_access_time::<T>(access_type, addr));
+        }
+        if !self.cp15.emulate_caches {
+            // Assume every cachable access hits
+            return (self.arm9_read(addr), HW::ARM9_INTERNAL_ACCESS_TIME);
+        }
+        if let Some(line_i) = self.arm9_cache(is_code).lookup(addr) {
+            return (
+                self.arm9_cache(is_code).read(line_i, addr),
+                HW::ARM9_INTERNAL_ACCESS_TIME,
+            );
+        }
+
+        let line_addr = addr & !(Cache::LINE_SIZE as u32 - 1);
+        let mut data = [0; Cache::LINE_SIZE];
+        for (i, word) in data.chunks_exact_mut(4).enumerate() {
+            let value = self.arm9_read::<u32>(line_addr + 4 * i as u32);
+            word.copy_from_slice(&value.to_le_bytes());
+        }
+        let access_time = self.arm9_get_access_time::<u32>(AccessType::N, line_addr)
+            + (Cache::LINE_SIZE / 4 - 1)
+                * self.arm9_get_access_time::<u32>(AccessType::S, line_addr);
+        let round_robin = self.cp15.round_robin_replacement();
+        let line_i = self.arm9_cache(is_code).fill(addr, data, round_robin);
+        let value = self.arm9_cache(is_code).read(line_i, addr);
+        self.write_back_arm9_dcache();
+        (value, access_time)
+    }
+
+    pub fn arm9_cpu_write<T: MemoryValue>(
+        &mut self,
+        access_type: AccessType,
+        addr: u32,
+        value: T,
     ) -> usize {
-        // TODO: Use accurate timings
-        1
+        if self.cp15.emulate_caches && self.cp15.cachable(false, addr) {
+            if let Some(line_i) = self.cp15.dcache.lookup(addr) {
+                let write_back = self.cp15.write_back(addr);
+                self.cp15.dcache.write(line_i, addr, value, write_back);
+                if write_back {
+                    return HW::ARM9_INTERNAL_ACCESS_TIME;
+                }
+            }
+        }
+        self.arm9_write(addr, value);
+        self.arm9_get_access_time::<T>(access_type, addr)
+    }
+
+    fn arm9_cache(&mut self, is_code: bool) -> &mut Cache {
+        if is_code {
+            &mut self.cp15.icache
+        } else {
+            &mut self.cp15.dcache
+        }
+    }
+
+    pub fn write_back_arm9_dcache(&mut self) {
+        for (addr, data) in std::mem::take(&mut self.cp15.dcache.writebacks) {
+            for (i, word) in data.chunks_exact(4).enumerate() {
+                let value = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                self.arm9_write(addr + 4 * i as u32, value);
+            }
+        }
+    }
+
+    pub fn set_arm9_cache_emulation(&mut self, enabled: bool) {
+        self.cp15.dcache.clean_all();
+        self.write_back_arm9_dcache();
+        self.cp15.dcache.invalidate_all();
+        self.cp15.icache.invalidate_all();
+        self.cp15.emulate_caches = enabled;
+        // Blocks decoded without the caches are stale once the I-cache is emulated
+        self.code_pages.invalidate_all();
     }
 
     pub fn init_arm9_page_tables(&mut self) {
+        self.remap_code_pages();
         Self::map_page_table(
             &mut self.arm9_page_table,
             HW::ARM9_PAGE_SHIFT,
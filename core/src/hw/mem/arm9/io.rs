@@ -1,108 +1,572 @@
 use super::{IORegister, HW};
+use crate::hw::mem::io_map::{is_sorted, registers_fit, IOMapEntry};
+use crate::hw::mem::io_registers::*;
 
-impl HW {
-    pub(super) fn arm9_read_io8(&self, addr: u32) -> u8 {
-        match addr {
-            0x0400_0000..=0x0400_0003 => self.gpu.engine_a.read_register(addr),
-            0x0400_0004 => self.gpu.dispstats[1].read(0),
-            0x0400_0005 => self.gpu.dispstats[1].read(1),
-            0x0400_0006 => (self.gpu.vcount >> 0) as u8,
-            0x0400_0007 => (self.gpu.vcount >> 8) as u8,
-            0x0400_0008..=0x0400_005F => self.gpu.engine_a.read_register(addr),
-            0x0400_0060..=0x0400_0063 => self.gpu.engine3d.disp3dcnt.read(addr as usize % 4),
-            0x0400_0064..=0x0400_0067 => self.gpu.dispcapcnt.read(addr as usize % 4),
-            0x0400_006C => self.gpu.engine_a.master_bright.read(0),
-            0x0400_006D => self.gpu.engine_a.master_bright.read(1),
-            0x0400_006E => self.gpu.engine_a.master_bright.read(2),
-            0x0400_006F => self.gpu.engine_a.master_bright.read(3),
-            0x0400_00B0..=0x0400_00BB => self.dmas[1].read(0, addr - 0xB0),
-            0x0400_00BC..=0x0400_00C7 => self.dmas[1].read(1, addr - 0xBC),
-            0x0400_00C8..=0x0400_00D3 => self.dmas[1].read(2, addr - 0xC8),
-            0x0400_00D4..=0x0400_00DF => self.dmas[1].read(3, addr - 0xD4),
-            0x0400_00E0..=0x0400_00E3 => {
-                HW::read_byte_from_value(&self.dma_fill[0], addr as usize % 4)
-            }
-            0x0400_00E4..=0x0400_00E7 => {
-                HW::read_byte_from_value(&self.dma_fill[1], addr as usize % 4)
+pub const ARM9_IO_MAP: &[IOMapEntry] = &[
+    io!(
+        "DISPCNT",
+        0x0400_0000,
+        4,
+        "GPU",
+        |hw, addr| hw.gpu.engine_a.read_register(addr),
+        |hw, addr, value| hw
+            .gpu
+            .engine_a
+            .write_register(&mut hw.scheduler, addr, value),
+        fields: DISPCNT
+    ),
+    io!("DISPSTAT", 0x0400_0004, 2, "GPU", |hw| hw.gpu.dispstats[1], fields: DISPSTAT),
+    io!(
+        "VCOUNT",
+        0x0400_0006,
+        2,
+        "GPU",
+        |hw, addr| (hw.gpu.vcount >> (8 * (addr & 0x1))) as u8,
+        |_, _, _| (), // VCOUNT is read only
+        fields: VCOUNT
+    ),
+    io!(
+        "BG0CNT-BLDY",
+        0x0400_0008,
+        0x58,
+        "GPU",
+        |hw, addr| hw.gpu.engine_a.read_register(addr),
+        |hw, addr, value| hw
+            .gpu
+            .engine_a
+            .write_register(&mut hw.scheduler, addr, value),
+        registers: [
+            register!("BG0CNT", 0x0400_0008, 2, BGCNT),
+            register!("BG1CNT", 0x0400_000A, 2, BGCNT),
+            register!("BG2CNT", 0x0400_000C, 2, BGCNT),
+            register!("BG3CNT", 0x0400_000E, 2, BGCNT),
+            register!("BG0HOFS", 0x0400_0010, 2, BGOFS),
+            register!("BG0VOFS", 0x0400_0012, 2, BGOFS),
+            register!("BG1HOFS", 0x0400_0014, 2, BGOFS),
+            register!("BG1VOFS", 0x0400_0016, 2, BGOFS),
+            register!("BG2HOFS", 0x0400_0018, 2, BGOFS),
+            register!("BG2VOFS", 0x0400_001A, 2, BGOFS),
+            register!("BG3HOFS", 0x0400_001C, 2, BGOFS),
+            register!("BG3VOFS", 0x0400_001E, 2, BGOFS),
+            register!("WININ", 0x0400_0048, 2, WININ),
+            register!("WINOUT", 0x0400_004A, 2, WINOUT),
+            register!("BLDCNT", 0x0400_0050, 2, BLDCNT),
+            register!("BLDALPHA", 0x0400_0052, 2, BLDALPHA),
+        ]
+    ),
+    io!(
+        "DISP3DCNT",
+        0x0400_0060,
+        4,
+        "GPU",
+        |hw| hw.gpu.engine3d.disp3dcnt,
+        fields: DISP3DCNT
+    ),
+    io!("DISPCAPCNT", 0x0400_0064, 4, "GPU", |hw| hw.gpu.dispcapcnt, fields: DISPCAPCNT),
+    io!(
+        "MASTER_BRIGHT",
+        0x0400_006C,
+        4,
+        "GPU",
+        |hw| hw.gpu.engine_a.master_bright,
+        fields: MASTER_BRIGHT
+    ),
+    io!(
+        "DMA0",
+        0x0400_00B0,
+        12,
+        "DMA",
+        |hw, addr| hw.dmas[1].read(0, addr - 0xB0),
+        |hw, addr, value| hw.dmas[1].write(0, &mut hw.scheduler, addr - 0xB0, value),
+        registers: [
+            register!("DMA0SAD", 0x0400_00B0, 4, NO_FIELDS),
+            register!("DMA0DAD", 0x0400_00B4, 4, NO_FIELDS),
+            register!("DMA0CNT", 0x0400_00B8, 4, ARM9_DMACNT),
+        ]
+    ),
+    io!(
+        "DMA1",
+        0x0400_00BC,
+        12,
+        "DMA",
+        |hw, addr| hw.dmas[1].read(1, addr - 0xBC),
+        |hw, addr, value| hw.dmas[1].write(1, &mut hw.scheduler, addr - 0xBC, value),
+        registers: [
+            register!("DMA1SAD", 0x0400_00BC, 4, NO_FIELDS),
+            register!("DMA1DAD", 0x0400_00C0, 4, NO_FIELDS),
+            register!("DMA1CNT", 0x0400_00C4, 4, ARM9_DMACNT),
+        ]
+    ),
+    io!(
+        "DMA2",
+        0x0400_00C8,
+        12,
+        "DMA",
+        |hw, addr| hw.dmas[1].read(2, addr - 0xC8),
+        |hw, addr, value| hw.dmas[1].write(2, &mut hw.scheduler, addr - 0xC8, value),
+        registers: [
+            register!("DMA2SAD", 0x0400_00C8, 4, NO_FIELDS),
+            register!("DMA2DAD", 0x0400_00CC, 4, NO_FIELDS),
+            register!("DMA2CNT", 0x0400_00D0, 4, ARM9_DMACNT),
+        ]
+    ),
+    io!(
+        "DMA3",
+        0x0400_00D4,
+        12,
+        "DMA",
+        |hw, addr| hw.dmas[1].read(3, addr - 0xD4),
+        |hw, addr, value| hw.dmas[1].write(3, &mut hw.scheduler, addr - 0xD4, value),
+        registers: [
+            register!("DMA3SAD", 0x0400_00D4, 4, NO_FIELDS),
+            register!("DMA3DAD", 0x0400_00D8, 4, NO_FIELDS),
+            register!("DMA3CNT", 0x0400_00DC, 4, ARM9_DMACNT),
+        ]
+    ),
+    io!(
+        "DMA0FILL",
+        0x0400_00E0,
+        4,
+        "DMA",
+        |hw, addr| HW::read_byte_from_value(&hw.dma_fill[0], addr as usize % 4),
+        |hw, addr, value| HW::write_byte_to_value(&mut hw.dma_fill[0], addr as usize % 4, value)
+    ),
+    io!(
+        "DMA1FILL",
+        0x0400_00E4,
+        4,
+        "DMA",
+        |hw, addr| HW::read_byte_from_value(&hw.dma_fill[1], addr as usize % 4),
+        |hw, addr, value| HW::write_byte_to_value(&mut hw.dma_fill[1], addr as usize % 4, value)
+    ),
+    io!(
+        "DMA2FILL",
+        0x0400_00E8,
+        4,
+        "DMA",
+        |hw, addr| HW::read_byte_from_value(&hw.dma_fill[2], addr as usize % 4),
+        |hw, addr, value| HW::write_byte_to_value(&mut hw.dma_fill[2], addr as usize % 4, value)
+    ),
+    io!(
+        "DMA3FILL",
+        0x0400_00EC,
+        4,
+        "DMA",
+        |hw, addr| HW::read_byte_from_value(&hw.dma_fill[3], addr as usize % 4),
+        |hw, addr, value| HW::write_byte_to_value(&mut hw.dma_fill[3], addr as usize % 4, value)
+    ),
+    io!(
+        "TM0CNT",
+        0x0400_0100,
+        4,
+        "Timers",
+        |hw, addr| hw.timers[1][0].read(&hw.scheduler, addr as usize % 4),
+        |hw, addr, value| hw.timers[1][0].write(&mut hw.scheduler, addr as usize % 4, value),
+        fields: TMCNT
+    ),
+    io!(
+        "TM1CNT",
+        0x0400_0104,
+        4,
+        "Timers",
+        |hw, addr| hw.timers[1][1].read(&hw.scheduler, addr as usize % 4),
+        |hw, addr, value| hw.timers[1][1].write(&mut hw.scheduler, addr as usize % 4, value),
+        fields: TMCNT
+    ),
+    io!(
+        "TM2CNT",
+        0x0400_0108,
+        4,
+        "Timers",
+        |hw, addr| hw.timers[1][2].read(&hw.scheduler, addr as usize % 4),
+        |hw, addr, value| hw.timers[1][2].write(&mut hw.scheduler, addr as usize % 4, value),
+        fields: TMCNT
+    ),
+    io!(
+        "TM3CNT",
+        0x0400_010C,
+        4,
+        "Timers",
+        |hw, addr| hw.timers[1][3].read(&hw.scheduler, addr as usize % 4),
+        |hw, addr, value| hw.timers[1][3].write(&mut hw.scheduler, addr as usize % 4, value),
+        fields: TMCNT
+    ),
+    io!(
+        "KEYINPUT",
+        0x0400_0130,
+        2,
+        "Keypad",
+        |hw| hw.keypad.keyinput,
+        fields: KEYINPUT
+    ),
+    io!("KEYCNT", 0x0400_0132, 2, "Keypad", |hw| hw.keypad.keycnt, fields: KEYCNT),
+    io!(
+        "EXTKEYIN",
+        0x0400_0136,
+        2,
+        "Keypad",
+        |hw| hw.keypad.extkeyin,
+        fields: EXTKEYIN
+    ),
+    io!(
+        "IPCSYNC",
+        0x0400_0180,
+        4,
+        "IPC",
+        |hw, addr| hw.ipc.read_sync9(addr as usize % 4),
+        |hw, addr, value| hw.interrupts[0].request |= hw.ipc.write_sync9(addr as usize % 4, value),
+        fields: IPCSYNC
+    ),
+    io!(
+        "IPCFIFOCNT",
+        0x0400_0184,
+        4,
+        "IPC",
+        |hw, addr| hw.ipc.read_fifocnt9(addr as usize % 4),
+        |hw, addr, value| {
+            hw.interrupts[1].request |= hw.ipc.write_fifocnt9(addr as usize % 4, value)
+        },
+        fields: IPCFIFOCNT
+    ),
+    io!(
+        "AUXSPICNT",
+        0x0400_01A0,
+        2,
+        "Cartridge",
+        |hw, addr| {
+            let has_access = !hw.exmem.nds_arm7_access;
+            hw.cartridge.spicnt.read(has_access, addr as usize % 2)
+        },
+        |hw, addr, value| {
+            let has_access = !hw.exmem.nds_arm7_access;
+            hw.cartridge
+                .spicnt
+                .write(has_access, addr as usize % 2, value)
+        },
+        fields: AUXSPICNT
+    ),
+    io!(
+        "AUXSPIDATA",
+        0x0400_01A2,
+        2,
+        "Cartridge",
+        |hw, addr| match addr & 0x1 {
+            0 => hw.cartridge.read_spi_data(!hw.exmem.nds_arm7_access),
+            _ => 0, // Upper byte of AUXSPIDATA is always 0
+        },
+        |hw, addr, value| {
+            // TODO: Does writing the upper byte do anything?
+            if addr & 0x1 == 0 {
+                hw.cartridge
+                    .write_spi_data(!hw.exmem.nds_arm7_access, value);
             }
-            0x0400_00E8..=0x0400_00EB => {
-                HW::read_byte_from_value(&self.dma_fill[2], addr as usize % 4)
+        }
+    ),
+    io!(
+        "ROMCTRL",
+        0x0400_01A4,
+        4,
+        "Cartridge",
+        |hw, addr| hw
+            .cartridge
+            .read_romctrl(!hw.exmem.nds_arm7_access, addr as usize % 4),
+        |hw, addr, value| hw.cartridge.write_romctrl(
+            &mut hw.scheduler,
+            true,
+            !hw.exmem.nds_arm7_access,
+            addr as usize % 4,
+            value,
+        ),
+        fields: ROMCTRL
+    ),
+    io!(
+        "CARD_COMMAND",
+        0x0400_01A8,
+        8,
+        "Cartridge",
+        |_, _| 0, // Write only
+        |hw, addr, value| {
+            let has_access = !hw.exmem.nds_arm7_access;
+            hw.cartridge
+                .write_command(has_access, addr as usize % 8, value)
+        }
+    ),
+    io!(
+        "ROMSEED",
+        0x0400_01B0,
+        0xC,
+        "Cartridge",
+        |_, _| 0, // Write only
+        |hw, addr, value| hw.cartridge.write_seed(
+            !hw.exmem.nds_arm7_access,
+            (addr - 0x0400_01B0) as usize,
+            value,
+        ),
+        registers: [
+            register!("ROMSEED0_L", 0x0400_01B0, 4, NO_FIELDS),
+            register!("ROMSEED1_L", 0x0400_01B4, 4, NO_FIELDS),
+            register!("ROMSEED0_H", 0x0400_01B8, 2, NO_FIELDS),
+            register!("ROMSEED1_H", 0x0400_01BA, 2, NO_FIELDS),
+        ]
+    ),
+    io!(
+        "EXMEMCNT",
+        0x0400_0204,
+        2,
+        "Memory",
+        |hw, addr| match addr & 0x1 {
+            0 => hw.exmem.read_arm9(),
+            _ => hw.exmem.read_common(),
+        },
+        |hw, addr, value| match addr & 0x1 {
+            0 => hw.exmem.write_arm9(value),
+            _ => hw.exmem.write_common(value),
+        },
+        fields: EXMEMCNT
+    ),
+    io!(
+        "IME",
+        0x0400_0208,
+        4,
+        "Interrupts",
+        |hw| hw.interrupts[1].master_enable,
+        fields: IME
+    ),
+    io!(
+        "IE",
+        0x0400_0210,
+        4,
+        "Interrupts",
+        |hw| hw.interrupts[1].enable,
+        fields: INTERRUPTS
+    ),
+    io!(
+        "IF",
+        0x0400_0214,
+        4,
+        "Interrupts",
+        |hw, addr| hw.interrupts[1].request.read(addr as usize & 0x3),
+        |hw, addr, value| {
+            hw.interrupts[1]
+                .request
+                .write(&mut hw.scheduler, addr as usize & 0x3, value);
+            hw.gpu
+                .engine3d
+                .check_interrupts(&mut hw.interrupts[1].request);
+        },
+        fields: INTERRUPTS
+    ),
+    io!(
+        "VRAMCNT_A-G",
+        0x0400_0240,
+        7,
+        "GPU",
+        |hw, addr| hw.gpu.vram.read_vram_cnt(addr as usize & 0xF),
+        |hw, addr, value| {
+            let index = addr as usize & 0xF;
+            if hw.gpu.vram.read_vram_cnt(index) != value {
+                hw.gpu.vram.write_vram_cnt(index, value);
+                hw.remap_code_pages();
             }
-            0x0400_00EC..=0x0400_00EF => {
-                HW::read_byte_from_value(&self.dma_fill[3], addr as usize % 4)
+        },
+        registers: [
+            register!("VRAMCNT_A", 0x0400_0240, 1, VRAMCNT),
+            register!("VRAMCNT_B", 0x0400_0241, 1, VRAMCNT),
+            register!("VRAMCNT_C", 0x0400_0242, 1, VRAMCNT),
+            register!("VRAMCNT_D", 0x0400_0243, 1, VRAMCNT),
+            register!("VRAMCNT_E", 0x0400_0244, 1, VRAMCNT),
+            register!("VRAMCNT_F", 0x0400_0245, 1, VRAMCNT),
+            register!("VRAMCNT_G", 0x0400_0246, 1, VRAMCNT),
+        ]
+    ),
+    io!(
+        "WRAMCNT",
+        0x0400_0247,
+        1,
+        "Memory",
+        |hw, _| hw.wramcnt.read(0),
+        |hw, _, value| {
+            hw.wramcnt.write(&mut hw.scheduler, 0, value);
+            hw.remap_code_pages();
+        },
+        fields: WRAMCNT
+    ),
+    io!(
+        "VRAMCNT_H-I",
+        0x0400_0248,
+        2,
+        "GPU",
+        |hw, addr| hw.gpu.vram.read_vram_cnt((addr as usize & 0xF) - 1),
+        |hw, addr, value| {
+            let index = (addr as usize & 0xF) - 1;
+            if hw.gpu.vram.read_vram_cnt(index) != value {
+                hw.gpu.vram.write_vram_cnt(index, value);
+                hw.remap_code_pages();
             }
-            0x0400_0100..=0x0400_0103 => self.timers[1][0].read(&self.scheduler, addr as usize % 4),
-            0x0400_0104..=0x0400_0107 => self.timers[1][1].read(&self.scheduler, addr as usize % 4),
-            0x0400_0108..=0x0400_010B => self.timers[1][2].read(&self.scheduler, addr as usize % 4),
-            0x0400_010C..=0x0400_010F => self.timers[1][3].read(&self.scheduler, addr as usize % 4),
-            0x0400_0130 => self.keypad.keyinput.read(0),
-            0x0400_0131 => self.keypad.keyinput.read(1),
-            0x0400_0132 => self.keypad.keycnt.read(0),
-            0x0400_0133 => self.keypad.keycnt.read(1),
-            0x0400_0136 => self.keypad.extkeyin.read(0),
-            0x0400_0137 => self.keypad.extkeyin.read(1),
-            0x0400_0180 => self.ipc.read_sync9(0),
-            0x0400_0181 => self.ipc.read_sync9(1),
-            0x0400_0182 => self.ipc.read_sync9(2),
-            0x0400_0183 => self.ipc.read_sync9(3),
-            0x0400_0184 => self.ipc.read_fifocnt9(0),
-            0x0400_0185 => self.ipc.read_fifocnt9(1),
-            0x0400_0186 => self.ipc.read_fifocnt9(2),
-            0x0400_0187 => self.ipc.read_fifocnt9(3),
-            0x0400_01A0 => self.cartridge.spicnt.read(!self.exmem.nds_arm7_access, 0),
-            0x0400_01A1 => self.cartridge.spicnt.read(!self.exmem.nds_arm7_access, 1),
-            0x0400_01A2 => self.cartridge.read_spi_data(!self.exmem.nds_arm7_access),
-            0x0400_01A3 => 0, // Upper byte of AUXSPIDATA is always 0
-            0x0400_01A4 => self.cartridge.read_romctrl(!self.exmem.nds_arm7_access, 0),
-            0x0400_01A5 => self.cartridge.read_romctrl(!self.exmem.nds_arm7_access, 1),
-            0x0400_01A6 => self.cartridge.read_romctrl(!self.exmem.nds_arm7_access, 2),
-            0x0400_01A7 => self.cartridge.read_romctrl(!self.exmem.nds_arm7_access, 3),
-            0x0400_0204 => self.exmem.read_arm9(),
-            0x0400_0205 => self.exmem.read_common(),
-            0x0400_0208 => self.interrupts[1].master_enable.read(0),
-            0x0400_0209 => self.interrupts[1].master_enable.read(1),
-            0x0400_020A => self.interrupts[1].master_enable.read(2),
-            0x0400_020B => self.interrupts[1].master_enable.read(3),
-            0x0400_0210 => self.interrupts[1].enable.read(0),
-            0x0400_0211 => self.interrupts[1].enable.read(1),
-            0x0400_0212 => self.interrupts[1].enable.read(2),
-            0x0400_0213 => self.interrupts[1].enable.read(3),
-            0x0400_0214 => self.interrupts[1].request.read(0),
-            0x0400_0215 => self.interrupts[1].request.read(1),
-            0x0400_0216 => self.interrupts[1].request.read(2),
-            0x0400_0217 => self.interrupts[1].request.read(3),
-            0x0400_0240..=0x0400_0246 => self.gpu.vram.read_vram_cnt(addr as usize & 0xF),
-            0x0400_0247 => self.wramcnt.read(0),
-            0x0400_0248..=0x0400_0249 => self.gpu.vram.read_vram_cnt((addr as usize & 0xF) - 1),
-            0x0400_0280..=0x0400_0283 => self.div.cnt.read(addr as usize & 0xF),
-            0x0400_0290..=0x0400_0297 => self.div.read_numer(addr as usize & 0x7),
-            0x0400_0298..=0x0400_029F => self.div.read_denom(addr as usize & 0x7),
-            0x0400_02A0..=0x0400_02A7 => self.div.read_quot(addr as usize & 0x7),
-            0x0400_02A8..=0x0400_02AF => self.div.read_rem(addr as usize & 0x7),
-            0x0400_02B0..=0x0400_02B3 => self.sqrt.cnt.read(addr as usize & 0xF),
-            0x0400_02B4..=0x0400_02B7 => self.sqrt.read_result(addr as usize & 0x3),
-            0x0400_02B8..=0x0400_02BF => self.sqrt.read_param(addr as usize & 0x7),
-            0x0400_0300 => self.postflg9,
-            0x0400_0301..=0x0400_0303 => 0, // Other Parts of POSTFLG
-            0x0400_0304 => self.gpu.powcnt1.read(0),
-            0x0400_0305 => self.gpu.powcnt1.read(1),
-            0x0400_0306 => self.gpu.powcnt1.read(2),
-            0x0400_0307 => self.gpu.powcnt1.read(3),
-            0x0400_0320..=0x0400_06A3 => self.gpu.engine3d.read_register(addr),
-            0x0400_1000..=0x0400_1003 => self.gpu.engine_b.read_register(addr),
-            0x0400_1004..=0x0400_1007 => 0,
-            0x0400_1008..=0x0400_105F => self.gpu.engine_b.read_register(addr),
-            0x0400_1060..=0x0400_106B => 0,
-            0x0400_106C => self.gpu.engine_b.master_bright.read(0),
-            0x0400_106D => self.gpu.engine_b.master_bright.read(1),
-            0x0400_106E => self.gpu.engine_b.master_bright.read(2),
-            0x0400_106F => self.gpu.engine_b.master_bright.read(3),
-            0x0400_4010..=0x0400_4011 => 0, // DSi register that's unused for NDS
-            _ => {
-                warn!("Ignoring ARM9 IO Register Read at 0x{:08X}", addr);
-                0
+        },
+        registers: [
+            register!("VRAMCNT_H", 0x0400_0248, 1, VRAMCNT),
+            register!("VRAMCNT_I", 0x0400_0249, 1, VRAMCNT),
+        ]
+    ),
+    io!("DIVCNT", 0x0400_0280, 4, "Math", |hw| hw.div.cnt, fields: DIVCNT),
+    io!(
+        "DIV_NUMER",
+        0x0400_0290,
+        8,
+        "Math",
+        |hw, addr| hw.div.read_numer(addr as usize & 0x7),
+        |hw, addr, value| hw
+            .div
+            .write_numer(&mut hw.scheduler, addr as usize & 0x7, value)
+    ),
+    io!(
+        "DIV_DENOM",
+        0x0400_0298,
+        8,
+        "Math",
+        |hw, addr| hw.div.read_denom(addr as usize & 0x7),
+        |hw, addr, value| hw
+            .div
+            .write_denom(&mut hw.scheduler, addr as usize & 0x7, value)
+    ),
+    // Div result registers are read-only
+    io!(
+        "DIV_RESULT",
+        0x0400_02A0,
+        8,
+        "Math",
+        |hw, addr| hw.div.read_quot(addr as usize & 0x7),
+        |_, _, _| ()
+    ),
+    io!(
+        "DIVREM_RESULT",
+        0x0400_02A8,
+        8,
+        "Math",
+        |hw, addr| hw.div.read_rem(addr as usize & 0x7),
+        |_, _, _| ()
+    ),
+    io!("SQRTCNT", 0x0400_02B0, 4, "Math", |hw| hw.sqrt.cnt, fields: SQRTCNT),
+    io!(
+        "SQRT_RESULT",
+        0x0400_02B4,
+        4,
+        "Math",
+        |hw, addr| hw.sqrt.read_result(addr as usize & 0x3),
+        |_, _, _| () // Sqrt result register is read-only
+    ),
+    io!(
+        "SQRT_PARAM",
+        0x0400_02B8,
+        8,
+        "Math",
+        |hw, addr| hw.sqrt.read_param(addr as usize & 0x7),
+        |hw, addr, value| hw
+            .sqrt
+            .write_param(&mut hw.scheduler, addr as usize & 0x7, value)
+    ),
+    // Only the lowest byte of POSTFLG is used
+    io!(
+        "POSTFLG",
+        0x0400_0300,
+        4,
+        "System",
+        |hw, addr| match addr & 0x3 {
+            0 => hw.postflg9,
+            _ => 0,
+        },
+        |hw, addr, value| {
+            // Only bit 1 is writable
+            if addr & 0x3 == 0 {
+                hw.postflg9 = (hw.postflg9 & !0x02 | value & 0x02) | (value & 0x1);
             }
-        }
+        },
+        fields: POSTFLG
+    ),
+    io!("POWCNT1", 0x0400_0304, 4, "GPU", |hw| hw.gpu.powcnt1, fields: POWCNT1),
+    io!(
+        "3D",
+        0x0400_0320,
+        0x384,
+        "GPU",
+        |hw, addr| hw.gpu.engine3d.read_register(addr),
+        |hw, addr, value| hw.gpu.engine3d.write_register(
+            &mut hw.interrupts[1].request,
+            &mut hw.scheduler,
+            addr,
+            value,
+        ),
+        registers: [
+            register!("GXSTAT", 0x0400_0600, 4, GXSTAT),
+            register!("RAM_COUNT", 0x0400_0604, 4, RAM_COUNT),
+        ]
+    ),
+    io!(
+        "DB_DISPCNT",
+        0x0400_1000,
+        4,
+        "GPU",
+        |hw, addr| hw.gpu.engine_b.read_register(addr),
+        |hw, addr, value| hw
+            .gpu
+            .engine_b
+            .write_register(&mut hw.scheduler, addr, value),
+        fields: DISPCNT
+    ),
+    io!("Unused", 0x0400_1004, 4, "GPU", |_, _| 0, |_, _, _| (), registers: []),
+    io!(
+        "DB_BG0CNT-DB_BLDY",
+        0x0400_1008,
+        0x58,
+        "GPU",
+        |hw, addr| hw.gpu.engine_b.read_register(addr),
+        |hw, addr, value| hw
+            .gpu
+            .engine_b
+            .write_register(&mut hw.scheduler, addr, value),
+        registers: [
+            register!("DB_BG0CNT", 0x0400_1008, 2, BGCNT),
+            register!("DB_BG1CNT", 0x0400_100A, 2, BGCNT),
+            register!("DB_BG2CNT", 0x0400_100C, 2, BGCNT),
+            register!("DB_BG3CNT", 0x0400_100E, 2, BGCNT),
+            register!("DB_BG0HOFS", 0x0400_1010, 2, BGOFS),
+            register!("DB_BG0VOFS", 0x0400_1012, 2, BGOFS),
+            register!("DB_BG1HOFS", 0x0400_1014, 2, BGOFS),
+            register!("DB_BG1VOFS", 0x0400_1016, 2, BGOFS),
+            register!("DB_BG2HOFS", 0x0400_1018, 2, BGOFS),
+            register!("DB_BG2VOFS", 0x0400_101A, 2, BGOFS),
+            register!("DB_BG3HOFS", 0x0400_101C, 2, BGOFS),
+            register!("DB_BG3VOFS", 0x0400_101E, 2, BGOFS),
+            register!("DB_WININ", 0x0400_1048, 2, WININ),
+            register!("DB_WINOUT", 0x0400_104A, 2, WINOUT),
+            register!("DB_BLDCNT", 0x0400_1050, 2, BLDCNT),
+            register!("DB_BLDALPHA", 0x0400_1052, 2, BLDALPHA),
+        ]
+    ),
+    io!("Unused", 0x0400_1060, 0xC, "GPU", |_, _| 0, |_, _, _| (), registers: []),
+    io!(
+        "DB_MASTER_BRIGHT",
+        0x0400_106C,
+        4,
+        "GPU",
+        |hw| hw.gpu.engine_b.master_bright,
+        fields: MASTER_BRIGHT
+    ),
+    // DSi register that's unused for NDS
+    io!("SCFG_MC", 0x0400_4010, 2, "System", |_, _| 0, |_, _, _| ()),
+];
+
+const _: () = assert!(is_sorted(ARM9_IO_MAP) && registers_fit(ARM9_IO_MAP));
+
+impl HW {
+    pub(super) fn arm9_read_io8(&self, addr: u32) -> u8 {
+        self.read_io8(ARM9_IO_MAP, "ARM9", addr)
+    }
+
+    // Returns None for unknown registers
+    pub(super) fn arm9_peek_io8(&self, addr: u32) -> Option<u8> {
+        self.peek_io8(ARM9_IO_MAP, addr)
     }
 
     pub(super) fn arm9_read_io16(&self, addr: u32) -> u16 {
@@ -123,280 +587,7 @@ impl HW {
     }
 
     pub(super) fn arm9_write_io8(&mut self, addr: u32, value: u8) {
-        match addr {
-            0x0400_0000..=0x0400_0003 => {
-                self.gpu
-                    .engine_a
-                    .write_register(&mut self.scheduler, addr, value)
-            }
-            0x0400_0004 => self.gpu.dispstats[1].write(&mut self.scheduler, 0, value),
-            0x0400_0005 => self.gpu.dispstats[1].write(&mut self.scheduler, 1, value),
-            0x0400_0006 => (), // VCOUNT is read only
-            0x0400_0007 => (), // VCOUNT is read only
-            0x0400_0008..=0x0400_005F => {
-                self.gpu
-                    .engine_a
-                    .write_register(&mut self.scheduler, addr, value)
-            }
-            0x0400_0060..=0x0400_0063 => {
-                self.gpu
-                    .engine3d
-                    .disp3dcnt
-                    .write(&mut self.scheduler, addr as usize % 4, value)
-            }
-            0x0400_0064..=0x0400_0067 => {
-                self.gpu
-                    .dispcapcnt
-                    .write(&mut self.scheduler, addr as usize % 4, value)
-            }
-            0x0400_006C => self
-                .gpu
-                .engine_a
-                .master_bright
-                .write(&mut self.scheduler, 0, value),
-            0x0400_006D => self
-                .gpu
-                .engine_a
-                .master_bright
-                .write(&mut self.scheduler, 1, value),
-            0x0400_006E => self
-                .gpu
-                .engine_a
-                .master_bright
-                .write(&mut self.scheduler, 2, value),
-            0x0400_006F => self
-                .gpu
-                .engine_a
-                .master_bright
-                .write(&mut self.scheduler, 3, value),
-            0x0400_00B0..=0x0400_00BB => {
-                self.dmas[1].write(0, &mut self.scheduler, addr - 0xB0, value)
-            }
-            0x0400_00BC..=0x0400_00C7 => {
-                self.dmas[1].write(1, &mut self.scheduler, addr - 0xBC, value)
-            }
-            0x0400_00C8..=0x0400_00D3 => {
-                self.dmas[1].write(2, &mut self.scheduler, addr - 0xC8, value)
-            }
-            0x0400_00D4..=0x0400_00DF => {
-                self.dmas[1].write(3, &mut self.scheduler, addr - 0xD4, value)
-            }
-            0x0400_00E0..=0x0400_00E3 => {
-                HW::write_byte_to_value(&mut self.dma_fill[0], addr as usize % 4, value)
-            }
-            0x0400_00E4..=0x0400_00E7 => {
-                HW::write_byte_to_value(&mut self.dma_fill[1], addr as usize % 4, value)
-            }
-            0x0400_00E8..=0x0400_00EB => {
-                HW::write_byte_to_value(&mut self.dma_fill[2], addr as usize % 4, value)
-            }
-            0x0400_00EC..=0x0400_00EF => {
-                HW::write_byte_to_value(&mut self.dma_fill[3], addr as usize % 4, value)
-            }
-            0x0400_0100..=0x0400_0103 => {
-                self.timers[1][0].write(&mut self.scheduler, addr as usize % 4, value)
-            }
-            0x0400_0104..=0x0400_0107 => {
-                self.timers[1][1].write(&mut self.scheduler, addr as usize % 4, value)
-            }
-            0x0400_0108..=0x0400_010B => {
-                self.timers[1][2].write(&mut self.scheduler, addr as usize % 4, value)
-            }
-            0x0400_010C..=0x0400_010F => {
-                self.timers[1][3].write(&mut self.scheduler, addr as usize % 4, value)
-            }
-            0x0400_0130 => self.keypad.keyinput.write(&mut self.scheduler, 0, value),
-            0x0400_0131 => self.keypad.keyinput.write(&mut self.scheduler, 1, value),
-            0x0400_0132 => self.keypad.keycnt.write(&mut self.scheduler, 0, value),
-            0x0400_0133 => self.keypad.keycnt.write(&mut self.scheduler, 1, value),
-            0x0400_0136 => self.keypad.extkeyin.write(&mut self.scheduler, 0, value),
-            0x0400_0137 => self.keypad.extkeyin.write(&mut self.scheduler, 1, value),
-            0x0400_0180 => self.interrupts[0].request |= self.ipc.write_sync9(0, value),
-            0x0400_0181 => self.interrupts[0].request |= self.ipc.write_sync9(1, value),
-            0x0400_0182 => self.interrupts[0].request |= self.ipc.write_sync9(2, value),
-            0x0400_0183 => self.interrupts[0].request |= self.ipc.write_sync9(3, value),
-            0x0400_0184 => self.interrupts[1].request |= self.ipc.write_fifocnt9(0, value),
-            0x0400_0185 => self.interrupts[1].request |= self.ipc.write_fifocnt9(1, value),
-            0x0400_0186 => self.interrupts[1].request |= self.ipc.write_fifocnt9(2, value),
-            0x0400_0187 => self.interrupts[1].request |= self.ipc.write_fifocnt9(3, value),
-            0x0400_01A0 => self
-                .cartridge
-                .spicnt
-                .write(!self.exmem.nds_arm7_access, 0, value),
-            0x0400_01A1 => self
-                .cartridge
-                .spicnt
-                .write(!self.exmem.nds_arm7_access, 1, value),
-            0x0400_01A2 => self
-                .cartridge
-                .write_spi_data(!self.exmem.nds_arm7_access, value),
-            0x0400_01A3 => (), // TODO: Does this write do anything?
-            0x0400_01A4 => self.cartridge.write_romctrl(
-                &mut self.scheduler,
-                true,
-                !self.exmem.nds_arm7_access,
-                0,
-                value,
-            ),
-            0x0400_01A5 => self.cartridge.write_romctrl(
-                &mut self.scheduler,
-                true,
-                !self.exmem.nds_arm7_access,
-                1,
-                value,
-            ),
-            0x0400_01A6 => self.cartridge.write_romctrl(
-                &mut self.scheduler,
-                true,
-                !self.exmem.nds_arm7_access,
-                2,
-                value,
-            ),
-            0x0400_01A7 => self.cartridge.write_romctrl(
-                &mut self.scheduler,
-                true,
-                !self.exmem.nds_arm7_access,
-                3,
-                value,
-            ),
-            0x0400_01A8 => self
-                .cartridge
-                .write_command(!self.exmem.nds_arm7_access, 0, value),
-            0x0400_01A9 => self
-                .cartridge
-                .write_command(!self.exmem.nds_arm7_access, 1, value),
-            0x0400_01AA => self
-                .cartridge
-                .write_command(!self.exmem.nds_arm7_access, 2, value),
-            0x0400_01AB => self
-                .cartridge
-                .write_command(!self.exmem.nds_arm7_access, 3, value),
-            0x0400_01AC => self
-                .cartridge
-                .write_command(!self.exmem.nds_arm7_access, 4, value),
-            0x0400_01AD => self
-                .cartridge
-                .write_command(!self.exmem.nds_arm7_access, 5, value),
-            0x0400_01AE => self
-                .cartridge
-                .write_command(!self.exmem.nds_arm7_access, 6, value),
-            0x0400_01AF => self
-                .cartridge
-                .write_command(!self.exmem.nds_arm7_access, 7, value),
-            0x0400_0204 => self.exmem.write_arm9(value),
-            0x0400_0205 => self.exmem.write_common(value),
-            0x0400_0208 => self.interrupts[1]
-                .master_enable
-                .write(&mut self.scheduler, 0, value),
-            0x0400_0209 => self.interrupts[1]
-                .master_enable
-                .write(&mut self.scheduler, 1, value),
-            0x0400_020A => self.interrupts[1]
-                .master_enable
-                .write(&mut self.scheduler, 2, value),
-            0x0400_020B => self.interrupts[1]
-                .master_enable
-                .write(&mut self.scheduler, 3, value),
-            0x0400_0210 => self.interrupts[1]
-                .enable
-                .write(&mut self.scheduler, 0, value),
-            0x0400_0211 => self.interrupts[1]
-                .enable
-                .write(&mut self.scheduler, 1, value),
-            0x0400_0212 => self.interrupts[1]
-                .enable
-                .write(&mut self.scheduler, 2, value),
-            0x0400_0213 => self.interrupts[1]
-                .enable
-                .write(&mut self.scheduler, 3, value),
-            0x0400_0214..=0x0400_0217 => {
-                self.interrupts[1]
-                    .request
-                    .write(&mut self.scheduler, (addr as usize) & 0x3, value);
-                self.gpu
-                    .engine3d
-                    .check_interrupts(&mut self.interrupts[1].request);
-            }
-            0x0400_0240..=0x0400_0246 => self.gpu.vram.write_vram_cnt(addr as usize & 0xF, value),
-            0x0400_0247 => self.wramcnt.write(&mut self.scheduler, 0, value),
-            0x0400_0248..=0x0400_0249 => self
-                .gpu
-                .vram
-                .write_vram_cnt((addr as usize & 0xF) - 1, value),
-            0x0400_0280..=0x0400_0283 => {
-                self.div
-                    .cnt
-                    .write(&mut self.scheduler, addr as usize & 0xF, value)
-            }
-            0x0400_0290..=0x0400_0297 => {
-                self.div
-                    .write_numer(&mut self.scheduler, addr as usize & 0x7, value)
-            }
-            0x0400_0298..=0x0400_029F => {
-                self.div
-                    .write_denom(&mut self.scheduler, addr as usize & 0x7, value)
-            }
-            0x0400_02A0..=0x0400_02A7 => (), // Div result registers are read-only
-            0x0400_02A8..=0x0400_02AF => (), // Div result registers are read-only
-            0x0400_02B0..=0x0400_02B3 => {
-                self.sqrt
-                    .cnt
-                    .write(&mut self.scheduler, addr as usize & 0xF, value)
-            }
-            0x0400_02B4..=0x0400_02B7 => (), // Sqrt result register is read-only
-            0x0400_02B8..=0x0400_02BF => {
-                self.sqrt
-                    .write_param(&mut self.scheduler, addr as usize & 0x7, value)
-            }
-            0x0400_0300 => self.postflg9 = (self.postflg9 & !0x02 | value & 0x02) | (value & 0x1), // Only bit 1 is writable
-            0x0400_0301..=0x0400_0303 => (), // Other Parts of POSTFLG
-            0x0400_0304 => self.gpu.powcnt1.write(&mut self.scheduler, 0, value),
-            0x0400_0305 => self.gpu.powcnt1.write(&mut self.scheduler, 1, value),
-            0x0400_0306 => self.gpu.powcnt1.write(&mut self.scheduler, 2, value),
-            0x0400_0307 => self.gpu.powcnt1.write(&mut self.scheduler, 3, value),
-            0x0400_0320..=0x0400_06A3 => self.gpu.engine3d.write_register(
-                &mut self.interrupts[1].request,
-                &mut self.scheduler,
-                addr,
-                value,
-            ),
-            0x0400_1000..=0x0400_1003 => {
-                self.gpu
-                    .engine_b
-                    .write_register(&mut self.scheduler, addr, value)
-            }
-            0x0400_1004..=0x0400_1007 => (),
-            0x0400_1008..=0x0400_105F => {
-                self.gpu
-                    .engine_b
-                    .write_register(&mut self.scheduler, addr, value)
-            }
-            0x0400_1060..=0x0400_106B => (),
-            0x0400_106C => self
-                .gpu
-                .engine_b
-                .master_bright
-                .write(&mut self.scheduler, 0, value),
-            0x0400_106D => self
-                .gpu
-                .engine_b
-                .master_bright
-                .write(&mut self.scheduler, 1, value),
-            0x0400_106E => self
-                .gpu
-                .engine_b
-                .master_bright
-                .write(&mut self.scheduler, 2, value),
-            0x0400_106F => self
-                .gpu
-                .engine_b
-                .master_bright
-                .write(&mut self.scheduler, 3, value),
-            _ => warn!(
-                "Ignoring ARM9 IO Register Write 0x{:08X} = {:02X}",
-                addr, value
-            ),
-        }
+        self.write_io8(ARM9_IO_MAP, "ARM9", addr, value)
     }
 
     pub(super) fn arm9_write_io16(&mut self, addr: u32, value: u16) {
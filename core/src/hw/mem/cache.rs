@@ -0,0 +1,156 @@
+use super::{MemoryValue, HW};
+
+// 4-way set associative with 32 byte lines
+pub struct Cache {
+    num_sets: usize,
+    lines: Vec<CacheLine>,
+    lockdown_base: usize,
+    lockdown_load: bool,
+    round_robin_counter: usize,
+    random_seed: u32,
+    pub writebacks: Vec<(u32, [u8; Cache::LINE_SIZE])>,
+}
+
+impl Cache {
+    pub const LINE_SIZE: usize = 32;
+    const WAYS: usize = 4;
+    const LINE_MASK: u32 = Cache::LINE_SIZE as u32 - 1;
+
+    pub fn new(size: usize) -> Self {
+        let num_sets = size / Cache::LINE_SIZE / Cache::WAYS;
+        Cache {
+            num_sets,
+            lines: vec![CacheLine::new(); num_sets * Cache::WAYS],
+            lockdown_base: 0,
+            lockdown_load: false,
+            round_robin_counter: 0,
+            random_seed: 1,
+            writebacks: Vec::new(),
+        }
+    }
+
+    fn set(&self, addr: u32) -> usize {
+        (addr as usize / Cache::LINE_SIZE) & (self.num_sets - 1)
+    }
+
+    pub fn lookup(&self, addr: u32) -> Option<usize> {
+        let set_start = self.set(addr) * Cache::WAYS;
+        let tag = addr & !Cache::LINE_MASK;
+        (set_start..set_start + Cache::WAYS)
+            .find(|&i| self.lines[i].valid && self.lines[i].tag == tag)
+    }
+
+    pub fn read<T: MemoryValue>(&self, line_i: usize, addr: u32) -> T {
+        HW::read_mem(&self.lines[line_i].data, addr & Cache::LINE_MASK)
+    }
+
+    pub fn write<T: MemoryValue>(&mut self, line_i: usize, addr: u32, value: T, write_back: bool) {
+        let line = &mut self.lines[line_i];
+        HW::write_mem(&mut line.data, addr & Cache::LINE_MASK, value);
+        line.dirty |= write_back;
+    }
+
+    // Returns the index of the line that was filled
+    pub fn fill(&mut self, addr: u32, data: [u8; Cache::LINE_SIZE], round_robin: bool) -> usize {
+        let way = if self.lockdown_load {
+            self.lockdown_base
+        } else {
+            // Locked down ways are never replaced
+            let unlocked_ways = Cache::WAYS - self.lockdown_base;
+            let victim = if round_robin {
+                self.round_robin_counter = (self.round_robin_counter + 1) % unlocked_ways;
+                self.round_robin_counter
+            } else {
+                self.random_seed ^= self.random_seed << 13;
+                self.random_seed ^= self.random_seed >> 17;
+                self.random_seed ^= self.random_seed << 5;
+                self.random_seed as usize % unlocked_ways
+            };
+            self.lockdown_base + victim
+        };
+        let line_i = self.set(addr) * Cache::WAYS + way;
+        self.clean(line_i);
+        self.lines[line_i] = CacheLine {
+            valid: true,
+            dirty: false,
+            tag: addr & !Cache::LINE_MASK,
+            data,
+        };
+        line_i
+    }
+
+    fn clean(&mut self, line_i: usize) {
+        let line = &mut self.lines[line_i];
+        if line.valid && line.dirty {
+            line.dirty = false;
+            self.writebacks.push((line.tag, line.data));
+        }
+    }
+
+    fn line_from_index(&self, value: u32) -> usize {
+        let way = (value >> 30) as usize;
+        let set = (value as usize >> 5) & (self.num_sets - 1);
+        set * Cache::WAYS + way
+    }
+
+    pub fn invalidate_all(&mut self) {
+        for line in self.lines.iter_mut() {
+            line.valid = false;
+        }
+    }
+
+    pub fn invalidate_line(&mut self, addr: u32) {
+        if let Some(line_i) = self.lookup(addr) {
+            self.lines[line_i].valid = false;
+        }
+    }
+
+    pub fn clean_line(&mut self, addr: u32) {
+        if let Some(line_i) = self.lookup(addr) {
+            self.clean(line_i);
+        }
+    }
+
+    pub fn clean_index(&mut self, value: u32) {
+        self.clean(self.line_from_index(value));
+    }
+
+    pub fn invalidate_index(&mut self, value: u32) {
+        let line_i = self.line_from_index(value);
+        self.lines[line_i].valid = false;
+    }
+
+    pub fn clean_all(&mut self) {
+        for line_i in 0..self.lines.len() {
+            self.clean(line_i);
+        }
+    }
+
+    pub fn read_lockdown(&self) -> u32 {
+        (self.lockdown_load as u32) << 31 | self.lockdown_base as u32
+    }
+
+    pub fn write_lockdown(&mut self, value: u32) {
+        self.lockdown_load = value >> 31 & 0x1 != 0;
+        self.lockdown_base = value as usize & 0x3;
+    }
+}
+
+#[derive(Clone, Copy)]
+struct CacheLine {
+    valid: bool,
+    dirty: bool,
+    tag: u32,
+    data: [u8; Cache::LINE_SIZE],
+}
+
+impl CacheLine {
+    pub fn new() -> Self {
+        CacheLine {
+            valid: false,
+            dirty: false,
+            tag: 0,
+            data: [0; Cache::LINE_SIZE],
+        }
+    }
+}
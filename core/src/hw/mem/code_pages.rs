@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::hw::HW;
+use crate::unlikely;
+
+// Tracks which chunks of each page contain decoded blocks for each CPU
+pub struct CodePages {
+    // Bits 0-15 are ARM7 chunks and bits 16-31 are ARM9 chunks
+    pages: Vec<u32>,
+    invalidated: [InvalidatedChunks; 2],
+    // Number of times each chunk has been written to after being decoded
+    code_writes: HashMap<usize, usize>,
+    layout: CodeLayout,
+}
+
+// Where each CPU sees the memories that have mirrors, so that every mirror maps to one chunk
+#[derive(Clone, Default)]
+pub struct CodeLayout {
+    pub itcm: Range<u32>,
+    pub dtcm: Range<u32>,
+    // Offset and mask into shared WRAM for ARM7 and ARM9, a mask of 0 means unmapped
+    pub shared_wram: [(u32, u32); 2],
+    // The LCDC address of the bank behind each VRAM slot for ARM7 and ARM9, if any
+    pub vram: [Vec<Option<u32>>; 2],
+}
+
+impl CodePages {
+    const PAGE_SHIFT: usize = 12;
+    const CHUNK_SHIFT: usize = 8;
+    const CHUNKS_PER_PAGE: usize = 1 << (CodePages::PAGE_SHIFT - CodePages::CHUNK_SHIFT);
+    const MAX_CODE_WRITES: usize = 16;
+
+    // VRAM is mirrored every 256 KB for ARM7 and every 16 MB for ARM9
+    pub const VRAM_SLOT_LEN: u32 = 0x4000;
+    pub const VRAM_SLOTS: [u32; 2] = [0x10, 0x400];
+
+    // Chunks are keyed by the memory backing them rather than the address it's seen at
+    // Those chunks come after the chunks of every raw address, which key everything else
+    const RAW_CHUNKS: usize = 1 << (32 - CodePages::CHUNK_SHIFT);
+    const MAIN_MEM_BASE: u32 = 0x00_0000;
+    const VRAM_BASE: u32 = 0x40_0000;
+    const SHARED_WRAM_BASE: u32 = 0x50_0000;
+    const IWRAM_BASE: u32 = 0x51_0000;
+    const ITCM_BASE: u32 = 0x52_0000;
+    const DTCM_BASE: u32 = 0x52_8000;
+    const BIOS7_BASE: u32 = 0x52_C000;
+    const PHYSICAL_SIZE: u32 = 0x53_0000;
+
+    pub fn new() -> Self {
+        CodePages {
+            pages: vec![
+                0;
+                (CodePages::RAW_CHUNKS
+                    + (CodePages::PHYSICAL_SIZE as usize >> CodePages::CHUNK_SHIFT))
+                    / CodePages::CHUNKS_PER_PAGE
+            ],
+            invalidated: [InvalidatedChunks::new(), InvalidatedChunks::new()],
+            code_writes: HashMap::new(),
+            layout: CodeLayout::default(),
+        }
+    }
+
+    // Mirrors of the same memory share the same chunk, including shared WRAM and VRAM banks seen
+    // by both CPUs
+    #[inline]
+    pub fn chunk(&self, is_arm9: bool, addr: u32) -> usize {
+        let physical_addr = match addr >> 24 {
+            _ if is_arm9 && self.layout.itcm.contains(&addr) => {
+                Some(CodePages::ITCM_BASE + (addr & (HW::ITCM_SIZE as u32 - 1)))
+            }
+            _ if is_arm9 && self.layout.dtcm.contains(&addr) => {
+                Some(CodePages::DTCM_BASE + (addr & (HW::DTCM_SIZE as u32 - 1)))
+            }
+            0x0 if !is_arm9 => Some(CodePages::BIOS7_BASE + (addr & (HW::BIOS7_SIZE as u32 - 1))),
+            0x2 => Some(CodePages::MAIN_MEM_BASE + (addr & (HW::MAIN_MEM_SIZE as u32 - 1))),
+            0x3 => {
+                let (offset, mask) = self.layout.shared_wram[is_arm9 as usize];
+                if is_arm9 || (addr & 0x0080_0000 == 0 && mask != 0) {
+                    Some(CodePages::SHARED_WRAM_BASE + offset + (addr & mask))
+                } else {
+                    Some(CodePages::IWRAM_BASE + (addr & (HW::IWRAM_SIZE as u32 - 1)))
+                }
+            }
+            0x6 => {
+                let slots = &self.layout.vram[is_arm9 as usize];
+                let slot = (addr / CodePages::VRAM_SLOT_LEN) as usize % slots.len().max(1);
+                slots.get(slot).copied().flatten().map(|lcdc_addr| {
+                    CodePages::VRAM_BASE + lcdc_addr + (addr & (CodePages::VRAM_SLOT_LEN - 1))
+                })
+            }
+            _ => None,
+        };
+        match physical_addr {
+            Some(physical_addr) => {
+                CodePages::RAW_CHUNKS + (physical_addr as usize >> CodePages::CHUNK_SHIFT)
+            }
+            None => addr as usize >> CodePages::CHUNK_SHIFT,
+        }
+    }
+
+    // IO is never decoded since reads can have side effects
+    pub fn cachable(addr: u32) -> bool {
+        addr >> 24 != 0x4
+    }
+
+    // Chunks that mix code and data would be rebuilt on every store
+    pub fn self_modifying(&self, is_arm9: bool, addr: u32) -> bool {
+        self.code_writes
+            .get(&self.chunk(is_arm9, addr))
+            .is_some_and(|&writes| writes >= CodePages::MAX_CODE_WRITES)
+    }
+
+    pub fn same_chunk(addr1: u32, addr2: u32) -> bool {
+        addr1 >> CodePages::CHUNK_SHIFT == addr2 >> CodePages::CHUNK_SHIFT
+    }
+
+    fn chunk_mask(chunk: usize) -> u32 {
+        (1 << (chunk % CodePages::CHUNKS_PER_PAGE)) * 0x1_0001
+    }
+
+    // Returns the chunk that blocks starting at addr should be invalidated with
+    pub fn mark(&mut self, is_arm9: bool, addr: u32) -> usize {
+        let chunk = self.chunk(is_arm9, addr);
+        let cpu_mask = if is_arm9 { 0xFFFF_0000 } else { 0x0000_FFFF };
+        self.pages[chunk / CodePages::CHUNKS_PER_PAGE] |= CodePages::chunk_mask(chunk) & cpu_mask;
+        chunk
+    }
+
+    #[inline]
+    pub fn write(&mut self, is_arm9: bool, addr: u32) {
+        let chunk = self.chunk(is_arm9, addr);
+        let page = &mut self.pages[chunk / CodePages::CHUNKS_PER_PAGE];
+        let cpus = *page & CodePages::chunk_mask(chunk);
+        if unlikely(cpus != 0) {
+            *page &= !cpus;
+            *self.code_writes.entry(chunk).or_insert(0) += 1;
+            if cpus & 0x0000_FFFF != 0 {
+                self.invalidated[0].chunks.push(chunk);
+            }
+            if cpus & 0xFFFF_0000 != 0 {
+                self.invalidated[1].chunks.push(chunk);
+            }
+        }
+    }
+
+    // Used when the memory map changes
+    pub fn remap(&mut self, layout: CodeLayout) {
+        self.layout = layout;
+        self.invalidate_all();
+    }
+
+    pub fn invalidate_all(&mut self) {
+        self.pages.fill(0);
+        // Whatever was written before may not even be code anymore
+        self.code_writes.clear();
+        for invalidated in self.invalidated.iter_mut() {
+            invalidated.all = true;
+            invalidated.chunks.clear();
+        }
+    }
+
+    #[inline]
+    pub fn invalidated(&self, is_arm9: bool) -> bool {
+        let invalidated = &self.invalidated[is_arm9 as usize];
+        invalidated.all || !invalidated.chunks.is_empty()
+    }
+
+    pub fn take_invalidated(&mut self, is_arm9: bool) -> InvalidatedChunks {
+        std::mem::replace(
+            &mut self.invalidated[is_arm9 as usize],
+            InvalidatedChunks::new(),
+        )
+    }
+}
+
+pub struct InvalidatedChunks {
+    pub all: bool,
+    pub chunks: Vec<usize>,
+}
+
+impl InvalidatedChunks {
+    pub fn new() -> Self {
+        InvalidatedChunks {
+            all: false,
+            chunks: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code_pages() -> CodePages {
+        let mut code_pages = CodePages::new();
+        code_pages.remap(CodeLayout {
+            itcm: 0x0000_0000..0x0000_8000,
+            dtcm: 0x0080_0000..0x0080_4000,
+            shared_wram: [(0x0000, 0x3FFF), (0x4000, 0x3FFF)],
+            // Bank A at 0x0600_0000 for ARM9 and at 0x0600_0000 and 0x0600_4000 for ARM7
+            vram: [
+                (0..0x10).map(|i| (i < 2).then_some(i * 0x4000)).collect(),
+                (0..0x400)
+                    .map(|i| match i {
+                        0x000..=0x007 => Some(i * 0x4000),
+                        0x200..=0x207 => Some((i - 0x200) * 0x4000),
+                        _ => None,
+                    })
+                    .collect(),
+            ],
+        });
+        code_pages.take_invalidated(false);
+        code_pages.take_invalidated(true);
+        code_pages
+    }
+
+    fn invalidated_by(is_arm9: bool, code_addr: u32, write_addr: u32) -> bool {
+        let mut code_pages = code_pages();
+        let chunk = code_pages.mark(is_arm9, code_addr);
+        code_pages.write(is_arm9, write_addr);
+        code_pages.take_invalidated(is_arm9).chunks == [chunk]
+    }
+
+    #[test]
+    fn writes_to_mirrors_invalidate_code() {
+        assert!(invalidated_by(true, 0x0000_0100, 0x0000_0100)); // ITCM
+        assert!(invalidated_by(true, 0x0080_0100, 0x0080_0100)); // DTCM
+        assert!(invalidated_by(true, 0x0200_0100, 0x02C0_0100)); // Main memory
+        assert!(invalidated_by(true, 0x0300_0100, 0x037F_C100)); // ARM9 shared WRAM
+        assert!(invalidated_by(false, 0x0300_0100, 0x0300_4100)); // ARM7 shared WRAM
+        assert!(invalidated_by(false, 0x0380_0100, 0x03FF_0100)); // ARM7 WRAM
+        assert!(!invalidated_by(true, 0x0000_0100, 0x0000_0200));
+        assert!(!invalidated_by(true, 0x0080_0100, 0x0000_0100));
+    }
+
+    #[test]
+    fn vram_is_keyed_by_bank() {
+        assert!(invalidated_by(true, 0x0680_0100, 0x0600_0100)); // LCDC and BG
+        assert!(invalidated_by(false, 0x0600_4100, 0x0604_4100)); // ARM7 mirror
+        assert!(invalidated_by(true, 0x0680_4100, 0x0600_4100)); // Second slot
+        assert!(!invalidated_by(true, 0x0680_0100, 0x0602_0100));
+        let mut code_pages = code_pages();
+        code_pages.mark(true, 0x0680_0100);
+        code_pages.write(false, 0x0600_0100);
+        assert!(code_pages.invalidated(true));
+    }
+
+    #[test]
+    fn raw_addresses_are_separate_from_memories() {
+        let mut code_pages = code_pages();
+        code_pages.mark(false, 0x0000_0100);
+        code_pages.mark(true, 0x0200_0100);
+        code_pages.write(true, 0x0100_0100);
+        code_pages.write(true, 0x0602_0100);
+        code_pages.write(false, 0x0800_0100);
+        assert!(!code_pages.invalidated(false));
+        assert!(!code_pages.invalidated(true));
+    }
+
+    #[test]
+    fn arm7_bios_is_separate_from_itcm() {
+        let mut code_pages = code_pages();
+        code_pages.mark(false, 0x0000_0100);
+        code_pages.write(true, 0x0000_0100);
+        assert!(!code_pages.invalidated(false));
+    }
+
+    #[test]
+    fn invalidate_all_forgets_self_modifying_code() {
+        let mut code_pages = code_pages();
+        for _ in 0..CodePages::MAX_CODE_WRITES {
+            code_pages.mark(true, 0x0200_0000);
+            code_pages.write(true, 0x0200_0000);
+        }
+        assert!(code_pages.self_modifying(true, 0x0200_0000));
+        code_pages.invalidate_all();
+        assert!(!code_pages.self_modifying(true, 0x0200_0000));
+    }
+}
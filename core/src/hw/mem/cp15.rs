@@ -1,7 +1,7 @@
 use _core::ops::Range;
 use bitflags::*;
 
-use super::HW;
+use super::{Cache, HW};
 
 pub struct CP15 {
     control: Control,
@@ -9,17 +9,28 @@ pub struct CP15 {
     itcm_control: TCMControl,
     dtcm_control: TCMControl,
     pub arm9_halted: bool,
+    // Caches
+    pub emulate_caches: bool,
+    pub icache: Cache,
+    pub dcache: Cache,
+    data_cachable: u32,
+    instr_cachable: u32,
+    data_bufferable: u32,
     // AP Regions
-    ap_data_region: u32,
-    ap_instr_region: u32,
     ext_ap_data_region: u32,
     ext_ap_instr_region: u32,
     // PU Regions
     pu_data_regions: [u32; 8],
     pu_instr_regions: [u32; 8],
+    page_flags: Vec<PageFlags>,
 }
 
 impl CP15 {
+    const ICACHE_SIZE: usize = 0x2000;
+    const DCACHE_SIZE: usize = 0x1000;
+    // Regions smaller than 4 KB are unpredictable
+    const PAGE_SHIFT: usize = 12;
+
     pub fn new() -> Self {
         CP15 {
             control: Control::new(),
@@ -27,14 +38,20 @@ impl CP15 {
             itcm_control: TCMControl::new(0, HW::ITCM_SIZE as u32),
             dtcm_control: TCMControl::new(0x0080_3000, HW::DTCM_SIZE as u32),
             arm9_halted: false,
+            // Caches
+            emulate_caches: false,
+            icache: Cache::new(CP15::ICACHE_SIZE),
+            dcache: Cache::new(CP15::DCACHE_SIZE),
+            data_cachable: 0,
+            instr_cachable: 0,
+            data_bufferable: 0,
             // AP Regions
-            ap_data_region: 0,
-            ap_instr_region: 0,
             ext_ap_data_region: 0,
             ext_ap_instr_region: 0,
             // PU Regions
             pu_data_regions: [0; 8],
             pu_instr_regions: [0; 8],
+            page_flags: vec![PageFlags::empty(); 1 << (32 - CP15::PAGE_SHIFT)],
         }
     }
 
@@ -43,10 +60,15 @@ impl CP15 {
         match n {
             0 if (m, p) == (0, 1) => 0x0F0D2112, // Cache Type Register
             1 => self.read_control_reg(m, p),
+            2 => self.read_cachability(m, p),
+            3 => self.read_cache_write_bufferability(m, p),
             5 => self.read_ap_regions(m, p),
             6 => self.read_pu_regions(m, p),
             9 => self.read_cache_control(m, p),
-            _ => todo!(),
+            _ => {
+                unimplemented_behavior!("CP15 Read from C{}, C{}, {}", n, m, p);
+                0
+            }
         }
     }
 
@@ -60,7 +82,7 @@ impl CP15 {
             6 => self.write_pu_regions(m, p, value),
             7 => self.write_cache_command(m, p, value),
             9 => self.write_cache_control(m, p, value),
-            _ => todo!(),
+            _ => unimplemented_behavior!("CP15 Write 0x{:X} to C{}, C{}, {}", value, n, m, p),
         }
     }
 
@@ -72,6 +94,143 @@ impl CP15 {
         self.dtcm_control.base..self.dtcm_control.base + self.dtcm_control.virtual_size
     }
 
+    fn page_flags(&self, addr: u32) -> PageFlags {
+        self.page_flags[addr as usize >> CP15::PAGE_SHIFT]
+    }
+
+    pub fn cachable(&self, is_code: bool, addr: u32) -> bool {
+        let (cache_enable, cachable) = if is_code {
+            (Control::INSTR_CACHE_ENABLE, PageFlags::INSTR_CACHABLE)
+        } else {
+            (Control::DATA_UNIFIED_CACHE_ENABLE, PageFlags::DATA_CACHABLE)
+        };
+        self.control.contains(Control::PU_ENABLE | cache_enable)
+            && !self.itcm_range().contains(&addr)
+            && (is_code || !self.dtcm_range().contains(&addr))
+            && self.page_flags(addr).contains(cachable)
+    }
+
+    // Bufferable cachable data regions use write-back instead of write-through
+    pub fn write_back(&self, addr: u32) -> bool {
+        self.page_flags(addr).contains(PageFlags::WRITE_BACK)
+    }
+
+    pub fn code_access_allowed(&self, privileged: bool, addr: u32) -> bool {
+        let permission = if privileged {
+            PageFlags::CODE_PRIV
+        } else {
+            PageFlags::CODE_USER
+        };
+        !self.control.contains(Control::PU_ENABLE) || self.page_flags(addr).contains(permission)
+    }
+
+    pub fn data_access_allowed(&self, privileged: bool, is_write: bool, addr: u32) -> bool {
+        let permission = match (is_write, privileged) {
+            (false, false) => PageFlags::READ_USER,
+            (false, true) => PageFlags::READ_PRIV,
+            (true, false) => PageFlags::WRITE_USER,
+            (true, true) => PageFlags::WRITE_PRIV,
+        };
+        !self.control.contains(Control::PU_ENABLE) || self.page_flags(addr).contains(permission)
+    }
+
+    fn ap_flags(ap: u32, is_code: bool) -> PageFlags {
+        let flags = match ap {
+            1 => PageFlags::READ_PRIV | PageFlags::WRITE_PRIV,
+            2 => PageFlags::READ_PRIV | PageFlags::WRITE_PRIV | PageFlags::READ_USER,
+            3 => {
+                PageFlags::READ_PRIV
+                    | PageFlags::WRITE_PRIV
+                    | PageFlags::READ_USER
+                    | PageFlags::WRITE_USER
+            }
+            5 => PageFlags::READ_PRIV,
+            6 => PageFlags::READ_PRIV | PageFlags::READ_USER,
+            _ => PageFlags::empty(),
+        };
+        if is_code {
+            let mut code_flags = PageFlags::empty();
+            code_flags.set(PageFlags::CODE_PRIV, flags.contains(PageFlags::READ_PRIV));
+            code_flags.set(PageFlags::CODE_USER, flags.contains(PageFlags::READ_USER));
+            code_flags
+        } else {
+            flags
+        }
+    }
+
+    // The pages a region covers, which are none while it's disabled
+    fn region_pages(region: u32) -> Range<usize> {
+        if region & 0x1 == 0 {
+            return 0..0;
+        }
+        let size = 2u64 << (region >> 1 & 0x1F);
+        let base = (region & !0xFFF) as u64 & !(size - 1);
+        let start = (base >> CP15::PAGE_SHIFT) as usize;
+        let end = ((base + size) >> CP15::PAGE_SHIFT).max(start as u64 + 1) as usize;
+        start..end
+    }
+
+    // Only pages covered by the regions whose settings changed need to be rebuilt
+    fn update_regions(&mut self, changed: u32) {
+        for i in (0..8).filter(|i| changed >> i & 0x1 != 0) {
+            self.update_page_flags(CP15::region_pages(self.pu_data_regions[i]));
+            self.update_page_flags(CP15::region_pages(self.pu_instr_regions[i]));
+        }
+    }
+
+    // Higher numbered regions have priority, so every region overlapping the pages is reapplied
+    fn update_page_flags(&mut self, pages: Range<usize>) {
+        if pages.is_empty() {
+            return;
+        }
+        for flags in self.page_flags[pages.clone()].iter_mut() {
+            *flags = PageFlags::empty();
+        }
+        for is_code in [false, true] {
+            let (regions, ext_ap, cachable) = if is_code {
+                (
+                    self.pu_instr_regions,
+                    self.ext_ap_instr_region,
+                    self.instr_cachable,
+                )
+            } else {
+                (
+                    self.pu_data_regions,
+                    self.ext_ap_data_region,
+                    self.data_cachable,
+                )
+            };
+            let mask = if is_code {
+                PageFlags::CODE_PRIV | PageFlags::CODE_USER | PageFlags::INSTR_CACHABLE
+            } else {
+                PageFlags::all()
+                    - (PageFlags::CODE_PRIV | PageFlags::CODE_USER | PageFlags::INSTR_CACHABLE)
+            };
+            for (i, region) in regions.iter().enumerate() {
+                let region_pages = CP15::region_pages(*region);
+                let start = region_pages.start.max(pages.start);
+                let end = region_pages.end.min(pages.end);
+                if start >= end {
+                    continue;
+                }
+                let mut flags = CP15::ap_flags(ext_ap >> (4 * i) & 0xF, is_code);
+                if cachable >> i & 0x1 != 0 {
+                    flags |= if is_code {
+                        PageFlags::INSTR_CACHABLE
+                    } else {
+                        PageFlags::DATA_CACHABLE
+                    };
+                }
+                if !is_code && self.data_bufferable >> i & 0x1 != 0 {
+                    flags |= PageFlags::WRITE_BACK;
+                }
+                for page_flags in self.page_flags[start..end].iter_mut() {
+                    *page_flags = (*page_flags - mask) | flags;
+                }
+            }
+        }
+    }
+
     fn read_control_reg(&self, m: u32, p: u32) -> u32 {
         if m != 0 || p != 0 {
             warn!(
@@ -83,21 +242,49 @@ impl CP15 {
         self.control.bits
     }
 
+    fn read_cachability(&self, m: u32, p: u32) -> u32 {
+        match (m, p) {
+            (0, 0) => self.data_cachable,
+            (0, 1) => self.instr_cachable,
+            _ => {
+                unimplemented_behavior!("CP15 Cachability Read: {} {}", m, p);
+                0
+            }
+        }
+    }
+
+    fn read_cache_write_bufferability(&self, m: u32, p: u32) -> u32 {
+        if m != 0 || p != 0 {
+            warn!(
+                "m and p are not 0 for CP15 Cache write Bufferability Read: {} {}",
+                m, p
+            );
+            return 0;
+        }
+        self.data_bufferable
+    }
+
     fn read_ap_regions(&self, m: u32, p: u32) -> u32 {
         match (m, p) {
-            (0, 0) => self.ap_data_region,
-            (0, 1) => self.ap_instr_region,
+            (0, 0) => CP15::compress_ap(self.ext_ap_data_region),
+            (0, 1) => CP15::compress_ap(self.ext_ap_instr_region),
             (0, 2) => self.ext_ap_data_region,
             (0, 3) => self.ext_ap_instr_region,
-            _ => todo!(),
+            _ => {
+                unimplemented_behavior!("CP15 Access Permission Read: {} {}", m, p);
+                0
+            }
         }
     }
 
     fn read_pu_regions(&self, m: u32, p: u32) -> u32 {
         match (m, p) {
-            (region, 0) => self.pu_data_regions[region as usize],
-            (region, 1) => self.pu_instr_regions[region as usize],
-            _ => todo!(),
+            (region @ 0..=7, 0) => self.pu_data_regions[region as usize],
+            (region @ 0..=7, 1) => self.pu_instr_regions[region as usize],
+            _ => {
+                unimplemented_behavior!("CP15 Protection Unit Region Read: {} {}", m, p);
+                0
+            }
         }
     }
 
@@ -119,11 +306,14 @@ impl CP15 {
     }
 
     fn write_cachability(&mut self, m: u32, p: u32, value: u32) {
+        let old = self.data_cachable | self.instr_cachable << 8;
         match (m, p) {
-            (0, 0) => warn!("Cachability Bits for Data/Unified Region: 0x{:X}", value),
-            (0, 1) => warn!("Cachability Bits for Instruction Region: 0x{:X}", value),
-            _ => todo!(),
+            (0, 0) => self.data_cachable = value & 0xFF,
+            (0, 1) => self.instr_cachable = value & 0xFF,
+            _ => unimplemented_behavior!("CP15 Cachability Write: {} {}", m, p),
         }
+        let changed = old ^ (self.data_cachable | self.instr_cachable << 8);
+        self.update_regions(changed | changed >> 8);
     }
 
     fn write_cache_write_bufferability(&mut self, m: u32, p: u32, value: u32) {
@@ -134,61 +324,98 @@ impl CP15 {
             );
             return;
         }
-        info!("Cache Write Bufferability: 0x{:X}", value);
+        let changed = self.data_bufferable ^ value & 0xFF;
+        self.data_bufferable = value & 0xFF;
+        self.update_regions(changed);
     }
 
     fn write_ap_regions(&mut self, m: u32, p: u32, value: u32) {
+        let (old_data, old_instr) = (self.ext_ap_data_region, self.ext_ap_instr_region);
         match (m, p) {
-            (0, 0) => self.ap_data_region = value & 0xFFFF,
-            (0, 1) => self.ap_instr_region = value & 0xFFFF,
+            (0, 0) => self.ext_ap_data_region = CP15::expand_ap(value),
+            (0, 1) => self.ext_ap_instr_region = CP15::expand_ap(value),
             (0, 2) => self.ext_ap_data_region = value,
             (0, 3) => self.ext_ap_instr_region = value,
-            _ => todo!(),
+            _ => unimplemented_behavior!("CP15 Access Permission Write: {} {}", m, p),
         }
+        let changed = (old_data ^ self.ext_ap_data_region) | (old_instr ^ self.ext_ap_instr_region);
+        self.update_regions((0..8).fold(0, |regions, i| {
+            regions | ((changed >> (4 * i) & 0xF != 0) as u32) << i
+        }));
     }
 
+    // The standard AP registers use 2 bits per region instead of 4
+    fn compress_ap(ext_ap: u32) -> u32 {
+        (0..8).fold(0, |ap, i| ap | (ext_ap >> (4 * i) & 0x3) << (2 * i))
+    }
+
+    fn expand_ap(ap: u32) -> u32 {
+        (0..8).fold(0, |ext_ap, i| ext_ap | (ap >> (2 * i) & 0x3) << (4 * i))
+    }
+
+    // Pages the region covered before are rebuilt too, since other regions may show through
     fn write_pu_regions(&mut self, m: u32, p: u32, value: u32) {
-        match (m, p) {
-            (region, 0) => self.pu_data_regions[region as usize] = value & !(0x3F << 6),
-            (region, 1) => self.pu_instr_regions[region as usize] = value & !(0x3F << 6),
-            _ => todo!(),
-        }
+        let region = match (m, p) {
+            (i @ 0..=7, 0) => &mut self.pu_data_regions[i as usize],
+            (i @ 0..=7, 1) => &mut self.pu_instr_regions[i as usize],
+            _ => {
+                unimplemented_behavior!("CP15 Protection Unit Region Write: {} {}", m, p);
+                return;
+            }
+        };
+        let old = std::mem::replace(region, value & !(0x3F << 6));
+        let new = *region;
+        self.update_page_flags(CP15::region_pages(old));
+        self.update_page_flags(CP15::region_pages(new));
     }
 
     fn write_cache_command(&mut self, m: u32, p: u32, value: u32) {
         match (m, p) {
             (0, 4) if value == 0 => self.arm9_halted = true,
-            (5, 0) if value == 0 => info!("Invalidate Entire Instruction Cache"), // TODO: Invalidate Entire Instruction Cache
-            (5, 1) => info!("Invalidate Instruction Cache Line 0x{:X}", value), // TODO: Invalidate Instruction Cache Line
-            (6, 0) if value == 0 => info!("Invalidate Entire Data Cache"), // TODO: Invalidate Entire Data Cache
-            (6, 1) => info!("Invalidate Data Cache Line 0x{:X}", value), // TODO: Invalidate Data Cache Line
-            (10, 1) => info!("Clean Data Cache Line 0x{:X}", value), // TODO: Clean Data Cache Line
-            (10, 2) => info!("Clean Data Cache Line Index 0x{:X}", value), // TODO: Clean Data Cache Line
-            (10, 4) if value == 0 => info!("Drain Write Buffer"), // TODO: Drain Write Buffer
-            (14, 1) => info!("Clean and Invalidate Data Cache Line 0x{:X}", value), // TODO: Clean and Invalidate Data Cache Line
-            (14, 2) => info!("Clean and Invalidate Data Cache Index 0x{:X}", value), // TODO: Clean and Invalidate Data Cache Line
-            _ => todo!(),
+            (5, 0) if value == 0 => self.icache.invalidate_all(),
+            (5, 1) => self.icache.invalidate_line(value),
+            (6, 0) if value == 0 => self.dcache.invalidate_all(),
+            (6, 1) => self.dcache.invalidate_line(value),
+            (10, 1) => self.dcache.clean_line(value),
+            (10, 2) => self.dcache.clean_index(value),
+            (10, 4) if value == 0 => info!("Drain Write Buffer"), // Writes aren't buffered
+            (14, 1) => {
+                self.dcache.clean_line(value);
+                self.dcache.invalidate_line(value);
+            }
+            (14, 2) => {
+                self.dcache.clean_index(value);
+                self.dcache.invalidate_index(value);
+            }
+            _ => unimplemented_behavior!("CP15 Cache Command: {} {}", m, p),
         }
     }
 
     fn read_cache_control(&self, m: u32, p: u32) -> u32 {
         match (m, p) {
+            (0, 0) => self.dcache.read_lockdown(),
+            (0, 1) => self.icache.read_lockdown(),
             (1, 0) => self.dtcm_control.read(),
             (1, 1) => self.itcm_control.read(),
-            _ => todo!(),
+            _ => {
+                unimplemented_behavior!("CP15 Cache Control Read: {} {}", m, p);
+                0
+            }
         }
     }
 
     fn write_cache_control(&mut self, m: u32, p: u32, value: u32) {
         match (m, p) {
-            (0, 0) => warn!("Data Cache Lockdown: 0x{:X}", value), // TODO: Data Cache Lockdown
-            (0, 1) => warn!("Instruction Cache Lockdown: 0x{:X}", value), // TODO: Instruction Cache Lockdown
+            (0, 0) => self.dcache.write_lockdown(value),
+            (0, 1) => self.icache.write_lockdown(value),
             (1, 0) => self.dtcm_control.write(value),
             (1, 1) => {
                 self.itcm_control.write(value);
-                assert_eq!(self.itcm_control.base, 0)
+                if self.itcm_control.base != 0 {
+                    unimplemented_behavior!("ITCM Base 0x{:08X}", self.itcm_control.base);
+                }
             }
-            _ => todo!(),
+            _ => unimplemented_behavior!("CP15 Cache Control Write: {} {}", m, p),
         }
     }
 }
@@ -222,7 +449,10 @@ impl TCMControl {
     pub fn write(&mut self, value: u32) {
         self.base = value & !0xFFF;
         self.virtual_size_shift = value >> 1 & 0x1F;
-        assert!((3..=23).contains(&self.virtual_size_shift));
+        if !(3..=23).contains(&self.virtual_size_shift) {
+            unimplemented_behavior!("TCM Virtual Size Shift {}", self.virtual_size_shift);
+            self.virtual_size_shift = self.virtual_size_shift.clamp(3, 23);
+        }
         self.virtual_size = 0x200 << self.virtual_size_shift;
     }
 }
@@ -249,6 +479,20 @@ bitflags! {
     }
 }
 
+bitflags! {
+    struct PageFlags: u16 {
+        const CODE_USER = 1 << 0;
+        const CODE_PRIV = 1 << 1;
+        const READ_USER = 1 << 2;
+        const READ_PRIV = 1 << 3;
+        const WRITE_USER = 1 << 4;
+        const WRITE_PRIV = 1 << 5;
+        const INSTR_CACHABLE = 1 << 6;
+        const DATA_CACHABLE = 1 << 7;
+        const WRITE_BACK = 1 << 8;
+    }
+}
+
 impl Control {
     const MASK: u32 = (1 << 19)
         | (1 << 18)
@@ -272,4 +516,68 @@ impl CP15 {
     pub fn interrupt_base(&self) -> u32 {
         self.interrupt_base
     }
+
+    pub fn round_robin_replacement(&self) -> bool {
+        self.control.contains(Control::CACHE_REPLACEMENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Region register for a region of 2^(shift + 1) bytes at base
+    fn region(base: u32, shift: u32) -> u32 {
+        base | shift << 1 | 0x1
+    }
+
+    #[test]
+    fn later_regions_take_priority() {
+        let mut cp15 = CP15::new();
+        cp15.write(1, 0, 0, 0x1);
+        cp15.write(6, 0, 0, region(0, 31));
+        cp15.write(6, 1, 0, region(0x0200_0000, 21));
+        // Region 0 is read/write for everyone and region 1 is privileged only
+        cp15.write(5, 0, 2, 0x13);
+        assert!(cp15.data_access_allowed(false, true, 0x0100_0000));
+        assert!(!cp15.data_access_allowed(false, false, 0x0200_0000));
+        assert!(cp15.data_access_allowed(true, true, 0x023F_FFFF));
+        assert!(cp15.data_access_allowed(false, true, 0x0240_0000));
+
+        // Moving region 1 away uncovers region 0 again
+        cp15.write(6, 1, 0, region(0x0400_0000, 21));
+        assert!(cp15.data_access_allowed(false, true, 0x0200_0000));
+        assert!(!cp15.data_access_allowed(false, false, 0x0400_0000));
+    }
+
+    #[test]
+    fn incremental_updates_match_a_rebuild() {
+        let mut cp15 = CP15::new();
+        let mut seed = 0x1234_5678u32;
+        let mut random = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        for _ in 0..200 {
+            let (value, i) = (random(), random() % 8);
+            match random() % 6 {
+                0 => cp15.write(2, 0, random() % 2, value),
+                1 => cp15.write(3, 0, 0, value),
+                2 => cp15.write(5, 0, random() % 4, value),
+                // Most regions are between 4 KB and 16 MB, like in games
+                _ => {
+                    let shift = match random() % 4 {
+                        0 => value >> 1 & 0x1F,
+                        _ => 11 + random() % 13,
+                    };
+                    cp15.write(6, i, random() % 2, value & !0x3E | shift << 1);
+                }
+            }
+        }
+        let incremental = cp15.page_flags.clone();
+        cp15.update_page_flags(0..cp15.page_flags.len());
+        assert!(incremental == cp15.page_flags);
+    }
 }
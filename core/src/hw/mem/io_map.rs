@@ -0,0 +1,131 @@
+use super::{IORegisterInfo, HW};
+use std::cmp::Ordering;
+
+// Which device handles each IO address, sorted by address
+// Byte accesses are dispatched through these, and debuggers can use them to see what's mapped
+pub struct IOMapEntry {
+    pub name: &'static str,
+    pub addr: u32,
+    pub size: u32, // In bytes
+    pub device: &'static str,
+    // What debuggers show, in the order they're listed
+    pub registers: &'static [IORegisterInfo],
+    pub(super) read: fn(&HW, u32) -> u8,
+    pub(super) write: fn(&mut HW, u32, u8),
+}
+
+impl IOMapEntry {
+    pub fn find(map: &'static [IOMapEntry], addr: u32) -> Option<&'static IOMapEntry> {
+        map.binary_search_by(|entry| {
+            if addr < entry.addr {
+                Ordering::Greater
+            } else if addr - entry.addr >= entry.size {
+                Ordering::Less
+            } else {
+                Ordering::Equal
+            }
+        })
+        .ok()
+        .map(|i| &map[i])
+    }
+}
+
+// Checked when the maps are compiled, since lookups rely on it
+pub(super) const fn is_sorted(map: &[IOMapEntry]) -> bool {
+    let mut i = 1;
+    while i < map.len() {
+        if map[i - 1].addr + map[i - 1].size > map[i].addr {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+// Values shown by debuggers are read into a u64, and each one has to be inside its entry
+pub(super) const fn registers_fit(map: &[IOMapEntry]) -> bool {
+    let mut i = 0;
+    while i < map.len() {
+        let entry = &map[i];
+        let mut j = 0;
+        while j < entry.registers.len() {
+            let register = &entry.registers[j];
+            if register.size > 8
+                || register.addr < entry.addr
+                || register.addr + register.size as u32 > entry.addr + entry.size
+            {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+// IORegister targets are given as `|hw| place` and get the offset into the register
+// Anything else is given as read and write closures that get the full address
+// An entry is shown as a single register unless `fields:` or `registers:` say otherwise
+macro_rules! io {
+    (@registers $name:literal, $addr:literal, $size:literal) => {
+        &[register!($name, $addr, $size, NO_FIELDS)]
+    };
+    (@registers $name:literal, $addr:literal, $size:literal, fields: $fields:expr) => {
+        &[register!($name, $addr, $size, $fields)]
+    };
+    (@registers $name:literal, $addr:literal, $size:literal, registers: [$($register:expr),* $(,)?]) => {
+        &[$($register),*]
+    };
+    ($name:literal, $addr:literal, $size:literal, $device:literal, |$hw:ident| $target:expr $(, $($info:tt)+)?) => {
+        io!(
+            $name,
+            $addr,
+            $size,
+            $device,
+            |$hw, addr| $target.read((addr - $addr) as usize),
+            |$hw, addr, value| $target.write(&mut $hw.scheduler, (addr - $addr) as usize, value)
+            $(, $($info)+)?
+        )
+    };
+    ($name:literal, $addr:literal, $size:literal, $device:literal, $read:expr, $write:expr $(, $($info:tt)+)?) => {
+        IOMapEntry {
+            name: $name,
+            addr: $addr,
+            size: $size,
+            device: $device,
+            registers: io!(@registers $name, $addr, $size $(, $($info)+)?),
+            read: $read,
+            write: $write,
+        }
+    };
+}
+
+impl HW {
+    // Returns None for unknown registers
+    pub(super) fn peek_io8(&self, map: &'static [IOMapEntry], addr: u32) -> Option<u8> {
+        IOMapEntry::find(map, addr).map(|entry| (entry.read)(self, addr))
+    }
+
+    pub(super) fn read_io8(&self, map: &'static [IOMapEntry], cpu: &str, addr: u32) -> u8 {
+        self.peek_io8(map, addr).unwrap_or_else(|| {
+            warn!("Ignoring {} IO Register Read at 0x{:08X}", cpu, addr);
+            0
+        })
+    }
+
+    pub(super) fn write_io8(
+        &mut self,
+        map: &'static [IOMapEntry],
+        cpu: &str,
+        addr: u32,
+        value: u8,
+    ) {
+        match IOMapEntry::find(map, addr) {
+            Some(entry) => (entry.write)(self, addr, value),
+            None => warn!(
+                "Ignoring {} IO Register Write 0x{:08X} = {:02X}",
+                cpu, addr, value
+            ),
+        }
+    }
+}
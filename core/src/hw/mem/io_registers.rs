@@ -0,0 +1,475 @@
+// Names and bitfield layouts of the IO registers, for debuggers
+// Each IO map entry lists the registers it contains, and the viewer groups them by device
+// Values are read with peek, so registers that are write only show up as 0
+
+use super::io_map::IOMapEntry;
+use crate::hw::gpu::{BGControl, Offset};
+use crate::hw::spi;
+use std::ops::RangeInclusive;
+
+pub struct IORegisterGroup {
+    pub name: &'static str,
+    pub registers: Vec<&'static IORegisterInfo>,
+}
+
+impl IORegisterGroup {
+    // Groups are in the order their devices first appear in the map
+    pub fn from_map(map: &'static [IOMapEntry]) -> Vec<IORegisterGroup> {
+        let mut groups: Vec<IORegisterGroup> = Vec::new();
+        for entry in map {
+            let group = match groups.iter().position(|group| group.name == entry.device) {
+                Some(i) => &mut groups[i],
+                None => {
+                    groups.push(IORegisterGroup {
+                        name: entry.device,
+                        registers: Vec::new(),
+                    });
+                    groups.last_mut().unwrap()
+                }
+            };
+            group.registers.extend(entry.registers);
+        }
+        groups
+    }
+}
+
+pub struct IORegisterInfo {
+    pub name: &'static str,
+    pub addr: u32,
+    pub size: usize, // In bytes
+    pub fields: &'static [IORegisterField],
+}
+
+#[derive(Clone, Copy)]
+pub struct IORegisterField {
+    pub name: &'static str,
+    pub lo: usize,
+    pub hi: usize,
+}
+
+impl IORegisterField {
+    // Converts the field table generated by bitfield!
+    const fn from_bitfield<const N: usize>(
+        fields: &[(&'static str, RangeInclusive<u8>, &'static str)],
+    ) -> [IORegisterField; N] {
+        let mut converted = [IORegisterField {
+            name: "",
+            lo: 0,
+            hi: 0,
+        }; N];
+        let mut i = 0;
+        while i < N {
+            converted[i] = IORegisterField {
+                name: fields[i].0,
+                lo: *fields[i].1.start() as usize,
+                hi: *fields[i].1.end() as usize,
+            };
+            i += 1;
+        }
+        converted
+    }
+
+    pub fn mask(&self) -> u64 {
+        ((1u64 << (self.hi - self.lo)) << 1).wrapping_sub(1)
+    }
+
+    pub fn get(&self, value: u64) -> u64 {
+        value >> self.lo & self.mask()
+    }
+
+    pub fn set(&self, value: u64, field: u64) -> u64 {
+        value & !(self.mask() << self.lo) | (field & self.mask()) << self.lo
+    }
+}
+
+macro_rules! fields {
+    ($($name:literal @ $lo:literal $(..= $hi:literal)?),* $(,)?) => {
+        &[$(IORegisterField {
+            name: $name,
+            lo: $lo,
+            hi: fields!(@hi $lo $(, $hi)?),
+        }),*]
+    };
+    (@hi $lo:literal) => { $lo };
+    (@hi $lo:literal, $hi:literal) => { $hi };
+}
+
+// Registers declared with bitfield! already know their layout
+macro_rules! bitfield_fields {
+    ($type:ty) => {
+        &IORegisterField::from_bitfield::<{ <$type>::FIELDS.len() }>(<$type>::FIELDS)
+    };
+}
+
+macro_rules! register {
+    ($name:literal, $addr:literal, $size:literal, $fields:expr) => {
+        IORegisterInfo {
+            name: $name,
+            addr: $addr,
+            size: $size,
+            fields: $fields,
+        }
+    };
+}
+
+pub(super) const NO_FIELDS: &[IORegisterField] = &[];
+
+pub(super) const DISPCNT: &[IORegisterField] = fields![
+    "bg_mode" @ 0..=2,
+    "bg0_3d" @ 3,
+    "tile_obj_1d" @ 4,
+    "bitmap_obj_2d_dim" @ 5,
+    "bitmap_obj_1d" @ 6,
+    "forced_blank" @ 7,
+    "display_bg0" @ 8,
+    "display_bg1" @ 9,
+    "display_bg2" @ 10,
+    "display_bg3" @ 11,
+    "display_obj" @ 12,
+    "display_win0" @ 13,
+    "display_win1" @ 14,
+    "display_obj_win" @ 15,
+    "display_mode" @ 16..=17,
+    "vram_block" @ 18..=19,
+    "tile_obj_1d_boundary" @ 20..=21,
+    "bitmap_obj_1d_boundary" @ 22,
+    "obj_during_hblank" @ 23,
+    "char_base" @ 24..=26,
+    "screen_base" @ 27..=29,
+    "bg_extended_palettes" @ 30,
+    "obj_extended_palettes" @ 31,
+];
+pub(super) const DISPSTAT: &[IORegisterField] = fields![
+    "vblank" @ 0,
+    "hblank" @ 1,
+    "vcounter" @ 2,
+    "vblank_irq" @ 3,
+    "hblank_irq" @ 4,
+    "vcounter_irq" @ 5,
+    "vcount_setting_hi" @ 7,
+    "vcount_setting" @ 8..=15,
+];
+pub(super) const VCOUNT: &[IORegisterField] = fields!["vcount" @ 0..=8];
+pub(super) const BGCNT: &[IORegisterField] = bitfield_fields!(BGControl);
+pub(super) const BGOFS: &[IORegisterField] = bitfield_fields!(Offset);
+pub(super) const WININ: &[IORegisterField] = fields![
+    "win0_layers" @ 0..=4,
+    "win0_color_special" @ 5,
+    "win1_layers" @ 8..=12,
+    "win1_color_special" @ 13,
+];
+pub(super) const WINOUT: &[IORegisterField] = fields![
+    "outside_layers" @ 0..=4,
+    "outside_color_special" @ 5,
+    "obj_win_layers" @ 8..=12,
+    "obj_win_color_special" @ 13,
+];
+pub(super) const BLDCNT: &[IORegisterField] = fields![
+    "target_1" @ 0..=5,
+    "effect" @ 6..=7,
+    "target_2" @ 8..=13,
+];
+pub(super) const BLDALPHA: &[IORegisterField] = fields!["eva" @ 0..=4, "evb" @ 8..=12];
+pub(super) const MASTER_BRIGHT: &[IORegisterField] = fields!["factor" @ 0..=4, "mode" @ 14..=15];
+pub(super) const DISP3DCNT: &[IORegisterField] = fields![
+    "texture_mapping" @ 0,
+    "highlight_shading" @ 1,
+    "alpha_test" @ 2,
+    "alpha_blending" @ 3,
+    "anti_aliasing" @ 4,
+    "edge_marking" @ 5,
+    "fog_alpha_only" @ 6,
+    "fog_enable" @ 7,
+    "fog_depth_shift" @ 8..=11,
+    "color_buffer_underflow" @ 12,
+    "poly_vert_ram_overflow" @ 13,
+    "rear_plane_bitmap" @ 14,
+];
+pub(super) const DISPCAPCNT: &[IORegisterField] = fields![
+    "eva" @ 0..=4,
+    "evb" @ 8..=12,
+    "vram_write_block" @ 16..=17,
+    "vram_write_offset" @ 18..=19,
+    "capture_size" @ 20..=21,
+    "source_a" @ 24,
+    "source_b" @ 25,
+    "vram_read_offset" @ 26..=27,
+    "capture_source" @ 29..=30,
+    "enable" @ 31,
+];
+pub(super) const GXSTAT: &[IORegisterField] = fields![
+    "test_busy" @ 0,
+    "box_test_inside" @ 1,
+    "pos_vec_stack_level" @ 8..=12,
+    "proj_stack_level" @ 13,
+    "stack_busy" @ 14,
+    "stack_error" @ 15,
+    "fifo_len" @ 16..=24,
+    "fifo_less_half" @ 25,
+    "fifo_empty" @ 26,
+    "busy" @ 27,
+    "fifo_irq" @ 30..=31,
+];
+pub(super) const RAM_COUNT: &[IORegisterField] = fields!["polygons" @ 0..=11, "vertices" @ 16..=28];
+pub(super) const POWCNT1: &[IORegisterField] = fields![
+    "enable_lcds" @ 0,
+    "enable_engine_a" @ 1,
+    "enable_3d_rendering" @ 2,
+    "enable_3d_geometry" @ 3,
+    "enable_engine_b" @ 9,
+    "top_a" @ 15,
+];
+pub(super) const VRAMCNT: &[IORegisterField] =
+    fields!["mst" @ 0..=2, "offset" @ 3..=4, "enable" @ 7];
+pub(super) const WRAMCNT: &[IORegisterField] = fields!["mode" @ 0..=1];
+
+pub(super) const ARM9_DMACNT: &[IORegisterField] = fields![
+    "count" @ 0..=20,
+    "dest_addr_ctrl" @ 21..=22,
+    "src_addr_ctrl" @ 23..=24,
+    "repeat" @ 25,
+    "transfer_32" @ 26,
+    "start_timing" @ 27..=29,
+    "irq" @ 30,
+    "enable" @ 31,
+];
+pub(super) const ARM7_DMACNT: &[IORegisterField] = fields![
+    "count" @ 0..=15,
+    "dest_addr_ctrl" @ 21..=22,
+    "src_addr_ctrl" @ 23..=24,
+    "repeat" @ 25,
+    "transfer_32" @ 26,
+    "start_timing" @ 28..=29,
+    "irq" @ 30,
+    "enable" @ 31,
+];
+pub(super) const TMCNT: &[IORegisterField] = fields![
+    "counter" @ 0..=15,
+    "prescaler" @ 16..=17,
+    "count_up" @ 18,
+    "irq" @ 22,
+    "enable" @ 23,
+];
+pub(super) const KEYINPUT: &[IORegisterField] = fields![
+    "a" @ 0,
+    "b" @ 1,
+    "select" @ 2,
+    "start" @ 3,
+    "right" @ 4,
+    "left" @ 5,
+    "up" @ 6,
+    "down" @ 7,
+    "r" @ 8,
+    "l" @ 9,
+];
+pub(super) const KEYCNT: &[IORegisterField] =
+    fields!["keys" @ 0..=9, "irq" @ 14, "irq_condition" @ 15];
+pub(super) const EXTKEYIN: &[IORegisterField] = fields![
+    "x" @ 0,
+    "y" @ 1,
+    "debug" @ 3,
+    "pen_up" @ 6,
+    "hinge_closed" @ 7,
+];
+pub(super) const RTC: &[IORegisterField] = fields![
+    "data" @ 0,
+    "clock" @ 1,
+    "select" @ 2,
+    "data_write" @ 4,
+    "clock_write" @ 5,
+    "select_write" @ 6,
+];
+pub(super) const IPCSYNC: &[IORegisterField] = fields![
+    "input" @ 0..=3,
+    "output" @ 8..=11,
+    "send_irq" @ 13,
+    "irq" @ 14,
+];
+pub(super) const IPCFIFOCNT: &[IORegisterField] = fields![
+    "send_empty" @ 0,
+    "send_full" @ 1,
+    "send_empty_irq" @ 2,
+    "send_clear" @ 3,
+    "recv_empty" @ 8,
+    "recv_full" @ 9,
+    "recv_not_empty_irq" @ 10,
+    "error" @ 14,
+    "enable" @ 15,
+];
+pub(super) const AUXSPICNT: &[IORegisterField] = fields![
+    "baudrate" @ 0..=1,
+    "hold" @ 6,
+    "busy" @ 7,
+    "spi_mode" @ 13,
+    "transfer_irq" @ 14,
+    "enable" @ 15,
+];
+pub(super) const ROMCTRL: &[IORegisterField] = fields![
+    "key1_gap1_len" @ 0..=12,
+    "key2_encrypt_data" @ 13,
+    "key2_apply_seed" @ 15,
+    "key1_gap2_len" @ 16..=21,
+    "key2_encrypt_cmd" @ 22,
+    "data_ready" @ 23,
+    "block_size" @ 24..=26,
+    "transfer_clk_rate" @ 27,
+    "key1_gap_clks" @ 28,
+    "resb_release_reset" @ 29,
+    "write" @ 30,
+    "block_start" @ 31,
+];
+pub(super) const EXMEMCNT: &[IORegisterField] = fields![
+    "gba_sram_access_time" @ 0..=1,
+    "gba_rom_1st_access_time" @ 2..=3,
+    "gba_rom_2nd_access_time" @ 4,
+    "gba_phi_pin_out" @ 5..=6,
+    "gba_arm7_access" @ 7,
+    "nds_arm7_access" @ 11,
+    "main_mem_interface_sync" @ 14,
+    "main_mem_arm7_priority" @ 15,
+];
+pub(super) const IME: &[IORegisterField] = fields!["enable" @ 0];
+pub(super) const INTERRUPTS: &[IORegisterField] = fields![
+    "vblank" @ 0,
+    "hblank" @ 1,
+    "vcounter_match" @ 2,
+    "timer0_overflow" @ 3,
+    "timer1_overflow" @ 4,
+    "timer2_overflow" @ 5,
+    "timer3_overflow" @ 6,
+    "sio_rcnt_rtc" @ 7,
+    "dma0" @ 8,
+    "dma1" @ 9,
+    "dma2" @ 10,
+    "dma3" @ 11,
+    "keypad" @ 12,
+    "game_pak" @ 13,
+    "ipc_sync" @ 16,
+    "ipc_send_fifo_empty" @ 17,
+    "ipc_recv_fifo_not_empty" @ 18,
+    "game_card_transfer_completion" @ 19,
+    "game_card_irq" @ 20,
+    "geometry_command_fifo" @ 21,
+    "screens_unfolding" @ 22,
+    "spi_bus" @ 23,
+    "wifi" @ 24,
+];
+pub(super) const POSTFLG: &[IORegisterField] = fields!["first_boot" @ 0, "bit1" @ 1];
+pub(super) const DIVCNT: &[IORegisterField] =
+    fields!["mode" @ 0..=1, "div_by_zero" @ 14, "busy" @ 15];
+pub(super) const SQRTCNT: &[IORegisterField] = fields!["mode" @ 0, "busy" @ 15];
+
+pub(super) const SPICNT: &[IORegisterField] = bitfield_fields!(spi::CNT);
+pub(super) const WIFIWAITCNT: &[IORegisterField] = fields![
+    "ws0_n" @ 0..=1,
+    "ws0_s" @ 2,
+    "ws1_n" @ 3..=4,
+    "ws1_s" @ 5,
+];
+pub(super) const W_MODE_RST: &[IORegisterField] = fields!["enable" @ 0];
+pub(super) const W_IRQS: &[IORegisterField] = fields![
+    "rx_complete" @ 0,
+    "tx_complete" @ 1,
+    "rx_event_increment" @ 2,
+    "tx_error_increment" @ 3,
+    "rx_event_half_overflow" @ 4,
+    "tx_error_half_overflow" @ 5,
+    "rx_start" @ 6,
+    "tx_start" @ 7,
+    "txbuf_count_expired" @ 8,
+    "rxbuf_count_expired" @ 9,
+    "rf_wakeup" @ 11,
+    "multiplay_cmd_done" @ 12,
+    "post_beacon" @ 13,
+    "beacon" @ 14,
+    "pre_beacon" @ 15,
+];
+pub(super) const W_POWERSTATE: &[IORegisterField] =
+    fields!["request_wakeup" @ 1, "powered_down" @ 9];
+pub(super) const W_TXSLOTS: &[IORegisterField] =
+    fields!["loc1" @ 0, "cmd" @ 1, "loc2" @ 2, "loc3" @ 3, "beacon" @ 4];
+pub(super) const W_TXBUF_LOC: &[IORegisterField] = fields!["addr" @ 0..=11, "enable" @ 15];
+pub(super) const W_TXSTAT: &[IORegisterField] = fields!["complete" @ 0, "slot" @ 8..=11];
+pub(super) const W_ENABLE: &[IORegisterField] = fields!["enable" @ 0];
+pub(super) const HALTCNT: &[IORegisterField] = fields!["mode" @ 6..=7];
+pub(super) const POWCNT2: &[IORegisterField] = fields!["enable_speakers" @ 0, "enable_wifi" @ 1];
+pub(super) const SOUNDCNT: &[IORegisterField] = fields![
+    "volume" @ 0..=6,
+    "volume_div" @ 8..=9,
+    "hold" @ 15,
+    "panning" @ 16..=22,
+    "wave_duty" @ 24..=26,
+    "repeat_mode" @ 27..=28,
+    "format" @ 29..=30,
+    "busy" @ 31,
+];
+pub(super) const MASTER_SOUNDCNT: &[IORegisterField] = fields![
+    "master_volume" @ 0..=6,
+    "left_output" @ 8..=9,
+    "right_output" @ 10..=11,
+    "output_ch1_to_mixer" @ 12,
+    "output_ch3_to_mixer" @ 13,
+    "enable" @ 15,
+];
+pub(super) const SOUNDBIAS: &[IORegisterField] = fields!["bias" @ 0..=9];
+pub(super) const SNDCAPCNT: &[IORegisterField] = fields![
+    "add" @ 0,
+    "source" @ 1,
+    "repeat" @ 2,
+    "format" @ 3,
+    "start" @ 7,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw::mem::{ARM7_IO_MAP, ARM9_IO_MAP};
+
+    #[test]
+    fn bitfield_registers_use_their_declared_fields() {
+        let names: Vec<_> = SPICNT.iter().map(|field| field.name).collect();
+        let declared: Vec<_> = spi::CNT::FIELDS.iter().map(|field| field.0).collect();
+        assert_eq!(names, declared);
+        let device = SPICNT.iter().find(|field| field.name == "device").unwrap();
+        assert_eq!((device.lo, device.hi), (8, 9));
+    }
+
+    #[test]
+    fn fields_fit_in_their_registers() {
+        for entry in ARM9_IO_MAP.iter().chain(ARM7_IO_MAP) {
+            for register in entry.registers {
+                for field in register.fields {
+                    assert!(field.lo <= field.hi, "{}.{}", register.name, field.name);
+                    assert!(
+                        field.hi < 8 * register.size,
+                        "{}.{}",
+                        register.name,
+                        field.name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn groups_show_every_mapped_register() {
+        for map in [ARM9_IO_MAP, ARM7_IO_MAP] {
+            let groups = IORegisterGroup::from_map(map);
+            let shown: usize = groups.iter().map(|group| group.registers.len()).sum();
+            let listed: usize = map.iter().map(|entry| entry.registers.len()).sum();
+            assert_eq!(shown, listed);
+            let names: Vec<_> = groups.iter().map(|group| group.name).collect();
+            let mut deduped = names.clone();
+            deduped.sort_unstable();
+            deduped.dedup();
+            assert_eq!(names.len(), deduped.len());
+        }
+        let arm7 = IORegisterGroup::from_map(ARM7_IO_MAP);
+        let find = |name| {
+            arm7.iter()
+                .flat_map(|group| &group.registers)
+                .any(|r| r.name == name)
+        };
+        assert!(find("W_TXSTAT") && find("ROMSEED0_L") && find("SPIDATA"));
+    }
+}
@@ -172,21 +172,34 @@ impl IORegister for RTC {
             Mode::StartCmd(_) => self.mode,
 
             Mode::SetCmd(command, 7) if prev_sck && !self.sck => {
-                assert!(self.data_write);
+                if !self.data_write {
+                    unimplemented_behavior!("RTC command bit clocked while data is an input");
+                }
                 let command = command << 1 | self.data as u8;
-                assert_eq!(command >> 4 & 0xF, RTC::COMMAND_CODE);
-
-                let parameter = Parameter::from(command >> 1 & 0x7);
-                let (parameter, access_type) = if command & 0x1 != 0 {
-                    let (parameter_byte, next_parameter) = self.read_parameter(parameter);
-                    (next_parameter, AccessType::Read(parameter_byte, 0))
-                } else {
-                    (parameter, AccessType::Write(0, 0))
-                };
-                Mode::ExecCmd(parameter, access_type)
+                match Parameter::from(command >> 1 & 0x7) {
+                    Some(parameter) if command >> 4 & 0xF == RTC::COMMAND_CODE => {
+                        let (parameter, access_type) = if command & 0x1 != 0 {
+                            let (parameter_byte, next_parameter) = self.read_parameter(parameter);
+                            (next_parameter, AccessType::Read(parameter_byte, 0))
+                        } else {
+                            (parameter, AccessType::Write(0, 0))
+                        };
+                        Mode::ExecCmd(parameter, access_type)
+                    }
+                    _ => {
+                        unimplemented_behavior!("RTC Command 0x{:02X}", command);
+                        Mode::EndCmd
+                    }
+                }
             }
             Mode::SetCmd(command, bit) if prev_sck && !self.sck => {
-                assert!(self.cs && self.data_write);
+                if !self.cs || !self.data_write {
+                    unimplemented_behavior!(
+                        "RTC command bit clocked with CS {} and data write {}",
+                        self.cs,
+                        self.data_write
+                    );
+                }
                 Mode::SetCmd(command << 1 | self.data as u8, bit + 1)
             }
             Mode::SetCmd(_, _) => self.mode,
@@ -250,8 +263,9 @@ enum Parameter {
 }
 
 impl Parameter {
-    pub fn from(value: u8) -> Self {
-        match value {
+    // Parameter 7 is the free register, which isn't emulated
+    pub fn from(value: u8) -> Option<Self> {
+        Some(match value {
             0 => Parameter::StatusReg1,
             1 => Parameter::StatusReg2,
             2 => Parameter::DateTime(0),
@@ -259,8 +273,8 @@ impl Parameter {
             4 => Parameter::Alarm1FreqDuty(0),
             5 => Parameter::Alarm2(0),
             6 => Parameter::ClockAdjust,
-            _ => panic!("Invalid RTC Command Parameter {}", value),
-        }
+            _ => return None,
+        })
     }
 }
 
@@ -86,6 +86,7 @@ impl Scheduler {
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Event {
     DMA(bool, usize),
+    DMATransfer(bool),
     StartNextLine,
     HBlank,
     VBlank,
@@ -96,6 +97,11 @@ pub enum Event {
     GenerateAudioSample,
     StepAudioChannel(spu::ChannelSpec),
     ResetAudioChannel(spu::ChannelSpec),
+    WiFiBeacon,
+    WiFiPreBeacon,
+    WiFiTransmitEnded,
+    WiFiMultiplayEnded,
+    WiFiPoll,
 }
 
 struct EventWrapper {
@@ -1,9 +1,10 @@
 mod tsc;
 
-use memmap::{MmapMut, MmapOptions};
-use std::fs::File;
+use bitfield::bitfield;
+use std::convert::TryFrom;
 
-use super::{mem::IORegister, Scheduler, GPU, HW};
+use super::{Scheduler, Storage, GPU, HW};
+use crate::error::{Error, FileKind};
 use crate::hw::cartridge::{Backup, Flash};
 use tsc::TSC;
 
@@ -14,34 +15,55 @@ pub struct SPI {
 }
 
 impl SPI {
-    pub fn new(firmware_file: File) -> Self {
-        SPI {
+    pub const FIRMWARE_SIZE: usize = 0x4_0000;
+
+    pub fn new(firmware: Box<dyn Storage>) -> Result<Self, Error> {
+        Ok(SPI {
             cnt: CNT::new(),
-            firmware: Flash::new_firmware(SPI::init_firmware(firmware_file)),
+            firmware: Flash::new_firmware(SPI::init_firmware(firmware)?),
             tsc: TSC::new(),
-        }
+        })
     }
 
     pub fn read_cnt(&self, byte: usize) -> u8 {
-        if self.cnt.enable {
-            self.cnt.read(byte)
-        } else {
-            0
+        if !self.cnt.enable() {
+            return 0;
+        }
+        match byte {
+            0 => self.cnt.byte0(),
+            1 => self.cnt.byte1(),
+            _ => unreachable!(),
         }
     }
     pub fn read_data(&self) -> u8 {
-        match self.cnt.device {
+        match self.cnt.device() {
             Device::Firmware => self.firmware.read(),
             Device::Touchscreen => self.tsc.read(),
             _ => 0,
         }
     }
 
-    pub fn write_cnt(&mut self, scheduler: &mut Scheduler, byte: usize, value: u8) {
-        let prev_enable = self.cnt.enable;
-        let prev_device = self.cnt.device;
-        self.cnt.write(scheduler, byte, value);
-        if prev_enable && !self.cnt.enable {
+    pub fn write_cnt(&mut self, _scheduler: &mut Scheduler, byte: usize, value: u8) {
+        let prev_enable = self.cnt.enable();
+        let prev_device = self.cnt.device();
+        match byte {
+            // TODO: Set busy flag properly
+            0 => self.cnt.set_byte0(value),
+            1 => {
+                self.cnt.set_byte1(value);
+                if self.cnt.irq() {
+                    unimplemented_behavior!("SPI IRQ");
+                }
+                if self.cnt.transfer16() {
+                    unimplemented_behavior!("16-bit SPI Transfers");
+                }
+                if let Device::Reserved = self.cnt.device() {
+                    unimplemented_behavior!("Reserved SPI Device");
+                }
+            }
+            _ => unreachable!(),
+        }
+        if prev_enable && !self.cnt.enable() {
             // Disabling requires device to be reset for libnds to work
             match prev_device {
                 Device::Firmware => self.firmware.deselect(),
@@ -52,11 +74,11 @@ impl SPI {
     }
 
     pub fn write_data(&mut self, value: u8) {
-        if !self.cnt.enable {
+        if !self.cnt.enable() {
             return;
         }
-        match self.cnt.device {
-            Device::Firmware => self.firmware.write(self.cnt.hold, value),
+        match self.cnt.device() {
+            Device::Firmware => self.firmware.write(self.cnt.hold(), value),
             Device::Touchscreen => self.tsc.write(value),
             _ => (),
         }
@@ -68,9 +90,18 @@ impl SPI {
     pub fn release_screen(&mut self) {
         self.tsc.release_screen()
     }
-    pub fn init_firmware(firmware_file: File) -> MmapMut {
-        let mut mmap = unsafe { MmapOptions::new().map_mut(&firmware_file).unwrap() };
-        let firmware = unsafe { std::slice::from_raw_parts_mut(mmap.as_mut_ptr(), mmap.len()) };
+    pub fn firmware_data(&self) -> &[u8] {
+        self.firmware.data()
+    }
+    pub fn init_firmware(mut storage: Box<dyn Storage>) -> Result<Box<dyn Storage>, Error> {
+        if storage.data().len() < SPI::FIRMWARE_SIZE {
+            return Err(Error::TooSmall {
+                file: FileKind::Firmware,
+                size: storage.data().len(),
+                min: SPI::FIRMWARE_SIZE,
+            });
+        }
+        let firmware = storage.data_mut();
         let user_settings_addr = 0x3FE00;
 
         // Set Touch Screen Calibration
@@ -108,66 +139,25 @@ impl SPI {
             crc as u16
         };
         HW::write_mem(firmware, user_settings_addr + 0x72, crc16);
-        mmap
+        Ok(storage)
     }
 }
 
-pub struct CNT {
-    baudrate: u8,
-    busy: bool,
-    device: Device,
-    transfer16: bool,
-    hold: bool,
-    irq: bool,
-    enable: bool,
-}
-
-impl CNT {
-    pub fn new() -> Self {
-        CNT {
-            baudrate: 0,
-            busy: false,
-            device: Device::Powerman,
-            transfer16: false,
-            hold: false,
-            irq: false,
-            enable: false,
-        }
-    }
-}
-
-impl IORegister for CNT {
-    fn read(&self, byte: usize) -> u8 {
-        match byte {
-            0 => (self.busy as u8) << 7 | self.baudrate,
-            1 => {
-                (self.enable as u8) << 7
-                    | (self.irq as u8) << 6
-                    | (self.hold as u8) << 3
-                    | (self.transfer16 as u8) << 2
-                    | (self.device as u8)
-            }
-            _ => unreachable!(),
-        }
-    }
-
-    fn write(&mut self, _scheduler: &mut Scheduler, byte: usize, value: u8) {
-        match byte {
-            0 => {
-                // TODO: Set busy flag properly
-                self.baudrate = value & 0x3;
-            }
-            1 => {
-                self.enable = value >> 7 & 0x1 != 0;
-                self.irq = value >> 6 & 0x1 != 0;
-                assert!(!self.irq);
-                self.hold = value >> 3 & 0x1 != 0;
-                self.transfer16 = value >> 2 & 0x1 != 0;
-                assert!(!self.transfer16);
-                self.device = Device::from_bits(value & 0x3);
-            }
-            _ => unreachable!(),
-        }
+bitfield! {
+    #[derive(Clone, Copy)]
+    pub struct CNT: u16 {
+        baudrate: u8 @ 0..=1,
+        #[ro]
+        _: u8 @ 2..=6,
+        #[ro]
+        busy: bool @ 7,
+        device: Device @ 8..=9,
+        transfer16: bool @ 10,
+        hold: bool @ 11,
+        #[ro]
+        _: u8 @ 12..=13,
+        irq: bool @ 14,
+        enable: bool @ 15,
     }
 }
 
@@ -176,16 +166,25 @@ enum Device {
     Powerman = 0,
     Firmware = 1,
     Touchscreen = 2,
+    Reserved = 3,
 }
 
-impl Device {
-    pub fn from_bits(value: u8) -> Self {
+impl TryFrom<u8> for Device {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 => Self::Powerman,
-            1 => Self::Firmware,
-            2 => Self::Touchscreen,
-            3 => panic!("Reserved SPI Device!"),
-            _ => unreachable!(),
+            0 => Ok(Self::Powerman),
+            1 => Ok(Self::Firmware),
+            2 => Ok(Self::Touchscreen),
+            3 => Ok(Self::Reserved),
+            _ => Err(()),
         }
     }
 }
+
+impl From<Device> for u8 {
+    fn from(device: Device) -> Self {
+        device as u8
+    }
+}
@@ -139,7 +139,8 @@ impl SPU {
     pub fn capture_data<T: super::MemoryValue>(&self, capture_i: usize) -> T {
         let capture_value = if self.captures[capture_i].cnt.use_channel {
             // TODO: Implement bugged behavior
-            todo!()
+            unimplemented_behavior!("Sound Capture from Channel Output");
+            0
         } else {
             let (mixer, _, _) = self.generate_mixer();
             let mixer_value = (if capture_i == 0 { mixer.0 } else { mixer.1 } >> 16) as u16;
@@ -151,10 +152,9 @@ impl SPU {
         };
         if self.captures[capture_i].cnt.add {
             // TODO: Implement adding channel
-            todo!()
-        } else {
-            num_traits::cast(capture_value).unwrap()
+            unimplemented_behavior!("Sound Capture Addition");
         }
+        num_traits::cast(capture_value).unwrap()
     }
 
     pub fn read_channels(&self, addr: usize) -> u8 {
@@ -259,7 +259,7 @@ impl HW {
                             };
                         self.spu.base_channels[num].schedule(&mut self.scheduler, reset);
                     }
-                    _ => todo!(),
+                    Format::Special => unimplemented_behavior!("Special Format on Base Channel"),
                 }
                 if let Some((_addr, capture_i, use_pcm8)) = self.spu.capture_addr(num) {
                     if use_pcm8 {
@@ -300,7 +300,7 @@ impl HW {
                             };
                         self.spu.psg_channels[num].schedule(&mut self.scheduler, reset);
                     }
-                    _ => todo!(),
+                    Format::Special => unimplemented_behavior!("PSG Channel"),
                 }
             }
             ChannelSpec::Noise(num) => {
@@ -332,7 +332,7 @@ impl HW {
                             };
                         self.spu.noise_channels[num].schedule(&mut self.scheduler, reset);
                     }
-                    _ => todo!(),
+                    Format::Special => unimplemented_behavior!("Noise Channel"),
                 }
             }
         }
@@ -2,31 +2,42 @@ use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use ringbuf::RingBuffer;
 
 pub struct Audio {
-    config: cpal::StreamConfig,
-    _stream: cpal::Stream,
-    prod: ringbuf::Producer<[f32; 2]>,
+    sample_rate: usize,
+    // None without an output device, in which case samples are dropped
+    output: Option<(cpal::Stream, ringbuf::Producer<[f32; 2]>)>,
 }
 
 impl Audio {
     const BUFFER_LEN: usize = 2048;
+    const SILENT_SAMPLE_RATE: usize = 32768;
 
     pub fn new() -> Self {
         let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .expect("No audio output device available!");
-        let config = device
-            .default_output_config()
-            .expect("No audio output config available!");
-
-        match config.sample_format() {
-            cpal::SampleFormat::F32 => Audio::init::<f32>(device, config.into()),
-            cpal::SampleFormat::I16 => Audio::init::<i16>(device, config.into()),
-            cpal::SampleFormat::U16 => Audio::init::<u16>(device, config.into()),
-        }
+        let config = host.default_output_device().and_then(|device| {
+            let config = device.default_output_config().ok()?;
+            Some((device, config))
+        });
+        let audio = match config {
+            Some((device, config)) => match config.sample_format() {
+                cpal::SampleFormat::F32 => Audio::init::<f32>(device, config.into()),
+                cpal::SampleFormat::I16 => Audio::init::<i16>(device, config.into()),
+                cpal::SampleFormat::U16 => Audio::init::<u16>(device, config.into()),
+            },
+            None => Err("No audio output device available".to_string()),
+        };
+        audio.unwrap_or_else(|e| {
+            warn!("{}, running without sound", e);
+            Audio {
+                sample_rate: Audio::SILENT_SAMPLE_RATE,
+                output: None,
+            }
+        })
     }
 
-    fn init<T: cpal::Sample>(device: cpal::Device, config: cpal::StreamConfig) -> Self {
+    fn init<T: cpal::Sample>(
+        device: cpal::Device,
+        config: cpal::StreamConfig,
+    ) -> Result<Self, String> {
         let buffer = RingBuffer::<[f32; 2]>::new(Audio::BUFFER_LEN);
         let (prod, mut cons) = buffer.split();
 
@@ -51,23 +62,24 @@ impl Audio {
                 },
                 |err| error!("Audio Stream Error: {}", err),
             )
-            .unwrap();
-        stream.play().unwrap();
+            .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
 
-        Audio {
-            config,
-            _stream: stream,
-            prod,
-        }
+        Ok(Audio {
+            sample_rate: config.sample_rate.0 as usize,
+            output: Some((stream, prod)),
+        })
     }
 
     pub fn push_sample(&mut self, left_sample: f32, right_sample: f32) {
-        while self.prod.is_full() {} // TODO: Block thread instead of using CPU
-        self.prod.push([left_sample, right_sample]).unwrap();
+        if let Some((_, prod)) = &mut self.output {
+            while prod.is_full() {} // TODO: Block thread instead of using CPU
+            prod.push([left_sample, right_sample]).unwrap();
+        }
     }
 
     pub fn sample_rate(&self) -> usize {
-        self.config.sample_rate.0 as usize
+        self.sample_rate
     }
 }
 
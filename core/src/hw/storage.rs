@@ -0,0 +1,99 @@
+use memmap::{MmapMut, MmapOptions};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Memory backing the save and firmware, which are both written to by games
+pub trait Storage {
+    // Grows the storage to at least size bytes, setting the new bytes to fill. Existing
+    // contents are kept even if the storage is larger than needed
+    fn init(&mut self, size: usize, fill: u8) -> io::Result<()>;
+    fn data(&self) -> &[u8];
+    fn data_mut(&mut self) -> &mut [u8];
+}
+
+// Writes go straight to the file through a memory map
+pub struct FileStorage {
+    file: File,
+    mmap: Option<MmapMut>,
+}
+
+impl FileStorage {
+    pub fn new(file: File) -> io::Result<Self> {
+        let mut storage = FileStorage { file, mmap: None };
+        storage.map()?;
+        Ok(storage)
+    }
+
+    // Creates the file if it doesn't exist
+    pub fn open(path: &Path) -> io::Result<Self> {
+        FileStorage::new(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?,
+        )
+    }
+
+    fn map(&mut self) -> io::Result<()> {
+        // Empty files can't be mapped
+        self.mmap = if self.file.metadata()?.len() == 0 {
+            None
+        } else {
+            Some(unsafe { MmapOptions::new().map_mut(&self.file)? })
+        };
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    fn init(&mut self, size: usize, fill: u8) -> io::Result<()> {
+        let len = self.file.metadata()?.len() as usize;
+        if len < size {
+            self.mmap = None;
+            self.file.seek(SeekFrom::End(0))?;
+            self.file.write_all(&vec![fill; size - len])?;
+            self.map()?;
+        }
+        Ok(())
+    }
+
+    fn data(&self) -> &[u8] {
+        self.mmap.as_deref().unwrap_or(&[])
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        self.mmap.as_deref_mut().unwrap_or(&mut [])
+    }
+}
+
+// Keeps everything in memory, so nothing is written unless the embedder saves NDS::save_data
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: Vec<u8>,
+}
+
+impl MemoryStorage {
+    pub fn new(data: Vec<u8>) -> Self {
+        MemoryStorage { data }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn init(&mut self, size: usize, fill: u8) -> io::Result<()> {
+        if self.data.len() < size {
+            self.data.resize(size, fill);
+        }
+        Ok(())
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
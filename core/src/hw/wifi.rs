@@ -0,0 +1,966 @@
+mod baseband;
+mod link;
+mod rf;
+
+use super::{
+    dma,
+    interrupt_controller::InterruptRequest,
+    scheduler::{Event, Scheduler},
+    HW,
+};
+use crate::nds::NDS;
+use baseband::Baseband;
+pub use link::{LocalHub, LocalLink, UdpLink, WiFiLink};
+use rf::RF;
+use std::iter;
+
+// Register offsets, named as in GBATEK
+const W_ID: usize = 0x000;
+const W_MODE_RST: usize = 0x004;
+const W_MODE_WEP: usize = 0x006;
+const W_IF: usize = 0x010;
+const W_IE: usize = 0x012;
+const W_MACADDR_0: usize = 0x018;
+const W_MACADDR_1: usize = 0x01A;
+const W_MACADDR_2: usize = 0x01C;
+const W_BSSID_0: usize = 0x020;
+const W_BSSID_1: usize = 0x022;
+const W_BSSID_2: usize = 0x024;
+const W_AID_LOW: usize = 0x028;
+const W_AID_FULL: usize = 0x02A;
+const W_TX_RETRYLIMIT: usize = 0x02C;
+const W_RXCNT: usize = 0x030;
+const W_POWERSTATE: usize = 0x03C;
+const W_POWERFORCE: usize = 0x040;
+const W_RANDOM: usize = 0x044;
+const W_RXBUF_BEGIN: usize = 0x050;
+const W_RXBUF_END: usize = 0x052;
+const W_RXBUF_WRCSR: usize = 0x054;
+const W_RXBUF_WR_ADDR: usize = 0x056;
+const W_RXBUF_RD_ADDR: usize = 0x058;
+const W_RXBUF_COUNT: usize = 0x05C;
+const W_RXBUF_RD_DATA: usize = 0x060;
+const W_RXBUF_GAP: usize = 0x062;
+const W_RXBUF_GAPDISP: usize = 0x064;
+const W_TXBUF_WR_ADDR: usize = 0x068;
+const W_TXBUF_COUNT: usize = 0x06C;
+const W_TXBUF_WR_DATA: usize = 0x070;
+const W_TXBUF_GAP: usize = 0x074;
+const W_TXBUF_GAPDISP: usize = 0x076;
+const W_TXBUF_BEACON: usize = 0x080;
+const W_BEACONINT: usize = 0x08C;
+const W_TXBUF_CMD: usize = 0x090;
+const W_TXBUF_REPLY1: usize = 0x094;
+const W_TXBUF_REPLY2: usize = 0x098;
+const W_TXBUF_LOC1: usize = 0x0A0;
+const W_TXBUF_LOC2: usize = 0x0A4;
+const W_TXBUF_LOC3: usize = 0x0A8;
+const W_TXREQ_RESET: usize = 0x0AC;
+const W_TXREQ_SET: usize = 0x0AE;
+const W_TXREQ_READ: usize = 0x0B0;
+const W_TXBUSY: usize = 0x0B6;
+const W_TXSTAT: usize = 0x0B8;
+const W_PREAMBLE: usize = 0x0BC;
+const W_RXFILTER: usize = 0x0D0;
+const W_RXFILTER2: usize = 0x0E0;
+const W_US_COUNTCNT: usize = 0x0E8;
+const W_US_COMPARECNT: usize = 0x0EA;
+const W_US_COMPARE_0: usize = 0x0F0;
+const W_US_COMPARE_3: usize = 0x0F6;
+const W_US_COUNT_0: usize = 0x0F8;
+const W_US_COUNT_3: usize = 0x0FE;
+const W_PRE_BEACON: usize = 0x110;
+const W_BB_CNT: usize = 0x158;
+const W_BB_WRITE: usize = 0x15A;
+const W_BB_READ: usize = 0x15C;
+const W_BB_BUSY: usize = 0x15E;
+const W_RF_DATA2: usize = 0x17C;
+const W_RF_DATA1: usize = 0x17E;
+const W_RF_BUSY: usize = 0x180;
+const W_RF_PINS: usize = 0x19C;
+const W_TX_SEQNO: usize = 0x210;
+const W_RF_STATUS: usize = 0x214;
+const W_IF_SET: usize = 0x21C;
+
+// W_IF and W_IE bits
+const IRQ_RX_COMPLETE: u16 = 1 << 0;
+const IRQ_TX_COMPLETE: u16 = 1 << 1;
+const IRQ_RX_START: u16 = 1 << 6;
+const IRQ_TX_START: u16 = 1 << 7;
+const IRQ_TXBUF_COUNT: u16 = 1 << 8;
+const IRQ_RXBUF_COUNT: u16 = 1 << 9;
+const IRQ_RF_WAKEUP: u16 = 1 << 11;
+const IRQ_MULTIPLAY_END: u16 = 1 << 12;
+const IRQ_BEACON: u16 = 1 << 14;
+const IRQ_PRE_BEACON: u16 = 1 << 15;
+
+// W_RF_STATUS values
+const RF_STATUS_RX: u16 = 1;
+const RF_STATUS_TX: u16 = 3;
+const RF_STATUS_IDLE: u16 = 9;
+
+// Frame control values of the DS multiplayer protocol
+const FC_MULTIPLAY_CMD: u16 = 0x0228;
+const FC_MULTIPLAY_ACK: u16 = 0x0218;
+const FC_MULTIPLAY_REPLY: u16 = 0x0118;
+const FC_MULTIPLAY_EMPTY_REPLY: u16 = 0x0158;
+const MULTIPLAY_ACK_ADDR: [u8; 6] = [0x03, 0x09, 0xBF, 0x00, 0x00, 0x03];
+
+pub struct WiFi {
+    waitcnt: u8,
+    regs: Vec<u16>,
+    ram: Vec<u8>,
+    baseband: Baseband,
+    rf: RF,
+    // W_US_COUNT when it was last written, which it counts up from while enabled
+    us_count: u64,
+    us_count_cycle: usize,
+    transmitting: Option<TxSlot>,
+    multiplay: Option<Multiplay>,
+    irq: bool,
+    link: Option<Box<dyn WiFiLink>>,
+}
+
+// A host waiting for its clients to reply to a multiplayer command
+struct Multiplay {
+    clients: u16,
+    replied: u16,
+    // When every client has had the chance to reply, in us of emulated time
+    reply_end_us: u64,
+    timeout_cycle: usize,
+}
+
+impl WiFi {
+    const RAM_SIZE: usize = 0x2000;
+    const POLL_INTERVAL_US: u64 = 64;
+    // Over links that don't share how far the other consoles have been emulated, hosts wait
+    // about a frame for replies before giving up on a client, so whether one makes it depends
+    // on how the processes are scheduled
+    const MULTIPLAY_TIMEOUT_US: u64 = 20_000;
+    const RESET_VALUES: [(usize, u16); 5] = [
+        (W_ID, 0x1440),
+        (W_POWERSTATE, 0x0200),
+        (W_RANDOM, 0x0001),
+        (W_RF_PINS, 0x0004),
+        (W_RF_STATUS, RF_STATUS_IDLE),
+    ];
+    // Also applied by setting bit 14 of W_MODE_RST
+    const MAC_RESET_VALUES: [(usize, u16); 16] = [
+        (W_MODE_WEP, 0x0000),
+        (W_MACADDR_0, 0x0000),
+        (W_MACADDR_1, 0x0000),
+        (W_MACADDR_2, 0x0000),
+        (W_BSSID_0, 0x0000),
+        (W_BSSID_1, 0x0000),
+        (W_BSSID_2, 0x0000),
+        (W_AID_LOW, 0x0000),
+        (W_AID_FULL, 0x0000),
+        (W_TX_RETRYLIMIT, 0x0707),
+        (W_RXBUF_BEGIN, 0x4000),
+        (W_RXBUF_END, 0x4800),
+        (W_PREAMBLE, 0x0001),
+        (W_RXFILTER, 0x0401),
+        (W_RXFILTER2, 0x0008),
+        (W_TXSTAT, 0x0000),
+    ];
+
+    pub fn new() -> Self {
+        let mut wifi = WiFi {
+            waitcnt: 0,
+            regs: vec![0; 0x800],
+            ram: vec![0; WiFi::RAM_SIZE],
+            baseband: Baseband::new(),
+            rf: RF::new(),
+            us_count: 0,
+            us_count_cycle: 0,
+            transmitting: None,
+            multiplay: None,
+            irq: false,
+            link: None,
+        };
+        for (addr, value) in WiFi::RESET_VALUES
+            .iter()
+            .chain(WiFi::MAC_RESET_VALUES.iter())
+        {
+            *wifi.reg_mut(*addr) = *value;
+        }
+        wifi
+    }
+
+    pub fn read_waitcnt(&self) -> u8 {
+        self.waitcnt
+    }
+
+    pub fn write_waitcnt(&mut self, value: u8) {
+        self.waitcnt = value & 0x3F;
+    }
+
+    // The registers are mirrored at 0x1000 and RAM starts at 0x4000, in both 32KB regions
+    pub fn peek(&self, scheduler: &Scheduler, addr: u32) -> u16 {
+        let addr = addr as usize & 0x7FFE;
+        match addr {
+            0x0000..=0x1FFF => self.peek_reg(scheduler, addr & 0xFFF),
+            0x4000..=0x5FFF => self.read_ram(addr),
+            _ => 0,
+        }
+    }
+
+    pub fn read(&mut self, scheduler: &Scheduler, addr: u32) -> u16 {
+        let value = self.peek(scheduler, addr);
+        let addr = addr as usize & 0x7FFE;
+        if addr < 0x2000 {
+            match addr & 0xFFF {
+                W_RXBUF_RD_DATA => self.advance_rx_read(),
+                W_RANDOM => {
+                    let random = self.reg(W_RANDOM);
+                    *self.reg_mut(W_RANDOM) = random & 0x1 ^ ((random & 0x3FF) << 1 | random >> 10);
+                }
+                _ => (),
+            }
+        }
+        value
+    }
+
+    pub fn write(&mut self, scheduler: &mut Scheduler, addr: u32, value: u16) {
+        let addr = addr as usize & 0x7FFE;
+        match addr {
+            0x0000..=0x1FFF => self.write_reg(scheduler, addr & 0xFFF, value),
+            0x4000..=0x5FFF => self.write_ram(addr, value),
+            _ => (),
+        }
+    }
+
+    // Returns whether the IRQ line was raised since the last call
+    fn take_irq(&mut self) -> bool {
+        let irq = self.reg(W_IF) & self.reg(W_IE) != 0;
+        let raised = irq && !self.irq;
+        self.irq = irq;
+        raised
+    }
+
+    fn reg(&self, addr: usize) -> u16 {
+        self.regs[addr / 2]
+    }
+
+    fn reg_mut(&mut self, addr: usize) -> &mut u16 {
+        &mut self.regs[addr / 2]
+    }
+
+    fn read_ram(&self, addr: usize) -> u16 {
+        let addr = addr & (WiFi::RAM_SIZE - 2);
+        u16::from_le_bytes([self.ram[addr], self.ram[addr + 1]])
+    }
+
+    fn write_ram(&mut self, addr: usize, value: u16) {
+        let addr = addr & (WiFi::RAM_SIZE - 2);
+        self.ram[addr..addr + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn peek_reg(&self, scheduler: &Scheduler, addr: usize) -> u16 {
+        match addr {
+            W_US_COUNT_0..=W_US_COUNT_3 => {
+                (self.us_count(scheduler.cycle) >> (8 * (addr - W_US_COUNT_0))) as u16
+            }
+            W_RXBUF_RD_DATA => self.read_ram(self.reg(W_RXBUF_RD_ADDR) as usize),
+            W_BB_READ => self.baseband.read() as u16,
+            // Transfers with the BB and RF chips finish instantly
+            W_BB_BUSY | W_RF_BUSY => 0,
+            W_TXREQ_RESET | W_TXREQ_SET | W_IF_SET => 0,
+            _ => self.reg(addr),
+        }
+    }
+
+    fn write_reg(&mut self, scheduler: &mut Scheduler, addr: usize, value: u16) {
+        match addr {
+            W_ID | W_RANDOM | W_RXBUF_RD_DATA | W_TXREQ_READ | W_TXBUSY | W_BB_READ | W_BB_BUSY
+            | W_RF_BUSY | W_RF_STATUS => (),
+            W_MODE_RST => {
+                let prev_enabled = self.reg(W_MODE_RST) & 0x1 != 0;
+                *self.reg_mut(W_MODE_RST) = value & 0x1;
+                if value & 0x2000 != 0 {
+                    *self.reg_mut(W_RXBUF_WR_ADDR) = 0;
+                    *self.reg_mut(W_RXBUF_WRCSR) = 0;
+                }
+                if value & 0x4000 != 0 {
+                    for (addr, value) in WiFi::MAC_RESET_VALUES.iter() {
+                        *self.reg_mut(*addr) = *value;
+                    }
+                }
+                if !prev_enabled && value & 0x1 != 0 {
+                    *self.reg_mut(W_RF_STATUS) = RF_STATUS_RX;
+                    *self.reg_mut(W_RF_PINS) = 0x0046;
+                    self.start_transmit(scheduler);
+                } else if prev_enabled && value & 0x1 == 0 {
+                    *self.reg_mut(W_RF_STATUS) = RF_STATUS_IDLE;
+                    *self.reg_mut(W_RF_PINS) = 0x0004;
+                }
+            }
+            W_IF => *self.reg_mut(W_IF) &= !value,
+            W_IF_SET => *self.reg_mut(W_IF) |= value,
+            W_RXCNT => {
+                *self.reg_mut(W_RXCNT) = value & 0xFF0E;
+                if value & 0x1 != 0 {
+                    *self.reg_mut(W_RXBUF_WRCSR) = self.reg(W_RXBUF_WR_ADDR);
+                }
+                if value & 0x80 != 0 {
+                    *self.reg_mut(W_TXBUF_REPLY1) = self.reg(W_TXBUF_REPLY2);
+                    *self.reg_mut(W_TXBUF_REPLY2) = 0;
+                }
+            }
+            W_POWERSTATE => {
+                *self.reg_mut(W_POWERSTATE) = self.reg(W_POWERSTATE) & 0x0200 | value & 0x0002;
+                if value & 0x0002 != 0 {
+                    self.wake_up();
+                }
+            }
+            W_POWERFORCE => {
+                *self.reg_mut(W_POWERFORCE) = value & 0x8001;
+                if value & 0x8000 != 0 {
+                    if value & 0x0001 != 0 {
+                        *self.reg_mut(W_POWERSTATE) |= 0x0200;
+                        *self.reg_mut(W_RF_STATUS) = RF_STATUS_IDLE;
+                    } else {
+                        self.wake_up();
+                    }
+                }
+            }
+            W_RXBUF_WR_ADDR => *self.reg_mut(W_RXBUF_WR_ADDR) = value & 0x0FFF,
+            W_RXBUF_RD_ADDR => *self.reg_mut(W_RXBUF_RD_ADDR) = value & 0x1FFE,
+            W_TXBUF_WR_ADDR => *self.reg_mut(W_TXBUF_WR_ADDR) = value & 0x1FFE,
+            W_TXBUF_WR_DATA => self.write_tx_data(value),
+            W_TXREQ_RESET => *self.reg_mut(W_TXREQ_READ) &= !(value & 0xF),
+            W_TXREQ_SET => {
+                *self.reg_mut(W_TXREQ_READ) |= value & 0xF;
+                self.start_transmit(scheduler);
+            }
+            W_US_COUNTCNT => {
+                self.latch_us_count(scheduler.cycle);
+                *self.reg_mut(W_US_COUNTCNT) = value & 0x1;
+                self.schedule_beacon(scheduler);
+            }
+            W_US_COUNT_0..=W_US_COUNT_3 => {
+                self.latch_us_count(scheduler.cycle);
+                let shift = 8 * (addr - W_US_COUNT_0);
+                self.us_count = self.us_count & !(0xFFFF << shift) | (value as u64) << shift;
+                self.schedule_beacon(scheduler);
+            }
+            W_US_COMPARECNT => {
+                *self.reg_mut(W_US_COMPARECNT) = value & 0x1;
+                if value & 0x2 != 0 {
+                    *self.reg_mut(W_IF) |= IRQ_BEACON;
+                }
+                self.schedule_beacon(scheduler);
+            }
+            W_US_COMPARE_0..=W_US_COMPARE_3 | W_BEACONINT | W_PRE_BEACON => {
+                *self.reg_mut(addr) = value;
+                self.schedule_beacon(scheduler);
+            }
+            W_BB_CNT => {
+                *self.reg_mut(W_BB_CNT) = value;
+                self.baseband.transfer(value, self.reg(W_BB_WRITE) as u8);
+            }
+            W_RF_DATA1 => {
+                let (data2, data1) = self.rf.transfer(self.reg(W_RF_DATA2), value);
+                *self.reg_mut(W_RF_DATA2) = data2;
+                *self.reg_mut(W_RF_DATA1) = data1;
+            }
+            _ => *self.reg_mut(addr) = value,
+        }
+    }
+
+    fn wake_up(&mut self) {
+        if self.reg(W_POWERSTATE) & 0x0200 != 0 {
+            *self.reg_mut(W_POWERSTATE) &= !0x0200;
+            *self.reg_mut(W_RF_STATUS) = RF_STATUS_RX;
+            *self.reg_mut(W_IF) |= IRQ_RF_WAKEUP;
+        }
+    }
+
+    fn decrement_count(&mut self, addr: usize, irq: u16) {
+        let count = self.reg(addr);
+        if count != 0 {
+            *self.reg_mut(addr) = count - 1;
+            if count == 1 {
+                *self.reg_mut(W_IF) |= irq;
+            }
+        }
+    }
+
+    // Reading W_RXBUF_RD_DATA advances through the circular RX buffer, skipping the gap
+    fn advance_rx_read(&mut self) {
+        let mut addr = self.reg(W_RXBUF_RD_ADDR) as usize + 2;
+        if addr == self.reg(W_RXBUF_GAP) as usize & 0x1FFE {
+            addr += 2 * self.reg(W_RXBUF_GAPDISP) as usize;
+        }
+        let begin = self.reg(W_RXBUF_BEGIN) as usize & 0x1FFE;
+        let end = self.reg(W_RXBUF_END) as usize & 0x1FFE;
+        if addr >= end {
+            addr = begin + (addr - end);
+        }
+        *self.reg_mut(W_RXBUF_RD_ADDR) = addr as u16 & 0x1FFE;
+        self.decrement_count(W_RXBUF_COUNT, IRQ_RXBUF_COUNT);
+    }
+
+    fn write_tx_data(&mut self, value: u16) {
+        let addr = self.reg(W_TXBUF_WR_ADDR) as usize;
+        self.write_ram(addr, value);
+        let mut addr = addr + 2;
+        if addr == self.reg(W_TXBUF_GAP) as usize & 0x1FFE {
+            addr += 2 * self.reg(W_TXBUF_GAPDISP) as usize;
+        }
+        *self.reg_mut(W_TXBUF_WR_ADDR) = addr as u16 & 0x1FFE;
+        self.decrement_count(W_TXBUF_COUNT, IRQ_TXBUF_COUNT);
+    }
+
+    fn us_count(&self, cycle: usize) -> u64 {
+        if self.reg(W_US_COUNTCNT) & 0x1 != 0 {
+            let cycles_passed = (cycle - self.us_count_cycle) as u64;
+            self.us_count + cycles_passed * 1_000_000 / NDS::CLOCK_RATE as u64
+        } else {
+            self.us_count
+        }
+    }
+
+    fn latch_us_count(&mut self, cycle: usize) {
+        self.us_count = self.us_count(cycle);
+        self.us_count_cycle = cycle;
+    }
+
+    fn cycles_to_us(cycles: usize) -> u64 {
+        cycles as u64 * 1_000_000 / NDS::CLOCK_RATE as u64
+    }
+
+    fn us_to_cycles(us: u64) -> usize {
+        (us * NDS::CLOCK_RATE as u64).div_ceil(1_000_000) as usize
+    }
+
+    // The lower 10 bits of W_US_COMPARE are ignored, so beacons are aligned to 1024 us
+    fn us_compare(&self) -> u64 {
+        (0..4).rev().fold(0, |compare, i| {
+            compare << 16 | self.reg(W_US_COMPARE_0 + 2 * i) as u64
+        }) & !0x3FF
+    }
+
+    fn schedule_beacon(&mut self, scheduler: &mut Scheduler) {
+        scheduler.remove(Event::WiFiBeacon);
+        scheduler.remove(Event::WiFiPreBeacon);
+        if self.reg(W_US_COUNTCNT) & 0x1 == 0 || self.reg(W_US_COMPARECNT) & 0x1 == 0 {
+            return;
+        }
+        let us_count = self.us_count(scheduler.cycle);
+        let compare = self.us_compare();
+        if compare <= us_count {
+            return;
+        }
+        let delay = compare - us_count;
+        scheduler.schedule(
+            Event::WiFiBeacon,
+            HW::on_wifi_beacon,
+            WiFi::us_to_cycles(delay),
+        );
+        let pre_beacon = self.reg(W_PRE_BEACON) as u64;
+        if pre_beacon != 0 && pre_beacon < delay {
+            scheduler.schedule(
+                Event::WiFiPreBeacon,
+                HW::on_wifi_pre_beacon,
+                WiFi::us_to_cycles(delay - pre_beacon),
+            );
+        }
+    }
+
+    // W_US_COMPARE moves to the next beacon interval, which is in units of 1024 us
+    fn on_beacon(&mut self, scheduler: &mut Scheduler) {
+        *self.reg_mut(W_IF) |= IRQ_BEACON;
+        let interval = (self.reg(W_BEACONINT) & 0x3FF) as u64;
+        let compare = self.us_compare() + (interval << 10);
+        for i in 0..4 {
+            *self.reg_mut(W_US_COMPARE_0 + 2 * i) = (compare >> (16 * i)) as u16;
+        }
+        if self.reg(W_TXBUF_BEACON) & 0x8000 != 0
+            && self.reg(W_MODE_RST) & 0x1 != 0
+            && self.transmitting.is_none()
+        {
+            self.transmit(scheduler, TxSlot::Beacon);
+        }
+        self.schedule_beacon(scheduler);
+    }
+
+    // The slot registers have the TX header's address in halfwords
+    fn tx_header_addr(&self, slot: TxSlot) -> usize {
+        (self.reg(slot.reg()) as usize & 0x0FFF) * 2
+    }
+
+    fn start_transmit(&mut self, scheduler: &mut Scheduler) {
+        if self.transmitting.is_some() || self.reg(W_MODE_RST) & 0x1 == 0 {
+            return;
+        }
+        let txreq = self.reg(W_TXREQ_READ);
+        let slot = TxSlot::QUEUED
+            .iter()
+            .find(|slot| txreq & slot.bit() != 0 && self.reg(slot.reg()) & 0x8000 != 0);
+        if let Some(slot) = slot {
+            self.transmit(scheduler, *slot);
+        }
+    }
+
+    // The TX header has the rate at 0x8 and the frame length, including the FCS, at 0xA
+    fn transmit(&mut self, scheduler: &mut Scheduler, slot: TxSlot) {
+        let addr = self.tx_header_addr(slot);
+        let rate_mbps = if self.read_ram(addr + 0x8) & 0xFF == 0x14 {
+            2
+        } else {
+            1
+        };
+        let len = (self.read_ram(addr + 0xA) & 0x3FFF) as u64;
+        // 192 us for the long preamble and PLCP header, then the frame itself
+        let duration = 192 + len * 8 / rate_mbps;
+        self.transmitting = Some(slot);
+        *self.reg_mut(W_TXBUSY) |= slot.bit();
+        *self.reg_mut(W_RF_STATUS) = RF_STATUS_TX;
+        *self.reg_mut(W_IF) |= IRQ_TX_START;
+        scheduler.schedule(
+            Event::WiFiTransmitEnded,
+            HW::on_wifi_transmit_ended,
+            WiFi::us_to_cycles(duration),
+        );
+    }
+
+    fn finish_transmit(&mut self, scheduler: &mut Scheduler) {
+        let slot = self.transmitting.unwrap();
+        let addr = self.tx_header_addr(slot);
+        self.write_ram(addr, 0x0001);
+        let packet = self.tx_packet(addr);
+        self.send(&packet);
+        // The command frame has the time each client gets to reply in us, then the clients
+        if slot == TxSlot::Cmd {
+            let reply_time = self.read_ram(addr + 0xC + 0x18) as u64;
+            let clients = self.read_ram(addr + 0xC + 0x1A) & 0xFFFE;
+            if clients != 0 {
+                let reply_window_us = reply_time * clients.count_ones() as u64 + 16;
+                self.multiplay = Some(Multiplay {
+                    clients,
+                    replied: 0,
+                    reply_end_us: WiFi::cycles_to_us(scheduler.cycle) + reply_window_us,
+                    timeout_cycle: scheduler.cycle + WiFi::us_to_cycles(WiFi::MULTIPLAY_TIMEOUT_US),
+                });
+                scheduler.schedule(
+                    Event::WiFiMultiplayEnded,
+                    HW::on_wifi_multiplay_ended,
+                    WiFi::us_to_cycles(reply_window_us),
+                );
+                return;
+            }
+        }
+        self.complete_transmit(scheduler, slot);
+    }
+
+    fn complete_transmit(&mut self, scheduler: &mut Scheduler, slot: TxSlot) {
+        self.transmitting = None;
+        if slot != TxSlot::Beacon {
+            *self.reg_mut(slot.reg()) &= !0x8000;
+        }
+        *self.reg_mut(W_TXBUSY) &= !slot.bit();
+        *self.reg_mut(W_TXSTAT) = (slot as u16) << 8 | 0x0001;
+        *self.reg_mut(W_TX_SEQNO) = self.reg(W_TX_SEQNO).wrapping_add(1) & 0x0FFF;
+        *self.reg_mut(W_RF_STATUS) = RF_STATUS_RX;
+        *self.reg_mut(W_IF) |= IRQ_TX_COMPLETE;
+        self.start_transmit(scheduler);
+    }
+
+    // Waits longer while clients haven't replied, since the other consoles may be running behind
+    // Their time is read before polling, so any reply they sent by then has been received
+    fn end_multiplay(&mut self, scheduler: &mut Scheduler) {
+        let peers_time = self.link.as_ref().map(|link| link.peers_time());
+        self.poll(scheduler);
+        let multiplay = self.multiplay.as_ref().unwrap();
+        let missed = multiplay.clients & !multiplay.replied;
+        let peers_behind = match peers_time {
+            Some(Some(time)) => time < multiplay.reply_end_us,
+            Some(None) => scheduler.cycle < multiplay.timeout_cycle,
+            None => false,
+        };
+        if missed != 0 && peers_behind {
+            scheduler.schedule(
+                Event::WiFiMultiplayEnded,
+                HW::on_wifi_multiplay_ended,
+                WiFi::us_to_cycles(WiFi::POLL_INTERVAL_US),
+            );
+            return;
+        }
+        self.multiplay = None;
+        let addr = self.tx_header_addr(TxSlot::Cmd);
+        self.write_ram(addr + 0x2, missed);
+        let mut frame = self.frame_header(FC_MULTIPLAY_ACK, MULTIPLAY_ACK_ADDR);
+        frame.extend_from_slice(&missed.to_le_bytes());
+        let rate = self.read_ram(addr + 0x8) as u8;
+        self.send(&[&[rate, 0], &frame[..]].concat());
+        *self.reg_mut(W_IF) |= IRQ_MULTIPLAY_END;
+        self.complete_transmit(scheduler, TxSlot::Cmd);
+    }
+
+    // Packets sent over links are the rate, the sender's AID and then the frame without its FCS
+    fn tx_packet(&self, addr: usize) -> Vec<u8> {
+        let rate = self.read_ram(addr + 0x8) as u8;
+        let len = (self.read_ram(addr + 0xA) & 0x3FFF) as usize;
+        let aid = self.reg(W_AID_LOW) as u8 & 0xF;
+        let frame = (0..len.saturating_sub(4)).map(|i| self.ram[(addr + 0xC + i) % WiFi::RAM_SIZE]);
+        [rate, aid].into_iter().chain(frame).collect()
+    }
+
+    fn send(&mut self, packet: &[u8]) {
+        if let Some(link) = &mut self.link {
+            link.send(packet);
+        }
+    }
+
+    // A data frame from this console with an empty sequence number
+    fn frame_header(&self, frame_control: u16, dest: [u8; 6]) -> Vec<u8> {
+        let mut frame = frame_control.to_le_bytes().to_vec();
+        frame.extend_from_slice(&[0; 2]);
+        frame.extend_from_slice(&dest);
+        for addr in [
+            W_MACADDR_0,
+            W_MACADDR_1,
+            W_MACADDR_2,
+            W_BSSID_0,
+            W_BSSID_1,
+            W_BSSID_2,
+        ] {
+            frame.extend_from_slice(&self.reg(addr).to_le_bytes());
+        }
+        frame.extend_from_slice(&[0; 2]);
+        frame
+    }
+
+    // Other consoles know everything sent before the time reported here has been received
+    fn poll(&mut self, scheduler: &Scheduler) {
+        let packets: Vec<_> = match &mut self.link {
+            Some(link) => iter::from_fn(|| link.receive()).collect(),
+            None => return,
+        };
+        for packet in packets {
+            self.receive(&packet);
+        }
+        if let Some(link) = &mut self.link {
+            link.set_time(WiFi::cycles_to_us(scheduler.cycle));
+        }
+    }
+
+    fn receive(&mut self, packet: &[u8]) {
+        if packet.len() < 2 + 0x18 {
+            return;
+        }
+        let (rate, aid, frame) = (packet[0], packet[1], &packet[2..]);
+        let frame_control = u16::from_le_bytes([frame[0], frame[1]]);
+        let is_reply =
+            frame_control == FC_MULTIPLAY_REPLY || frame_control == FC_MULTIPLAY_EMPTY_REPLY;
+        if let Some(multiplay) = &mut self.multiplay {
+            if is_reply && aid < 16 {
+                multiplay.replied |= 1 << aid;
+            }
+        }
+        if self.reg(W_MODE_RST) & 0x1 == 0 || self.reg(W_RXCNT) & 0x8000 == 0 {
+            return;
+        }
+        let mac = [W_MACADDR_0, W_MACADDR_1, W_MACADDR_2].map(|addr| self.reg(addr));
+        let bssid = [W_BSSID_0, W_BSSID_1, W_BSSID_2].map(|addr| self.reg(addr));
+        let addr = |offset: usize| {
+            [0, 2, 4].map(|i| u16::from_le_bytes([frame[offset + i], frame[offset + i + 1]]))
+        };
+        // Group addresses have the lowest bit of their first byte set
+        if frame[4] & 0x1 == 0 && addr(4) != mac {
+            return;
+        }
+        let flags = match frame_control {
+            _ if frame_control & 0x00FC == 0x0080 && addr(16) == bssid => 0x8001,
+            _ if frame_control & 0x00FC == 0x0080 => 0x0001,
+            FC_MULTIPLAY_CMD => 0x000C,
+            FC_MULTIPLAY_ACK => 0x000D,
+            _ if is_reply => 0x000E,
+            _ if frame_control & 0x000C == 0x0008 => 0x0008,
+            _ => 0x0000,
+        };
+        self.store_rx_frame(flags, rate, frame);
+        if frame_control == FC_MULTIPLAY_CMD && frame.len() >= 0x1C {
+            let clients = u16::from_le_bytes([frame[0x1A], frame[0x1B]]);
+            self.reply(clients, addr(10));
+        }
+    }
+
+    // Frames are stored after a 12 byte RX header at W_RXBUF_WRCSR, which stays word aligned
+    fn store_rx_frame(&mut self, flags: u16, rate: u8, frame: &[u8]) {
+        let begin = self.reg(W_RXBUF_BEGIN) as usize & 0x1FFE;
+        let end = self.reg(W_RXBUF_END) as usize & 0x1FFE;
+        if end <= begin {
+            return;
+        }
+        let mut data = [flags, 0, 0, rate as u16, frame.len() as u16, 0x0040]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .chain(frame.iter().copied())
+            .collect::<Vec<_>>();
+        data.resize(data.len().next_multiple_of(4), 0);
+        let mut addr = (self.reg(W_RXBUF_WRCSR) as usize & 0x0FFF) * 2;
+        for value in data.chunks(2) {
+            self.write_ram(addr, u16::from_le_bytes([value[0], value[1]]));
+            addr += 2;
+            if addr >= end {
+                addr = begin;
+            }
+        }
+        *self.reg_mut(W_RXBUF_WRCSR) = (addr / 2) as u16;
+        *self.reg_mut(W_IF) |= IRQ_RX_START | IRQ_RX_COMPLETE;
+    }
+
+    // Clients answer commands that include their AID right away with W_TXBUF_REPLY1, or an
+    // empty reply if it isn't ready
+    fn reply(&mut self, clients: u16, host: [u16; 3]) {
+        let aid = self.reg(W_AID_LOW) & 0xF;
+        if aid == 0 || clients & 1 << aid == 0 {
+            return;
+        }
+        let reply = self.reg(W_TXBUF_REPLY1);
+        let packet = if reply & 0x8000 != 0 {
+            let addr = (reply as usize & 0x0FFF) * 2;
+            self.write_ram(addr, 0x0001);
+            *self.reg_mut(W_TXBUF_REPLY1) &= !0x8000;
+            self.tx_packet(addr)
+        } else {
+            let dest = host.map(u16::to_le_bytes).concat().try_into().unwrap();
+            let frame = self.frame_header(FC_MULTIPLAY_EMPTY_REPLY, dest);
+            [&[0x14, aid as u8], &frame[..]].concat()
+        };
+        self.send(&packet);
+    }
+}
+
+impl HW {
+    pub fn read_wifi(&mut self, addr: u32) -> u16 {
+        let value = self.wifi.read(&self.scheduler, addr);
+        self.check_wifi_irq();
+        value
+    }
+
+    pub fn write_wifi(&mut self, addr: u32, value: u16) {
+        self.wifi.write(&mut self.scheduler, addr, value);
+        self.check_wifi_irq();
+    }
+
+    // The same signal requests the IRQ and starts Wireless Interrupt DMAs
+    fn check_wifi_irq(&mut self) {
+        if self.wifi.take_irq() {
+            self.interrupts[0].request |= InterruptRequest::WIFI;
+            self.run_dmas_single(dma::Occasion::WirelessInterrupt, false);
+        }
+    }
+
+    fn on_wifi_beacon(&mut self, _event: Event) {
+        self.wifi.on_beacon(&mut self.scheduler);
+        self.check_wifi_irq();
+    }
+
+    fn on_wifi_pre_beacon(&mut self, _event: Event) {
+        *self.wifi.reg_mut(W_IF) |= IRQ_PRE_BEACON;
+        self.check_wifi_irq();
+    }
+
+    fn on_wifi_transmit_ended(&mut self, _event: Event) {
+        self.wifi.finish_transmit(&mut self.scheduler);
+        self.check_wifi_irq();
+    }
+
+    fn on_wifi_multiplay_ended(&mut self, _event: Event) {
+        self.wifi.end_multiplay(&mut self.scheduler);
+        self.check_wifi_irq();
+    }
+
+    // Frames are delivered to the RX buffer in batches, which is often enough to be invisible
+    fn on_wifi_poll(&mut self, _event: Event) {
+        self.wifi.poll(&self.scheduler);
+        self.check_wifi_irq();
+        self.scheduler.schedule(
+            Event::WiFiPoll,
+            HW::on_wifi_poll,
+            WiFi::us_to_cycles(WiFi::POLL_INTERVAL_US),
+        );
+    }
+
+    pub fn connect_wifi(&mut self, link: Box<dyn WiFiLink>) {
+        self.wifi.link = Some(link);
+        self.scheduler.remove(Event::WiFiPoll);
+        self.scheduler.schedule(
+            Event::WiFiPoll,
+            HW::on_wifi_poll,
+            WiFi::us_to_cycles(WiFi::POLL_INTERVAL_US),
+        );
+    }
+
+    pub fn disconnect_wifi(&mut self) {
+        self.wifi.link = None;
+        self.scheduler.remove(Event::WiFiPoll);
+    }
+}
+
+// Bits in W_TXREQ and W_TXBUSY
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TxSlot {
+    Loc1 = 0,
+    Cmd = 1,
+    Loc2 = 2,
+    Loc3 = 3,
+    Beacon = 4,
+}
+
+impl TxSlot {
+    // In priority order
+    const QUEUED: [TxSlot; 4] = [TxSlot::Cmd, TxSlot::Loc3, TxSlot::Loc2, TxSlot::Loc1];
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+
+    fn reg(self) -> usize {
+        match self {
+            TxSlot::Loc1 => W_TXBUF_LOC1,
+            TxSlot::Cmd => W_TXBUF_CMD,
+            TxSlot::Loc2 => W_TXBUF_LOC2,
+            TxSlot::Loc3 => W_TXBUF_LOC3,
+            TxSlot::Beacon => W_TXBUF_BEACON,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST_MAC: u16 = 0x1100;
+    const CLIENT_MAC: u16 = 0x2200;
+
+    // Powers on the Wi-Fi and starts receiving, like games do before multiplayer
+    fn console(hub: &LocalHub, mac: u16, aid: u16) -> HW {
+        let mut hw = HW::new_for_tests();
+        hw.connect_wifi(Box::new(hub.connect()));
+        hw.arm7_write::<u32>(0x0400_0304, 0x3);
+        for (reg, value) in [
+            (W_POWERFORCE, 0x8000),
+            (W_US_COUNTCNT, 0x0001),
+            (W_RXCNT, 0x8000),
+            (W_MODE_RST, 0x0001),
+            (W_MACADDR_0, mac),
+            (W_AID_LOW, aid),
+        ] {
+            hw.arm7_write::<u16>(0x0480_8000 + reg as u32, value);
+        }
+        hw
+    }
+
+    // A 32 byte frame with a TX header at addr, sent from the host to the multiplayer group
+    fn write_frame(hw: &mut HW, addr: u32, frame_control: u16, body: [u16; 2]) {
+        let mut values = vec![0; 4];
+        values.extend([0x0014, 32, frame_control, 0x0000, 0x0903, 0x0009, 0x00BF]);
+        values.extend([HOST_MAC, 0, 0, HOST_MAC, 0, 0, 0x0000]);
+        values.extend(body);
+        for (i, value) in values.into_iter().enumerate() {
+            hw.arm7_write::<u16>(0x0480_4000 + addr + 2 * i as u32, value);
+        }
+    }
+
+    // The host asks the clients in the mask for a reply within 256 us each
+    fn send_command(hw: &mut HW, clients: u16) {
+        write_frame(hw, 0x1200, FC_MULTIPLAY_CMD, [0x0100, clients]);
+        hw.arm7_write::<u16>(0x0480_8000 + W_TXBUF_CMD as u32, 0x8900);
+        hw.arm7_write::<u16>(0x0480_8000 + W_TXREQ_SET as u32, 0x0002);
+    }
+
+    fn prepare_reply(hw: &mut HW) {
+        write_frame(hw, 0x1100, FC_MULTIPLAY_REPLY, [0xBEEF, 0xCAFE]);
+        hw.arm7_write::<u16>(0x0480_8000 + W_TXBUF_REPLY1 as u32, 0x8880);
+    }
+
+    // Emulates the consoles one after the other in slices, like a frontend running them in
+    // one thread would
+    fn run_together(consoles: &mut [HW], slice_us: u64, total_us: u64) {
+        for _ in 0..total_us / slice_us {
+            for hw in consoles.iter_mut() {
+                let target = hw.cycle() + WiFi::us_to_cycles(slice_us);
+                while hw.cycle() < target {
+                    let next = hw.cycle_at_next_event().min(target);
+                    hw.clock_until(next);
+                }
+            }
+        }
+    }
+
+    // The RX header flags and first body halfword of the nth frame in the RX buffer
+    fn rx_frame(hw: &HW, n: usize) -> (u16, u16) {
+        let addr = 0x4000 + n * 0x28;
+        (hw.wifi.read_ram(addr), hw.wifi.read_ram(addr + 0x24))
+    }
+
+    #[test]
+    fn multiplayer_exchange_does_not_depend_on_scheduling() {
+        for slice_us in [50, 1000, 8000] {
+            let hub = LocalHub::new();
+            let mut consoles = [console(&hub, HOST_MAC, 0), console(&hub, CLIENT_MAC, 1)];
+            prepare_reply(&mut consoles[1]);
+            send_command(&mut consoles[0], 0x0002);
+            run_together(&mut consoles, slice_us, 16_000);
+
+            let [host, client] = &consoles;
+            assert_ne!(host.wifi.reg(W_IF) & IRQ_MULTIPLAY_END, 0);
+            assert_eq!(host.wifi.reg(W_TXBUF_CMD) & 0x8000, 0);
+            assert_eq!(host.wifi.read_ram(0x1202), 0x0000);
+            assert_eq!(rx_frame(host, 0), (0x000E, 0xBEEF));
+
+            assert_eq!(client.wifi.reg(W_TXBUF_REPLY1) & 0x8000, 0);
+            assert_eq!(client.wifi.read_ram(0x1100), 0x0001);
+            assert_eq!(rx_frame(client, 0), (0x000C, 0x0100));
+            assert_eq!(rx_frame(client, 1).0, 0x000D);
+        }
+    }
+
+    #[test]
+    fn hosts_wait_for_every_console_before_missing_a_client() {
+        let hub = LocalHub::new();
+        let mut consoles = [
+            console(&hub, HOST_MAC, 0),
+            console(&hub, CLIENT_MAC, 1),
+            console(&hub, CLIENT_MAC + 1, 2),
+        ];
+        prepare_reply(&mut consoles[1]);
+        // The second client isn't listening, so it never replies
+        consoles[2].arm7_write::<u16>(0x0480_8000 + W_RXCNT as u32, 0x0000);
+        send_command(&mut consoles[0], 0x0006);
+
+        // Nothing is decided while the last client hasn't caught up with the reply window
+        run_together(&mut consoles[..2], 2000, 40_000);
+        assert_eq!(consoles[0].wifi.reg(W_IF) & IRQ_MULTIPLAY_END, 0);
+
+        run_together(&mut consoles, 2000, 4000);
+        let host = &consoles[0];
+        assert_ne!(host.wifi.reg(W_IF) & IRQ_MULTIPLAY_END, 0);
+        assert_eq!(host.wifi.read_ram(0x1202), 0x0004);
+        assert_eq!(rx_frame(host, 0), (0x000E, 0xBEEF));
+    }
+
+    #[test]
+    fn hubs_broadcast_to_every_other_console() {
+        let hub = LocalHub::new();
+        let mut links = [hub.connect(), hub.connect(), hub.connect()];
+        links[0].send(&[1]);
+        links[1].send(&[2]);
+        assert_eq!(links[0].receive(), Some(vec![2]));
+        assert_eq!(links[0].receive(), None);
+        assert_eq!(links[1].receive(), Some(vec![1]));
+        assert_eq!(links[2].receive(), Some(vec![1]));
+        assert_eq!(links[2].receive(), Some(vec![2]));
+
+        links[1].set_time(100);
+        links[2].set_time(50);
+        assert_eq!(links[0].peers_time(), Some(50));
+        let [first, _, last] = links;
+        drop(last);
+        assert_eq!(first.peers_time(), Some(100));
+    }
+}
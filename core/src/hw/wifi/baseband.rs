@@ -0,0 +1,50 @@
+// BB chip, accessed indirectly through W_BB_CNT, W_BB_WRITE and W_BB_READ
+pub struct Baseband {
+    regs: [u8; 0x100],
+    read_value: u8,
+}
+
+impl Baseband {
+    const CHIP_ID: u8 = 0x6D;
+    const WRITABLE: [std::ops::RangeInclusive<usize>; 9] = [
+        0x01..=0x0C,
+        0x13..=0x15,
+        0x1B..=0x26,
+        0x28..=0x4C,
+        0x4E..=0x5C,
+        0x62..=0x63,
+        0x65..=0x65,
+        0x67..=0x68,
+        0x6A..=0x6A,
+    ];
+
+    pub fn new() -> Self {
+        let mut regs = [0; 0x100];
+        regs[0x00] = Baseband::CHIP_ID;
+        Baseband {
+            regs,
+            read_value: 0,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.read_value
+    }
+
+    // Bits 0-7 select the register and bits 12-15 are the direction, 5 to write and 6 to read
+    pub fn transfer(&mut self, cnt: u16, write_value: u8) {
+        let index = (cnt & 0xFF) as usize;
+        match cnt >> 12 {
+            5 => {
+                if Baseband::WRITABLE
+                    .iter()
+                    .any(|range| range.contains(&index))
+                {
+                    self.regs[index] = write_value
+                }
+            }
+            6 => self.read_value = self.regs[index],
+            direction => warn!("Unknown Wi-Fi Baseband Direction: {}", direction),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+// Carries frames between emulated consoles, standing in for the air
+// Packets are opaque to links, go to every other console and receive never blocks
+pub trait WiFiLink {
+    fn send(&mut self, packet: &[u8]);
+    fn receive(&mut self) -> Option<Vec<u8>>;
+
+    // Links that know how far the other consoles have been emulated let hosts wait exactly
+    // until every client has had the chance to reply, instead of for a fixed time
+    fn set_time(&mut self, _us: u64) {}
+
+    fn peers_time(&self) -> Option<u64> {
+        None
+    }
+}
+
+// Consoles in the same process, which all receive what any of them sends
+// Emulating them in a fixed order makes multiplayer exchanges deterministic
+#[derive(Clone, Default)]
+pub struct LocalHub {
+    peers: Arc<Mutex<Vec<Peer>>>,
+}
+
+struct Peer {
+    id: usize,
+    sender: Sender<Vec<u8>>,
+    time: u64,
+}
+
+impl LocalHub {
+    pub fn new() -> Self {
+        LocalHub::default()
+    }
+
+    pub fn connect(&self) -> LocalLink {
+        let (sender, receiver) = mpsc::channel();
+        let mut peers = self.peers.lock().unwrap();
+        let id = peers.last().map_or(0, |peer| peer.id + 1);
+        peers.push(Peer {
+            id,
+            sender,
+            time: 0,
+        });
+        LocalLink {
+            id,
+            hub: self.clone(),
+            receiver,
+        }
+    }
+}
+
+pub struct LocalLink {
+    id: usize,
+    hub: LocalHub,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl LocalLink {
+    pub fn pair() -> (LocalLink, LocalLink) {
+        let hub = LocalHub::new();
+        (hub.connect(), hub.connect())
+    }
+}
+
+impl WiFiLink for LocalLink {
+    fn send(&mut self, packet: &[u8]) {
+        let peers = self.hub.peers.lock().unwrap();
+        for peer in peers.iter().filter(|peer| peer.id != self.id) {
+            let _ = peer.sender.send(packet.to_vec());
+        }
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn set_time(&mut self, us: u64) {
+        let mut peers = self.hub.peers.lock().unwrap();
+        if let Some(peer) = peers.iter_mut().find(|peer| peer.id == self.id) {
+            peer.time = us;
+        }
+    }
+
+    // The console furthest behind, or forever if this is the only one left
+    fn peers_time(&self) -> Option<u64> {
+        let peers = self.hub.peers.lock().unwrap();
+        let time = peers
+            .iter()
+            .filter(|peer| peer.id != self.id)
+            .map(|peer| peer.time)
+            .min();
+        Some(time.unwrap_or(u64::MAX))
+    }
+}
+
+// A dropped console is the same as one out of range, which nobody waits for
+impl Drop for LocalLink {
+    fn drop(&mut self) {
+        let mut peers = self.hub.peers.lock().unwrap();
+        peers.retain(|peer| peer.id != self.id);
+    }
+}
+
+// Connects to consoles in other processes on the same machine
+pub struct UdpLink {
+    socket: UdpSocket,
+    peer_ports: Vec<u16>,
+}
+
+impl UdpLink {
+    const MAX_PACKET_LEN: usize = 0x1000;
+
+    pub fn new(port: u16, peer_ports: &[u16]) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, port))?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpLink {
+            socket,
+            peer_ports: peer_ports.to_vec(),
+        })
+    }
+}
+
+impl WiFiLink for UdpLink {
+    fn send(&mut self, packet: &[u8]) {
+        for &port in &self.peer_ports {
+            // Fails while the peer isn't running yet
+            let _ = self.socket.send_to(packet, (Ipv4Addr::LOCALHOST, port));
+        }
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        let mut packet = vec![0; UdpLink::MAX_PACKET_LEN];
+        loop {
+            match self.socket.recv_from(&mut packet) {
+                Ok((len, addr)) if self.peer_ports.contains(&addr.port()) => {
+                    packet.truncate(len);
+                    return Some(packet);
+                }
+                // Anything else on the port isn't a console
+                Ok(_) => continue,
+                // Reported on Linux after sending to a port nothing is bound to
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+// RF chip, accessed serially through W_RF_DATA2 and W_RF_DATA1
+// Only the RF2958 (type 2) transfer format is emulated, which is what most DS consoles have
+pub struct RF {
+    regs: [u32; 0x20],
+}
+
+impl RF {
+    pub fn new() -> Self {
+        RF { regs: [0; 0x20] }
+    }
+
+    // DATA2 has the index in bits 2-6 and a read flag in bit 7 along with the upper 2 bits of
+    // the 18-bit data, which replaces DATA1 and DATA2 with the register's value on reads
+    pub fn transfer(&mut self, data2: u16, data1: u16) -> (u16, u16) {
+        let index = (data2 >> 2 & 0x1F) as usize;
+        if data2 & 0x80 != 0 {
+            let value = self.regs[index];
+            (data2 & !0x3 | (value >> 16) as u16 & 0x3, value as u16)
+        } else {
+            self.regs[index] = ((data2 & 0x3) as u32) << 16 | data1 as u32;
+            (data2, data1)
+        }
+    }
+}
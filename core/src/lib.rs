@@ -6,9 +6,15 @@ pub extern crate log;
 use num_traits as num;
 pub use simplelog;
 
+#[macro_use]
+mod unimplemented;
 mod arm;
+mod error;
 mod hw;
 
+pub mod gdb;
 pub mod nds;
+pub mod rom;
 
+pub use arm::disassembler;
 pub use nds::NDS;
@@ -1,18 +1,78 @@
-use crate::likely;
+mod archive;
+mod builder;
+
+use crate::{likely, unlikely};
 use std::{
-    fs::{self, File, OpenOptions},
+    fs::{self, OpenOptions},
+    io::Write,
+    ops::Range,
     path::{Path, PathBuf},
 };
 
-use crate::arm::ARM;
+use crate::arm::{DebugStop, ARM};
 use crate::hw::HW;
+use crate::unimplemented;
 
-pub use crate::hw::{Engine, GraphicsType, Key};
+pub use crate::arm::{Mode as CpuMode, WatchKind};
+pub use crate::error::{Error, FileKind};
+pub use crate::hw::{
+    replay_geometry, Engine, FileStorage, GraphicsType, IOMapEntry, IORegisterField,
+    IORegisterGroup, IORegisterInfo, Key, LocalHub, LocalLink, MemoryStorage, OAMEntry, OBJAffine,
+    OBJMode, Polygon, PolygonAttributes, PolygonMode, Storage, TexCoordTransformationMode,
+    TextureFormat, TextureParams, UdpLink, Vertex, WiFiLink,
+};
+pub use crate::unimplemented::UnimplementedPolicy;
+pub use builder::NDSBuilder;
 
 pub struct NDS {
     arm7: ARM<false>,
     arm9: ARM<true>,
     hw: HW,
+    // Set when a debugger stopped a CPU partway through a slice
+    slice_target: Option<usize>,
+    unimplemented_policy: UnimplementedPolicy,
+    halt_reason: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cpu {
+    ARM9,
+    ARM7,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    FrameRendered,
+    Breakpoint {
+        cpu: Cpu,
+        addr: u32,
+    },
+    // Kind is the access that triggered the watchpoint, so it's never Access
+    Watchpoint {
+        cpu: Cpu,
+        kind: WatchKind,
+        addr: u32,
+        value: u32,
+    },
+    Step(Cpu),
+    // Unimplemented behavior was hit with UnimplementedPolicy::Halt
+    Halted,
+}
+
+// Runs the same code on whichever CPU is selected
+macro_rules! with_cpu {
+    ($nds:expr, $cpu:expr, |$arm:ident, $hw:ident| $body:expr) => {
+        match $cpu {
+            Cpu::ARM9 => {
+                let ($arm, $hw) = (&mut $nds.arm9, &mut $nds.hw);
+                $body
+            }
+            Cpu::ARM7 => {
+                let ($arm, $hw) = (&mut $nds.arm7, &mut $nds.hw);
+                $body
+            }
+        }
+    };
 }
 
 impl NDS {
@@ -21,35 +81,222 @@ impl NDS {
     pub fn new(
         bios7: Vec<u8>,
         bios9: Vec<u8>,
-        firmware_file: File,
+        firmware: Box<dyn Storage>,
         rom: Vec<u8>,
-        save_file: File,
-    ) -> Self {
+        save: Box<dyn Storage>,
+    ) -> Result<Self, Error> {
         let direct_boot = true;
-        let mut hw = HW::new(bios7, bios9, firmware_file, rom, save_file, direct_boot);
-        NDS {
+        let mut hw = HW::new(bios7, bios9, firmware, rom, save, direct_boot)?;
+        Ok(NDS {
             arm7: ARM::new(&mut hw, direct_boot),
             arm9: ARM::new(&mut hw, direct_boot),
             hw,
-        }
+            slice_target: None,
+            unimplemented_policy: UnimplementedPolicy::Log,
+            halt_reason: None,
+        })
     }
 
-    pub fn emulate_frame(&mut self) {
+    // Runs until a frame is rendered, the debugger stops a CPU or emulation halts
+    pub fn emulate_frame(&mut self) -> StopReason {
+        if self.halt_reason.is_some() {
+            return StopReason::Halted;
+        }
+        unimplemented::set_policy(self.unimplemented_policy);
         while !self.hw.rendered_frame() {
             if likely(!self.hw.gpu.bus_stalled()) {
-                let cycle = self.hw.cycle();
-                // The max cycle desync was ~30 when the CPUs were running tightly
-                let target = std::cmp::min(cycle + 30, self.hw.cycle_at_next_event());
+                let target = self.slice_target.take().unwrap_or_else(|| {
+                    let cycle = self.hw.cycle();
+                    // The max cycle desync was ~30 when the CPUs were running tightly
+                    std::cmp::min(cycle + 30, self.hw.cycle_at_next_event())
+                });
 
+                // A CPU that already reached the target doesn't run again when resuming
                 self.arm9.emulate(&mut self.hw, target * 2);
+                if unlikely(self.arm9.debugger().stopped()) {
+                    self.slice_target = Some(target);
+                    return self.take_debug_stop(Cpu::ARM9);
+                }
                 self.arm7.emulate(&mut self.hw, target);
+                if unlikely(self.arm7.debugger().stopped()) {
+                    self.slice_target = Some(target);
+                    return self.take_debug_stop(Cpu::ARM7);
+                }
                 self.hw.clock_until(target);
             } else {
                 self.hw.clock_until_event();
                 self.arm9.set_cycle(self.hw.cycle() * 2);
                 self.arm7.set_cycle(self.hw.cycle());
             }
+            if unlikely(self.take_halt()) {
+                return StopReason::Halted;
+            }
+        }
+        StopReason::FrameRendered
+    }
+
+    // The CPUs finish their slice after a halt is reported, which is at most a few instructions
+    fn take_halt(&mut self) -> bool {
+        self.halt_reason = unimplemented::take_halt_reason();
+        self.halt_reason.is_some()
+    }
+
+    pub fn set_unimplemented_policy(&mut self, policy: UnimplementedPolicy) {
+        self.unimplemented_policy = policy;
+    }
+
+    pub fn halt_reason(&self) -> Option<&str> {
+        self.halt_reason.as_deref()
+    }
+
+    // Continues emulating after a halt
+    pub fn resume(&mut self) {
+        self.halt_reason = None;
+    }
+
+    fn take_debug_stop(&mut self, cpu: Cpu) -> StopReason {
+        // Keeps a halt from this slice from being picked up by another NDS on this thread
+        self.take_halt();
+        let stop = with_cpu!(self, cpu, |arm, _hw| arm.debugger_mut().take_stop());
+        match stop {
+            Some(DebugStop::Breakpoint(addr)) => StopReason::Breakpoint { cpu, addr },
+            Some(DebugStop::Watchpoint(kind, addr, value)) => StopReason::Watchpoint {
+                cpu,
+                kind,
+                addr,
+                value,
+            },
+            Some(DebugStop::Step) => StopReason::Step(cpu),
+            None => unreachable!(),
+        }
+    }
+
+    // Runs until the CPU has executed one instruction, which can take more than one call if
+    // the CPU is halted
+    pub fn step_instruction(&mut self, cpu: Cpu) -> StopReason {
+        with_cpu!(self, cpu, |arm, _hw| arm.debugger_mut().step());
+        let stop = self.emulate_frame();
+        if matches!(stop, StopReason::FrameRendered | StopReason::Halted) {
+            with_cpu!(self, cpu, |arm, _hw| arm.debugger_mut().cancel_step());
         }
+        stop
+    }
+
+    pub fn breakpoints(&self, cpu: Cpu) -> &[u32] {
+        match cpu {
+            Cpu::ARM9 => self.arm9.debugger().breakpoints(),
+            Cpu::ARM7 => self.arm7.debugger().breakpoints(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, cpu: Cpu, addr: u32) {
+        with_cpu!(self, cpu, |arm, _hw| arm
+            .debugger_mut()
+            .add_breakpoint(addr))
+    }
+
+    pub fn remove_breakpoint(&mut self, cpu: Cpu, addr: u32) {
+        with_cpu!(self, cpu, |arm, _hw| arm
+            .debugger_mut()
+            .remove_breakpoint(addr))
+    }
+
+    // Watches CPU accesses that overlap addr..addr + len, optionally only ones of a value
+    pub fn add_watchpoint(
+        &mut self,
+        cpu: Cpu,
+        addr: u32,
+        len: u32,
+        kind: WatchKind,
+        value: Option<u32>,
+    ) {
+        with_cpu!(self, cpu, |arm, _hw| arm
+            .debugger_mut()
+            .add_watchpoint(addr, len, kind, value))
+    }
+
+    pub fn remove_watchpoint(&mut self, cpu: Cpu, addr: u32, len: u32, kind: WatchKind) {
+        with_cpu!(self, cpu, |arm, _hw| arm
+            .debugger_mut()
+            .remove_watchpoint(addr, len, kind))
+    }
+
+    // Writes executed instructions within cycles, counted in the CPU's own clock, to writer
+    pub fn start_trace(&mut self, cpu: Cpu, writer: Box<dyn Write>, cycles: Range<usize>) {
+        with_cpu!(self, cpu, |arm, _hw| arm.start_trace(writer, cycles))
+    }
+
+    pub fn stop_trace(&mut self, cpu: Cpu) {
+        with_cpu!(self, cpu, |arm, _hw| arm.stop_trace())
+    }
+
+    // Registers 0-15 are the current mode's registers and 16 is the CPSR
+    pub fn cpu_reg(&mut self, cpu: Cpu, reg: u32) -> u32 {
+        with_cpu!(self, cpu, |arm, _hw| if reg == 16 {
+            arm.cpsr()
+        } else {
+            arm.reg(reg)
+        })
+    }
+
+    pub fn set_cpu_reg(&mut self, cpu: Cpu, reg: u32, value: u32) {
+        with_cpu!(self, cpu, |arm, hw| if reg == 16 {
+            arm.set_cpsr(hw, value)
+        } else {
+            arm.set_reg(hw, reg, value)
+        })
+    }
+
+    // R0-R14 of any mode, not just the current one
+    pub fn cpu_mode_regs(&mut self, cpu: Cpu, mode: CpuMode) -> [u32; 15] {
+        with_cpu!(self, cpu, |arm, _hw| arm.mode_regs(mode))
+    }
+
+    pub fn cpu_spsr(&mut self, cpu: Cpu, mode: CpuMode) -> Option<u32> {
+        with_cpu!(self, cpu, |arm, _hw| arm.mode_spsr(mode))
+    }
+
+    // Doesn't affect IO registers, unlike reads done by the CPUs
+    pub fn peek_cpu_mem(&self, cpu: Cpu, addr: u32) -> u8 {
+        match cpu {
+            Cpu::ARM9 => self.hw.arm9_peek(addr),
+            Cpu::ARM7 => self.hw.arm7_peek(addr),
+        }
+    }
+
+    pub fn write_cpu_mem(&mut self, cpu: Cpu, addr: u32, value: u8) {
+        match cpu {
+            Cpu::ARM9 => self.hw.arm9_write(addr, value),
+            Cpu::ARM7 => self.hw.arm7_write(addr, value),
+        }
+    }
+
+    pub fn io_registers(&self, cpu: Cpu) -> Vec<IORegisterGroup> {
+        IORegisterGroup::from_map(self.io_map(cpu))
+    }
+
+    // Every mapped IO address range and the device that handles it, sorted by address
+    pub fn io_map(&self, cpu: Cpu) -> &'static [IOMapEntry] {
+        match cpu {
+            Cpu::ARM9 => crate::hw::ARM9_IO_MAP,
+            Cpu::ARM7 => crate::hw::ARM7_IO_MAP,
+        }
+    }
+
+    pub fn itcm_range(&self) -> Range<u32> {
+        self.hw.cp15.itcm_range()
+    }
+
+    pub fn dtcm_range(&self) -> Range<u32> {
+        self.hw.cp15.dtcm_range()
+    }
+
+    pub fn cache_emulation(&self) -> bool {
+        self.hw.cp15.emulate_caches
+    }
+
+    pub fn set_cache_emulation(&mut self, enabled: bool) {
+        self.hw.set_arm9_cache_emulation(enabled);
     }
 
     #[inline]
@@ -77,6 +324,15 @@ impl NDS {
         self.hw.release_screen();
     }
 
+    // Saves are written in place, so this is only needed for storage that isn't a file
+    pub fn save_data(&self) -> &[u8] {
+        self.hw.save_data()
+    }
+
+    pub fn firmware_data(&self) -> &[u8] {
+        self.hw.firmware_data()
+    }
+
     #[inline]
     pub fn render_palettes(
         &self,
@@ -119,51 +375,139 @@ impl NDS {
         )
     }
 
+    // 3D polygons and vertices of the last rendered frame
+    #[inline]
+    pub fn frame_polygons(&self) -> &[Polygon] {
+        self.hw.frame_polygons()
+    }
+
+    #[inline]
+    pub fn frame_vertices(&self) -> &[Vertex] {
+        self.hw.frame_vertices()
+    }
+
+    #[inline]
+    pub fn render_texture(&self, polygon_i: usize) -> (Vec<u16>, usize, usize) {
+        self.hw.render_texture(polygon_i)
+    }
+
+    // Writes the geometry commands of the next complete frame along with the VRAM and registers
+    // needed to render it, which can be replayed with replay_geometry
+    #[inline]
+    pub fn record_geometry_frame(&mut self, writer: Box<dyn Write>) {
+        self.hw.record_geometry_frame(writer)
+    }
+
+    // The 3D layer is only shown by engine A
+    #[inline]
+    pub fn engine_a_on_top(&self) -> bool {
+        self.hw.gpu.engine_a_on_top()
+    }
+
+    #[inline]
+    pub fn oam_entry(&self, engine: Engine, obj_i: usize) -> OAMEntry {
+        self.hw.oam_entry(engine, obj_i)
+    }
+
+    #[inline]
+    pub fn render_obj(&self, engine: Engine, obj_i: usize) -> (Vec<u16>, usize, usize) {
+        self.hw.render_obj(engine, obj_i)
+    }
+
     #[inline]
     pub fn render_bank(&self, bank: usize, ignore_alpha: bool) -> (Vec<u16>, usize, usize) {
         self.hw.render_bank(ignore_alpha, bank)
     }
 
+    // Frames transmitted by the Wi-Fi hardware are sent over the link, and frames received
+    // from it are delivered as if they came over the air
+    pub fn connect_wifi(&mut self, link: Box<dyn WiFiLink>) {
+        self.hw.connect_wifi(link)
+    }
+
+    pub fn disconnect_wifi(&mut self) {
+        self.hw.disconnect_wifi()
+    }
+
     pub fn load_rom(
         bios7_path: &PathBuf,
         bios9_path: &PathBuf,
         firmware_path: &PathBuf,
         rom_path: &Path,
-    ) -> Self {
-        let save_file_path = rom_path.with_extension("sav");
-        let save_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&save_file_path)
-            .unwrap();
-        let mut firmware_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&firmware_path)
-            .unwrap();
-        let firmware_bak = PathBuf::from(firmware_path.to_str().unwrap().to_owned() + ".bak");
-
-        let mut firmware_bak_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&firmware_bak)
-            .unwrap();
-        if firmware_file.metadata().unwrap().len() != firmware_bak_file.metadata().unwrap().len() {
-            std::io::copy(&mut firmware_file, &mut firmware_bak_file).unwrap();
-        }
+    ) -> Result<Self, Error> {
+        let open = |path: &Path| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+        };
+        // The firmware is written to, so it's backed up the first time it's used
+        let mut firmware_file = open(firmware_path).map_err(Error::io(FileKind::Firmware))?;
+        let mut firmware_bak = firmware_path.clone().into_os_string();
+        firmware_bak.push(".bak");
+        (|| {
+            let mut firmware_bak_file = open(Path::new(&firmware_bak))?;
+            if firmware_file.metadata()?.len() != firmware_bak_file.metadata()?.len() {
+                std::io::copy(&mut firmware_file, &mut firmware_bak_file)?;
+            }
+            Ok(())
+        })()
+        .map_err(Error::io(FileKind::Firmware))?;
+        let save_path = NDS::save_path(rom_path);
 
-        NDS::new(
-            fs::read(bios7_path).unwrap(),
-            fs::read(bios9_path).unwrap(),
-            firmware_file,
-            fs::read(rom_path).unwrap(),
-            save_file,
-        )
+        NDSBuilder::new()
+            .bios7(fs::read(bios7_path).map_err(Error::io(FileKind::Bios7))?)
+            .bios9(fs::read(bios9_path).map_err(Error::io(FileKind::Bios9))?)
+            .firmware_storage(
+                FileStorage::new(firmware_file).map_err(Error::io(FileKind::Firmware))?,
+            )
+            .rom(fs::read(rom_path).map_err(Error::io(FileKind::Rom))?)
+            .save_storage(FileStorage::open(&save_path).map_err(Error::io(FileKind::Save))?)
+            .build()
+    }
+
+    // game.nds, game.nds.gz and game.zip all save to game.sav, but only .nds is stripped from
+    // inside an archive's name, so my.game.zip saves to my.game.sav
+    fn save_path(rom_path: &Path) -> PathBuf {
+        let has_extension = |path: &Path, extensions: &[&str]| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| extensions.contains(&ext.to_lowercase().as_str()))
+        };
+        if !has_extension(rom_path, &["gz", "zip"]) {
+            return rom_path.with_extension("sav");
+        }
+        let inner_path = rom_path.with_extension("");
+        if has_extension(&inner_path, &["nds"]) {
+            return inner_path.with_extension("sav");
+        }
+        let mut save_path = inner_path.into_os_string();
+        save_path.push(".sav");
+        save_path.into()
     }
 }
 
 pub const WIDTH: usize = crate::hw::GPU::WIDTH;
 pub const HEIGHT: usize = crate::hw::GPU::HEIGHT;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_are_named_after_the_game() {
+        for (rom_path, save_path) in [
+            ("game.nds", "game.sav"),
+            ("game.nds.gz", "game.sav"),
+            ("game.NDS.GZ", "game.sav"),
+            ("game.zip", "game.sav"),
+            ("my.game.zip", "my.game.sav"),
+            ("dir.v2/game.zip", "dir.v2/game.sav"),
+            ("game", "game.sav"),
+        ] {
+            assert_eq!(NDS::save_path(Path::new(rom_path)), Path::new(save_path));
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use flate2::read::GzDecoder;
+use std::io::{Cursor, Read};
+use zip::{result::ZipError, ZipArchive};
+
+use crate::error::{Error, FileKind};
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+
+// Archives are detected by their contents so it works the same for buffers and files. A ROM
+// header starts with the ASCII game title, so it can't be mistaken for either magic
+pub fn extract_rom(data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if data.starts_with(&GZIP_MAGIC) {
+        let mut rom = Vec::new();
+        GzDecoder::new(&data[..])
+            .read_to_end(&mut rom)
+            .map_err(Error::io(FileKind::Rom))?;
+        Ok(rom)
+    } else if data.starts_with(&ZIP_MAGIC) {
+        extract_zip(data)
+    } else {
+        Ok(data)
+    }
+}
+
+// Uses the first .nds file in the archive, going by index since file_names isn't in order
+fn extract_zip(data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let zip_error = |e: ZipError| Error::io(FileKind::Rom)(e.into());
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(zip_error)?;
+    let index = (0..archive.len())
+        .find(|&i| {
+            archive
+                .by_index(i)
+                .is_ok_and(|file| file.name().to_lowercase().ends_with(".nds"))
+        })
+        .ok_or(Error::InvalidRom("Archive doesn't contain a .nds file"))?;
+    let mut file = archive.by_index(index).map_err(zip_error)?;
+    let mut rom = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut rom)
+        .map_err(Error::io(FileKind::Rom))?;
+    Ok(rom)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+    use zip::{write::FileOptions, ZipWriter};
+
+    pub fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    pub fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn plain_roms_are_unchanged() {
+        let rom = b"GAME TITLE\0\0ABCD".to_vec();
+        assert_eq!(extract_rom(rom.clone()).unwrap(), rom);
+    }
+
+    #[test]
+    fn gzip_archives_are_decompressed() {
+        let rom = vec![0x5A; 0x1000];
+        assert_eq!(extract_rom(gzip(&rom)).unwrap(), rom);
+    }
+
+    #[test]
+    fn zip_archives_use_the_first_nds_file() {
+        let archive = zip(&[
+            ("readme.txt", b"not a ROM"),
+            ("Game.NDS", b"first"),
+            ("other.nds", b"second"),
+        ]);
+        assert_eq!(extract_rom(archive).unwrap(), b"first");
+    }
+
+    #[test]
+    fn zip_archives_need_an_nds_file() {
+        let archive = zip(&[("readme.txt", b"not a ROM")]);
+        assert!(matches!(extract_rom(archive), Err(Error::InvalidRom(_))));
+    }
+
+    #[test]
+    fn corrupt_archives_are_io_errors() {
+        let mut archive = gzip(&[0x5A; 0x1000]);
+        archive.truncate(0x10);
+        assert!(matches!(
+            extract_rom(archive),
+            Err(Error::Io {
+                file: FileKind::Rom,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+use std::io::{self, Read};
+
+use super::{archive, MemoryStorage, Storage, NDS};
+use crate::error::{Error, FileKind};
+
+// Creates an NDS from buffers, readers or custom storage so nothing has to touch the filesystem.
+// Errors from readers are returned by build, so calls can be chained
+#[derive(Default)]
+pub struct NDSBuilder {
+    bios7: Option<io::Result<Vec<u8>>>,
+    bios9: Option<io::Result<Vec<u8>>>,
+    firmware: Option<io::Result<Box<dyn Storage>>>,
+    rom: Option<io::Result<Vec<u8>>>,
+    save: Option<io::Result<Box<dyn Storage>>>,
+}
+
+impl NDSBuilder {
+    pub fn new() -> Self {
+        NDSBuilder::default()
+    }
+
+    pub fn bios7(mut self, bios7: Vec<u8>) -> Self {
+        self.bios7 = Some(Ok(bios7));
+        self
+    }
+
+    pub fn bios7_reader(mut self, reader: impl Read) -> Self {
+        self.bios7 = Some(read_all(reader));
+        self
+    }
+
+    pub fn bios9(mut self, bios9: Vec<u8>) -> Self {
+        self.bios9 = Some(Ok(bios9));
+        self
+    }
+
+    pub fn bios9_reader(mut self, reader: impl Read) -> Self {
+        self.bios9 = Some(read_all(reader));
+        self
+    }
+
+    // The firmware is written to, so buffers and readers are copied into a MemoryStorage
+    pub fn firmware(self, firmware: Vec<u8>) -> Self {
+        self.firmware_storage(MemoryStorage::new(firmware))
+    }
+
+    pub fn firmware_reader(mut self, reader: impl Read) -> Self {
+        self.firmware = Some(read_all(reader).map(to_storage));
+        self
+    }
+
+    pub fn firmware_storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.firmware = Some(Ok(Box::new(storage)));
+        self
+    }
+
+    // May also be a .zip or .gz archive containing the ROM
+    pub fn rom(mut self, rom: Vec<u8>) -> Self {
+        self.rom = Some(Ok(rom));
+        self
+    }
+
+    pub fn rom_reader(mut self, reader: impl Read) -> Self {
+        self.rom = Some(read_all(reader));
+        self
+    }
+
+    // Without a save, the game starts with an empty one that's kept in memory
+    pub fn save(self, save: Vec<u8>) -> Self {
+        self.save_storage(MemoryStorage::new(save))
+    }
+
+    pub fn save_reader(mut self, reader: impl Read) -> Self {
+        self.save = Some(read_all(reader).map(to_storage));
+        self
+    }
+
+    pub fn save_storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.save = Some(Ok(Box::new(storage)));
+        self
+    }
+
+    pub fn build(self) -> Result<NDS, Error> {
+        fn take<T>(input: Option<io::Result<T>>, file: FileKind) -> Result<T, Error> {
+            input.ok_or(Error::Missing(file))?.map_err(Error::io(file))
+        }
+        let save = match self.save {
+            Some(save) => save.map_err(Error::io(FileKind::Save))?,
+            None => to_storage(Vec::new()),
+        };
+        NDS::new(
+            take(self.bios7, FileKind::Bios7)?,
+            take(self.bios9, FileKind::Bios9)?,
+            take(self.firmware, FileKind::Firmware)?,
+            archive::extract_rom(take(self.rom, FileKind::Rom)?)?,
+            save,
+        )
+    }
+}
+
+fn read_all(mut reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn to_storage(data: Vec<u8>) -> Box<dyn Storage> {
+    Box::new(MemoryStorage::new(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::archive::tests::{gzip, zip};
+    use super::*;
+
+    // A game the save database knows to have an 8KB EEPROM
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0C..0x10].copy_from_slice(b"A2DC");
+        rom
+    }
+
+    fn builder() -> NDSBuilder {
+        NDSBuilder::new()
+            .bios7(vec![0; 0x4000])
+            .bios9(vec![0; 0x1000])
+            .firmware(vec![0; 0x40000])
+    }
+
+    #[test]
+    fn archived_roms_are_extracted() {
+        for rom in [gzip(&rom()), zip(&[("game.nds", &rom())])] {
+            let nds = builder().rom(rom).build().unwrap();
+            assert_eq!(nds.save_data().len(), 0x2000);
+        }
+    }
+
+    #[test]
+    fn memory_saves_round_trip() {
+        let mut save = vec![0; 0x2000];
+        save[..4].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        let nds = builder()
+            .rom_reader(&rom()[..])
+            .save_reader(&save[..])
+            .build()
+            .unwrap();
+        assert_eq!(nds.save_data(), &save[..]);
+
+        // Short saves are padded with zeros to the size of the chip
+        let nds = builder()
+            .rom(rom())
+            .save(save[..4].to_vec())
+            .build()
+            .unwrap();
+        assert_eq!(nds.save_data(), &save[..]);
+    }
+
+    #[test]
+    fn missing_inputs_are_reported() {
+        let result = NDSBuilder::new().bios7(vec![0; 0x4000]).build();
+        assert!(matches!(result, Err(Error::Missing(FileKind::Bios9))));
+    }
+
+    #[test]
+    fn reader_errors_are_returned_by_build() {
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+        }
+        let result = builder().rom_reader(Failing).build();
+        assert!(matches!(
+            result,
+            Err(Error::Io {
+                file: FileKind::Rom,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,2 @@
+// Utilities for ROM dumps that don't need a running NDS
+pub use crate::hw::{crc16, CrcCheck, RomChecks, RomTools, SecureAreaState};
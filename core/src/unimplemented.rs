@@ -0,0 +1,72 @@
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// What to do when a game relies on behavior that isn't emulated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnimplementedPolicy {
+    // Warn and carry on as well as possible
+    Log,
+    // Stop emulating until the frontend resumes
+    Halt,
+    Panic,
+}
+
+// Devices don't have access to the NDS, so the policy of whichever NDS is emulating on this thread
+// is kept here along with the reason it needs to halt
+thread_local! {
+    static POLICY: Cell<UnimplementedPolicy> = const { Cell::new(UnimplementedPolicy::Log) };
+    static HALT_REASON: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn set_policy(policy: UnimplementedPolicy) {
+    POLICY.with(|cur| cur.set(policy));
+}
+
+// Some behavior is hit for every sample or scanline, so each call site is only logged once. Halting
+// and panicking happen every time so they keep working after resuming or in another NDS
+pub fn report(reported: &AtomicBool, reason: impl FnOnce() -> String) {
+    match POLICY.with(Cell::get) {
+        UnimplementedPolicy::Log => {
+            if !reported.swap(true, Ordering::Relaxed) {
+                warn!("Unimplemented: {}", reason());
+            }
+        }
+        UnimplementedPolicy::Halt => HALT_REASON.with(|cur| {
+            // Keep the first reason since later ones are often caused by it
+            let mut cur = cur.borrow_mut();
+            if cur.is_none() {
+                let reason = reason();
+                error!("Halting on unimplemented behavior: {}", reason);
+                *cur = Some(reason);
+            }
+        }),
+        UnimplementedPolicy::Panic => panic!("Unimplemented: {}", reason()),
+    }
+}
+
+pub fn take_halt_reason() -> Option<String> {
+    HALT_REASON.with(|cur| cur.borrow_mut().take())
+}
+
+// Reports guest behavior that isn't emulated, after which the caller carries on as best it can
+macro_rules! unimplemented_behavior {
+    ($($arg:tt)+) => {{
+        static REPORTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+        crate::unimplemented::report(&REPORTED, || format!($($arg)+))
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halt_is_reported_every_time() {
+        set_policy(UnimplementedPolicy::Halt);
+        for _ in 0..2 {
+            unimplemented_behavior!("Feature {}", 1);
+            assert_eq!(take_halt_reason().as_deref(), Some("Feature 1"));
+        }
+        set_policy(UnimplementedPolicy::Log);
+    }
+}
@@ -4,7 +4,12 @@ use std::time::Instant;
 
 use imgui::*;
 
-use super::{DebugWindowState, Engine, GraphicsType, NDS};
+use nds_core::disassembler;
+use nds_core::nds::{
+    self, Cpu, CpuMode, IORegisterInfo, OAMEntry, OBJMode, Polygon, StopReason, Vertex,
+};
+
+use super::{DebugWindowState, Engine, GraphicsType, Texture, NDS};
 
 pub struct PalettesWindowState {
     palettes_extended: bool,
@@ -278,3 +283,1030 @@ impl StatsWindow {
         }
     }
 }
+
+const CPUS: [Cpu; 2] = [Cpu::ARM9, Cpu::ARM7];
+const REG_NAMES: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp", "lr",
+    "pc",
+];
+
+fn cpu_combo(ui: &Ui, cpu: &mut usize) {
+    ui.set_next_item_width(ui.window_size()[0] * 0.3);
+    ComboBox::new(im_str!("CPU")).build_simple(
+        ui,
+        cpu,
+        &CPUS,
+        &(|cpu| Cow::from(ImString::new(format!("{:?}", cpu)))),
+    );
+}
+
+fn read_u16(nds: &NDS, cpu: Cpu, addr: u32) -> u16 {
+    nds.peek_cpu_mem(cpu, addr) as u16 | (nds.peek_cpu_mem(cpu, addr.wrapping_add(1)) as u16) << 8
+}
+
+fn read_u32(nds: &NDS, cpu: Cpu, addr: u32) -> u32 {
+    read_u16(nds, cpu, addr) as u32 | (read_u16(nds, cpu, addr.wrapping_add(2)) as u32) << 16
+}
+
+pub struct RegistersWindow {
+    opened: bool,
+    cpu: usize,
+}
+
+impl RegistersWindow {
+    // USR and SYS share registers
+    const MODES: [CpuMode; 6] = [
+        CpuMode::USR,
+        CpuMode::FIQ,
+        CpuMode::IRQ,
+        CpuMode::SVC,
+        CpuMode::ABT,
+        CpuMode::UND,
+    ];
+    // Words above SP checked for return addresses
+    const STACK_WORDS: u32 = 64;
+
+    pub fn new() -> Self {
+        RegistersWindow {
+            opened: false,
+            cpu: 0,
+        }
+    }
+
+    pub fn render(&mut self, ui: &Ui, nds: &mut NDS) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        Window::new(im_str!("Registers"))
+            .opened(&mut opened)
+            .build(ui, || {
+                cpu_combo(ui, &mut self.cpu);
+                let cpu = CPUS[self.cpu];
+
+                for (reg, name) in REG_NAMES.iter().enumerate() {
+                    ui.text(format!("{:>3}: {:08X}", name, nds.cpu_reg(cpu, reg as u32)));
+                    if reg % 4 != 3 {
+                        ui.same_line(0.0);
+                    }
+                }
+                let cpsr = nds.cpu_reg(cpu, 16);
+                let mode = match cpsr & 0x1F {
+                    0x10 => "USR",
+                    0x11 => "FIQ",
+                    0x12 => "IRQ",
+                    0x13 => "SVC",
+                    0x17 => "ABT",
+                    0x1B => "UND",
+                    0x1F => "SYS",
+                    _ => "???",
+                };
+                ui.text(format!(
+                    "cpsr: {:08X} {} {}",
+                    cpsr,
+                    RegistersWindow::flags(cpsr),
+                    mode
+                ));
+
+                if CollapsingHeader::new(im_str!("Banked Registers")).build(ui) {
+                    let mode_regs: Vec<_> = Self::MODES
+                        .iter()
+                        .map(|&mode| (nds.cpu_mode_regs(cpu, mode), nds.cpu_spsr(cpu, mode)))
+                        .collect();
+                    ui.columns(Self::MODES.len() as i32 + 1, im_str!("banked"), true);
+                    ui.next_column();
+                    for mode in Self::MODES.iter() {
+                        ui.text(format!("{:?}", mode));
+                        ui.next_column();
+                    }
+                    // Only R8-R14 are banked
+                    for reg in 8..15 {
+                        ui.text(REG_NAMES[reg]);
+                        ui.next_column();
+                        for (regs, _) in mode_regs.iter() {
+                            ui.text(format!("{:08X}", regs[reg]));
+                            ui.next_column();
+                        }
+                    }
+                    ui.text("spsr");
+                    ui.next_column();
+                    for (_, spsr) in mode_regs.iter() {
+                        ui.text(spsr.map_or(String::new(), |spsr| format!("{:08X}", spsr)));
+                        ui.next_column();
+                    }
+                    ui.columns(1, im_str!("banked"), false);
+                }
+
+                if CollapsingHeader::new(im_str!("Call Stack")).build(ui) {
+                    ui.text(format!("lr: {:08X}", nds.cpu_reg(cpu, 14)));
+                    let sp = nds.cpu_reg(cpu, 13);
+                    for i in 0..Self::STACK_WORDS {
+                        let addr = sp.wrapping_add(i * 4);
+                        let value = read_u32(nds, cpu, addr);
+                        if RegistersWindow::is_return_addr(nds, cpu, value) {
+                            ui.text(format!("{:08X} (sp + 0x{:X})", value, i * 4));
+                        }
+                    }
+                }
+            });
+        self.opened = opened;
+    }
+
+    // Uppercase when set
+    fn flags(cpsr: u32) -> String {
+        "NZCVQ"
+            .chars()
+            .enumerate()
+            .map(|(i, flag)| (cpsr >> (31 - i), flag))
+            .chain([(cpsr >> 7, 'I'), (cpsr >> 6, 'F'), (cpsr >> 5, 'T')])
+            .map(|(bit, flag)| {
+                if bit & 0x1 != 0 {
+                    flag
+                } else {
+                    flag.to_ascii_lowercase()
+                }
+            })
+            .collect()
+    }
+
+    // A call stack can't be known without debug info, so this guesses by checking if the
+    // instruction before a stack value is a BL or BLX
+    fn is_return_addr(nds: &mut NDS, cpu: Cpu, addr: u32) -> bool {
+        if !matches!(addr >> 24, 0x01..=0x03 | 0xFF) {
+            return false;
+        }
+        if addr & 0x1 != 0 {
+            let addr = addr & !0x1;
+            let instr = read_u16(nds, cpu, addr.wrapping_sub(2));
+            let prefix = read_u16(nds, cpu, addr.wrapping_sub(4));
+            // BLX reg or BL/BLX label
+            instr & 0xFF87 == 0x4780
+                || (prefix >> 11 == 0b11110 && matches!(instr >> 11, 0b11111 | 0b11101))
+        } else if addr & 0x3 == 0 {
+            let instr = read_u32(nds, cpu, addr.wrapping_sub(4));
+            // BL, BLX label or BLX reg
+            (instr >> 28 != 0xF && instr & 0x0F00_0000 == 0x0B00_0000)
+                || instr >> 25 == 0b111_1101
+                || instr & 0x0FFF_FFF0 == 0x012F_FF30
+        } else {
+            false
+        }
+    }
+
+    pub fn menu_item(&mut self, ui: &Ui) {
+        let clicked = MenuItem::new(im_str!("Registers"))
+            .selected(self.opened)
+            .build(ui);
+        if clicked {
+            self.opened = !self.opened
+        }
+    }
+}
+
+pub struct DisassemblyWindow {
+    opened: bool,
+    cpu: usize,
+    follow_pc: bool,
+    // Address of the first line
+    addr: u32,
+    goto_addr: ImString,
+    stop: Option<StopReason>,
+}
+
+impl DisassemblyWindow {
+    const NUM_LINES: u32 = 32;
+
+    pub fn new() -> Self {
+        DisassemblyWindow {
+            opened: false,
+            cpu: 0,
+            follow_pc: true,
+            addr: 0,
+            goto_addr: ImString::with_capacity(8),
+            stop: None,
+        }
+    }
+
+    // Shows where the CPU stopped after a breakpoint or watchpoint
+    pub fn stopped(&mut self, stop: StopReason) {
+        let cpu = match stop {
+            StopReason::FrameRendered | StopReason::Halted => return,
+            StopReason::Breakpoint { cpu, .. }
+            | StopReason::Watchpoint { cpu, .. }
+            | StopReason::Step(cpu) => cpu,
+        };
+        self.cpu = CPUS.iter().position(|&i| i == cpu).unwrap();
+        self.follow_pc = true;
+        self.stop = Some(stop);
+    }
+
+    // Without paused, something else like a GDB client is running the CPUs
+    pub fn render(&mut self, ui: &Ui, nds: &mut NDS, paused: Option<&mut bool>) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        Window::new(im_str!("Disassembly"))
+            .opened(&mut opened)
+            .scrollable(false)
+            .build(ui, || {
+                cpu_combo(ui, &mut self.cpu);
+                let cpu = CPUS[self.cpu];
+
+                match paused {
+                    Some(paused) => {
+                        if *paused {
+                            if ui.button(im_str!("Run"), [0.0, 0.0]) {
+                                nds.resume();
+                                *paused = false;
+                                self.stop = None;
+                            }
+                        } else if ui.button(im_str!("Pause"), [0.0, 0.0]) {
+                            *paused = true;
+                            self.follow_pc = true;
+                        }
+                        ui.same_line(0.0);
+                        if ui.button(im_str!("Step"), [0.0, 0.0]) {
+                            *paused = true;
+                            let stop = nds.step_instruction(cpu);
+                            self.stopped(stop);
+                        }
+                    }
+                    None => ui.text("Controlled by GDB"),
+                }
+                ui.same_line(0.0);
+                ui.checkbox(im_str!("Follow PC"), &mut self.follow_pc);
+
+                ui.set_next_item_width(ui.window_size()[0] * 0.3);
+                let entered = ui
+                    .input_text(im_str!("Go to"), &mut self.goto_addr)
+                    .chars_hexadecimal(true)
+                    .enter_returns_true(true)
+                    .build();
+                if entered {
+                    if let Ok(addr) = u32::from_str_radix(self.goto_addr.to_str(), 16) {
+                        self.addr = addr;
+                        self.follow_pc = false;
+                    }
+                }
+                match self.stop {
+                    Some(StopReason::Breakpoint { cpu, addr }) => {
+                        ui.text(format!("{:?} hit a breakpoint at {:08X}", cpu, addr))
+                    }
+                    Some(StopReason::Watchpoint {
+                        cpu,
+                        kind,
+                        addr,
+                        value,
+                    }) => ui.text(format!(
+                        "{:?} hit a {:?} watchpoint at {:08X} with {:08X}",
+                        cpu, kind, addr, value
+                    )),
+                    _ => (),
+                }
+                ui.separator();
+
+                let thumb = nds.cpu_reg(cpu, 16) >> 5 & 0x1 != 0;
+                let instr_size = if thumb { 2 } else { 4 };
+                let pc = nds.cpu_reg(cpu, 15);
+                if ui.is_window_hovered() && ui.io().mouse_wheel != 0.0 {
+                    let lines = -ui.io().mouse_wheel as i32 * 3;
+                    self.addr = self.addr.wrapping_add((lines * instr_size as i32) as u32);
+                    self.follow_pc = false;
+                }
+                let end_addr = self.addr.wrapping_add(Self::NUM_LINES * instr_size);
+                // Keep some lines before PC visible
+                if self.follow_pc && (pc < self.addr.wrapping_add(4 * instr_size) || pc >= end_addr)
+                {
+                    self.addr = pc.wrapping_sub(8 * instr_size);
+                }
+                self.addr &= !(instr_size - 1);
+
+                let breakpoints = nds.breakpoints(cpu).to_vec();
+                for i in 0..Self::NUM_LINES {
+                    let addr = self.addr.wrapping_add(i * instr_size);
+                    let (opcode, disassembly) = if thumb {
+                        let instr = read_u16(nds, cpu, addr);
+                        let next_instr = read_u16(nds, cpu, addr.wrapping_add(2));
+                        (
+                            format!("    {:04X}", instr),
+                            disassembler::disassemble_thumb(
+                                cpu == Cpu::ARM9,
+                                addr,
+                                instr,
+                                next_instr,
+                            ),
+                        )
+                    } else {
+                        let instr = read_u32(nds, cpu, addr);
+                        (
+                            format!("{:08X}", instr),
+                            disassembler::disassemble_arm(cpu == Cpu::ARM9, addr, instr),
+                        )
+                    };
+                    let breakpoint = breakpoints.contains(&addr);
+                    let label = ImString::new(format!(
+                        "{}{} {:08X}  {}  {}",
+                        if breakpoint { "*" } else { " " },
+                        if addr == pc { ">" } else { " " },
+                        addr,
+                        opcode,
+                        disassembly
+                    ));
+                    // Clicking a line toggles a breakpoint on it
+                    if Selectable::new(&label).selected(addr == pc).build(ui) {
+                        if breakpoint {
+                            nds.remove_breakpoint(cpu, addr);
+                        } else {
+                            nds.add_breakpoint(cpu, addr);
+                        }
+                    }
+                }
+            });
+        self.opened = opened;
+    }
+
+    pub fn menu_item(&mut self, ui: &Ui) {
+        let clicked = MenuItem::new(im_str!("Disassembly"))
+            .selected(self.opened)
+            .build(ui);
+        if clicked {
+            self.opened = !self.opened
+        }
+    }
+}
+
+pub struct MemoryWindow {
+    opened: bool,
+    cpu: usize,
+    region: usize,
+    // Address of the first row
+    addr: u32,
+    goto_addr: ImString,
+    selected: Option<u32>,
+    value: ImString,
+    pattern: ImString,
+    search_result: Option<String>,
+}
+
+impl MemoryWindow {
+    const BYTES_PER_ROW: u32 = 16;
+    const NUM_ROWS: u32 = 32;
+    // VRAM banks are only visible here while they're mapped to LCDC
+    const ARM9_REGIONS: [(&'static str, u32); 19] = [
+        ("ITCM", 0),
+        ("DTCM", 0),
+        ("Main RAM", 0x0200_0000),
+        ("Shared WRAM", 0x0300_0000),
+        ("IO", 0x0400_0000),
+        ("Palettes A", 0x0500_0000),
+        ("Palettes B", 0x0500_0400),
+        ("VRAM A", 0x0680_0000),
+        ("VRAM B", 0x0682_0000),
+        ("VRAM C", 0x0684_0000),
+        ("VRAM D", 0x0686_0000),
+        ("VRAM E", 0x0688_0000),
+        ("VRAM F", 0x0689_0000),
+        ("VRAM G", 0x0689_4000),
+        ("VRAM H", 0x0689_8000),
+        ("VRAM I", 0x068A_0000),
+        ("OAM A", 0x0700_0000),
+        ("OAM B", 0x0700_0400),
+        ("BIOS", 0xFFFF_0000),
+    ];
+    const ARM7_REGIONS: [(&'static str, u32); 6] = [
+        ("BIOS", 0),
+        ("Main RAM", 0x0200_0000),
+        ("Shared WRAM", 0x0300_0000),
+        ("ARM7 WRAM", 0x0380_0000),
+        ("IO", 0x0400_0000),
+        ("VRAM", 0x0600_0000),
+    ];
+
+    pub fn new() -> Self {
+        MemoryWindow {
+            opened: false,
+            cpu: 0,
+            region: 0,
+            addr: 0,
+            goto_addr: ImString::with_capacity(8),
+            selected: None,
+            value: ImString::with_capacity(2),
+            pattern: ImString::with_capacity(64),
+            search_result: None,
+        }
+    }
+
+    fn regions(nds: &NDS, cpu: Cpu) -> Vec<(&'static str, u32)> {
+        match cpu {
+            Cpu::ARM9 => {
+                let mut regions = Self::ARM9_REGIONS.to_vec();
+                // The TCMs can be moved with CP15
+                regions[0].1 = nds.itcm_range().start;
+                regions[1].1 = nds.dtcm_range().start;
+                regions
+            }
+            Cpu::ARM7 => Self::ARM7_REGIONS.to_vec(),
+        }
+    }
+
+    pub fn render(&mut self, ui: &Ui, nds: &mut NDS) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        Window::new(im_str!("Memory"))
+            .opened(&mut opened)
+            .scrollable(false)
+            .build(ui, || {
+                let prev_cpu = self.cpu;
+                cpu_combo(ui, &mut self.cpu);
+                let cpu = CPUS[self.cpu];
+                let regions = MemoryWindow::regions(nds, cpu);
+                if self.cpu != prev_cpu {
+                    self.region = 0;
+                    self.addr = regions[0].1;
+                    self.selected = None;
+                }
+
+                ui.set_next_item_width(ui.window_size()[0] * 0.3);
+                let region_changed = ComboBox::new(im_str!("Region")).build_simple(
+                    ui,
+                    &mut self.region,
+                    &regions,
+                    &(|(name, addr)| Cow::from(ImString::new(format!("{} ({:08X})", name, addr)))),
+                );
+                if region_changed {
+                    self.addr = regions[self.region].1;
+                }
+
+                ui.set_next_item_width(ui.window_size()[0] * 0.3);
+                let entered = ui
+                    .input_text(im_str!("Go to"), &mut self.goto_addr)
+                    .chars_hexadecimal(true)
+                    .enter_returns_true(true)
+                    .build();
+                if entered {
+                    if let Ok(addr) = u32::from_str_radix(self.goto_addr.to_str(), 16) {
+                        self.addr = addr;
+                        self.selected = Some(addr);
+                    }
+                }
+
+                // Hex bytes in memory order
+                ui.set_next_item_width(ui.window_size()[0] * 0.3);
+                let entered = ui
+                    .input_text(im_str!("Find"), &mut self.pattern)
+                    .chars_hexadecimal(true)
+                    .enter_returns_true(true)
+                    .build();
+                ui.same_line(0.0);
+                if ui.button(im_str!("Find Next"), [0.0, 0.0]) || entered {
+                    self.search_result = match MemoryWindow::parse_pattern(self.pattern.to_str()) {
+                        Some(pattern) => {
+                            let start =
+                                self.selected.map_or(self.addr, |addr| addr.wrapping_add(1));
+                            match MemoryWindow::find(nds, cpu, start, &pattern) {
+                                Some(addr) => {
+                                    self.addr = addr & !(Self::BYTES_PER_ROW - 1);
+                                    self.selected = Some(addr);
+                                    None
+                                }
+                                None => Some("Not found".to_string()),
+                            }
+                        }
+                        None => Some("Pattern must be whole bytes".to_string()),
+                    };
+                }
+                if let Some(search_result) = &self.search_result {
+                    ui.same_line(0.0);
+                    ui.text(search_result);
+                }
+
+                if let Some(addr) = self.selected {
+                    ui.set_next_item_width(ui.window_size()[0] * 0.1);
+                    let entered = ui
+                        .input_text(&ImString::new(format!("{:08X}", addr)), &mut self.value)
+                        .chars_hexadecimal(true)
+                        .enter_returns_true(true)
+                        .build();
+                    if entered {
+                        if let Ok(value) = u8::from_str_radix(self.value.to_str(), 16) {
+                            nds.write_cpu_mem(cpu, addr, value);
+                            // Move on so consecutive bytes can be typed in
+                            self.select(nds, cpu, addr.wrapping_add(1));
+                        }
+                    }
+                }
+                ui.separator();
+
+                if ui.is_window_hovered() && ui.io().mouse_wheel != 0.0 {
+                    let rows = -ui.io().mouse_wheel as i32 * 3;
+                    self.addr = self
+                        .addr
+                        .wrapping_add((rows * Self::BYTES_PER_ROW as i32) as u32);
+                }
+                self.addr &= !(Self::BYTES_PER_ROW - 1);
+
+                let byte_width = ui.calc_text_size(im_str!("00"), false, -1.0)[0];
+                for row in 0..Self::NUM_ROWS {
+                    let row_addr = self.addr.wrapping_add(row * Self::BYTES_PER_ROW);
+                    let bytes: Vec<_> = (0..Self::BYTES_PER_ROW)
+                        .map(|i| nds.peek_cpu_mem(cpu, row_addr.wrapping_add(i)))
+                        .collect();
+                    ui.text(format!("{:08X}:", row_addr));
+                    for (i, byte) in bytes.iter().enumerate() {
+                        let addr = row_addr.wrapping_add(i as u32);
+                        ui.same_line(0.0);
+                        // Clicking a byte selects it for editing
+                        let label = ImString::new(format!("{:02X}##{:08X}", byte, addr));
+                        let clicked = Selectable::new(&label)
+                            .selected(self.selected == Some(addr))
+                            .size([byte_width, 0.0])
+                            .build(ui);
+                        if clicked {
+                            self.select(nds, cpu, addr);
+                        }
+                    }
+                    ui.same_line(0.0);
+                    let ascii: String = bytes
+                        .iter()
+                        .map(|&byte| {
+                            if byte.is_ascii_graphic() || byte == b' ' {
+                                byte as char
+                            } else {
+                                '.'
+                            }
+                        })
+                        .collect();
+                    ui.text(ascii);
+                }
+            });
+        self.opened = opened;
+    }
+
+    fn select(&mut self, nds: &NDS, cpu: Cpu, addr: u32) {
+        self.selected = Some(addr);
+        self.value = ImString::with_capacity(2);
+        self.value
+            .push_str(&format!("{:02X}", nds.peek_cpu_mem(cpu, addr)));
+    }
+
+    fn parse_pattern(pattern: &str) -> Option<Vec<u8>> {
+        if pattern.is_empty() || !pattern.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..pattern.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&pattern[i..i + 2], 16).ok())
+            .collect()
+    }
+
+    // Searches up to the end of the 16 MB area start is in, which covers every region
+    fn find(nds: &NDS, cpu: Cpu, start: u32, pattern: &[u8]) -> Option<u32> {
+        let end = (start | 0x00FF_FFFF).checked_sub(pattern.len() as u32 - 1)?;
+        (start..=end).find(|&addr| {
+            pattern
+                .iter()
+                .enumerate()
+                .all(|(i, &byte)| nds.peek_cpu_mem(cpu, addr + i as u32) == byte)
+        })
+    }
+
+    pub fn menu_item(&mut self, ui: &Ui) {
+        let clicked = MenuItem::new(im_str!("Memory"))
+            .selected(self.opened)
+            .build(ui);
+        if clicked {
+            self.opened = !self.opened
+        }
+    }
+}
+
+pub struct OAMWindow {
+    opened: bool,
+    engine: usize,
+    obj_i: usize,
+    texture: Texture,
+}
+
+impl OAMWindow {
+    const ENGINES: [Engine; 2] = [Engine::A, Engine::B];
+    const NUM_OBJS: usize = 128;
+    const SCALE: f32 = 2.0;
+
+    pub fn new() -> Self {
+        OAMWindow {
+            opened: false,
+            engine: 0,
+            obj_i: 0,
+            texture: Texture::new(),
+        }
+    }
+
+    pub fn render(&mut self, ui: &Ui, nds: &NDS) {
+        if !self.opened {
+            return;
+        }
+        let engine = OAMWindow::ENGINES[self.engine];
+        let (pixels, width, height) = nds.render_obj(engine, self.obj_i);
+        self.texture.update_pixels(pixels, width, height);
+        let mut opened = self.opened;
+        Window::new(im_str!("OAM"))
+            .opened(&mut opened)
+            .build(ui, || {
+                ui.set_next_item_width(ui.window_size()[0] * 0.3);
+                ComboBox::new(im_str!("Engine")).build_simple(
+                    ui,
+                    &mut self.engine,
+                    &OAMWindow::ENGINES,
+                    &(|i| Cow::from(ImString::new(i.label()))),
+                );
+
+                ChildWindow::new(im_str!("OBJs"))
+                    .size([ui.window_size()[0] * 0.4, 0.0])
+                    .border(true)
+                    .build(ui, || {
+                        for obj_i in 0..OAMWindow::NUM_OBJS {
+                            let entry = nds.oam_entry(engine, obj_i);
+                            let label = ImString::new(format!(
+                                "{:3} {:4},{:3} {:2}x{:<2}{}",
+                                obj_i,
+                                entry.x,
+                                entry.y,
+                                entry.width,
+                                entry.height,
+                                if entry.disabled { " (disabled)" } else { "" }
+                            ));
+                            if Selectable::new(&label)
+                                .selected(obj_i == self.obj_i)
+                                .build(ui)
+                            {
+                                self.obj_i = obj_i;
+                            }
+                        }
+                    });
+                ui.same_line(0.0);
+                ui.group(|| {
+                    let entry = nds.oam_entry(engine, self.obj_i);
+                    for line in OAMWindow::describe(&entry) {
+                        ui.text(line);
+                    }
+                    self.texture.render(OAMWindow::SCALE).build(ui);
+                });
+            });
+        self.opened = opened;
+    }
+
+    fn describe(entry: &OAMEntry) -> Vec<String> {
+        let mut lines = vec![
+            format!("Position: {}, {}", entry.x, entry.y),
+            format!("Size: {}x{}", entry.width, entry.height),
+            format!("Mode: {:?}", entry.mode),
+            format!("Priority: {}", entry.priority),
+            format!("Disabled: {}", entry.disabled),
+            format!("Mosaic: {}", entry.mosaic),
+        ];
+        match entry.affine {
+            Some(affine) => {
+                lines.push(format!(
+                    "Affine: {} {}",
+                    affine.index,
+                    if affine.double_size {
+                        "(double size)"
+                    } else {
+                        ""
+                    }
+                ));
+                let [pa, pb, pc, pd] = affine.params;
+                lines.push(format!("  PA {:.4} PB {:.4}", pa, pb));
+                lines.push(format!("  PC {:.4} PD {:.4}", pc, pd));
+            }
+            None => lines.push(format!("Flip: x {} y {}", entry.flip_x, entry.flip_y)),
+        }
+        if entry.mode == OBJMode::Bitmap {
+            lines.push(format!("Bitmap: 0x{:X}", entry.addr));
+        } else {
+            lines.push(format!(
+                "Tile: {} at 0x{:X} ({} colors)",
+                entry.tile_num,
+                entry.addr,
+                if entry.bpp8 { 256 } else { 16 }
+            ));
+            lines.push(if entry.extended_palette {
+                format!("Palette: {} (extended)", entry.palette)
+            } else if entry.bpp8 {
+                "Palette: 256 colors".to_string()
+            } else {
+                format!("Palette: {}", entry.palette)
+            });
+        }
+        lines
+    }
+
+    pub fn menu_item(&mut self, ui: &Ui) {
+        let clicked = MenuItem::new(im_str!("OAM"))
+            .selected(self.opened)
+            .build(ui);
+        if clicked {
+            self.opened = !self.opened
+        }
+    }
+}
+
+pub struct Scene3DWindow {
+    opened: bool,
+    polygon_i: Option<usize>,
+    highlight: bool,
+    texture: Texture,
+}
+
+impl Scene3DWindow {
+    const TEXTURE_SCALE: f32 = 2.0;
+    const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.0, 1.0, 1.0];
+
+    pub fn new() -> Self {
+        Scene3DWindow {
+            opened: false,
+            polygon_i: None,
+            highlight: true,
+            texture: Texture::new(),
+        }
+    }
+
+    // screens_area is where both screens are drawn in the main window, for highlighting
+    pub fn render(&mut self, ui: &Ui, nds: &NDS, screens_area: [f32; 4]) {
+        if !self.opened {
+            return;
+        }
+        let polygons = nds.frame_polygons();
+        let vertices = nds.frame_vertices();
+        // The number of polygons changes between frames
+        self.polygon_i = self.polygon_i.filter(|&i| i < polygons.len());
+        let mut opened = self.opened;
+        Window::new(im_str!("3D Scene"))
+            .opened(&mut opened)
+            .build(ui, || {
+                ui.text(format!(
+                    "{} polygons, {} vertices",
+                    polygons.len(),
+                    vertices.len()
+                ));
+                ui.same_line(0.0);
+                ui.checkbox(im_str!("Highlight"), &mut self.highlight);
+
+                ChildWindow::new(im_str!("Polygons"))
+                    .size([ui.window_size()[0] * 0.35, 0.0])
+                    .border(true)
+                    .build(ui, || {
+                        for (i, polygon) in polygons.iter().enumerate() {
+                            let label = ImString::new(format!(
+                                "{:4} {} verts {:?}",
+                                i,
+                                polygon.end_vert - polygon.start_vert,
+                                polygon.tex_params.format
+                            ));
+                            if Selectable::new(&label)
+                                .selected(self.polygon_i == Some(i))
+                                .build(ui)
+                            {
+                                self.polygon_i = Some(i);
+                            }
+                        }
+                    });
+                ui.same_line(0.0);
+                let polygon_i = match self.polygon_i {
+                    Some(polygon_i) => polygon_i,
+                    None => return,
+                };
+                let polygon = &polygons[polygon_i];
+                let vertices = &vertices[polygon.start_vert..polygon.end_vert];
+                ui.group(|| {
+                    for line in Scene3DWindow::describe(polygon) {
+                        ui.text(line);
+                    }
+                    if CollapsingHeader::new(im_str!("Vertices"))
+                        .default_open(true)
+                        .build(ui)
+                    {
+                        for vertex in vertices.iter() {
+                            ui.text(Scene3DWindow::describe_vertex(vertex));
+                        }
+                    }
+                    let (pixels, width, height) = nds.render_texture(polygon_i);
+                    if width != 0 {
+                        self.texture.update_pixels(pixels, width, height);
+                        self.texture.render(Scene3DWindow::TEXTURE_SCALE).build(ui);
+                    }
+                });
+
+                if self.highlight {
+                    Scene3DWindow::highlight(ui, nds, screens_area, vertices);
+                }
+            });
+        self.opened = opened;
+    }
+
+    fn describe(polygon: &Polygon) -> Vec<String> {
+        let attrs = &polygon.attrs;
+        let tex_params = &polygon.tex_params;
+        vec![
+            format!(
+                "Mode: {:?} Alpha: {} ID: {}",
+                attrs.mode, attrs.alpha, attrs.polygon_id
+            ),
+            format!(
+                "Facing: {} Render front: {} back: {}",
+                if polygon.is_front { "front" } else { "back" },
+                attrs.render_front,
+                attrs.render_back
+            ),
+            format!(
+                "Depth test equal: {} Fog: {} Lights: {:?}",
+                attrs.depth_test_eq, attrs.fog_enable, attrs.lights_enabled
+            ),
+            format!(
+                "Texture: {:?} {}x{} at 0x{:X}",
+                tex_params.format, tex_params.size_s, tex_params.size_t, tex_params.vram_offset
+            ),
+            format!(
+                "Repeat: {} {} Flip: {} {} Color 0 transparent: {}",
+                tex_params.repeat_s,
+                tex_params.repeat_t,
+                tex_params.flip_s,
+                tex_params.flip_t,
+                tex_params.color0_transparent
+            ),
+            format!(
+                "Coordinate transformation: {:?}",
+                tex_params.coord_transformation_mode
+            ),
+            format!("Palette: 0x{:X}", polygon.palette_base),
+        ]
+    }
+
+    // Texture coordinates are 12.4 fixed point
+    fn describe_vertex(vertex: &Vertex) -> String {
+        format!(
+            "({:3}, {:3}) z {:06X} w {:5} rgb {:2},{:2},{:2} st {:.2},{:.2}",
+            vertex.screen_coords[0],
+            vertex.screen_coords[1],
+            vertex.z_depth,
+            vertex.normalized_w,
+            vertex.color.r5(),
+            vertex.color.g5(),
+            vertex.color.b5(),
+            vertex.tex_coord[0] as f32 / 16.0,
+            vertex.tex_coord[1] as f32 / 16.0,
+        )
+    }
+
+    fn highlight(ui: &Ui, nds: &NDS, screens_area: [f32; 4], vertices: &[Vertex]) {
+        let [x, y, width, height] = screens_area;
+        let (scale_x, scale_y) = (width / nds::WIDTH as f32, height / (2 * nds::HEIGHT) as f32);
+        let y = if nds.engine_a_on_top() {
+            y
+        } else {
+            y + nds::HEIGHT as f32 * scale_y
+        };
+        let points: Vec<_> = vertices
+            .iter()
+            .map(|vertex| {
+                [
+                    x + vertex.screen_coords[0] as f32 * scale_x,
+                    y + vertex.screen_coords[1] as f32 * scale_y,
+                ]
+            })
+            .collect();
+        let draw_list = ui.get_background_draw_list();
+        for (i, &point) in points.iter().enumerate() {
+            let next_point = points[(i + 1) % points.len()];
+            draw_list
+                .add_line(point, next_point, Scene3DWindow::HIGHLIGHT_COLOR)
+                .thickness(2.0)
+                .build();
+        }
+    }
+
+    pub fn menu_item(&mut self, ui: &Ui) {
+        let clicked = MenuItem::new(im_str!("3D Scene"))
+            .selected(self.opened)
+            .build(ui);
+        if clicked {
+            self.opened = !self.opened
+        }
+    }
+}
+
+pub struct IORegistersWindow {
+    opened: bool,
+    cpu: usize,
+}
+
+impl IORegistersWindow {
+    pub fn new() -> Self {
+        IORegistersWindow {
+            opened: false,
+            cpu: 0,
+        }
+    }
+
+    pub fn render(&mut self, ui: &Ui, nds: &mut NDS) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        Window::new(im_str!("IO Registers"))
+            .opened(&mut opened)
+            .build(ui, || {
+                cpu_combo(ui, &mut self.cpu);
+                let cpu = CPUS[self.cpu];
+                ui.separator();
+
+                for group in nds.io_registers(cpu) {
+                    if !CollapsingHeader::new(&ImString::new(group.name)).build(ui) {
+                        continue;
+                    }
+                    for register in group.registers {
+                        let value = IORegistersWindow::read(nds, cpu, register);
+                        let label = ImString::new(format!(
+                            "{:08X} {:<14} {:0width$X}",
+                            register.addr,
+                            register.name,
+                            value,
+                            width = 2 * register.size
+                        ));
+                        let id = ImString::new(format!("{:08X}", register.addr));
+                        TreeNode::new(&id).label(&label).build(ui, || {
+                            let new_value = IORegistersWindow::render_fields(ui, register, value);
+                            if let Some(new_value) = new_value {
+                                IORegistersWindow::write(nds, cpu, register, value, new_value);
+                            }
+                        });
+                    }
+                }
+            });
+        self.opened = opened;
+    }
+
+    // Returns the new value of the register if a field was edited
+    fn render_fields(ui: &Ui, register: &IORegisterInfo, value: u64) -> Option<u64> {
+        let mut new_value = None;
+        ui.set_next_item_width(ui.window_size()[0] * 0.3);
+        let mut raw = ImString::new(format!("{:0width$X}", value, width = 2 * register.size));
+        let entered = ui
+            .input_text(im_str!("Value"), &mut raw)
+            .chars_hexadecimal(true)
+            .enter_returns_true(true)
+            .build();
+        if entered {
+            new_value = u64::from_str_radix(raw.to_str(), 16).ok();
+        }
+        for field in register.fields {
+            let bits = if field.lo == field.hi {
+                format!("{}", field.lo)
+            } else {
+                format!("{}-{}", field.lo, field.hi)
+            };
+            let label = ImString::new(format!("{} ({})", field.name, bits));
+            if field.lo == field.hi {
+                let mut set = field.get(value) != 0;
+                if ui.checkbox(&label, &mut set) {
+                    new_value = Some(field.set(value, set as u64));
+                }
+            } else {
+                let mut field_value = field.get(value) as i32;
+                ui.set_next_item_width(ui.window_size()[0] * 0.3);
+                if ui.input_int(&label, &mut field_value).build() {
+                    new_value = Some(field.set(value, field_value as u64));
+                }
+            }
+        }
+        new_value
+    }
+
+    fn read(nds: &NDS, cpu: Cpu, register: &IORegisterInfo) -> u64 {
+        (0..register.size).rev().fold(0, |value, i| {
+            value << 8 | nds.peek_cpu_mem(cpu, register.addr + i as u32) as u64
+        })
+    }
+
+    // Only changed bytes are written, since writes to some registers have side effects
+    fn write(nds: &mut NDS, cpu: Cpu, register: &IORegisterInfo, old_value: u64, value: u64) {
+        for i in 0..register.size {
+            let (old_byte, byte) = ((old_value >> (8 * i)) as u8, (value >> (8 * i)) as u8);
+            if old_byte != byte {
+                nds.write_cpu_mem(cpu, register.addr + i as u32, byte);
+            }
+        }
+    }
+
+    pub fn menu_item(&mut self, ui: &Ui) {
+        let clicked = MenuItem::new(im_str!("IO Registers"))
+            .selected(self.opened)
+            .build(ui);
+        if clicked {
+            self.opened = !self.opened
+        }
+    }
+}
@@ -12,6 +12,8 @@ pub struct Display {
     window: Window,
     events: std::sync::mpsc::Receiver<(f64, glfw::WindowEvent)>,
     screen_tex: u32,
+    // Position and size of both screens in window coordinates
+    screens_area: [f32; 4],
 
     imgui_renderer: imgui_opengl_renderer::Renderer,
     glfw: Glfw, // Dropped last
@@ -90,6 +92,7 @@ impl Display {
             window,
             events,
             screen_tex,
+            screens_area: [0.0, 0.0, Display::WIDTH as f32, Display::HEIGHT as f32],
 
             imgui_renderer,
 
@@ -212,6 +215,12 @@ impl Display {
         let y_start = tex_y;
         let x_end = width - tex_x;
         let y_end = height - tex_y;
+        self.screens_area = [
+            x_start as f32,
+            main_menu_height + y_start as f32,
+            (x_end - x_start) as f32,
+            (y_end - y_start) as f32,
+        ];
 
         unsafe {
             gl::BindTexture(gl::TEXTURE_2D, self.screen_tex);
@@ -326,6 +335,10 @@ impl Display {
         (keys_pressed, files_dropped)
     }
 
+    pub fn screens_area(&self) -> [f32; 4] {
+        self.screens_area
+    }
+
     pub fn render_imgui<F>(
         &mut self,
         imgui: &mut imgui::Context,
@@ -2,10 +2,13 @@ mod debug;
 mod display;
 
 use std::fs::File;
+use std::io::BufWriter;
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use nds_core::gdb::GdbServer;
 use nds_core::log::*;
-use nds_core::nds::{Engine, GraphicsType, NDS};
+use nds_core::nds::{Cpu, Engine, GraphicsType, StopReason, UdpLink, UnimplementedPolicy, NDS};
 use nds_core::simplelog::*;
 
 use debug::*;
@@ -15,62 +18,100 @@ use imgui::*;
 fn main() {
     let args: Vec<_> = std::env::args().collect();
 
-    if args.len() != 2 {
-        println!("Usage: {} <ROM file>", args[0]);
-        std::process::exit(1);
+    let mut rom_arg = None;
+    let mut gdb_port = None;
+    let mut traces = Vec::new();
+    let mut trace_cycles = 0..usize::MAX;
+    let mut wifi_ports = None;
+    let mut unimplemented_policy = UnimplementedPolicy::Log;
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        if arg == "--gdb" {
+            gdb_port = arg_iter.next().and_then(|port| port.parse::<u16>().ok());
+        } else if arg == "--trace" {
+            let cpu = match arg_iter.next().map(|cpu| cpu.as_str()) {
+                Some("arm9") => Some(Cpu::ARM9),
+                Some("arm7") => Some(Cpu::ARM7),
+                _ => None,
+            };
+            if let (Some(cpu), Some(path)) = (cpu, arg_iter.next()) {
+                traces.push((cpu, path));
+            }
+        } else if arg == "--trace-cycles" {
+            let range = arg_iter.next().and_then(|range| range.split_once('-'));
+            if let Some((start, end)) = range {
+                let start = start.parse().unwrap_or(0);
+                let end = end.parse().unwrap_or(usize::MAX);
+                trace_cycles = start..end;
+            }
+        } else if arg == "--wifi" {
+            let port = arg_iter.next().and_then(|port| port.parse::<u16>().ok());
+            let peer_ports = arg_iter.next().and_then(|ports| {
+                ports
+                    .split(',')
+                    .map(|port| port.parse::<u16>().ok())
+                    .collect::<Option<Vec<_>>>()
+            });
+            wifi_ports = port.zip(peer_ports);
+        } else if arg == "--unimplemented" {
+            match arg_iter.next().map(|policy| policy.as_str()) {
+                Some("log") => unimplemented_policy = UnimplementedPolicy::Log,
+                Some("halt") => unimplemented_policy = UnimplementedPolicy::Halt,
+                Some("panic") => unimplemented_policy = UnimplementedPolicy::Panic,
+                _ => (),
+            }
+        } else {
+            rom_arg = Some(arg);
+        }
     }
+    let rom_arg = match rom_arg {
+        Some(rom_arg) => rom_arg,
+        None => {
+            println!(
+                "Usage: {} <ROM file> [--gdb <port>] [--trace <arm9|arm7> <file>]... \
+                [--trace-cycles <start>-<end>] [--wifi <port> <peer port>[,<peer port>]...] \
+                [--unimplemented <log|halt|panic>]",
+                args[0]
+            );
+            std::process::exit(1);
+        }
+    };
 
-    let rom_path = Path::new(&args[1]);
+    let rom_path = Path::new(rom_arg);
     let bios7_path = PathBuf::from("ROMs/bios7.bin");
     let bios9_path = PathBuf::from("ROMs/bios9.bin");
     let firmware_path = PathBuf::from("ROMs/firmware.bin");
 
-    let arm7_file_name = "ROMs/arm7.log";
-    let arm9_file_name = "ROMs/arm9.log";
-    let instructions7_filter = LevelFilter::Off;
-    let instructions9_filter = LevelFilter::Off;
-
-    let arm7_file = File::create(arm7_file_name);
-    let arm9_file = File::create(arm9_file_name);
-    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
+    TermLogger::init(
         LevelFilter::Warn,
         Config::default(),
         TerminalMode::Mixed,
         ColorChoice::Auto,
-    )];
-    if let Ok(file) = arm7_file {
-        loggers.push(WriteLogger::new(
-            instructions7_filter,
-            ConfigBuilder::new()
-                .set_time_level(LevelFilter::Off)
-                .set_thread_level(LevelFilter::Off)
-                .set_target_level(LevelFilter::Off)
-                .set_location_level(LevelFilter::Off)
-                .set_time_level(LevelFilter::Off)
-                .set_max_level(LevelFilter::Off)
-                .add_filter_allow_str("nds_core::arm7")
-                .build(),
-            file,
-        ));
-    }
-    if let Ok(file) = arm9_file {
-        loggers.push(WriteLogger::new(
-            instructions9_filter,
-            ConfigBuilder::new()
-                .set_time_level(LevelFilter::Off)
-                .set_thread_level(LevelFilter::Off)
-                .set_target_level(LevelFilter::Off)
-                .set_location_level(LevelFilter::Off)
-                .set_time_level(LevelFilter::Off)
-                .set_max_level(LevelFilter::Off)
-                .add_filter_allow_str("nds_core::arm9")
-                .build(),
-            file,
-        ))
+    )
+    .unwrap();
+
+    let mut nds = match NDS::load_rom(&bios7_path, &bios9_path, &firmware_path, rom_path) {
+        Ok(nds) => nds,
+        Err(e) => {
+            error!("Unable to load {}: {}", rom_arg, e);
+            std::process::exit(1);
+        }
+    };
+    nds.set_unimplemented_policy(unimplemented_policy);
+    connect_wifi(&mut nds, wifi_ports.as_ref());
+    for (cpu, path) in traces {
+        match File::create(path) {
+            Ok(file) => nds.start_trace(cpu, Box::new(file), trace_cycles.clone()),
+            Err(e) => warn!("Unable to create trace file {}: {}", path, e),
+        }
     }
-    CombinedLogger::init(loggers).unwrap();
-
-    let mut nds = NDS::load_rom(&bios7_path, &bios9_path, &firmware_path, rom_path);
+    let mut gdb_server = gdb_port.and_then(|port| match GdbServer::new(port) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            warn!("Unable to start GDB server on port {}: {}", port, e);
+            None
+        }
+    });
 
     let mut main_menu_height = 0.0;
     let mut palettes_window = DebugWindow::<PalettesWindowState>::new("Palettes");
@@ -78,16 +119,39 @@ fn main() {
     let mut tiles_window = DebugWindow::<TilesWindowState>::new("Tiles");
     let mut vram_window = DebugWindow::<VRAMWindowState>::new("VRAM");
     let mut stats_window = StatsWindow::new();
+    let mut registers_window = RegistersWindow::new();
+    let mut disassembly_window = DisassemblyWindow::new();
+    let mut memory_window = MemoryWindow::new();
+    let mut io_registers_window = IORegistersWindow::new();
+    let mut oam_window = OAMWindow::new();
+    let mut scene_3d_window = Scene3DWindow::new();
+    let mut paused = false;
 
     let mut imgui = Context::create();
     let mut display = Display::new(&mut imgui);
 
     let main_loop = move |display: &mut Display| {
-        nds.emulate_frame();
+        if let Some(gdb_server) = gdb_server.as_mut() {
+            gdb_server.poll(&mut nds);
+        }
+        let gdb_attached = gdb_server.as_ref().is_some_and(GdbServer::attached);
+        if gdb_attached {
+            gdb_server.as_mut().unwrap().emulate_frame(&mut nds);
+        } else if !paused {
+            let stop = nds.emulate_frame();
+            if stop != StopReason::FrameRendered {
+                if let Some(reason) = nds.halt_reason() {
+                    error!("Halted: {}", reason);
+                }
+                paused = true;
+                disassembly_window.stopped(stop);
+            }
+        }
         stats_window.frame_completed();
 
         let (keys_pressed, files_dropped) =
             display.render_main(&mut nds, &mut imgui, main_menu_height);
+        let screens_area = display.screens_area();
         display.render_imgui(&mut imgui, keys_pressed, |ui, keys_pressed| {
             ui.main_menu_bar(|| {
                 ui.menu(im_str!("Debug Windows"), true, || {
@@ -96,6 +160,31 @@ fn main() {
                     tiles_window.menu_item(ui);
                     vram_window.menu_item(ui);
                     stats_window.menu_item(ui);
+                    registers_window.menu_item(ui);
+                    disassembly_window.menu_item(ui);
+                    memory_window.menu_item(ui);
+                    io_registers_window.menu_item(ui);
+                    oam_window.menu_item(ui);
+                    scene_3d_window.menu_item(ui);
+                });
+                ui.menu(im_str!("Emulation"), true, || {
+                    let cache_emulation = nds.cache_emulation();
+                    let clicked = MenuItem::new(im_str!("Emulate ARM9 Caches"))
+                        .selected(cache_emulation)
+                        .build(ui);
+                    if clicked {
+                        nds.set_cache_emulation(!cache_emulation);
+                    }
+                    if MenuItem::new(im_str!("Record 3D Frame")).build(ui) {
+                        let secs = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |time| time.as_secs());
+                        let path = format!("frame-{}.gxrec", secs);
+                        match File::create(&path) {
+                            Ok(file) => nds.record_geometry_frame(Box::new(BufWriter::new(file))),
+                            Err(e) => warn!("Unable to create geometry recording {}: {}", path, e),
+                        }
+                    }
                 });
                 main_menu_height = ui.window_size()[1];
             });
@@ -105,20 +194,36 @@ fn main() {
             tiles_window.render(&mut nds, ui, &keys_pressed);
             vram_window.render(&mut nds, ui, &keys_pressed);
             stats_window.render(ui);
+            registers_window.render(ui, &mut nds);
+            let paused = (!gdb_attached).then_some(&mut paused);
+            disassembly_window.render(ui, &mut nds, paused);
+            memory_window.render(ui, &mut nds);
+            io_registers_window.render(ui, &mut nds);
+            oam_window.render(ui, &nds);
+            scene_3d_window.render(ui, &nds, screens_area);
         });
 
         if files_dropped.len() == 1 {
             if let Some(ext) = files_dropped[0].extension() {
                 if let Some(str) = ext.to_str() {
-                    if str.to_lowercase() == "nds" {
-                        nds = NDS::load_rom(
+                    if matches!(str.to_lowercase().as_str(), "nds" | "zip" | "gz") {
+                        match NDS::load_rom(
                             &bios7_path,
                             &bios9_path,
                             &firmware_path,
                             &files_dropped[0],
-                        );
+                        ) {
+                            Ok(new_nds) => {
+                                nds = new_nds;
+                                nds.set_unimplemented_policy(unimplemented_policy);
+                                connect_wifi(&mut nds, wifi_ports.as_ref());
+                                paused = false;
+                            }
+                            // Keep running the current game
+                            Err(e) => error!("Unable to load ROM: {}", e),
+                        }
                     } else {
-                        error!("File is not a .nds file!")
+                        error!("File is not a .nds, .zip or .gz file!")
                     }
                 }
             } else {
@@ -131,3 +236,13 @@ fn main() {
 
     display.run_main_loop(main_loop);
 }
+
+// Links with other instances on this machine, each started with its own port and the others'
+fn connect_wifi(nds: &mut NDS, ports: Option<&(u16, Vec<u16>)>) {
+    if let Some((port, peer_ports)) = ports {
+        match UdpLink::new(*port, peer_ports) {
+            Ok(link) => nds.connect_wifi(Box::new(link)),
+            Err(e) => warn!("Unable to open Wi-Fi link on port {}: {}", port, e),
+        }
+    }
+}